@@ -0,0 +1,17 @@
+//! Sets the `jit` cfg on the platforms the JIT compiler supports, so the modules, the command
+//! line and the tests do not have to repeat the list of platforms.
+
+use std::env;
+
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(jit)");
+
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    if matches!(
+        (arch.as_str(), os.as_str()),
+        ("x86_64" | "aarch64", "linux" | "macos") | ("x86_64", "windows")
+    ) {
+        println!("cargo:rustc-cfg=jit");
+    }
+}
@@ -0,0 +1,169 @@
+//! Measurements of the phases of the compiler on generated programs of increasing size, so
+//! steps that do not scale linearly with the size of the source stand out.
+
+use std::time::{Duration, Instant};
+
+use crate::compiler::{self, Compiler};
+#[cfg(jit)]
+use crate::jit::JitCompiler;
+use crate::passes::{self, OptLevel};
+#[cfg(jit)]
+use crate::timing::Phase;
+use crate::tokenizer::tokenize;
+use crate::{json, remove_non_idents};
+
+/// Size in bytes of the smallest generated program.
+pub const MIN_SIZE: usize = 1_000;
+
+/// A piece of a generated program, with folds, loops that are lowered, nested loops that are
+/// kept, input and output, and a comment. Its brackets are balanced.
+const CHUNK: &str = "++++++++[>++++[>++>+++<<-]>+<<-]>>.[-]<[>]>,[->+>++<<]\n\
+                     multiply the counter and print it again\n";
+
+/// The time of a phase of the compiler for a program of `size` bytes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Measurement {
+    pub size: usize,
+    pub phase: &'static str,
+    pub time: Duration,
+}
+
+/// Returns the sizes of the generated programs, from [MIN_SIZE] up to `max_size` bytes, each
+/// ten times the one before.
+pub fn sizes(max_size: usize) -> Vec<usize> {
+    std::iter::successors(Some(MIN_SIZE), |size| size.checked_mul(10))
+        .take_while(|size| *size <= max_size)
+        .collect()
+}
+
+/// Returns a program of exactly `size` bytes with balanced brackets, repeating [CHUNK] and
+/// filling the rest with spaces.
+pub fn program(size: usize) -> String {
+    let mut program = CHUNK.repeat(size / CHUNK.len());
+    program.push_str(&" ".repeat(size - program.len()));
+    program
+}
+
+/// Measures each phase of compiling `source`, in the order they run.
+///
+/// The phases run one after the other on the result of the previous one, like the compiler
+/// runs them.
+pub fn measure(source: &str) -> Vec<(&'static str, Duration)> {
+    let mut times = Vec::new();
+    let mut measure = |phase, f: &mut dyn FnMut()| {
+        let start = Instant::now();
+        f();
+        times.push((phase, start.elapsed()));
+    };
+
+    measure("tokenize", &mut || drop(tokenize(source)));
+    measure("match brackets", &mut || {
+        compiler::check_brackets(source).ok();
+    });
+    measure("filter", &mut || drop(remove_non_idents(source)));
+
+    let compiler = Compiler::new(source);
+    let mut lowered = Vec::new();
+    measure("parse", &mut || {
+        lowered = passes::unoptimized(&compiler.parse())
+    });
+    for pass in OptLevel::default().passes() {
+        measure(pass.name(), &mut || {
+            lowered = pass.run(std::mem::take(&mut lowered))
+        });
+    }
+    let mut instructions = Vec::new();
+    measure("resolve jumps", &mut || {
+        instructions = passes::instructions(&lowered)
+    });
+
+    #[cfg(jit)]
+    {
+        let (mut reader, mut writer) = (std::io::empty(), std::io::sink());
+        if let Ok(program) = JitCompiler::new(&instructions, &mut reader, &mut writer).compile() {
+            times.push(("codegen", program.times().get(Phase::Codegen).wall));
+        }
+    }
+    times
+}
+
+/// Returns the measurements as comma separated values with a header, the time in nanoseconds.
+pub fn to_csv(measurements: &[Measurement]) -> String {
+    let mut csv = String::from("size,phase,nanoseconds\n");
+    for measurement in measurements {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            measurement.size,
+            measurement.phase,
+            measurement.time.as_nanos()
+        ));
+    }
+    csv
+}
+
+/// Returns the measurements as a JSON array of objects, the time in nanoseconds.
+pub fn to_json(measurements: &[Measurement]) -> String {
+    json::array(measurements.iter().map(|measurement| {
+        json::Object::default()
+            .field("size", measurement.size)
+            .field("phase", json::string(measurement.phase))
+            .field("nanoseconds", measurement.time.as_nanos())
+            .finish()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program() {
+        assert_eq!(sizes(99_999), [1_000, 10_000]);
+        assert_eq!(sizes(100_000), [1_000, 10_000, 100_000]);
+
+        for size in [0, 10, CHUNK.len(), 12_345] {
+            let program = program(size);
+            assert_eq!(program.len(), size);
+            compiler::check_brackets(&program).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_measure() {
+        let phases: Vec<&str> = measure(&program(MIN_SIZE))
+            .into_iter()
+            .map(|(phase, _)| phase)
+            .collect();
+        assert_eq!(
+            phases[..9],
+            [
+                "tokenize",
+                "match brackets",
+                "filter",
+                "parse",
+                "fold-runs",
+                "clear-loops",
+                "copy-loops",
+                "scan-loops",
+                "resolve jumps"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let measurements = [Measurement {
+            size: 1000,
+            phase: "fold",
+            time: Duration::from_micros(2),
+        }];
+        assert_eq!(
+            to_csv(&measurements),
+            "size,phase,nanoseconds\n1000,fold,2000\n"
+        );
+        assert_eq!(
+            to_json(&measurements),
+            r#"[{"size":1000,"phase":"fold","nanoseconds":2000}]"#
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! A compact binary format for compiled programs, so big programs can be cached on disk and
+//! executed without compiling them again.
+//!
+//! The format starts with [MAGIC] and the [VERSION] byte, followed by the number of
+//! instructions and the instructions. Every instruction is an opcode byte followed by its
+//! arguments, which are LEB128 encoded and zigzag encoded if they are signed.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::compiler::Instruction;
+use crate::verifier::{self, VerifyError};
+
+/// The bytes every compiled program starts with.
+pub const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// Version of the format written by [encode].
+/// It is increased whenever opcodes are removed or change their meaning.
+pub const VERSION: u8 = 1;
+
+const OP_INC_DP: u8 = 0;
+const OP_DEC_DP: u8 = 1;
+const OP_INC_BYTE: u8 = 2;
+const OP_DEC_BYTE: u8 = 3;
+const OP_WRITE_BYTE: u8 = 4;
+const OP_READ_BYTE: u8 = 5;
+const OP_SET_ZERO: u8 = 6;
+const OP_FIND_ZERO: u8 = 7;
+const OP_ADD_TO: u8 = 8;
+const OP_JUMP_ZERO: u8 = 9;
+const OP_JUMP_NOT_ZERO: u8 = 10;
+const OP_EXIT: u8 = 11;
+const OP_JUMP_ZERO_PLACEHOLDER: u8 = 12;
+const OP_JUMP_NOT_ZERO_PLACEHOLDER: u8 = 13;
+const OP_SELECT_INPUT: u8 = 14;
+
+/// Describes why bytes could not be decoded into instructions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    /// The bytes do not start with [MAGIC].
+    NotBytecode,
+
+    /// The bytes were written by a different version of the format.
+    UnsupportedVersion(u8),
+
+    /// The bytes end in the middle of an instruction, or before all instructions were read.
+    Truncated,
+
+    /// The byte at `offset` is not a known opcode.
+    UnknownOpcode { offset: usize, opcode: u8 },
+
+    /// An argument at `offset` does not fit into its type.
+    Overflow { offset: usize },
+
+    /// There are bytes after the last instruction.
+    TrailingBytes { offset: usize },
+
+    /// The decoded instructions are not well formed.
+    Invalid(VerifyError),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => f.write_str("not a compiled brainfuck program"),
+            BytecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode version {version}, expected version {VERSION}"
+            ),
+            BytecodeError::Truncated => f.write_str("the bytecode ends unexpectedly"),
+            BytecodeError::UnknownOpcode { offset, opcode } => {
+                write!(f, "unknown opcode {opcode} at byte {offset}")
+            }
+            BytecodeError::Overflow { offset } => write!(f, "argument at byte {offset} overflows"),
+            BytecodeError::TrailingBytes { offset } => {
+                write!(
+                    f,
+                    "unexpected bytes after the last instruction at byte {offset}"
+                )
+            }
+            BytecodeError::Invalid(err) => write!(f, "invalid instructions: {err}"),
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+/// Returns whether `bytes` start like a compiled program, regardless of its version.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serializes `instructions`, which [decode] turns back into the same instructions.
+pub fn encode(instructions: &[Instruction]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    write_unsigned(&mut bytes, instructions.len() as u64);
+
+    for instruction in instructions {
+        let (opcode, args): (u8, &[i64]) = match *instruction {
+            Instruction::IncDP(n) => (OP_INC_DP, &[n as i64]),
+            Instruction::DecDP(n) => (OP_DEC_DP, &[n as i64]),
+            Instruction::IncByteAtDP(n) => (OP_INC_BYTE, &[n as i64]),
+            Instruction::DecByteAtDP(n) => (OP_DEC_BYTE, &[n as i64]),
+            Instruction::WriteByte(n) => (OP_WRITE_BYTE, &[n as i64]),
+            Instruction::ReadByte => (OP_READ_BYTE, &[]),
+            Instruction::SetZero => (OP_SET_ZERO, &[]),
+            Instruction::FindZero(stride) => (OP_FIND_ZERO, &[stride as i64]),
+            Instruction::AddTo { offset, factor } => (OP_ADD_TO, &[offset as i64, factor as i64]),
+            Instruction::JumpZero(n) => (OP_JUMP_ZERO, &[n as i64]),
+            Instruction::JumpNotZero(n) => (OP_JUMP_NOT_ZERO, &[n as i64]),
+            Instruction::Exit => (OP_EXIT, &[]),
+            Instruction::SelectInput => (OP_SELECT_INPUT, &[]),
+            Instruction::JumpZeroPlaceholder => (OP_JUMP_ZERO_PLACEHOLDER, &[]),
+            Instruction::JumpNotZeroPlaceholder => (OP_JUMP_NOT_ZERO_PLACEHOLDER, &[]),
+        };
+        bytes.push(opcode);
+
+        let signed = matches!(opcode, OP_FIND_ZERO | OP_ADD_TO);
+        for &arg in args {
+            if signed {
+                write_unsigned(&mut bytes, ((arg << 1) ^ (arg >> 63)) as u64);
+            } else {
+                // Counts and jump offsets are `usize`, which is never wider than 64 bits.
+                write_unsigned(&mut bytes, arg as u64);
+            }
+        }
+    }
+    bytes
+}
+
+/// Deserializes instructions written by [encode], checking that they are
+/// [well formed](verifier::verify) so they can be executed by every engine.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Instruction>, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    let mut reader = Reader {
+        bytes,
+        offset: MAGIC.len(),
+    };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+
+    let len = reader.unsigned()?;
+    // Every instruction takes at least one byte, so a corrupt length cannot allocate more
+    // than the input.
+    let mut instructions = Vec::with_capacity(len.min(bytes.len() as u64) as usize);
+    for _ in 0..len {
+        let offset = reader.offset;
+        let instruction = match reader.byte()? {
+            OP_INC_DP => Instruction::IncDP(reader.usize()?),
+            OP_DEC_DP => Instruction::DecDP(reader.usize()?),
+            OP_INC_BYTE => Instruction::IncByteAtDP(reader.usize()?),
+            OP_DEC_BYTE => Instruction::DecByteAtDP(reader.usize()?),
+            OP_WRITE_BYTE => Instruction::WriteByte(reader.usize()?),
+            OP_READ_BYTE => Instruction::ReadByte,
+            OP_SET_ZERO => Instruction::SetZero,
+            OP_FIND_ZERO => Instruction::FindZero(reader.isize()?),
+            OP_ADD_TO => {
+                let offset = reader.isize()?;
+                let at = reader.offset;
+                let factor = reader
+                    .signed()?
+                    .try_into()
+                    .map_err(|_| BytecodeError::Overflow { offset: at })?;
+                Instruction::AddTo { offset, factor }
+            }
+            OP_JUMP_ZERO => Instruction::JumpZero(reader.usize()?),
+            OP_JUMP_NOT_ZERO => Instruction::JumpNotZero(reader.usize()?),
+            OP_EXIT => Instruction::Exit,
+            OP_SELECT_INPUT => Instruction::SelectInput,
+            OP_JUMP_ZERO_PLACEHOLDER => Instruction::JumpZeroPlaceholder,
+            OP_JUMP_NOT_ZERO_PLACEHOLDER => Instruction::JumpNotZeroPlaceholder,
+            opcode => return Err(BytecodeError::UnknownOpcode { offset, opcode }),
+        };
+        instructions.push(instruction);
+    }
+
+    if reader.offset != bytes.len() {
+        return Err(BytecodeError::TrailingBytes {
+            offset: reader.offset,
+        });
+    }
+    verifier::verify(&instructions).map_err(BytecodeError::Invalid)?;
+    Ok(instructions)
+}
+
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Reads the parts of an instruction, keeping track of the offset for errors.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(BytecodeError::Truncated)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u64, BytecodeError> {
+        let start = self.offset;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if bits << shift >> shift != bits {
+                return Err(BytecodeError::Overflow { offset: start });
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::Overflow { offset: start })
+    }
+
+    fn signed(&mut self) -> Result<i64, BytecodeError> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        let start = self.offset;
+        self.unsigned()?
+            .try_into()
+            .map_err(|_| BytecodeError::Overflow { offset: start })
+    }
+
+    fn isize(&mut self) -> Result<isize, BytecodeError> {
+        let start = self.offset;
+        self.signed()?
+            .try_into()
+            .map_err(|_| BytecodeError::Overflow { offset: start })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, BytecodeError, MAGIC, VERSION};
+    use crate::compiler::{Compiler, Instruction};
+    use crate::verifier::VerifyError;
+
+    #[test]
+    fn test_round_trip() {
+        let program = format!("+[->++<]>[>]<[<<]{}[.,-]!", "-".repeat(300));
+        let instructions = Compiler::new(&program).compile().unwrap();
+        let extremes = [
+            Instruction::IncDP(usize::MAX),
+            Instruction::FindZero(isize::MIN),
+            Instruction::AddTo {
+                offset: isize::MAX,
+                factor: i32::MIN,
+            },
+        ];
+
+        for instructions in [&instructions[..], &extremes] {
+            assert_eq!(decode(&encode(instructions)).unwrap(), instructions);
+        }
+    }
+
+    #[test]
+    fn test_compact() {
+        let instructions = Compiler::new("+++[->+<]").compile().unwrap();
+        // The header, the length and the opcodes with their arguments.
+        assert_eq!(encode(&instructions).len(), 4 + 1 + 1 + 2 + 3 + 1);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let bytes = encode(&[Instruction::IncDP(300), Instruction::ReadByte]);
+
+        assert_eq!(decode(b"+[-]"), Err(BytecodeError::NotBytecode));
+        assert_eq!(
+            decode(&[&MAGIC[..], &[VERSION + 1]].concat()),
+            Err(BytecodeError::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 2]),
+            Err(BytecodeError::Truncated)
+        );
+        assert_eq!(
+            decode(&[&bytes[..], &[0]].concat()),
+            Err(BytecodeError::TrailingBytes { offset: 10 })
+        );
+        assert_eq!(
+            decode(&[&MAGIC[..], &[VERSION, 1, 99]].concat()),
+            Err(BytecodeError::UnknownOpcode {
+                offset: 6,
+                opcode: 99
+            })
+        );
+        assert_eq!(
+            decode(&[&MAGIC[..], &[VERSION, 1, 0], &[0xff; 10], &[1]].concat()),
+            Err(BytecodeError::Overflow { offset: 7 })
+        );
+        // Corrupt jumps are rejected, so the engines never execute them.
+        assert_eq!(
+            decode(&encode(&[
+                Instruction::JumpZero(3),
+                Instruction::JumpNotZero(0)
+            ])),
+            Err(BytecodeError::Invalid(VerifyError::InvalidJump {
+                index: 0
+            }))
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::extensions::EXTENSIONS;
+use crate::json;
+use crate::tape::{CellWidth, TapePolicy};
+use crate::tokenizer::DIRECTIVES;
+
+/// An execution environment a program can be run in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Backend {
+    Interpreter,
+    VirtualMachine,
+    Closures,
+    /// Only compiled on x86_64 and AArch64 on Linux and macOS, and on x86_64 on Windows, as it
+    /// emits machine code for them.
+    Jit,
+}
+
+impl Backend {
+    /// All backends, including the ones not available on this platform.
+    pub const ALL: [Backend; 4] = [
+        Backend::Interpreter,
+        Backend::VirtualMachine,
+        Backend::Closures,
+        Backend::Jit,
+    ];
+
+    /// Returns the name used on the command line and in the JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Interpreter => "interpreter",
+            Backend::VirtualMachine => "vm",
+            Backend::Closures => "closures",
+            Backend::Jit => "jit",
+        }
+    }
+
+    /// Returns whether the backend is part of this build.
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::Jit => cfg!(jit),
+            Backend::Interpreter | Backend::VirtualMachine | Backend::Closures => true,
+        }
+    }
+
+    /// Returns the options beyond the tape, the input and the output of the
+    /// [ExecOptions](crate::engine::ExecOptions) that the backend supports, which all backends
+    /// support.
+    pub fn capabilities(self) -> &'static [BackendOption] {
+        match self {
+            Backend::Interpreter => &[
+                BackendOption::NoOptimizations,
+                BackendOption::Speed,
+                BackendOption::Progress,
+                BackendOption::Limits,
+                BackendOption::Cancellation,
+            ],
+            Backend::VirtualMachine => &[
+                BackendOption::NoOptimizations,
+                BackendOption::Speed,
+                BackendOption::Progress,
+                BackendOption::LoopAssertions,
+                BackendOption::Inspection,
+                BackendOption::Sanitize,
+                BackendOption::Limits,
+                BackendOption::Cancellation,
+            ],
+            Backend::Closures => &[],
+            Backend::Jit => &[
+                BackendOption::LoopCounters,
+                BackendOption::Limits,
+                BackendOption::Cancellation,
+            ],
+        }
+    }
+
+    /// Returns whether the backend supports `option`.
+    pub fn supports(self, option: BackendOption) -> bool {
+        self.capabilities().contains(&option)
+    }
+
+    /// Checks that the backend supports all `options`, returning the first one it does not
+    /// support.
+    pub fn check(self, options: &[BackendOption]) -> Result<(), UnsupportedOption> {
+        match options.iter().find(|option| !self.supports(**option)) {
+            Some(option) => Err(UnsupportedOption {
+                backend: self,
+                option: *option,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the fastest backend available on this platform that supports all `options`,
+    /// e.g. `Backend::auto(&options.backend_options())` for [ExecOptions](crate::engine::ExecOptions),
+    /// or what the virtual machine does not support if none does.
+    pub fn auto(options: &[BackendOption]) -> Result<Backend, UnsupportedOption> {
+        let fastest = [Backend::Jit, Backend::Closures, Backend::VirtualMachine];
+        match fastest
+            .into_iter()
+            .filter(|backend| backend.is_available())
+            .find(|backend| backend.check(options).is_ok())
+        {
+            Some(backend) => Ok(backend),
+            None => Backend::VirtualMachine
+                .check(options)
+                .map(|()| Backend::VirtualMachine),
+        }
+    }
+}
+
+/// An option of the execution that only some backends support.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BackendOption {
+    /// Executing every character as one instruction, like `--no-opt`.
+    NoOptimizations,
+
+    /// Limiting the number of instructions per second, like `--speed`.
+    Speed,
+
+    /// Reporting the progress while executing, with
+    /// [run_with_progress](crate::engine::Engine::run_with_progress).
+    Progress,
+
+    /// Checking the `;; assert loop<=N` directives, like `--check-assertions`.
+    LoopAssertions,
+
+    /// Inspecting the tape or the execution, like `--dump-tape` or `--profile`.
+    Inspection,
+
+    /// Reporting behavior that differs among implementations, like `--sanitize`.
+    Sanitize,
+
+    /// Counting the iterations of every loop.
+    LoopCounters,
+
+    /// Stopping the execution after a number of steps or a time, like `--max-steps` or
+    /// `--timeout`.
+    Limits,
+
+    /// Stopping the execution once a flag is set, like Ctrl-C on the command line.
+    Cancellation,
+}
+
+impl BackendOption {
+    /// Returns what the option does, for messages like "{description} requires ...".
+    pub fn description(self) -> &'static str {
+        match self {
+            BackendOption::NoOptimizations => "disabling optimizations",
+            BackendOption::Speed => "limiting the speed",
+            BackendOption::Progress => "reporting the progress",
+            BackendOption::LoopAssertions => "checking assertions",
+            BackendOption::Inspection => "inspecting the execution",
+            BackendOption::Sanitize => "sanitizing the execution",
+            BackendOption::LoopCounters => "counting loop iterations",
+            BackendOption::Limits => "limiting the execution",
+            BackendOption::Cancellation => "cancelling the execution",
+        }
+    }
+}
+
+/// A backend was asked for an option it does not support.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnsupportedOption {
+    pub backend: Backend,
+    pub option: BackendOption,
+}
+
+impl Display for UnsupportedOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not supported by the `{}` backend",
+            self.option.description(),
+            self.backend.name()
+        )
+    }
+}
+
+impl Error for UnsupportedOption {}
+
+/// Describes what this build of the crate supports, so tools wrapping the binary can adapt
+/// without parsing help texts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    /// Version of the crate.
+    pub version: &'static str,
+
+    /// Execution environments that are available on this platform.
+    pub backends: Vec<Backend>,
+
+    /// Language extensions and dialects that can be enabled, the
+    /// [extensions](crate::extensions) and the [directives](crate::tokenizer::DIRECTIVES).
+    pub extensions: Vec<&'static str>,
+
+    /// Supported cell widths in bits.
+    pub cell_widths: Vec<u32>,
+
+    /// Supported behaviors of the tape when the data pointer moves.
+    pub tape_policies: Vec<&'static str>,
+
+    /// Cargo features the crate was compiled with.
+    pub features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    /// Version of the JSON format returned by [to_json](Capabilities::to_json).
+    /// It is increased whenever fields are removed or change their meaning.
+    pub const FORMAT_VERSION: u32 = 1;
+
+    /// Returns the capabilities of this build.
+    pub fn current() -> Self {
+        let mut features = Vec::new();
+        if cfg!(feature = "png") {
+            features.push("png");
+        }
+        if cfg!(feature = "internals") {
+            features.push("internals");
+        }
+        if cfg!(feature = "llvm") {
+            features.push("llvm");
+        }
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            backends: Backend::ALL
+                .into_iter()
+                .filter(|backend| backend.is_available())
+                .collect(),
+            // The `extension` directive enables the extensions, it is not one itself.
+            extensions: DIRECTIVES
+                .into_iter()
+                .filter(|directive| *directive != "extension")
+                .chain(EXTENSIONS.iter().copied())
+                .collect(),
+            cell_widths: CellWidth::ALL.iter().map(|width| width.bits()).collect(),
+            tape_policies: TapePolicy::ALL.iter().map(|policy| policy.name()).collect(),
+            features,
+        }
+    }
+
+    /// Serializes the capabilities as a JSON object.
+    pub fn to_json(&self) -> String {
+        let strings = |values: &[&str]| json::array(values.iter().map(|value| json::string(value)));
+
+        json::Object::default()
+            .field("format_version", Self::FORMAT_VERSION)
+            .field("version", json::string(self.version))
+            .field(
+                "backends",
+                json::array(
+                    self.backends
+                        .iter()
+                        .map(|backend| json::string(backend.name())),
+                ),
+            )
+            .field("extensions", strings(&self.extensions))
+            .field(
+                "cell_widths",
+                json::array(self.cell_widths.iter().map(u32::to_string)),
+            )
+            .field("tape_policies", strings(&self.tape_policies))
+            .field("features", strings(&self.features))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, BackendOption, Capabilities, UnsupportedOption};
+
+    #[test]
+    fn test_to_json() {
+        let capabilities = Capabilities {
+            version: "1.2.3",
+            backends: vec![Backend::Interpreter, Backend::VirtualMachine],
+            extensions: Vec::new(),
+            cell_widths: vec![8],
+            tape_policies: vec!["fixed"],
+            features: Vec::new(),
+        };
+
+        assert_eq!(
+            capabilities.to_json(),
+            r#"{"format_version":1,"version":"1.2.3","backends":["interpreter","vm"],"extensions":[],"cell_widths":[8],"tape_policies":["fixed"],"features":[]}"#
+        );
+    }
+
+    #[test]
+    #[cfg(jit)]
+    fn test_jit_reported_on_supported_targets() {
+        assert!(Backend::Jit.is_available());
+        assert!(Capabilities::current().backends.contains(&Backend::Jit));
+    }
+
+    #[test]
+    #[cfg(not(jit))]
+    fn test_jit_not_reported_on_other_targets() {
+        assert!(!Backend::Jit.is_available());
+        assert!(!Capabilities::current().backends.contains(&Backend::Jit));
+    }
+
+    #[test]
+    fn test_features_match_cfgs() {
+        let features = Capabilities::current().features;
+
+        assert_eq!(features.contains(&"png"), cfg!(feature = "png"));
+        assert_eq!(features.contains(&"internals"), cfg!(feature = "internals"));
+        assert_eq!(features.contains(&"llvm"), cfg!(feature = "llvm"));
+    }
+
+    #[test]
+    fn test_extensions_reported() {
+        let extensions = Capabilities::current().extensions;
+
+        assert!(extensions.contains(&"env"));
+        assert!(extensions.contains(&"exit"));
+        assert!(extensions.contains(&"include"));
+        assert!(!extensions.contains(&"extension"));
+    }
+
+    #[test]
+    fn test_check_options() {
+        let options = [BackendOption::Speed, BackendOption::Inspection];
+
+        assert_eq!(Backend::VirtualMachine.check(&options), Ok(()));
+        assert_eq!(
+            Backend::Interpreter.check(&options),
+            Err(UnsupportedOption {
+                backend: Backend::Interpreter,
+                option: BackendOption::Inspection
+            })
+        );
+        assert_eq!(
+            Backend::Closures.check(&options).unwrap_err().to_string(),
+            "limiting the speed is not supported by the `closures` backend"
+        );
+        assert!(Backend::Jit.supports(BackendOption::LoopCounters));
+        assert!(!Backend::Closures.supports(BackendOption::Limits));
+    }
+
+    #[test]
+    fn test_auto() {
+        let (fastest, stoppable) = match Backend::Jit.is_available() {
+            true => (Backend::Jit, Backend::Jit),
+            false => (Backend::Closures, Backend::VirtualMachine),
+        };
+        assert_eq!(Backend::auto(&[]), Ok(fastest));
+        assert_eq!(Backend::auto(&[BackendOption::Cancellation]), Ok(stoppable));
+        assert_eq!(
+            Backend::auto(&[BackendOption::Limits, BackendOption::Inspection]),
+            Ok(Backend::VirtualMachine)
+        );
+        assert_eq!(
+            Backend::auto(&[BackendOption::Inspection, BackendOption::LoopCounters]),
+            Err(UnsupportedOption {
+                backend: Backend::VirtualMachine,
+                option: BackendOption::LoopCounters
+            })
+        );
+    }
+
+    #[test]
+    fn test_portable_backends_always_reported() {
+        let backends = Capabilities::current().backends;
+
+        for backend in [
+            Backend::Interpreter,
+            Backend::VirtualMachine,
+            Backend::Closures,
+        ] {
+            assert!(backend.is_available());
+            assert!(backends.contains(&backend));
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Names for cells declared in the program with directives like `;; name cell 3 counter`, so
+//! tools can show `counter=7` instead of `cell[3]=7`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::tokenizer::{tokenize, TokenKind, DIRECTIVE_PREFIX};
+
+/// The cell names declared in a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CellNames {
+    names: BTreeMap<usize, String>,
+}
+
+/// A `name` directive that could not be parsed, or that conflicts with an earlier one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellNameError {
+    /// Line of the directive, starting at 1.
+    pub line: usize,
+
+    /// Column of the directive, starting at 1.
+    pub column: usize,
+
+    pub message: String,
+}
+
+impl Display for CellNameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid cell name at line {} column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for CellNameError {}
+
+impl CellNames {
+    /// Collects the `;; name cell <index> <name>` directives of `source`.
+    ///
+    /// Directives that start with another word are left for other tools. Names have to be
+    /// identifiers, and neither a cell nor a name may be declared twice.
+    pub fn parse(source: &str) -> Result<Self, CellNameError> {
+        let mut names = BTreeMap::new();
+
+        for token in tokenize(source) {
+            if token.kind != TokenKind::Directive {
+                continue;
+            }
+
+            let mut words = token.text[DIRECTIVE_PREFIX.len()..].split_whitespace();
+            if words.next() != Some("name") {
+                continue;
+            }
+
+            let error = |message: String| CellNameError {
+                line: token.line,
+                column: token.column,
+                message,
+            };
+            let usage = || error("expected `name cell <index> <name>`".to_string());
+
+            if words.next() != Some("cell") {
+                return Err(usage());
+            }
+            let index = words.next().ok_or_else(usage)?;
+            let index: usize = index
+                .parse()
+                .map_err(|_| error(format!("`{index}` is not a cell index")))?;
+            let name = words.next().ok_or_else(usage)?;
+            if words.next().is_some() {
+                return Err(usage());
+            }
+
+            if !is_identifier(name) {
+                return Err(error(format!("`{name}` is not an identifier")));
+            }
+            if let Some(previous) = names.get(&index) {
+                return Err(error(format!("cell {index} is already named `{previous}`")));
+            }
+            if names.values().any(|previous| previous == name) {
+                return Err(error(format!("`{name}` already names another cell")));
+            }
+
+            names.insert(index, name.to_string());
+        }
+
+        Ok(Self { names })
+    }
+
+    /// Returns the name of the cell at `index`, if it has one.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.names.get(&index).map(String::as_str)
+    }
+
+    /// Returns the name of the cell at `index`, or `cell[index]` if it has none.
+    pub fn label(&self, index: usize) -> String {
+        match self.get(index) {
+            Some(name) => name.to_string(),
+            None => format!("cell[{index}]"),
+        }
+    }
+
+    /// Returns the named cells ordered by their index.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names
+            .iter()
+            .map(|(index, name)| (*index, name.as_str()))
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CellNames;
+
+    #[test]
+    fn test_parse_names() {
+        let names = CellNames::parse(
+            ";; name cell 3 counter\n+++\n;; name cell 0 sum_1\n;; include other.b\n",
+        )
+        .unwrap();
+
+        assert_eq!(names.get(3), Some("counter"));
+        assert_eq!(names.label(0), "sum_1");
+        assert_eq!(names.label(1), "cell[1]");
+        assert_eq!(names.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let error = |source| CellNames::parse(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("+\n ;; name cell x counter"),
+            "invalid cell name at line 2 column 2: `x` is not a cell index"
+        );
+        assert_eq!(
+            error(";; name 3 counter"),
+            "invalid cell name at line 1 column 1: expected `name cell <index> <name>`"
+        );
+        assert_eq!(
+            error(";; name cell 1 a\n;; name cell 1 b"),
+            "invalid cell name at line 2 column 1: cell 1 is already named `a`"
+        );
+        assert_eq!(
+            error(";; name cell 1 a\n;; name cell 2 a"),
+            "invalid cell name at line 2 column 1: `a` already names another cell"
+        );
+        assert_eq!(
+            error(";; name cell 1 2a"),
+            "invalid cell name at line 1 column 1: `2a` is not an identifier"
+        );
+    }
+}
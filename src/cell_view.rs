@@ -0,0 +1,193 @@
+//! Interpretations of cell ranges using the encodings brainfuck programs commonly use for values
+//! that do not fit into a single cell.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::tape::Cell;
+
+/// Number of cells per line of a [dump].
+const CELLS_PER_LINE: usize = 16;
+
+/// How a range of cells is decoded when inspecting the tape.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CellView {
+    /// Every cell as a decimal number.
+    Decimal,
+    /// Pairs of cells as little endian 16-bit numbers.
+    U16Le,
+    /// Pairs of cells as big endian 16-bit numbers.
+    U16Be,
+    /// Groups of four cells as little endian 32-bit numbers.
+    U32Le,
+    /// Groups of four cells as big endian 32-bit numbers.
+    U32Be,
+    /// Cells as ASCII characters up to the first NUL cell.
+    Ascii,
+    /// Cells as the decimal digits of a single number, most significant digit first.
+    Digits,
+}
+
+impl FromStr for CellView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" | "decimal" => Ok(CellView::Decimal),
+            "u16le" => Ok(CellView::U16Le),
+            "u16be" => Ok(CellView::U16Be),
+            "u32le" => Ok(CellView::U32Le),
+            "u32be" => Ok(CellView::U32Be),
+            "ascii" => Ok(CellView::Ascii),
+            "digits" => Ok(CellView::Digits),
+            _ => Err(format!(
+                "unknown view `{s}`, valid views are `u8`, `u16le`, `u16be`, `u32le`, `u32be`, \
+                `ascii` and `digits`"
+            )),
+        }
+    }
+}
+
+impl CellView {
+    /// Decodes `cells` according to this view.
+    ///
+    /// Numbers are separated by spaces, cells that do not fill a whole multi-cell number are
+    /// ignored. Digit cells greater than 9 are shown as `?`. The byte order of multi-cell
+    /// numbers is part of the view, so the result is the same on little and big endian hosts.
+    pub fn decode(self, cells: &[u8]) -> String {
+        let numbers = |size: usize, f: fn(&[u8]) -> u32| {
+            cells
+                .chunks_exact(size)
+                .map(|chunk| f(chunk).to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        match self {
+            CellView::Decimal => numbers(1, |c| c[0] as u32),
+            CellView::U16Le => numbers(2, |c| u16::from_le_bytes([c[0], c[1]]) as u32),
+            CellView::U16Be => numbers(2, |c| u16::from_be_bytes([c[0], c[1]]) as u32),
+            CellView::U32Le => numbers(4, |c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+            CellView::U32Be => numbers(4, |c| u32::from_be_bytes([c[0], c[1], c[2], c[3]])),
+            CellView::Ascii => {
+                let mut text = String::from("\"");
+                for cell in cells.iter().take_while(|cell| **cell != 0) {
+                    text.extend((*cell as char).escape_default());
+                }
+                text.push('"');
+                text
+            }
+            CellView::Digits => cells
+                .iter()
+                .map(|cell| match cell {
+                    0..=9 => (b'0' + cell) as char,
+                    _ => '?',
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the number of cells a single value of this view spans, if it has a fixed size.
+    fn size(self) -> Option<usize> {
+        match self {
+            CellView::Decimal => Some(1),
+            CellView::U16Le | CellView::U16Be => Some(2),
+            CellView::U32Le | CellView::U32Be => Some(4),
+            CellView::Ascii | CellView::Digits => None,
+        }
+    }
+}
+
+/// Returns all cells up to the last non-zero cell decoded with `view`.
+///
+/// Numeric views are printed in lines of 16 cells prefixed with the index of their first cell,
+/// while text views decode all cells at once.
+pub fn dump(data: &[u8], view: CellView) -> String {
+    let len = data
+        .iter()
+        .rposition(|cell| *cell != 0)
+        .map_or(0, |last| last + 1);
+
+    let size = match view.size() {
+        Some(size) => size,
+        None => return format!("{:>6}: {}\n", 0, view.decode(&data[..len])),
+    };
+
+    // Extend the range so the last value is complete.
+    let len = (len.div_ceil(size) * size).min(data.len());
+
+    let mut text = String::new();
+    for (line, cells) in data[..len].chunks(CELLS_PER_LINE).enumerate() {
+        writeln!(text, "{:>6}: {}", line * CELLS_PER_LINE, view.decode(cells)).unwrap();
+    }
+    text
+}
+
+/// Returns all cells up to the last non-zero cell as decimal numbers, for cells wider than a
+/// byte where the views do not apply.
+pub fn dump_numbers<C: Cell>(data: &[C]) -> String {
+    let len = data
+        .iter()
+        .rposition(|cell| *cell != C::ZERO)
+        .map_or(0, |last| last + 1);
+
+    let mut text = String::new();
+    for (line, cells) in data[..len].chunks(CELLS_PER_LINE).enumerate() {
+        let numbers: Vec<String> = cells.iter().map(C::to_string).collect();
+        writeln!(text, "{:>6}: {}", line * CELLS_PER_LINE, numbers.join(" ")).unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump, dump_numbers, CellView};
+
+    #[test]
+    fn test_multi_cell_numbers() {
+        let cells = [1, 2, 3, 4, 5];
+
+        assert_eq!(CellView::Decimal.decode(&cells), "1 2 3 4 5");
+        assert_eq!(CellView::U16Le.decode(&cells), "513 1027");
+        assert_eq!(CellView::U16Be.decode(&cells), "258 772");
+        assert_eq!(CellView::U32Le.decode(&cells), "67305985");
+        assert_eq!(CellView::U32Be.decode(&cells), "16909060");
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(CellView::Ascii.decode(b"Hi\n\0ignored"), r#""Hi\n""#);
+        assert_eq!(CellView::Digits.decode(&[1, 0, 2, 4, 10]), "1024?");
+    }
+
+    #[test]
+    fn test_dump() {
+        let mut data = [0; 40];
+        data[0] = 1;
+        data[17] = 2;
+
+        assert_eq!(
+            dump(&data, CellView::U16Le),
+            "     0: 1 0 0 0 0 0 0 0\n    16: 512\n"
+        );
+        assert_eq!(
+            dump(&data, CellView::Digits),
+            "     0: 100000000000000002\n"
+        );
+        assert_eq!(dump(&[0; 4], CellView::Decimal), "");
+    }
+
+    #[test]
+    fn test_dump_numbers() {
+        let mut data = [0_u16; 40];
+        data[0] = 1000;
+        data[16] = 65_535;
+
+        assert_eq!(
+            dump_numbers(&data),
+            "     0: 1000 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0
+    16: 65535
+"
+        );
+    }
+}
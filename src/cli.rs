@@ -0,0 +1,2290 @@
+//! The command line interface of the `brainfuck` binary, exposed so other tools can embed the
+//! same commands.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use argh::{FromArgs, SubCommands};
+use once_cell::sync::OnceCell;
+
+use crate::bench;
+use crate::bytecode;
+use crate::capabilities::{Backend, BackendOption, Capabilities};
+use crate::cell_names::CellNames;
+use crate::cell_view::{self, CellView};
+use crate::compiler::{self, Compiler, Instruction};
+use crate::debugger::Debugger;
+use crate::decode::{self, Decode};
+use crate::engine::{self, Engine, ExecOptions, State};
+use crate::expectations::Expectations;
+use crate::explain::{self, Position};
+use crate::extensions;
+use crate::heatmap;
+use crate::host::HostInputs;
+use crate::hot_loops;
+use crate::interpreter::Interpreter;
+use crate::loop_assertions::LoopAssertions;
+use crate::manifest::{self, Data, Manifest};
+use crate::newline::{Newline, NewlineMode, NewlineReader, NewlineWriter};
+use crate::passes::OptLevel;
+use crate::preprocessor::{self, Preprocessed};
+use crate::profiler;
+use crate::recording::Recording;
+use crate::repl::Repl;
+use crate::sanitizer::Sanitizer;
+use crate::source_map::{self, SourceMap, Span};
+use crate::tape::{Cell, CellWidth, TapePolicy};
+use crate::timing::{Phase, PhaseTimes, Timed};
+use crate::tokenizer::{tokenize, TokenKind};
+use crate::transpile::Language;
+use crate::virtual_machine::VirtualMachine;
+use crate::wasm;
+use crate::{AdaptiveFlush, EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+/// Execute Brainfuck programs and choose the execution environment to run them in.
+#[derive(FromArgs, Debug)]
+struct Args {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+// The command is parsed once per process, so its size does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Run(RunArgs),
+    Compile(CompileArgs),
+    Translate(TranslateArgs),
+    Explain(ExplainArgs),
+    Check(CheckArgs),
+    Fmt(FmtArgs),
+    Bench(BenchArgs),
+    Debug(DebugArgs),
+    Capabilities(CapabilitiesArgs),
+    Test(TestArgs),
+    Build(BuildArgs),
+    New(NewArgs),
+    Replay(ReplayArgs),
+    Repl(ReplArgs),
+}
+
+/// Execute a brainfuck program (default when no command is given).
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "run")]
+struct RunArgs {
+    /// execution environment to run the brainfuck program in (`interpreter`, `vm`, `closures`
+    /// or `jit`)
+    #[argh(option, default = "Environment::JitCompiler")]
+    env: Environment,
+
+    /// file to read the input of the program from instead of stdin
+    #[argh(option)]
+    input: Option<String>,
+
+    /// file to write the output of the program to instead of stdout
+    #[argh(option)]
+    output: Option<String>,
+
+    /// file to write a copy of the output of the program to, flushed together with the output
+    #[argh(option)]
+    tee: Option<String>,
+
+    /// translate newlines on input and output to `raw` (unchanged), `lf`, `crlf` or `cr`,
+    /// a comma separates the input and output mode, e.g. `cr,lf`
+    #[argh(option, default = "NewlineMode::default()")]
+    newline_mode: NewlineMode,
+
+    /// buffer the output and decode it as `utf8`, replacing invalid sequences, or print it as
+    /// `hex` dump
+    #[argh(option)]
+    decode: Option<Decode>,
+
+    /// print how often each cell was written to after the execution (virtual machine only)
+    #[argh(switch)]
+    tape_heatmap: bool,
+
+    /// write how often each cell was written to as a PNG image (requires the `png` feature)
+    #[argh(option)]
+    tape_heatmap_png: Option<String>,
+
+    /// print the tape and the named cells after the execution (virtual machine only)
+    #[argh(switch)]
+    dump_tape: bool,
+
+    /// how cells are decoded by `--dump-tape` (`u8`, `u16le`, `u16be`, `u32le`, `u32be`,
+    /// `ascii` or `digits`)
+    #[argh(option, default = "CellView::Decimal")]
+    tape_view: CellView,
+
+    /// sample which loops are active during the execution and write them as Chrome trace to
+    /// the given file, viewable in `chrome://tracing` or Perfetto (virtual machine only)
+    #[argh(option)]
+    profile: Option<String>,
+
+    /// number of executed instructions between two samples of `--profile`
+    #[argh(option, default = "1000")]
+    sample_interval: u64,
+
+    /// print the number of executed instructions of every kind, the number of bytes read and
+    /// written, the highest data pointer and the elapsed time (virtual machine only)
+    #[argh(switch)]
+    stats: bool,
+
+    /// count how often every loop and every instruction is executed and print the loops and
+    /// source positions that executed the most instructions (virtual machine only)
+    #[argh(switch)]
+    hot_loops: bool,
+
+    /// number of cells of the tape
+    #[argh(option, default = "DEFAULT_TAPE_SIZE")]
+    tape_size: usize,
+
+    /// what happens when the data pointer moves past the end of the tape, `fixed` fails and
+    /// `growable` grows the tape
+    #[argh(option, default = "TapePolicy::Fixed")]
+    tape: TapePolicy,
+
+    /// number of bits of a cell (`8`, `16` or `32`), arithmetic wraps around at this width
+    #[argh(option, default = "CellWidth::U8")]
+    cell_width: CellWidth,
+
+    /// what reading past the end of the input does: `error` fails, `zero` and `minus-one` set
+    /// the cell and `unchanged` leaves it as is
+    #[argh(option, default = "EofBehavior::Error")]
+    eof: EofBehavior,
+
+    /// disable all optimizations, so every character is executed as one instruction and loops
+    /// are not replayed (interpreter and vm only, others fall back to the vm)
+    #[argh(switch)]
+    no_opt: bool,
+
+    /// how much the program is optimized, from `0` for no optimizations like `--no-opt` to `3`
+    /// for the most, `2` by default
+    #[argh(option)]
+    opt_level: Option<OptLevel>,
+
+    /// print the compiled instructions with the targets of their jumps before executing them
+    #[argh(switch)]
+    dump_ir: bool,
+
+    /// print the machine code the jit compiler emits for every instruction as hex bytes
+    /// before executing the program
+    #[argh(switch)]
+    dump_asm: bool,
+
+    /// write the program, its input and output and the options to the given `.bfrun` file,
+    /// which `replay` executes again
+    #[argh(option)]
+    record: Option<String>,
+
+    /// expand `;;include "file"` directives before executing the program
+    #[argh(switch)]
+    preprocess: bool,
+
+    /// fail once a loop runs more iterations than a `;; assert loop<=N` directive before it
+    /// allows (vm only, others fall back to the vm)
+    #[argh(switch)]
+    check_assertions: bool,
+
+    /// report behavior that differs among brainfuck implementations with its position as it
+    /// happens, like cell wraparound or reads at the end of the input (vm only, others fall
+    /// back to the vm)
+    #[argh(switch)]
+    sanitize: bool,
+
+    /// execute at most this many instructions per second, e.g. to follow a program in slow
+    /// motion (interpreter and vm only, others fall back to the vm)
+    #[argh(option)]
+    speed: Option<u64>,
+
+    /// stop with an error instead of executing more than this many steps of the source, e.g. of
+    /// an untrusted program that loops forever (interpreter, vm and jit, others fall back to the vm)
+    #[argh(option)]
+    max_steps: Option<u64>,
+
+    /// stop with an error once the execution took longer than this many seconds, e.g. `1.5`
+    /// (interpreter, vm and jit, others fall back to the vm)
+    #[argh(option, from_str_fn(parse_seconds))]
+    timeout: Option<Duration>,
+
+    /// an input programs that enable the `env` extension select with `%`, numbered from 1 in
+    /// the order given: `NAME=VALUE` for a value or `NAME` for the environment variable, which
+    /// programs can not read otherwise
+    #[argh(option)]
+    host_input: Vec<HostInputArg>,
+
+    /// the brainfuck program to execute, defaults to the main program of the project or stdin
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// Compile a brainfuck program and print the resulting instructions.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "compile")]
+struct CompileArgs {
+    /// print the instructions without optimizations, one per character
+    #[argh(switch)]
+    no_opt: bool,
+
+    /// how much the program is optimized, from `0` for no optimizations like `--no-opt` to `3`
+    /// for the most, `2` by default
+    #[argh(option)]
+    opt_level: Option<OptLevel>,
+
+    /// expand `;;include "file"` directives before compiling the program
+    #[argh(switch)]
+    preprocess: bool,
+
+    /// write the compiled program as bytecode to the given file instead of printing it, which
+    /// `run` executes without compiling it again
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+
+    /// format of the file written with `-o`, `bytecode`, `wasm` for a WebAssembly module or
+    /// `elf` for a static x86-64 Linux executable
+    #[argh(option, default = "Target::Bytecode")]
+    target: Target,
+
+    /// also write `<output>.map`, mapping byte offsets of the WebAssembly module written with
+    /// `-o` back to spans of the source (`--target wasm` only)
+    #[argh(switch)]
+    source_map: bool,
+
+    /// the brainfuck program to compile, defaults to the main program of the project or stdin
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// The format `compile` writes the program in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Target {
+    Bytecode,
+    Wasm,
+    Elf,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytecode" => Ok(Target::Bytecode),
+            "wasm" => Ok(Target::Wasm),
+            "elf" => Ok(Target::Elf),
+            _ => Err("valid values are `bytecode`, `wasm` and `elf`".to_string()),
+        }
+    }
+}
+
+/// Translate a brainfuck program to the source of another language, e.g. to build it with a C
+/// compiler.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "translate")]
+struct TranslateArgs {
+    /// language to translate the program to, `c`, `rust` or `llvm` for LLVM IR if the `llvm`
+    /// feature is enabled
+    #[argh(option, default = "Language::C")]
+    to: Language,
+
+    /// translate the instructions without optimizations, one per character
+    #[argh(switch)]
+    no_opt: bool,
+
+    /// how much the program is optimized, from `0` for no optimizations like `--no-opt` to `3`
+    /// for the most, `2` by default
+    #[argh(option)]
+    opt_level: Option<OptLevel>,
+
+    /// expand `;;include "file"` directives before translating the program
+    #[argh(switch)]
+    preprocess: bool,
+
+    /// file to write the translated program to instead of printing it
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+
+    /// also write `<output>.map`, mapping lines of the translation written with `-o` back to
+    /// spans of the source
+    #[argh(switch)]
+    source_map: bool,
+
+    /// the brainfuck program to translate, defaults to the main program of the project or stdin
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// Explain what the optimizer turned the instruction or loop at a position into.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "explain")]
+struct ExplainArgs {
+    /// position of the instruction or loop as `LINE:COL`, e.g. `3:14`
+    #[argh(option)]
+    at: Position,
+
+    /// the brainfuck program to explain
+    #[argh(positional)]
+    file: String,
+}
+
+/// Check a brainfuck program for unbalanced brackets, invalid cell names and loop assertions.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "check")]
+struct CheckArgs {
+    /// expand `;;include "file"` directives before checking the program
+    #[argh(switch)]
+    preprocess: bool,
+
+    /// the brainfuck program to check, defaults to the main program of the project or stdin
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// Format a brainfuck program, dropping comments except directives and indenting loops.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "fmt")]
+struct FmtArgs {
+    /// number of spaces to indent each loop level with
+    #[argh(option, default = "4")]
+    indent: usize,
+
+    /// maximum number of instructions per line
+    #[argh(option, default = "80")]
+    width: usize,
+
+    /// the brainfuck program to format, defaults to the main program of the project or stdin
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// Measure how long a brainfuck program takes in each execution environment.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "bench")]
+struct BenchArgs {
+    /// execution environment to measure, can be repeated (default: all)
+    #[argh(option)]
+    env: Vec<Environment>,
+
+    /// the brainfuck program to execute
+    #[argh(positional)]
+    file: Option<String>,
+
+    #[argh(subcommand)]
+    mode: Option<BenchMode>,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum BenchMode {
+    Compile(BenchCompileArgs),
+}
+
+/// Measure each phase of the compiler on generated programs of increasing size, from 1 KB up
+/// to `--max-size`, so phases that do not scale linearly stand out.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "compile")]
+struct BenchCompileArgs {
+    /// size in bytes of the largest generated program (default: 100000000)
+    #[argh(option, default = "100_000_000")]
+    max_size: usize,
+
+    /// output format (`csv` or `json`)
+    #[argh(option, default = "TableFormat::Csv")]
+    format: TableFormat,
+}
+
+/// An input given with `--host-input`.
+#[derive(Debug, Clone, PartialEq)]
+enum HostInputArg {
+    /// `NAME=VALUE`
+    Value(String, String),
+
+    /// `NAME`, the environment variable.
+    EnvVar(String),
+}
+
+impl FromStr for HostInputArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("", _)) => Err("expected `NAME=VALUE` or `NAME`".to_string()),
+            Some((name, value)) => Ok(HostInputArg::Value(name.to_string(), value.to_string())),
+            None if s.is_empty() => Err("expected `NAME=VALUE` or `NAME`".to_string()),
+            None => Ok(HostInputArg::EnvVar(s.to_string())),
+        }
+    }
+}
+
+/// The format of measurements printed by `bench compile`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum TableFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for TableFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(TableFormat::Csv),
+            "json" => Ok(TableFormat::Json),
+            _ => Err("valid values are `csv` and `json`".to_string()),
+        }
+    }
+}
+
+/// Execute a brainfuck program step by step and watch expressions over its cells, stopping at
+/// every `#` breakpoint.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "debug")]
+struct DebugArgs {
+    /// file to read the input of the program from, commands are read from stdin
+    #[argh(option)]
+    input: Option<String>,
+
+    /// the brainfuck program to debug
+    #[argh(positional)]
+    file: String,
+}
+
+/// Run the tests of the project described by the `bf.toml` manifest, or check the output of a
+/// program against its `;; expect "<output>"` directives.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "test")]
+struct TestArgs {
+    /// the brainfuck program to test on its own instead of the project
+    #[argh(positional)]
+    file: Option<String>,
+}
+
+/// Build the targets of the project described by the `bf.toml` manifest.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "build")]
+struct BuildArgs {}
+
+/// Create a project with a `bf.toml` manifest, a main program and a test of its output.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "new")]
+struct NewArgs {
+    /// the directory to create, whose name is the name of the project
+    #[argh(positional)]
+    path: String,
+}
+
+/// Execute a recording written by `run --record` again and check that the output matches.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "replay")]
+struct ReplayArgs {
+    /// the `.bfrun` file to replay
+    #[argh(positional)]
+    file: String,
+}
+
+/// Execute brainfuck code interactively, line by line on the same tape.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "repl")]
+struct ReplArgs {}
+
+/// Print what this build supports, e.g. the available execution environments.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "capabilities")]
+struct CapabilitiesArgs {
+    /// output format (`text` or `json`)
+    #[argh(option, default = "Format::Text")]
+    format: Format,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err("valid values are `text` and `json`".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Environment {
+    Interpreter,
+    VirtualMachine,
+    Closures,
+    JitCompiler,
+}
+
+impl FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpreter" => Ok(Environment::Interpreter),
+            "vm" => Ok(Environment::VirtualMachine),
+            "closures" => Ok(Environment::Closures),
+            "jit" => Ok(Environment::JitCompiler),
+            _ => Err(r#"
+
+    valid values:
+    - `interpreter` to use the interpreter     (slow)
+    - `vm`          to use the virtual machine (faster)
+    - `closures`    to use compiled closures   (faster, portable)
+    - `jit`         to use the jit compiler    (fastest but fallbacks to `vm` on systems it does not support)"#
+                .to_string()),
+        }
+    }
+}
+
+impl From<Backend> for Environment {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Interpreter => Environment::Interpreter,
+            Backend::VirtualMachine => Environment::VirtualMachine,
+            Backend::Closures => Environment::Closures,
+            Backend::Jit => Environment::JitCompiler,
+        }
+    }
+}
+
+impl Environment {
+    fn backend(self) -> Backend {
+        match self {
+            Environment::Interpreter => Backend::Interpreter,
+            Environment::VirtualMachine => Backend::VirtualMachine,
+            Environment::Closures => Backend::Closures,
+            Environment::JitCompiler => Backend::Jit,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Environment::Interpreter => "Interpreter",
+            Environment::VirtualMachine => "Virtual Machine",
+            Environment::Closures => "Closures",
+            Environment::JitCompiler => "JIT Compiler",
+        }
+    }
+
+    /// Describes how the environment executes a program, for error messages.
+    fn description(self) -> &'static str {
+        match self {
+            Environment::Interpreter => "with the interpreter",
+            Environment::VirtualMachine => "on the virtual machine",
+            Environment::Closures => "with compiled closures",
+            Environment::JitCompiler => "with the jit compiler",
+        }
+    }
+
+    /// Returns the environment, or the virtual machine if it is not available on this platform.
+    fn available(self) -> Self {
+        if self.backend().is_available() {
+            self
+        } else {
+            eprintln!(
+                "note: the jit compiler is not available on this platform, \
+                falling back to the virtual machine"
+            );
+            Environment::VirtualMachine
+        }
+    }
+
+    fn execute(
+        self,
+        program: &str,
+        level: OptLevel,
+        options: ExecOptions,
+        streams: &mut Streams,
+    ) -> Result<()> {
+        let environment = self.available();
+        let compiled = match environment {
+            Environment::Interpreter => compiler::Program::new(Vec::new()),
+            _ => compile_program(program, level)?,
+        };
+        environment.execute_instructions(program, &compiled, options, streams)
+    }
+
+    /// Executes an already compiled program, with the virtual machine instead of the
+    /// interpreter, which needs the source.
+    fn execute_compiled(
+        self,
+        program: &compiler::Program,
+        options: ExecOptions,
+        streams: &mut Streams,
+    ) -> Result<()> {
+        let environment = match self.available() {
+            Environment::Interpreter => {
+                eprintln!(
+                    "note: the interpreter needs the source of the program, \
+                    using the virtual machine instead"
+                );
+                Environment::VirtualMachine
+            }
+            environment => environment,
+        };
+        environment.execute_instructions("", program, options, streams)
+    }
+
+    /// Executes `compiled`, or `program` with the interpreter, naming the position in the
+    /// source of the instruction that failed if it is known.
+    fn execute_instructions(
+        self,
+        program: &str,
+        compiled: &compiler::Program,
+        options: ExecOptions,
+        streams: &mut Streams,
+    ) -> Result<()> {
+        // How often the output can be flushed depends on where it goes.
+        let mut options = options.with_flush(streams.flush);
+        // The closures can not be stopped.
+        let interruptible = self.backend().supports(BackendOption::Cancellation);
+        if interruptible {
+            options = options.with_cancel_flag(&INTERRUPTED);
+        }
+        let _interruptible = interruptible.then(Interruptible::start);
+        let mut engine = engine::select(
+            self.backend(),
+            program,
+            compiled,
+            &mut streams.reader,
+            &mut streams.writer,
+            &options,
+        )?;
+        let result = engine.run(&options);
+        report_interruption(&result, &*engine);
+        let position = result.as_ref().err().and_then(RuntimeError::ip);
+        result.with_context(|| match position.and_then(|ip| compiled.position(ip)) {
+            Some(position) => format!(
+                "failed to execute the program {} at {position}",
+                self.description()
+            ),
+            None => format!("failed to execute the program {}", self.description()),
+        })
+    }
+}
+
+impl RunArgs {
+    /// Returns the level of `--opt-level`, or no optimizations with `--no-opt`.
+    fn opt_level(&self) -> Result<OptLevel> {
+        opt_level(self.no_opt, self.opt_level)
+    }
+
+    /// Returns the options the program is executed with, except for the flushing, which is
+    /// decided by the [Streams].
+    fn options(&self) -> ExecOptions {
+        let mut options = ExecOptions::new()
+            .with_tape_size(self.tape_size)
+            .with_tape_policy(self.tape)
+            .with_cell_width(self.cell_width)
+            .with_eof(self.eof);
+        if let Some(max) = self.max_steps {
+            options = options.with_max_steps(max);
+        }
+        if let Some(timeout) = self.timeout {
+            options = options.with_timeout(timeout);
+        }
+        if let Some(inputs) = self.host_inputs() {
+            options = options.with_host_inputs(inputs);
+        }
+        options
+    }
+
+    /// Returns the inputs of `--host-input`, if any, which are read once per process.
+    fn host_inputs(&self) -> Option<&'static HostInputs> {
+        if self.host_input.is_empty() {
+            return None;
+        }
+        Some(HOST_INPUTS.get_or_init(|| {
+            self.host_input
+                .iter()
+                .fold(HostInputs::new(), |inputs, input| match input {
+                    HostInputArg::Value(name, value) => inputs.with_value(name, value.as_bytes()),
+                    HostInputArg::EnvVar(name) => inputs.with_env_var(name),
+                })
+        }))
+    }
+
+    /// Returns the options that only some backends support, in the order their absence is
+    /// reported.
+    fn backend_options(&self) -> Result<Vec<BackendOption>> {
+        let inspect = self.tape_heatmap
+            || self.tape_heatmap_png.is_some()
+            || self.dump_tape
+            || self.profile.is_some()
+            || self.stats
+            || self.hot_loops;
+        let options = [
+            (self.check_assertions, BackendOption::LoopAssertions),
+            (inspect, BackendOption::Inspection),
+            (self.sanitize, BackendOption::Sanitize),
+            (
+                self.opt_level()? == OptLevel::O0,
+                BackendOption::NoOptimizations,
+            ),
+            (self.speed.is_some(), BackendOption::Speed),
+            (
+                self.max_steps.is_some() || self.timeout.is_some(),
+                BackendOption::Limits,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(requested, option)| requested.then_some(option))
+        .collect();
+        Ok(options)
+    }
+}
+
+/// The input and output a program is executed with.
+struct Streams {
+    // Sendable so the jit compiler can execute the program on its own thread.
+    reader: Box<dyn Read + Send>,
+    writer: Box<dyn Write + Send>,
+    flush: FlushBehavior,
+}
+
+impl Streams {
+    fn stdio() -> Self {
+        Self {
+            reader: Box::new(io::stdin()),
+            // Stdout is line buffered, which would flush every line of a burst of output.
+            writer: Box::new(BufWriter::new(io::stdout())),
+            flush: FlushBehavior::Adaptive(AdaptiveFlush::default()),
+        }
+    }
+
+    /// Opens the given files, falling back to stdin and stdout respectively.
+    fn open(input: Option<&str>, output: Option<&str>) -> Result<Self> {
+        let mut streams = Self::stdio();
+
+        if let Some(file) = input {
+            let file = File::open(file).with_context(|| format!("failed to open file {file}"))?;
+            streams.reader = Box::new(BufReader::new(file));
+        }
+
+        if let Some(file) = output {
+            let file =
+                File::create(file).with_context(|| format!("failed to create file {file}"))?;
+            streams.writer = Box::new(BufWriter::new(file));
+            streams.flush = FlushBehavior::OnEnd;
+        }
+
+        Ok(streams)
+    }
+
+    /// Replaces the writer with a buffer, returning the original writer and the buffer.
+    fn capture(&mut self) -> (Box<dyn Write + Send>, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        let writer = std::mem::replace(&mut self.writer, Box::new(buffer.clone()));
+        self.flush = FlushBehavior::OnEnd;
+        (writer, buffer)
+    }
+
+    /// Copies the bytes read and written through the streams into the returned buffers.
+    fn record(&mut self) -> (SharedBuffer, SharedBuffer) {
+        let (input, output) = (SharedBuffer::default(), SharedBuffer::default());
+        let reader = std::mem::replace(&mut self.reader, Box::new(io::empty()));
+        self.reader = Box::new(Tee::new(reader, input.clone()));
+        let writer = std::mem::replace(&mut self.writer, Box::new(io::sink()));
+        self.writer = Box::new(Tee::new(writer, output.clone()));
+        (input, output)
+    }
+
+    /// Copies the output into `file`, which is flushed whenever the output is.
+    fn tee(mut self, file: &str) -> Result<Self> {
+        let file = File::create(file).with_context(|| format!("failed to create file {file}"))?;
+        self.writer = Box::new(Tee::new(self.writer, BufWriter::new(file)));
+        Ok(self)
+    }
+
+    /// Wraps the streams to translate newlines according to `mode`.
+    fn translate_newlines(mut self, mode: NewlineMode) -> Self {
+        if mode.input != Newline::Raw {
+            self.reader = Box::new(NewlineReader::new(self.reader, mode.input));
+        }
+        if mode.output != Newline::Raw {
+            self.writer = Box::new(NewlineWriter::new(self.writer, mode.output));
+        }
+        self
+    }
+}
+
+/// A writer whose bytes can still be accessed through its clones after it was moved.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// Returns the bytes written so far, leaving the buffer empty.
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A reader or writer that copies the bytes passing through it into another writer, a buffer
+/// by default.
+struct Tee<T, C = SharedBuffer> {
+    inner: T,
+    copy: C,
+}
+
+impl<T, C> Tee<T, C> {
+    fn new(inner: T, copy: C) -> Self {
+        Self { inner, copy }
+    }
+}
+
+impl<T: Read, C: Write> Read for Tee<T, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<T: Write, C: Write> Write for Tee<T, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.copy.flush()?;
+        self.inner.flush()
+    }
+}
+
+/// Runs the command line interface with the given arguments, where the first argument is the
+/// name of the binary.
+///
+/// If the first argument after the binary name is not the name of a command, the `run` command
+/// is used. Usage errors and help requests are printed and reflected in the returned exit code,
+/// while errors of the executed command are returned.
+pub fn run<I>(args: I) -> Result<ExitCode>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    let args = match parse_args(args.into_iter().map(Into::into).collect()) {
+        Ok(args) => args,
+        Err(code) => return Ok(code),
+    };
+
+    let result = match args.command {
+        Command::Run(args) => run_program(args),
+        Command::Compile(args) => compile(
+            &read_program(args.file.as_deref().map(Path::new), args.preprocess)?,
+            opt_level(args.no_opt, args.opt_level)?,
+            args.output.as_deref(),
+            args.target,
+            args.source_map,
+        ),
+        Command::Translate(args) => translate(
+            &read_program(args.file.as_deref().map(Path::new), args.preprocess)?,
+            args.to,
+            opt_level(args.no_opt, args.opt_level)?,
+            args.output.as_deref(),
+            args.source_map,
+        ),
+        Command::Explain(args) => {
+            explain(&read_program(Some(Path::new(&args.file)), false)?, args.at)
+        }
+        Command::Check(args) => check(&read_program(
+            args.file.as_deref().map(Path::new),
+            args.preprocess,
+        )?),
+        Command::Fmt(args) => format(
+            &read_program(args.file.as_deref().map(Path::new), false)?,
+            args.indent,
+            args.width,
+        ),
+        Command::Bench(BenchArgs {
+            mode: Some(BenchMode::Compile(args)),
+            ..
+        }) => bench_compile(args.max_size, args.format),
+        Command::Bench(args) => match &args.file {
+            Some(file) => bench(
+                &read_program(Some(Path::new(file)), false)?.source,
+                &args.env,
+            ),
+            None => Err(anyhow!("missing the program to measure")),
+        },
+        Command::Debug(args) => debug(
+            &read_program(Some(Path::new(&args.file)), false)?.source,
+            args.input.as_deref(),
+        ),
+        Command::Capabilities(args) => capabilities(args.format),
+        Command::Test(args) => test(args.file.as_deref()),
+        Command::Build(_) => build(),
+        Command::New(args) => new_project(&args.path),
+        Command::Replay(args) => replay(&args.file),
+        Command::Repl(_) => Repl::new()
+            .run_interactive()
+            .context("failed to run the repl"),
+    };
+
+    match result {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        // The exit code of a process only keeps the low byte on most platforms.
+        Err(err) => match err.downcast_ref::<RuntimeError>() {
+            Some(RuntimeError::Exit(code)) => Ok(ExitCode::from(*code as u8)),
+            // Like a process killed by SIGINT, which is reported by `report_interruption`.
+            Some(RuntimeError::Cancelled) => Ok(ExitCode::from(130)),
+            _ => Err(err),
+        },
+    }
+}
+
+/// The inputs of `--host-input`, which [ExecOptions] borrow for the whole process.
+static HOST_INPUTS: OnceCell<HostInputs> = OnceCell::new();
+
+/// Set once the running program is asked to stop, see [interrupt].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a program runs on an engine that stops once [INTERRUPTED] is set.
+static INTERRUPTIBLE: AtomicBool = AtomicBool::new(false);
+
+/// Asks the running program to stop at the next instruction boundary, e.g. on Ctrl-C, after
+/// which [run] reports how far it got.
+///
+/// Returns `false` if no program runs that can be stopped, or if it was asked to stop before,
+/// so the caller should exit right away instead. Only atomics are accessed, so it can be
+/// called from a signal handler.
+pub fn interrupt() -> bool {
+    INTERRUPTIBLE.load(Ordering::SeqCst) && !INTERRUPTED.swap(true, Ordering::SeqCst)
+}
+
+/// Marks the running program as stopped by [interrupt] until it is dropped.
+struct Interruptible;
+
+impl Interruptible {
+    fn start() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        INTERRUPTIBLE.store(true, Ordering::SeqCst);
+        Interruptible
+    }
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        INTERRUPTIBLE.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Prints how far the program got and the cells around the data pointer if `result` is an
+/// interruption.
+fn report_interruption<T>(result: &Result<T, RuntimeError>, engine: &dyn Engine) {
+    if !matches!(result, Err(RuntimeError::Cancelled)) {
+        return;
+    }
+    match engine.state() {
+        Some(state) => eprint!("{}", interruption_report(&state)),
+        None => eprintln!("interrupted"),
+    }
+}
+
+/// Describes where an interrupted program stopped, marking the cell at the data pointer.
+fn interruption_report(state: &State) -> String {
+    let cells: Vec<String> = (state.first..)
+        .zip(&state.cells)
+        .map(|(index, cell)| match index == state.dp {
+            true => format!("[{cell}]"),
+            false => cell.to_string(),
+        })
+        .collect();
+    format!(
+        "interrupted after {} instructions at cell {}\ncells {}..{}: {}\n",
+        state.steps,
+        state.dp,
+        state.first,
+        state.first + state.cells.len(),
+        cells.join(" ")
+    )
+}
+
+/// Parses the command line, falling back to the `run` command if the first argument is not the
+/// name of a command.
+///
+/// Returns the exit code if parsing exited early, e.g. because help was requested.
+fn parse_args(mut args: Vec<String>) -> Result<Args, ExitCode> {
+    let is_command = |arg: &str| {
+        Command::COMMANDS.iter().any(|command| command.name == arg)
+            || matches!(arg, "help" | "--help")
+    };
+    if args.len() > 1 && !is_command(&args[1]) {
+        args.insert(1, "run".to_string());
+    }
+
+    let name = args
+        .first()
+        .and_then(|arg| std::path::Path::new(arg).file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or("brainfuck")
+        .to_string();
+    let args: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
+
+    Args::from_args(&[&name], &args).map_err(|early_exit| match early_exit.status {
+        Ok(()) => {
+            println!("{}", early_exit.output);
+            ExitCode::SUCCESS
+        }
+        Err(()) => {
+            eprintln!(
+                "{}\nRun {name} --help for more information.",
+                early_exit.output
+            );
+            ExitCode::FAILURE
+        }
+    })
+}
+
+/// Name of a program read from stdin, includes are relative to the current directory.
+const STDIN: &str = "<stdin>";
+
+/// The source of a program and where its lines come from.
+struct Program {
+    source: String,
+
+    /// Path of the file the program was read from, [STDIN] for the standard input.
+    path: PathBuf,
+
+    /// Origin of every line of `source` if the includes of the program were expanded.
+    preprocessed: Option<Preprocessed>,
+}
+
+impl Program {
+    /// Describes a position in `source`, in the file it came from if includes were expanded.
+    fn position(&self, line: usize, column: usize) -> String {
+        match self
+            .preprocessed
+            .as_ref()
+            .and_then(|preprocessed| preprocessed.locate(line, column))
+        {
+            Some(location) => location.to_string(),
+            None => format!("line {line} column {column}"),
+        }
+    }
+}
+
+/// Reads the program from `file`. If no file is given, the main program of the project in the
+/// current directory is read, or stdin if there is no project.
+///
+/// Includes are expanded if `preprocess` is set or the project asks for it.
+fn read_program(file: Option<&Path>, mut preprocess: bool) -> Result<Program> {
+    let file = match file {
+        Some(file) => file.to_path_buf(),
+        None => match load_manifest()? {
+            Some(manifest) => {
+                preprocess |= manifest.preprocess;
+                manifest.main
+            }
+            None => Path::new(STDIN).to_path_buf(),
+        },
+    };
+
+    let mut source = String::new();
+    if file == Path::new(STDIN) {
+        io::stdin()
+            .read_to_string(&mut source)
+            .context("failed to read the program from stdin")?;
+    } else {
+        File::open(&file)
+            .with_context(|| format!("failed to open file {}", file.display()))?
+            .read_to_string(&mut source)
+            .with_context(|| format!("failed to read file {}", file.display()))?;
+    }
+
+    if !preprocess {
+        return Ok(Program {
+            source,
+            path: file,
+            preprocessed: None,
+        });
+    }
+
+    let mut preprocessed =
+        preprocessor::preprocess(&source, &file, |path| fs::read_to_string(path))?;
+    Ok(Program {
+        source: std::mem::take(&mut preprocessed.source),
+        path: file,
+        preprocessed: Some(preprocessed),
+    })
+}
+
+/// Loads the manifest of the project in the current directory or one of its ancestors.
+fn load_manifest() -> Result<Option<Manifest>> {
+    let dir = std::env::current_dir().context("failed to get the current directory")?;
+    let path = match Manifest::find(&dir) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+
+    let source = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read file {}", path.display()))?;
+    let manifest = Manifest::parse(&source, path.parent().unwrap_or(&dir))
+        .with_context(|| format!("invalid manifest {}", path.display()))?;
+
+    Ok(Some(manifest))
+}
+
+/// Like [load_manifest], but fails if there is no project.
+fn require_manifest() -> Result<Manifest> {
+    match load_manifest()? {
+        Some(manifest) => Ok(manifest),
+        None => bail!(
+            "could not find {} in the current directory or any parent directory",
+            manifest::FILE_NAME
+        ),
+    }
+}
+
+fn run_program(args: RunArgs) -> Result<()> {
+    if args.tape_size == 0 {
+        bail!("the tape needs at least one cell");
+    }
+    if args.speed == Some(0) {
+        bail!("the speed needs to be at least one instruction per second");
+    }
+    let level = args.opt_level()?;
+
+    let bytecode = match &args.file {
+        Some(file) => read_bytecode(Path::new(file))?,
+        None => None,
+    };
+    let program = match bytecode {
+        Some(_) => String::new(),
+        None => {
+            let program = read_program(args.file.as_deref().map(Path::new), args.preprocess)?;
+            check_brackets(&program)?;
+            program.source
+        }
+    };
+    if args.dump_ir || args.dump_asm {
+        let compiled = match &bytecode {
+            Some(bytecode) => bytecode.clone(),
+            None => compile_program(&program, level)?,
+        };
+        if args.dump_ir {
+            eprint!("{}", compiled.disassemble());
+        }
+        if args.dump_asm {
+            dump_machine_code(&compiled, args.cell_width)?;
+        }
+    }
+    let mut streams = Streams::open(args.input.as_deref(), args.output.as_deref())?;
+    if let Some(file) = &args.tee {
+        streams = streams.tee(file)?;
+    }
+    let mut streams = streams.translate_newlines(args.newline_mode);
+    let execute = |streams: &mut Streams| match &bytecode {
+        Some(bytecode) => execute_bytecode(&args, bytecode, streams),
+        None => execute_recorded(&args, &program, streams),
+    };
+
+    let decode = match args.decode {
+        Some(decode) => decode,
+        None => return execute(&mut streams),
+    };
+
+    let (mut writer, buffer) = streams.capture();
+    // Print what the program wrote even if it failed.
+    let result = execute(&mut streams);
+    let output = buffer.take();
+
+    match decode {
+        Decode::Utf8 => {
+            let decoded = decode::utf8(&output);
+            writer.write_all(decoded.text.as_bytes())?;
+            if let Some(summary) = decoded.summary() {
+                eprintln!("note: {summary}");
+            }
+        }
+        Decode::Hex => writer.write_all(decode::hex(&output).as_bytes())?,
+    }
+    writer.flush()?;
+
+    result
+}
+
+/// Prints the machine code the jit compiler emits for `instructions`.
+fn dump_machine_code(instructions: &[Instruction], cell_width: CellWidth) -> Result<()> {
+    #[cfg(jit)]
+    {
+        eprint!(
+            "{}",
+            crate::jit::dump_machine_code(instructions, cell_width)
+        );
+        Ok(())
+    }
+
+    #[cfg(not(jit))]
+    {
+        let _ = (instructions, cell_width);
+        bail!("cannot dump the machine code, the jit compiler is not available on this platform");
+    }
+}
+
+/// Reads the program compiled by `compile --output` from `file`, or returns `None` if the file
+/// does not contain bytecode.
+fn read_bytecode(file: &Path) -> Result<Option<compiler::Program>> {
+    let mut magic = [0; bytecode::MAGIC.len()];
+    let is_bytecode = match File::open(file) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && bytecode::is_bytecode(&magic),
+        // Reported when the file is read as source.
+        Err(_) => false,
+    };
+    if !is_bytecode {
+        return Ok(None);
+    }
+
+    let bytes =
+        fs::read(file).with_context(|| format!("failed to read file {}", file.display()))?;
+    let program = compiler::Program::from_bytes(&bytes)
+        .with_context(|| format!("invalid compiled program {}", file.display()))?;
+    Ok(Some(program))
+}
+
+/// Executes a compiled program with the environment selected by `args`, which cannot inspect
+/// the execution without the source.
+fn execute_bytecode(
+    args: &RunArgs,
+    program: &compiler::Program,
+    streams: &mut Streams,
+) -> Result<()> {
+    let options = [
+        ("--tape-heatmap", args.tape_heatmap),
+        ("--tape-heatmap-png", args.tape_heatmap_png.is_some()),
+        ("--dump-tape", args.dump_tape),
+        ("--profile", args.profile.is_some()),
+        ("--stats", args.stats),
+        ("--hot-loops", args.hot_loops),
+        ("--no-opt", args.no_opt),
+        ("--opt-level", args.opt_level.is_some()),
+        ("--record", args.record.is_some()),
+        ("--preprocess", args.preprocess),
+        ("--check-assertions", args.check_assertions),
+        ("--sanitize", args.sanitize),
+        ("--speed", args.speed.is_some()),
+    ];
+    if let Some((option, _)) = options.iter().find(|(_, set)| *set) {
+        bail!(
+            "`{option}` requires the source of the program, which compiled programs do not contain"
+        );
+    }
+
+    args.env.execute_compiled(program, args.options(), streams)
+}
+
+/// Executes the program like [execute_program], writing a recording of the execution to the
+/// file given by `--record`, even if the execution failed.
+fn execute_recorded(args: &RunArgs, program: &str, streams: &mut Streams) -> Result<()> {
+    let file = match &args.record {
+        Some(file) => file,
+        None => return execute_program(args, program, streams),
+    };
+
+    let (input, output) = streams.record();
+    let result = execute_program(args, program, streams);
+
+    let recording = Recording {
+        crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        source: program.to_string(),
+        input: input.take(),
+        output: output.take(),
+        backend: args.env.backend(),
+        tape_size: args.tape_size,
+        tape_policy: args.tape,
+        cell_width: args.cell_width,
+        eof: args.eof,
+        no_opt: args.no_opt,
+        opt_level: args.opt_level()?,
+    };
+    fs::write(file, recording.to_toml()).with_context(|| format!("failed to write file {file}"))?;
+
+    result
+}
+
+/// Executes the program with the environment selected by `args`, or with the virtual machine
+/// if the tape or the execution is inspected, or if optimizations are disabled.
+fn execute_program(args: &RunArgs, program: &str, streams: &mut Streams) -> Result<()> {
+    let level = args.opt_level()?;
+    let requested = args.backend_options()?;
+    let backend = args.env.backend();
+    // The engines enforce the limits of the options themselves.
+    let limits_only = requested
+        .iter()
+        .all(|option| *option == BackendOption::Limits);
+    if limits_only && backend.check(&requested).is_ok() {
+        return args.env.execute(program, level, args.options(), streams);
+    }
+    if backend == Backend::Interpreter && backend.check(&requested).is_ok() {
+        // The interpreter never optimizes and can be throttled itself.
+        return match (args.speed, args.cell_width) {
+            (None, _) => args.env.execute(program, level, args.options(), streams),
+            (Some(speed), CellWidth::U8) => run_throttled::<u8>(program, args, speed, streams),
+            (Some(speed), CellWidth::U16) => run_throttled::<u16>(program, args, speed, streams),
+            (Some(speed), CellWidth::U32) => run_throttled::<u32>(program, args, speed, streams),
+        };
+    }
+
+    if let Err(unsupported) = backend.check(&requested) {
+        let reason = unsupported.option.description();
+        eprintln!("note: {reason} requires the virtual machine, using it instead");
+    }
+
+    match args.cell_width {
+        CellWidth::U8 => inspect_program::<u8>(args, program, streams),
+        CellWidth::U16 => inspect_program::<u16>(args, program, streams),
+        CellWidth::U32 => inspect_program::<u32>(args, program, streams),
+    }
+}
+
+/// Executes the program on the virtual machine, with the inspections requested by `args`.
+fn inspect_program<C: Cell>(args: &RunArgs, program: &str, streams: &mut Streams) -> Result<()> {
+    let level = args.opt_level()?;
+    let instructions = compile_program(program, level)?;
+    let spans = source_map::instruction_spans_with_passes(program, level.passes());
+    let assertions = if args.check_assertions {
+        LoopAssertions::parse(program)?
+    } else {
+        LoopAssertions::default()
+    };
+    let (limits, lowered) = assertions.limits(&instructions, &spans);
+    for assertion in lowered {
+        eprintln!(
+            "note: the loop at line {} column {} is optimized away, `{assertion}` is not checked",
+            assertion.loop_line, assertion.loop_column
+        );
+    }
+    let mut vm = VirtualMachine::new(&instructions, &mut streams.reader, &mut streams.writer)
+        .with_tape_size(args.tape_size)
+        .with_tape_policy(args.tape)
+        .with_write_counts()
+        .with_cells::<C>();
+    // Loops replayed by the effect cache would not count the instructions of their body.
+    if level != OptLevel::O0 && !args.hot_loops {
+        vm = vm.with_effect_cache();
+    }
+    if args.hot_loops {
+        vm = vm.with_instruction_counts();
+    }
+    if args.profile.is_some() {
+        vm = vm.with_sampling(args.sample_interval);
+    }
+    if let Some(speed) = args.speed {
+        vm = vm.with_speed(speed);
+    }
+    if let Some(max) = args.max_steps {
+        vm = vm.with_max_steps(max, instructions.step_costs());
+    }
+    if let Some(timeout) = args.timeout {
+        vm = vm.with_timeout(timeout);
+    }
+    vm = vm.with_cancel_flag(&INTERRUPTED);
+    if let Some(inputs) = args.host_inputs() {
+        vm = vm.with_host_inputs(inputs);
+    }
+    if !assertions.is_empty() {
+        vm = vm.with_loop_limits(&limits);
+    }
+    if args.sanitize {
+        let spans = spans.clone();
+        vm = vm.with_sanitizer(Sanitizer::new().with_callback(move |finding| {
+            let span = spans[finding.ip];
+            eprintln!(
+                "warning: {} at line {} column {} (cell {})",
+                finding.issue.description(),
+                span.line,
+                span.column,
+                finding.dp
+            );
+        }));
+    }
+    let interruptible = Interruptible::start();
+    let result = vm.execute(streams.flush, args.eof);
+    drop(interruptible);
+    report_interruption(&result, &vm);
+    let result = result.map_err(|err| match err {
+        RuntimeError::LoopLimitExceeded { ip, max } => {
+            loop_limit_error(&assertions, spans[ip], max)
+        }
+        err => {
+            let context = match err.ip().and_then(|ip| instructions.position(ip)) {
+                Some(position) => {
+                    format!("failed to execute the program on the virtual machine at {position}")
+                }
+                None => "failed to execute the program on the virtual machine".to_string(),
+            };
+            anyhow::Error::new(err).context(context)
+        }
+    });
+
+    // A profile of a failed execution still shows where it spent its time.
+    if let (Some(file), Some(samples)) = (&args.profile, vm.samples()) {
+        let trace = profiler::chrome_trace(&instructions, &spans, samples);
+        fs::write(file, trace).with_context(|| format!("failed to write file {file}"))?;
+    }
+    if args.stats {
+        eprint!("{}", vm.report());
+    }
+    if let Some(counts) = vm.instruction_counts() {
+        eprint!("{}", hot_loops::Profile::new(&instructions, &spans, counts));
+    }
+    if let Some(sanitizer) = vm.sanitizer() {
+        let repeated = sanitizer.occurrences() - sanitizer.findings().len() as u64;
+        if repeated > 0 {
+            eprintln!(
+                "note: {repeated} repeated occurrences at the same positions were not reported"
+            );
+        }
+    }
+    result?;
+
+    let counts = vm.write_counts().unwrap_or_default();
+
+    if args.dump_tape {
+        if C::WIDTH == CellWidth::U8 {
+            let bytes: Vec<u8> = vm.data().iter().map(|cell| cell.low_byte()).collect();
+            eprint!("{}", cell_view::dump(&bytes, args.tape_view));
+        } else {
+            // Views combine bytes, so they do not apply to wider cells.
+            eprint!("{}", cell_view::dump_numbers(vm.data()));
+        }
+        for (index, name) in CellNames::parse(program)?.iter() {
+            eprintln!("{name}={}", vm.data()[index]);
+        }
+    }
+
+    if args.tape_heatmap {
+        eprint!("{}", heatmap::render_text(counts, 64));
+    }
+
+    if let Some(file) = &args.tape_heatmap_png {
+        #[cfg(feature = "png")]
+        std::fs::write(file, heatmap::render_png(counts, 64, 8))
+            .with_context(|| format!("failed to write file {file}"))?;
+
+        #[cfg(not(feature = "png"))]
+        bail!("cannot write {file}, PNG output requires the `png` feature");
+    }
+
+    Ok(())
+}
+
+/// Describes the failed assertion of the loop that starts at `span`.
+fn loop_limit_error(assertions: &LoopAssertions, span: Span, max: u64) -> anyhow::Error {
+    let at = match assertions.get(span) {
+        Some(assertion) => format!(
+            "assertion `{assertion}` at line {} column {}",
+            assertion.line, assertion.column
+        ),
+        None => format!("assertion `loop<={max}`"),
+    };
+    anyhow!(
+        "{at} failed: the loop at line {} column {} ran more than {max} iterations",
+        span.line,
+        span.column
+    )
+}
+
+/// Executes the program with the interpreter at `speed` instructions per second.
+fn run_throttled<C: Cell>(
+    program: &str,
+    args: &RunArgs,
+    speed: u64,
+    streams: &mut Streams,
+) -> Result<()> {
+    let mut interpreter = Interpreter::new(program, &mut streams.reader, &mut streams.writer)
+        .with_tape_size(args.tape_size)
+        .with_tape_policy(args.tape)
+        .with_cells::<C>()
+        .with_speed(speed);
+    if let Some(max) = args.max_steps {
+        interpreter = interpreter.with_max_steps(max);
+    }
+    if let Some(timeout) = args.timeout {
+        interpreter = interpreter.with_timeout(timeout);
+    }
+    interpreter = interpreter.with_cancel_flag(&INTERRUPTED);
+    if let Some(inputs) = args.host_inputs() {
+        interpreter = interpreter.with_host_inputs(inputs);
+    }
+    let interruptible = Interruptible::start();
+    let result = interpreter.execute(streams.flush, args.eof);
+    drop(interruptible);
+    report_interruption(&result, &interpreter);
+    result.context("failed to execute the program with the interpreter")?;
+    Ok(())
+}
+
+/// Parses the number of seconds of `--timeout`.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| "expected a number of seconds, e.g. `1.5`".to_string())
+}
+
+fn compile(
+    program: &Program,
+    level: OptLevel,
+    output: Option<&str>,
+    target: Target,
+    source_map: bool,
+) -> Result<()> {
+    check(program)?;
+    if source_map && target != Target::Wasm {
+        bail!("`--source-map` is only supported for `--target wasm`");
+    }
+    let compiled = compile_program(&program.source, level)?;
+
+    match output {
+        Some(file) => {
+            let bytes = match target {
+                Target::Bytecode => compiled.to_bytes(),
+                Target::Wasm => {
+                    let (module, offsets) = wasm::compile_with_offsets(&compiled);
+                    if source_map {
+                        write_source_map(program, level, file, offsets)?;
+                    }
+                    module
+                }
+                Target::Elf => elf_executable(&compiled)?,
+            };
+            fs::write(file, bytes).with_context(|| format!("failed to write file {file}"))?;
+            #[cfg(unix)]
+            if target == Target::Elf {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(file, fs::Permissions::from_mode(0o755))
+                    .with_context(|| format!("failed to make file {file} executable"))?;
+            }
+            Ok(())
+        }
+        None if target != Target::Bytecode => {
+            bail!("the compiled program can not be printed, write it to a file with `-o`")
+        }
+        None => {
+            print!("{}", compiled.disassemble());
+            Ok(())
+        }
+    }
+}
+
+fn translate(
+    program: &Program,
+    language: Language,
+    level: OptLevel,
+    output: Option<&str>,
+    source_map: bool,
+) -> Result<()> {
+    check(program)?;
+    let (translated, lines) =
+        language.translate_with_lines(&compile_program(&program.source, level)?);
+
+    match output {
+        Some(file) => {
+            if source_map {
+                write_source_map(program, level, file, lines)?;
+            }
+            fs::write(file, translated).with_context(|| format!("failed to write file {file}"))
+        }
+        None if source_map => bail!("`--source-map` needs the translation written with `-o`"),
+        None => {
+            print!("{translated}");
+            Ok(())
+        }
+    }
+}
+
+/// Writes `<file>.map`, mapping the generated position of every instruction of `program`
+/// compiled with `level` in `file`, like a line or a byte offset, to its span in the source.
+fn write_source_map(
+    program: &Program,
+    level: OptLevel,
+    file: &str,
+    positions: Vec<usize>,
+) -> Result<()> {
+    let spans = source_map::instruction_spans_with_passes(&program.source, level.passes());
+    let mut map = SourceMap::new(file, &program.path.display().to_string());
+    for (position, span) in positions.into_iter().zip(spans) {
+        map.add(position, span);
+    }
+
+    let path = format!("{file}.map");
+    fs::write(&path, map.to_json()).with_context(|| format!("failed to write file {path}"))
+}
+
+/// Returns a static x86-64 Linux executable of `instructions`.
+fn elf_executable(instructions: &[Instruction]) -> Result<Vec<u8>> {
+    #[cfg(all(jit, target_arch = "x86_64"))]
+    {
+        Ok(crate::elf::compile(instructions))
+    }
+
+    #[cfg(not(all(jit, target_arch = "x86_64")))]
+    {
+        let _ = instructions;
+        bail!("cannot compile an executable, the x86-64 jit compiler is not available here");
+    }
+}
+
+/// Compiles `source` with the passes of `level`.
+fn compile_program(source: &str, level: OptLevel) -> Result<compiler::Program> {
+    Ok(Compiler::new(source)
+        .with_opt_level(level)
+        .compile_program()?)
+}
+
+/// Returns the level given by `--opt-level`, which `--no-opt` can only be combined with if it
+/// is `0` as well.
+fn opt_level(no_opt: bool, level: Option<OptLevel>) -> Result<OptLevel> {
+    match (no_opt, level) {
+        (true, Some(level)) if level != OptLevel::O0 => bail!(
+            "`--no-opt` can not be combined with `--opt-level {}`",
+            level.number()
+        ),
+        (true, _) => Ok(OptLevel::O0),
+        (false, level) => Ok(level.unwrap_or_default()),
+    }
+}
+
+fn explain(program: &Program, at: Position) -> Result<()> {
+    check_brackets(program)?;
+    let explanation = match explain::explain(&program.source, at)? {
+        Some(explanation) => explanation,
+        None => bail!(
+            "there is no instruction at line {} column {}, only a comment",
+            at.line,
+            at.column
+        ),
+    };
+
+    let span = explanation.span;
+    println!(
+        "line {} column {} to line {} column {}: {}",
+        span.line, span.column, span.end_line, span.end_column, explanation.summary
+    );
+    let original = &explanation.original;
+    println!(
+        "pass `{}`, replaces instructions {}..{} of the unoptimized program",
+        explanation.pass.name(),
+        original.start,
+        original.end
+    );
+    for (i, instruction) in explanation.instructions.iter().enumerate() {
+        println!("{:>6}  {instruction:?}", explanation.index + i);
+    }
+    Ok(())
+}
+
+fn check(program: &Program) -> Result<()> {
+    check_brackets(program)?;
+
+    if let Err(err) = CellNames::parse(&program.source) {
+        bail!(
+            "invalid cell name at {}: {}",
+            program.position(err.line, err.column),
+            err.message
+        );
+    }
+    if let Err(err) = Expectations::parse(&program.source) {
+        bail!(
+            "invalid expected output at {}: {}",
+            program.position(err.line, err.column),
+            err.message
+        );
+    }
+    if let Err(err) = LoopAssertions::parse(&program.source) {
+        bail!(
+            "invalid loop assertion at {}: {}",
+            program.position(err.line, err.column),
+            err.message
+        );
+    }
+    if let Err(err) = extensions::check(&program.source) {
+        bail!(
+            "invalid extension at {}: {}",
+            program.position(err.line, err.column),
+            err.message
+        );
+    }
+    Ok(())
+}
+
+/// Fails if a bracket of `program` has no matching one, pointing at the file it came from.
+fn check_brackets(program: &Program) -> Result<()> {
+    match compiler::check_brackets(&program.source) {
+        Ok(()) => Ok(()),
+        Err(err) => bail!(
+            "unmatched `{}` at {}",
+            err.bracket,
+            program.position(err.line, err.column)
+        ),
+    }
+}
+
+/// A loop, a run of non-loop instructions or a directive of a program that is being formatted.
+enum Node {
+    Run(String),
+    Loop(Vec<Node>),
+    Directive(String),
+}
+
+fn format(program: &Program, indent: usize, width: usize) -> Result<()> {
+    check(program)?;
+
+    // Parse the program into a tree of loops, the brackets are known to be balanced.
+    let mut stack = vec![Vec::new()];
+    for token in tokenize(&program.source) {
+        match (token.kind, token.text) {
+            (TokenKind::Instruction, "[") => stack.push(Vec::new()),
+            (TokenKind::Instruction, "]") => {
+                let body = stack.pop().unwrap();
+                stack.last_mut().unwrap().push(Node::Loop(body));
+            }
+            (TokenKind::Instruction, c) => match stack.last_mut().unwrap().last_mut() {
+                Some(Node::Run(run)) => run.push_str(c),
+                _ => stack.last_mut().unwrap().push(Node::Run(c.to_string())),
+            },
+            (TokenKind::Directive, directive) => stack
+                .last_mut()
+                .unwrap()
+                .push(Node::Directive(directive.trim_end().to_string())),
+            (TokenKind::Comment, _) => {}
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    format_nodes(&stack[0], 0, width, &mut line, &mut lines);
+    if !line.is_empty() {
+        lines.push((0, line));
+    }
+
+    let mut stdout = io::stdout().lock();
+    for (depth, line) in lines {
+        writeln!(stdout, "{:indent$}{line}", "", indent = depth * indent)?;
+    }
+
+    Ok(())
+}
+
+/// Appends the formatted `nodes` to `lines`, where each line is stored with its loop depth.
+/// Loops without nested loops or directives that fit into `width` are kept on a single line,
+/// directives always get a line of their own.
+fn format_nodes(
+    nodes: &[Node],
+    depth: usize,
+    width: usize,
+    line: &mut String,
+    lines: &mut Vec<(usize, String)>,
+) {
+    let push_atom = |atom: &str, line: &mut String, lines: &mut Vec<(usize, String)>| {
+        if !line.is_empty() && line.len() + atom.len() > width {
+            lines.push((depth, std::mem::take(line)));
+        }
+        line.push_str(atom);
+    };
+
+    for node in nodes {
+        match node {
+            Node::Run(run) => {
+                for c in run.chars() {
+                    push_atom(c.encode_utf8(&mut [0; 4]), line, lines);
+                }
+            }
+            Node::Directive(directive) => {
+                if !line.is_empty() {
+                    lines.push((depth, std::mem::take(line)));
+                }
+                lines.push((depth, directive.clone()));
+            }
+            Node::Loop(body) if body.iter().all(|node| matches!(node, Node::Run(_))) => {
+                let mut atom = String::from("[");
+                for node in body {
+                    if let Node::Run(run) = node {
+                        atom.push_str(run);
+                    }
+                }
+                atom.push(']');
+
+                if atom.len() <= width {
+                    push_atom(&atom, line, lines);
+                } else {
+                    line.push('[');
+                    lines.push((depth, std::mem::take(line)));
+                    format_nodes(body, depth + 1, width, line, lines);
+                    lines.push((depth + 1, std::mem::take(line)));
+                    line.push(']');
+                }
+            }
+            Node::Loop(body) => {
+                line.push('[');
+                lines.push((depth, std::mem::take(line)));
+                format_nodes(body, depth + 1, width, line, lines);
+                if !line.is_empty() {
+                    lines.push((depth + 1, std::mem::take(line)));
+                }
+                line.push(']');
+            }
+        }
+    }
+}
+
+fn bench(program: &str, environments: &[Environment]) -> Result<()> {
+    let environments = if environments.is_empty() {
+        &[
+            Environment::Interpreter,
+            Environment::VirtualMachine,
+            Environment::Closures,
+            Environment::JitCompiler,
+        ]
+    } else {
+        environments
+    };
+
+    let mut results = Vec::new();
+    for environment in environments {
+        eprintln!("{}:\n", environment.name());
+        // Waiting for the terminal is told apart from the time the program takes.
+        let times = Arc::new(Mutex::new(PhaseTimes::default()));
+        let mut streams = Streams::stdio();
+        streams.reader = Box::new(Timed::new(streams.reader, Arc::clone(&times)));
+        streams.writer = Box::new(Timed::new(streams.writer, Arc::clone(&times)));
+        let start = Instant::now();
+        environment.execute(
+            program,
+            OptLevel::default(),
+            ExecOptions::new(),
+            &mut streams,
+        )?;
+        streams
+            .writer
+            .flush()
+            .context("failed to flush the output")?;
+        let io_wait = times.lock().unwrap().get(Phase::IoWait).wall;
+        results.push((environment.name(), start.elapsed(), io_wait));
+        eprintln!();
+    }
+
+    for (name, elapsed, io_wait) in results {
+        eprintln!(
+            "{:<16} {elapsed:.3?} (io wait {io_wait:.3?})",
+            format!("{name}:")
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints how long each phase of the compiler takes on generated programs of up to `max_size`
+/// bytes, reporting the progress on stderr.
+fn bench_compile(max_size: usize, format: TableFormat) -> Result<()> {
+    let sizes = bench::sizes(max_size);
+    if sizes.is_empty() {
+        bail!(
+            "the largest program needs at least {} bytes",
+            bench::MIN_SIZE
+        );
+    }
+
+    let mut measurements = Vec::new();
+    for size in sizes {
+        eprintln!("measuring a program of {size} bytes");
+        let program = bench::program(size);
+        for (phase, time) in bench::measure(&program) {
+            measurements.push(bench::Measurement { size, phase, time });
+        }
+    }
+
+    match format {
+        TableFormat::Csv => print!("{}", bench::to_csv(&measurements)),
+        TableFormat::Json => println!("{}", bench::to_json(&measurements)),
+    }
+    Ok(())
+}
+
+fn debug(program: &str, input: Option<&str>) -> Result<()> {
+    let mut reader: Box<dyn Read> = match input {
+        Some(file) => Box::new(BufReader::new(
+            File::open(file).with_context(|| format!("failed to open file {file}"))?,
+        )),
+        None => Box::new(io::empty()),
+    };
+
+    Debugger::new(program, &mut reader, &mut io::stdout())
+        .with_cell_names(CellNames::parse(program)?)
+        .run(&mut io::stdin().lock(), &mut io::stdout())
+        .context("failed to debug the program")
+}
+
+/// Executes a test on the virtual machine with the given tape.
+fn run_test<C: Cell>(
+    tape: TapePolicy,
+    instructions: &[Instruction],
+    mut input: &[u8],
+    output: &mut Vec<u8>,
+) -> Result<(), RuntimeError> {
+    VirtualMachine::new(instructions, &mut input, output)
+        .with_tape_policy(tape)
+        .with_cells::<C>()
+        .with_effect_cache()
+        .execute(FlushBehavior::OnEnd, EofBehavior::default())?;
+    Ok(())
+}
+
+/// Runs the tests of the project, or of `file` on its own with the default tape.
+///
+/// The `;; expect` directives of the program are one more test without input.
+fn test(file: Option<&str>) -> Result<()> {
+    let (manifest, main) = match file {
+        Some(file) => (None, PathBuf::from(file)),
+        None => {
+            let manifest = require_manifest()?;
+            let main = manifest.main.clone();
+            (Some(manifest), main)
+        }
+    };
+    let preprocess = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.preprocess);
+    let program = read_program(Some(&main), preprocess)?;
+    check(&program)?;
+    let instructions = Compiler::new(&program.source).compile()?;
+    let (cell_width, tape) = match &manifest {
+        Some(manifest) => (manifest.cell_width, manifest.tape),
+        None => (CellWidth::U8, TapePolicy::Fixed),
+    };
+
+    let read = |data: &Data| match data {
+        Data::Inline(data) => Ok(data.as_bytes().to_vec()),
+        Data::File(file) => {
+            fs::read(file).with_context(|| format!("failed to read file {}", file.display()))
+        }
+    };
+
+    let mut tests = Vec::new();
+    let expectations = Expectations::parse(&program.source)?;
+    if !expectations.is_empty() {
+        let name = main.display().to_string();
+        tests.push((name, Vec::new(), expectations.output().to_vec()));
+    }
+    for test in manifest.iter().flat_map(|manifest| &manifest.tests) {
+        tests.push((test.name.clone(), read(&test.input)?, read(&test.output)?));
+    }
+    if manifest.is_none() && tests.is_empty() {
+        bail!(
+            "{} has no `;; expect \"<output>\"` directives to test",
+            main.display()
+        );
+    }
+
+    println!("running {} tests", tests.len());
+    let mut failures = Vec::new();
+    for (name, input, expected) in &tests {
+        let mut output = Vec::new();
+        let result = match cell_width {
+            CellWidth::U8 => run_test::<u8>(tape, &instructions, input, &mut output),
+            CellWidth::U16 => run_test::<u16>(tape, &instructions, input, &mut output),
+            CellWidth::U32 => run_test::<u32>(tape, &instructions, input, &mut output),
+        };
+
+        let failure = match result {
+            Err(err) => Some(format!("failed to execute the program: {err}")),
+            Ok(()) if output != *expected => Some(format!(
+                "expected: {:?}\nactual:   {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(&output)
+            )),
+            Ok(()) => None,
+        };
+        match failure {
+            Some(failure) => {
+                println!("test {name} ... FAILED");
+                failures.push((name, failure));
+            }
+            None => println!("test {name} ... ok"),
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, failure) in &failures {
+            println!("---- {name} ----\n{failure}");
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len()
+    );
+
+    if !failures.is_empty() {
+        bail!("{} of {} tests failed", failures.len(), tests.len());
+    }
+    Ok(())
+}
+
+/// The main program of a project created by `new`, which the test of the project checks.
+const NEW_PROJECT_MAIN: &str = "Prints Hello World and a newline
+
++[>[<-[]>+[>+++>[+++++++++++>][>]-[<]>-]]++++++++++<]>
+>>>>>----.<<+++.<-..+++.<-.>>>.<<.+++.------.>-.<<+.<.
+";
+
+fn new_project(path: &str) -> Result<()> {
+    let dir = Path::new(path);
+    let name = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || !name.chars().all(valid) {
+        bail!("invalid project name `{path}`, names may only contain letters, digits, `-` and `_`");
+    }
+    if dir.exists() {
+        bail!("{} already exists", dir.display());
+    }
+
+    let files = [
+        (manifest::FILE_NAME, manifest::template(name)),
+        ("src/main.b", NEW_PROJECT_MAIN.to_string()),
+        ("tests/hello.txt", "Hello World!\n".to_string()),
+        (".gitignore", "/target\n".to_string()),
+    ];
+    for (file, contents) in files {
+        let file = dir.join(file);
+        let parent = file.parent().expect("files are inside of the project");
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {}", parent.display()))?;
+        fs::write(&file, contents)
+            .with_context(|| format!("failed to write file {}", file.display()))?;
+        eprintln!("created {}", file.display());
+    }
+    Ok(())
+}
+
+fn build() -> Result<()> {
+    let manifest = require_manifest()?;
+    let program = read_program(Some(&manifest.main), manifest.preprocess)?;
+    check(&program)?;
+
+    if manifest.targets.is_empty() {
+        eprintln!("note: no targets to build, add them to `build.targets` in the manifest");
+    }
+
+    fs::create_dir_all(&manifest.out_dir)
+        .with_context(|| format!("failed to create directory {}", manifest.out_dir.display()))?;
+    for target in &manifest.targets {
+        let file = manifest.out_dir.join(format!("{}.{target}", manifest.name));
+        let mut map = SourceMap::new(
+            &file.display().to_string(),
+            &manifest.main.display().to_string(),
+        );
+
+        let artifact = match target.as_str() {
+            "instructions" => {
+                let spans = source_map::instruction_spans(&program.source);
+                for (line, span) in spans.into_iter().enumerate() {
+                    map.add(line + 1, span);
+                }
+                Compiler::new(&program.source)
+                    .compile_program()?
+                    .disassemble()
+            }
+            _ => unreachable!("targets are validated by the manifest"),
+        };
+
+        fs::write(&file, artifact)
+            .with_context(|| format!("failed to write file {}", file.display()))?;
+        eprintln!("built {}", file.display());
+
+        if manifest.source_maps {
+            let file = file.with_extension(format!("{target}.map"));
+            fs::write(&file, map.to_json())
+                .with_context(|| format!("failed to write file {}", file.display()))?;
+            eprintln!("built {}", file.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn replay(file: &str) -> Result<()> {
+    let source = fs::read_to_string(file).with_context(|| format!("failed to open file {file}"))?;
+    let recording =
+        Recording::parse(&source).with_context(|| format!("invalid recording {file}"))?;
+
+    let version = env!("CARGO_PKG_VERSION");
+    if recording.crate_version != version {
+        eprintln!(
+            "note: the recording was made with version {}, replaying it with version {version} \
+            may behave differently",
+            recording.crate_version
+        );
+    }
+
+    let args = RunArgs {
+        env: recording.backend.into(),
+        input: None,
+        output: None,
+        tee: None,
+        newline_mode: NewlineMode::default(),
+        decode: None,
+        tape_heatmap: false,
+        tape_heatmap_png: None,
+        dump_tape: false,
+        tape_view: CellView::Decimal,
+        profile: None,
+        sample_interval: 1000,
+        stats: false,
+        hot_loops: false,
+        tape_size: recording.tape_size,
+        tape: recording.tape_policy,
+        cell_width: recording.cell_width,
+        eof: recording.eof,
+        no_opt: recording.no_opt,
+        opt_level: Some(recording.opt_level),
+        dump_ir: false,
+        dump_asm: false,
+        record: None,
+        preprocess: false,
+        check_assertions: false,
+        sanitize: false,
+        speed: None,
+        max_steps: None,
+        timeout: None,
+        host_input: Vec::new(),
+        file: None,
+    };
+    let buffer = SharedBuffer::default();
+    let mut streams = Streams {
+        reader: Box::new(io::Cursor::new(recording.input)),
+        writer: Box::new(buffer.clone()),
+        flush: FlushBehavior::OnEnd,
+    };
+    let result = execute_program(&args, &recording.source, &mut streams);
+
+    let output = buffer.take();
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    result?;
+
+    if output != recording.output {
+        let at = output
+            .iter()
+            .zip(&recording.output)
+            .position(|(replayed, recorded)| replayed != recorded)
+            .unwrap_or(output.len().min(recording.output.len()));
+        bail!("the output differs from the recording starting at byte {at}");
+    }
+
+    Ok(())
+}
+
+fn capabilities(format: Format) -> Result<()> {
+    let capabilities = Capabilities::current();
+
+    if format == Format::Json {
+        println!("{}", capabilities.to_json());
+        return Ok(());
+    }
+
+    let list = |values: &[&str]| {
+        if values.is_empty() {
+            "none".to_string()
+        } else {
+            values.join(", ")
+        }
+    };
+    let cell_widths: Vec<String> = capabilities
+        .cell_widths
+        .iter()
+        .map(|width| format!("{width} bit"))
+        .collect();
+
+    println!("version:       {}", capabilities.version);
+    let backends: Vec<&str> = capabilities.backends.iter().map(|b| b.name()).collect();
+    println!("backends:      {}", list(&backends));
+    println!("extensions:    {}", list(&capabilities.extensions));
+    println!("cell widths:   {}", cell_widths.join(", "));
+    println!("tape policies: {}", list(&capabilities.tape_policies));
+    println!("features:      {}", list(&capabilities.features));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{check, parse_args, Command, Program};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn program(source: &str) -> Program {
+        Program {
+            source: source.to_string(),
+            path: PathBuf::from("test.b"),
+            preprocessed: None,
+        }
+    }
+
+    #[test]
+    fn test_run_is_default_command() {
+        let args = parse_args(args(&["brainfuck", "--env", "vm", "hello.b"])).unwrap();
+
+        match args.command {
+            Command::Run(run) => assert_eq!(run.file.as_deref(), Some("hello.b")),
+            command => panic!("unexpected command {command:?}"),
+        }
+    }
+
+    #[test]
+    fn test_explicit_command() {
+        let args = parse_args(args(&["brainfuck", "check", "hello.b"])).unwrap();
+
+        assert!(matches!(args.command, Command::Check(_)));
+    }
+
+    #[test]
+    fn test_check_unbalanced_brackets() {
+        assert!(check(&program("+[>+<-]")).is_ok());
+        assert_eq!(
+            check(&program("+[>\n+<-]]")).unwrap_err().to_string(),
+            "unmatched `]` at line 2 column 5"
+        );
+        assert_eq!(
+            check(&program("[[]")).unwrap_err().to_string(),
+            "unmatched `[` at line 1 column 1"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Executes instructions compiled into nested closures, which avoids the dispatch of the
+//! [virtual machine](crate::virtual_machine::VirtualMachine) on every instruction while
+//! staying portable, unlike the [JIT compiler](crate::jit).
+
+use std::io::{Read, Write};
+
+use crate::compiler::Instruction;
+use crate::flush::Flusher;
+use crate::host::{HostInputs, InputSelector};
+use crate::tape::{Cell, OutOfBounds, Tape, TapePolicy};
+use crate::{EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+/// State shared by all closures of a program.
+struct Machine<'a, R, W, C> {
+    data: Tape<C>,
+    dp: usize,
+    reader: &'a mut R,
+    input: InputSelector<'a>,
+    writer: &'a mut W,
+    flush: FlushBehavior,
+    eof: EofBehavior,
+    flusher: Flusher,
+}
+
+/// One compiled instruction, or a whole loop.
+type Op<'a, R, W, C> = Box<dyn Fn(&mut Machine<'a, R, W, C>) -> Result<(), RuntimeError> + 'a>;
+
+/// Compiles instructions into closures and executes them on cells of type `C`.
+pub struct ClosureCompiler<'a, R, W, C = u8> {
+    instructions: &'a [Instruction],
+    ops: Vec<Op<'a, R, W, C>>,
+    machine: Machine<'a, R, W, C>,
+}
+
+impl<'a, R, W> ClosureCompiler<'a, R, W>
+where
+    R: Read + 'a,
+    W: Write + 'a,
+{
+    /// Compiles `instructions` that read input from `reader` and write output to `writer`.
+    pub fn new(instructions: &'a [Instruction], reader: &'a mut R, writer: &'a mut W) -> Self {
+        Self {
+            instructions,
+            ops: compile(instructions, 0),
+            machine: Machine {
+                data: Tape::new(DEFAULT_TAPE_SIZE, TapePolicy::Fixed),
+                dp: 0,
+                reader,
+                input: InputSelector::default(),
+                writer,
+                flush: FlushBehavior::Disabled,
+                eof: EofBehavior::default(),
+                flusher: Flusher::default(),
+            },
+        }
+    }
+}
+
+impl<'a, R, W, C> ClosureCompiler<'a, R, W, C>
+where
+    R: Read + 'a,
+    W: Write + 'a,
+    C: Cell,
+{
+    /// Compiles the instructions again for cells of type `C` instead of bytes, e.g.
+    /// `ClosureCompiler::new(instructions, reader, writer).with_cells::<u16>()`.
+    pub fn with_cells<D: Cell>(self) -> ClosureCompiler<'a, R, W, D> {
+        let machine = self.machine;
+        ClosureCompiler {
+            instructions: self.instructions,
+            ops: compile(self.instructions, 0),
+            machine: Machine {
+                data: Tape::new(machine.data.len(), machine.data.policy()),
+                dp: machine.dp,
+                reader: machine.reader,
+                input: machine.input,
+                writer: machine.writer,
+                flush: machine.flush,
+                eof: machine.eof,
+                flusher: machine.flusher,
+            },
+        }
+    }
+
+    /// Sets the number of cells of the tape, which is [DEFAULT_TAPE_SIZE] by default.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.machine.data = Tape::new(tape_size, self.machine.data.policy());
+        self
+    }
+
+    /// Sets what happens when the data pointer moves past the end of the tape.
+    pub fn with_tape_policy(mut self, policy: TapePolicy) -> Self {
+        self.machine.data = Tape::new(self.machine.data.len(), policy);
+        self
+    }
+
+    /// Lets the program read the host inputs once it selected them with `%`, see the `env`
+    /// [extension](crate::extensions).
+    pub fn with_host_inputs(mut self, inputs: &'a HostInputs) -> Self {
+        self.machine.input = InputSelector::new(inputs);
+        self
+    }
+
+    /// Returns the memory of the program.
+    pub fn data(&self) -> &[C] {
+        &self.machine.data
+    }
+
+    /// Returns the tape of the program, e.g. to compare it with the tape of another execution.
+    pub fn tape(&self) -> &Tape<C> {
+        &self.machine.data
+    }
+
+    /// Executes the compiled closures, handling reads past the end of the input according to
+    /// `eof`, and fails if the data pointer moves outside of the tape.
+    pub fn execute(&mut self, flush: FlushBehavior, eof: EofBehavior) -> Result<(), RuntimeError> {
+        let machine = &mut self.machine;
+        machine.flush = flush;
+        machine.eof = eof;
+        for op in &self.ops {
+            op(machine)?;
+        }
+        Ok(machine.flusher.finished(flush, machine.writer)?)
+    }
+}
+
+/// Compiles `instructions`, in which every `JumpZero` has its matching `JumpNotZero`, and whose
+/// first instruction is at `base` in the whole program.
+fn compile<'a, R, W, C>(instructions: &[Instruction], base: usize) -> Vec<Op<'a, R, W, C>>
+where
+    R: Read + 'a,
+    W: Write + 'a,
+    C: Cell,
+{
+    let mut ops: Vec<Op<'a, R, W, C>> = Vec::new();
+    let mut i = 0;
+
+    while i < instructions.len() {
+        let mut len = 1;
+        let ip = base + i;
+        let out_of_bounds = move |OutOfBounds(dp)| RuntimeError::PointerOutOfBounds { ip, dp };
+        let op: Op<'a, R, W, C> = match instructions[i] {
+            Instruction::IncDP(n) => Box::new(move |m| {
+                m.dp = m.data.offset(m.dp, n as isize).map_err(out_of_bounds)?;
+                Ok(())
+            }),
+            Instruction::DecDP(n) => Box::new(move |m| {
+                m.dp =
+                    m.dp.checked_sub(n)
+                        .ok_or(out_of_bounds(OutOfBounds(m.dp as isize - n as isize)))?;
+                Ok(())
+            }),
+            Instruction::IncByteAtDP(n) => {
+                let n = C::truncate(n as u64);
+                Box::new(move |m| {
+                    m.data[m.dp] = m.data[m.dp].wrapping_add(n);
+                    Ok(())
+                })
+            }
+            Instruction::DecByteAtDP(n) => {
+                let n = C::truncate(n as u64);
+                Box::new(move |m| {
+                    m.data[m.dp] = m.data[m.dp].wrapping_sub(n);
+                    Ok(())
+                })
+            }
+            Instruction::ReadByte => Box::new(|m| {
+                m.flusher.reading(m.flush, m.writer)?;
+                m.eof.read(m.input.reader(m.reader), &mut m.data[m.dp])?;
+                Ok(())
+            }),
+            Instruction::SetZero => Box::new(|m| {
+                m.data[m.dp] = C::ZERO;
+                Ok(())
+            }),
+            Instruction::FindZero(stride) => Box::new(move |m| {
+                m.dp = m.data.find_zero(m.dp, stride).map_err(out_of_bounds)?;
+                Ok(())
+            }),
+            Instruction::AddTo { offset, factor } => {
+                let factor = C::truncate(factor as u64);
+                Box::new(move |m| {
+                    let value = m.data[m.dp];
+                    if value != C::ZERO {
+                        let target = m.data.offset(m.dp, offset).map_err(out_of_bounds)?;
+                        m.data[target] = m.data[target].wrapping_add(value.wrapping_mul(factor));
+                    }
+                    Ok(())
+                })
+            }
+            Instruction::WriteByte(n) => Box::new(move |m| {
+                let byte = m.data[m.dp].low_byte();
+                for _ in 0..n {
+                    m.writer.write_all(&[byte])?;
+                }
+                Ok(m.flusher.written(m.flush, m.writer, n)?)
+            }),
+            Instruction::JumpZero(n) => {
+                // The body lies between the `JumpZero` and the `JumpNotZero` that precedes the
+                // target of the jump.
+                let body = compile(&instructions[i + 1..i + n - 1], ip + 1);
+                len = n;
+                Box::new(move |m| {
+                    while m.data[m.dp] != C::ZERO {
+                        for op in &body {
+                            op(m)?;
+                        }
+                    }
+                    Ok(())
+                })
+            }
+            Instruction::Exit => Box::new(|m| {
+                m.flusher.finished(m.flush, m.writer)?;
+                Err(RuntimeError::Exit(m.data[m.dp].to_u64() as u32))
+            }),
+            Instruction::SelectInput => Box::new(|m| m.input.select(m.data[m.dp].to_u64())),
+            instruction => unreachable!("unexpected instruction {instruction:?}"),
+        };
+        ops.push(op);
+        i += len;
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::ClosureCompiler;
+    use crate::compiler::Compiler;
+    use crate::virtual_machine::VirtualMachine;
+    use crate::{EofBehavior, FlushBehavior, RuntimeError};
+
+    #[test]
+    fn test_program_hello_world() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        let instructions = Compiler::new(include_str!("../programs/hello_world.b"))
+            .compile()
+            .unwrap();
+
+        ClosureCompiler::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(writer), Ok("Hello World!\n".into()));
+    }
+
+    #[test]
+    fn test_matches_virtual_machine() {
+        let programs = [
+            include_str!("../programs/bitwidth.b"),
+            ",[->+>+<<]>[-<+>]>[-<<+>>]<<.>.>.",
+            "+++[>++[>+++<-]<-]>>.>[]+[-[]]",
+        ];
+
+        for program in programs {
+            let instructions = Compiler::new(program).compile().unwrap();
+
+            let mut vm_writer = Vec::new();
+            let mut reader = Cursor::new([7]);
+            let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut vm_writer);
+            vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+            let vm_tape = vm.tape().clone();
+
+            let mut writer = Vec::new();
+            let mut reader = Cursor::new([7]);
+            let mut closures = ClosureCompiler::new(&instructions, &mut reader, &mut writer);
+            closures
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+            assert_eq!(closures.tape(), &vm_tape);
+
+            drop(closures);
+            assert_eq!(writer, vm_writer);
+        }
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new("+[[>+]]").compile().unwrap();
+
+        let err = ClosureCompiler::new(&instructions, &mut reader, &mut writer)
+            .with_tape_size(4)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::PointerOutOfBounds { ip: 3, dp: 4 }
+        ));
+    }
+}
@@ -1,125 +1,612 @@
-use crate::remove_non_idents;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::Deref;
+
+use crate::bytecode::{self, BytecodeError};
+use crate::passes::{self, OptLevel, Optimization};
 use crate::syntax::{
-    IDENTS, IDENT_DEC_DATA, IDENT_DEC_DP, IDENT_INC_DATA, IDENT_INC_DP, IDENT_JUMP_NOT_ZERO,
-    IDENT_JUMP_ZERO, IDENT_READ_BYTE, IDENT_WRITE_BYTE,
+    IDENT_DEC_DATA, IDENT_DEC_DP, IDENT_EXIT, IDENT_INC_DATA, IDENT_INC_DP, IDENT_JUMP_NOT_ZERO,
+    IDENT_JUMP_ZERO, IDENT_READ_BYTE, IDENT_SELECT_INPUT, IDENT_WRITE_BYTE,
 };
+use crate::tape::Cell;
+use crate::timing::{Phase, PhaseTimes, Stopwatch};
+use crate::tokenizer::{tokenize, Token, TokenKind};
 
 /// A compiler that turns a Brainfuck program into a list of instructions which can then be
 /// executed by the [virtual machine](crate::virtual_machine::VirtualMachine).
 pub struct Compiler {
     code: Vec<u8>,
+
+    /// Position of every character of `code` in the source.
+    positions: Vec<SourcePosition>,
+
+    /// The passes of the optimizer, in the order they run.
+    passes: Vec<Optimization>,
+
+    /// The first unmatched bracket of the program, returned by [compile](Compiler::compile).
+    error: Option<CompileError>,
+
+    /// The time spent compiling and optimizing so far.
+    times: PhaseTimes,
+}
+
+/// Describes a bracket without a matching one, which makes a program impossible to compile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    /// The unmatched bracket, `[` or `]`.
+    pub bracket: char,
+
+    /// Byte offset of the bracket in the source.
+    pub offset: usize,
+
+    /// Line of the bracket, starting at 1.
+    pub line: usize,
+
+    /// Column of the bracket in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unmatched `{}` at line {} column {}",
+            self.bracket, self.line, self.column
+        )
+    }
+}
+
+impl Error for CompileError {}
+
+/// Where the character an instruction was compiled from is in the source, see
+/// [Program::position].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SourcePosition {
+    /// Byte offset of the character in the source.
+    pub offset: usize,
+
+    /// Line of the character, starting at 1.
+    pub line: usize,
+
+    /// Column of the character in characters, starting at 1.
+    pub column: usize,
+}
+
+impl From<&Token<'_>> for SourcePosition {
+    fn from(token: &Token<'_>) -> Self {
+        Self {
+            offset: token.offset,
+            line: token.line,
+            column: token.column,
+        }
+    }
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} column {}", self.line, self.column)
+    }
+}
+
+/// The instructions returned by [Compiler::compile_with_positions], with the step cost and the
+/// source position of each.
+type Compiled = (Vec<Instruction>, Vec<StepCost>, Vec<SourcePosition>);
+
+/// Checks that every bracket of `source` has a matching one, returning the first `]` without
+/// a `[` before it, or else the last `[` without a `]` after it.
+pub fn check_brackets(source: &str) -> Result<(), CompileError> {
+    let error = |token: Token| CompileError {
+        bracket: token.text.chars().next().unwrap(),
+        offset: token.offset,
+        line: token.line,
+        column: token.column,
+    };
+    let mut open = Vec::new();
+
+    for token in tokenize(source) {
+        match (token.kind, token.text) {
+            (TokenKind::Instruction, "[") => open.push(token),
+            (TokenKind::Instruction, "]") if open.pop().is_none() => return Err(error(token)),
+            _ => {}
+        }
+    }
+
+    match open.pop() {
+        Some(token) => Err(error(token)),
+        None => Ok(()),
+    }
 }
 
 impl Compiler {
     /// Create a new Compiler.
     pub fn new(code: &str) -> Self {
+        let tokens: Vec<Token> = tokenize(code)
+            .into_iter()
+            .filter(|token| token.kind == TokenKind::Instruction)
+            .collect();
         Self {
-            code: remove_non_idents(code),
+            code: tokens
+                .iter()
+                .map(|token| token.text.as_bytes()[0])
+                .collect(),
+            positions: tokens.iter().map(SourcePosition::from).collect(),
+            passes: OptLevel::default().passes().to_vec(),
+            error: check_brackets(code).err(),
+            times: PhaseTimes::default(),
         }
     }
 
-    /// Analyze the given program and return a list of instructions to execute.
-    pub fn compile(&mut self) -> Vec<Instruction> {
-        let mut instructions = Vec::new();
-        let mut i = 0;
+    /// Disable all optimizations, so every character of the program becomes exactly one
+    /// instruction and the instructions execute in the same steps as the source.
+    pub fn without_optimizations(self) -> Self {
+        self.with_opt_level(OptLevel::O0)
+    }
 
-        while i < self.code.len() {
-            let prev_i = i;
+    /// Run the passes of the given level, [O2](OptLevel::O2) by default.
+    pub fn with_opt_level(self, level: OptLevel) -> Self {
+        self.with_passes(level.passes())
+    }
 
-            for ident in IDENTS.iter() {
-                self.push_instruction(&mut i, *ident, &mut instructions);
-            }
+    /// Run the given passes of the optimizer in order instead of the ones of a level.
+    pub fn with_passes(mut self, passes: &[Optimization]) -> Self {
+        self.passes = passes.to_vec();
+        self
+    }
 
-            if prev_i == i {
-                i += 1;
-            }
+    /// Analyze the given program and return a list of instructions to execute, or the
+    /// position of a bracket without a matching one.
+    pub fn compile(&mut self) -> Result<Vec<Instruction>, CompileError> {
+        Ok(self.compile_with_step_costs()?.0)
+    }
+
+    /// Like [compile](Compiler::compile), but returns an owned [Program] that knows its
+    /// structure, the [position](Program::position) of every instruction in the source and its
+    /// [step cost](Program::step_costs).
+    pub fn compile_program(&mut self) -> Result<Program, CompileError> {
+        let (instructions, step_costs, positions) = self.compile_with_positions()?;
+        Ok(Program::new(instructions)
+            .with_positions(positions)
+            .with_step_costs(step_costs))
+    }
+
+    /// Like [compile](Compiler::compile), but also returns how many steps of the unoptimized
+    /// program each instruction stands for.
+    pub fn compile_with_step_costs(
+        &mut self,
+    ) -> Result<(Vec<Instruction>, Vec<StepCost>), CompileError> {
+        let (instructions, costs, _) = self.compile_with_positions()?;
+        Ok((instructions, costs))
+    }
+
+    /// Compiles the program, returning the step cost and the source position of every
+    /// instruction.
+    fn compile_with_positions(&mut self) -> Result<Compiled, CompileError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
         }
 
-        i = 0;
-        while i < instructions.len() {
-            if instructions[i] == Instruction::JumpZeroPlaceholder {
-                let mut jumps = 0;
-                let mut j = i;
-                loop {
-                    if j == instructions.len() {
-                        break;
-                    }
-                    match instructions[j] {
-                        Instruction::JumpZeroPlaceholder => jumps += 1,
-                        Instruction::JumpNotZeroPlaceholder => jumps -= 1,
-                        _ => {}
-                    };
-                    if jumps == 0 {
-                        break;
-                    }
-                    j += 1;
+        let mut stopwatch = Stopwatch::start();
+        let mut lowered = passes::unoptimized(&self.parse());
+
+        if !self.passes.is_empty() {
+            self.times.add(Phase::Compile, stopwatch.elapsed());
+            lowered = self
+                .times
+                .measure(Phase::Optimize, || passes::apply(&self.passes, lowered));
+            stopwatch = Stopwatch::start();
+        }
+
+        let instructions = passes::instructions(&lowered);
+        let costs = lowered.iter().map(|lowered| lowered.cost).collect();
+        let positions = self.positions_of(&lowered);
+        self.times.add(Phase::Compile, stopwatch.elapsed());
+        Ok((instructions, costs, positions))
+    }
+
+    /// Returns the program after each pass of the optimizer in order, named like
+    /// `("fold-runs", program)`, so changes of the optimizer can be reviewed pass by pass.
+    ///
+    /// Without optimizations, only the unoptimized program is returned, named `none`.
+    #[cfg_attr(not(feature = "internals"), doc(hidden))]
+    pub fn compile_passes(&mut self) -> Result<Vec<(&'static str, Program)>, CompileError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let mut lowered = passes::unoptimized(&self.parse());
+        let mut programs = Vec::new();
+        if self.passes.is_empty() {
+            programs.push((Pass::Unoptimized.name(), lowered.clone()));
+        }
+        for pass in &self.passes {
+            lowered = pass.run(lowered);
+            programs.push((pass.name(), lowered.clone()));
+        }
+
+        Ok(programs
+            .into_iter()
+            .map(|(name, lowered)| {
+                let program = Program::new(passes::instructions(&lowered))
+                    .with_step_costs(lowered.iter().map(|lowered| lowered.cost).collect());
+                (name, program.with_positions(self.positions_of(&lowered)))
+            })
+            .collect())
+    }
+
+    /// Returns the position of every lowered instruction in the source, where a lowered loop
+    /// starts at its `[`.
+    fn positions_of(&self, lowered: &[passes::Lowered]) -> Vec<SourcePosition> {
+        lowered
+            .iter()
+            .map(|lowered| self.positions[lowered.original.start])
+            .collect()
+    }
+
+    /// Returns the time spent in the [Compile](Phase::Compile) and [Optimize](Phase::Optimize)
+    /// phases of all compilations so far.
+    pub fn times(&self) -> PhaseTimes {
+        self.times
+    }
+
+    /// Turns the code into instructions with placeholders for the jumps, one per character.
+    pub(crate) fn parse(&self) -> Vec<Instruction> {
+        self.code
+            .iter()
+            .map(|ident| instruction(*ident, 1))
+            .collect()
+    }
+}
+
+/// Replaces the jump placeholders of `instructions` with jumps to the instruction after the
+/// matching one.
+pub(crate) fn resolve_jumps(instructions: &mut [Instruction]) {
+    let mut i = 0;
+    while i < instructions.len() {
+        if instructions[i] == Instruction::JumpZeroPlaceholder {
+            let mut jumps = 0;
+            let mut j = i;
+            loop {
+                if j == instructions.len() {
+                    break;
+                }
+                match instructions[j] {
+                    Instruction::JumpZeroPlaceholder => jumps += 1,
+                    Instruction::JumpNotZeroPlaceholder => jumps -= 1,
+                    _ => {}
+                };
+                if jumps == 0 {
+                    break;
                 }
-                // Jump target is the instruction after the matching backward jump.
-                instructions[i] = Instruction::JumpZero(j - i + 1);
+                j += 1;
             }
-            i += 1;
+            // Jump target is the instruction after the matching backward jump.
+            instructions[i] = Instruction::JumpZero(j - i + 1);
         }
+        i += 1;
+    }
+
+    i = 0;
+    while i < instructions.len() {
+        if let Instruction::JumpZero(offset) = instructions[i] {
+            // Jump target is the instruction after the matching backward jump.
+            let target = i + offset;
+            let matching_jump = target - 1;
+            assert_eq!(
+                instructions[matching_jump],
+                Instruction::JumpNotZeroPlaceholder
+            );
+            // Jump target is the instruction after the matching forward jump.
+            instructions[matching_jump] = Instruction::JumpNotZero(matching_jump - i - 1);
+        }
+        i += 1;
+    }
+}
+
+/// The compiled instructions of a program, which dereference to a slice so they can be passed
+/// to every engine, e.g. `VirtualMachine::new(&program, reader, writer)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+
+    /// The greatest number of loops nested in each other.
+    max_loop_depth: usize,
+
+    /// Position of every instruction in the source, empty if it is not known.
+    positions: Vec<SourcePosition>,
+
+    /// How many steps of the unoptimized program every instruction stands for.
+    step_costs: Vec<StepCost>,
+}
 
-        i = 0;
-        while i < instructions.len() {
-            if let Instruction::JumpZero(offset) = instructions[i] {
-                // Jump target is the instruction after the matching backward jump.
-                let target = i + offset;
-                let matching_jump = target - 1;
-                assert_eq!(
-                    instructions[matching_jump],
-                    Instruction::JumpNotZeroPlaceholder
-                );
-                // Jump target is the instruction after the matching forward jump.
-                instructions[matching_jump] = Instruction::JumpNotZero(matching_jump - i - 1);
+impl Program {
+    /// Creates a program from `instructions` as returned by [Compiler::compile].
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        let mut depth = 0usize;
+        let mut max_loop_depth = 0;
+        for instruction in &instructions {
+            match instruction {
+                Instruction::JumpZero(_) => {
+                    depth += 1;
+                    max_loop_depth = max_loop_depth.max(depth);
+                }
+                Instruction::JumpNotZero(_) => depth = depth.saturating_sub(1),
+                _ => {}
             }
-            i += 1;
         }
 
-        instructions
+        Self {
+            step_costs: instructions.iter().map(StepCost::of).collect(),
+            instructions,
+            max_loop_depth,
+            positions: Vec::new(),
+        }
     }
 
-    fn push_instruction(
-        &self,
-        i: &mut usize,
-        instruction: u8,
-        instructions: &mut Vec<Instruction>,
-    ) {
-        let mut args = 0;
+    /// Sets the [StepCost] of every instruction, in the order of the instructions. Without
+    /// them, a lowered loop counts as a single step.
+    pub fn with_step_costs(mut self, step_costs: Vec<StepCost>) -> Self {
+        assert_eq!(step_costs.len(), self.instructions.len());
+        self.step_costs = step_costs;
+        self
+    }
 
-        while *i < self.code.len() {
-            if self.code[*i] != instruction && !IDENTS.contains(&self.code[*i]) {
-                // Ignore unknown identifiers.
-                *i += 1;
-                continue;
-            } else if self.code[*i] != instruction && IDENTS.contains(&self.code[*i]) {
-                // We reached a valid instruction but it differs from the one we are processing in
-                // this call.
-                break;
+    /// Sets the position in the source of every instruction, in the order of the instructions.
+    pub fn with_positions(mut self, positions: Vec<SourcePosition>) -> Self {
+        assert_eq!(positions.len(), self.instructions.len());
+        self.positions = positions;
+        self
+    }
+
+    /// Returns where the instruction at `ip` was compiled from, which is the first character
+    /// of folded instructions and the `[` of lowered loops, or `None` if the program was not
+    /// compiled from source, like [bytecode](Program::from_bytes).
+    pub fn position(&self, ip: usize) -> Option<SourcePosition> {
+        self.positions.get(ip).copied()
+    }
+
+    /// Returns the position in the source of every instruction, which is empty if they are not
+    /// known.
+    pub fn positions(&self) -> &[SourcePosition] {
+        &self.positions
+    }
+
+    /// Returns how many steps of the unoptimized program every instruction stands for, e.g. to
+    /// limit the steps with [ExecOptions](crate::engine::ExecOptions::with_max_steps).
+    pub fn step_costs(&self) -> &[StepCost] {
+        &self.step_costs
+    }
+
+    /// Returns the number of instructions.
+    pub fn instruction_count(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Returns the greatest number of loops nested in each other, `0` for a program without
+    /// loops.
+    pub fn max_loop_depth(&self) -> usize {
+        self.max_loop_depth
+    }
+
+    /// Returns the instructions, giving up the metadata and the positions.
+    pub fn into_instructions(self) -> Vec<Instruction> {
+        self.instructions
+    }
+
+    /// Returns the instructions one per line with their index, the bodies of loops indented
+    /// and the jumps followed by the index they jump to, e.g. `     3  JumpZero(4) -> 7`.
+    pub fn disassemble(&self) -> String {
+        let mut text = String::new();
+        let mut depth = 0;
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            if let Instruction::JumpNotZero(_) = instruction {
+                depth -= 1;
             }
+            let indent = "  ".repeat(depth);
+            let line = match instruction {
+                Instruction::JumpZero(n) => format!("{i:>6}  {indent}{instruction:?} -> {}", i + n),
+                Instruction::JumpNotZero(n) => {
+                    format!("{i:>6}  {indent}{instruction:?} -> {}", i - n)
+                }
+                _ => format!("{i:>6}  {indent}{instruction:?}"),
+            };
+            text.push_str(&line);
+            text.push('\n');
+            if let Instruction::JumpZero(_) = instruction {
+                depth += 1;
+            }
+        }
+        text
+    }
+
+    /// Serializes the program into the compact [bytecode] format, so it can be cached on disk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bytecode::encode(&self.instructions)
+    }
 
-            args += 1;
-            *i += 1;
+    /// Deserializes a program written by [to_bytes](Program::to_bytes), checking that its
+    /// instructions are well formed. The bytecode does not contain the positions in the source
+    /// or the step costs.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        Ok(Self::new(bytecode::decode(bytes)?))
+    }
+}
 
-            // Jump instructions can not be folded.
-            match instruction {
-                IDENT_JUMP_ZERO | IDENT_JUMP_NOT_ZERO => break,
+impl Deref for Program {
+    type Target = [Instruction];
+
+    fn deref(&self) -> &[Instruction] {
+        &self.instructions
+    }
+}
+
+/// Returns the instruction for `count` repetitions of the character `ident`.
+pub(crate) fn instruction(ident: u8, count: usize) -> Instruction {
+    match ident {
+        IDENT_INC_DP => Instruction::IncDP(count),
+        IDENT_DEC_DP => Instruction::DecDP(count),
+        IDENT_INC_DATA => Instruction::IncByteAtDP(count),
+        IDENT_DEC_DATA => Instruction::DecByteAtDP(count),
+        IDENT_WRITE_BYTE => Instruction::WriteByte(count),
+        IDENT_READ_BYTE => Instruction::ReadByte,
+        IDENT_JUMP_ZERO => Instruction::JumpZeroPlaceholder,
+        IDENT_JUMP_NOT_ZERO => Instruction::JumpNotZeroPlaceholder,
+        IDENT_EXIT => Instruction::Exit,
+        IDENT_SELECT_INPUT => Instruction::SelectInput,
+        _ => unreachable!(),
+    }
+}
+
+/// Compiles a program that arrives in pieces, like the lines typed into the
+/// [REPL](crate::repl::Repl), where a loop can be opened by one piece and closed by a later
+/// one.
+#[derive(Debug, Default)]
+pub struct IncrementalCompiler {
+    /// Pieces of the loops that are still open.
+    pending: String,
+
+    /// Number of loops that are still open.
+    depth: usize,
+}
+
+/// The result of [IncrementalCompiler::feed].
+#[derive(Debug, PartialEq)]
+pub enum Feed {
+    /// All loops are closed, the instructions of the pieces since the last complete one can
+    /// be executed.
+    Complete(Vec<Instruction>),
+
+    /// The given number of loops is still open, more pieces are needed.
+    Incomplete { depth: usize },
+
+    /// The piece closes a loop that was never opened, it has been discarded.
+    Unbalanced,
+}
+
+impl IncrementalCompiler {
+    /// Adds the next piece of the program.
+    pub fn feed(&mut self, piece: &str) -> Feed {
+        let mut depth = self.depth;
+        for token in tokenize(piece) {
+            match (token.kind, token.text) {
+                (TokenKind::Instruction, "[") => depth += 1,
+                (TokenKind::Instruction, "]") if depth == 0 => return Feed::Unbalanced,
+                (TokenKind::Instruction, "]") => depth -= 1,
                 _ => {}
             }
         }
 
-        if args > 0 {
-            instructions.push(match instruction {
-                IDENT_INC_DP => Instruction::IncDP(args),
-                IDENT_DEC_DP => Instruction::DecDP(args),
-                IDENT_INC_DATA => Instruction::IncByteAtDP(args),
-                IDENT_DEC_DATA => Instruction::DecByteAtDP(args),
-                IDENT_WRITE_BYTE => Instruction::WriteByte(args),
-                IDENT_READ_BYTE => Instruction::ReadByte,
-                IDENT_JUMP_ZERO => Instruction::JumpZeroPlaceholder,
-                IDENT_JUMP_NOT_ZERO => Instruction::JumpNotZeroPlaceholder,
-                _ => unreachable!(),
-            });
+        self.pending.push_str(piece);
+        // Directives end at the end of the line, so they must not swallow the next piece.
+        self.pending.push('\n');
+        self.depth = depth;
+        if depth > 0 {
+            return Feed::Incomplete { depth };
+        }
+
+        let source = std::mem::take(&mut self.pending);
+        Feed::Complete(
+            Compiler::new(&source)
+                .compile()
+                .expect("the brackets of the pieces are balanced"),
+        )
+    }
+
+    /// Returns the number of loops that are still open.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Discards the pieces of the loops that are still open.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.depth = 0;
+    }
+}
+
+/// How many steps of the unoptimized program an [Instruction] stands for, e.g. to count the
+/// executed steps the same way regardless of the optimizations.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StepCost {
+    /// A fixed number of steps, like 3 for `IncByteAtDP(3)`. Folded reads are a single step,
+    /// as only one byte is read.
+    Fixed(u64),
+
+    /// A loop lowered to `SetZero`, with `body` steps per iteration. It iterates as often as
+    /// the byte at the data pointer is decremented, or incremented if `increments`, to reach
+    /// zero.
+    Loop { body: u64, increments: bool },
+
+    /// A scan loop lowered to `FindZero`, which moves the data pointer by `stride` cells per
+    /// iteration.
+    Scan { stride: u64 },
+}
+
+impl StepCost {
+    /// Returns the cost of an instruction that is not a lowered loop.
+    pub(crate) fn of(instruction: &Instruction) -> Self {
+        match *instruction {
+            Instruction::IncDP(n)
+            | Instruction::DecDP(n)
+            | Instruction::IncByteAtDP(n)
+            | Instruction::DecByteAtDP(n)
+            | Instruction::WriteByte(n) => StepCost::Fixed(n as u64),
+            Instruction::AddTo { .. } => StepCost::Fixed(0),
+            _ => StepCost::Fixed(1),
+        }
+    }
+
+    /// Returns the number of steps of the instruction, where `value` is the cell at the data
+    /// pointer before it is executed and `moved` is how far it moved the data pointer.
+    pub fn steps<C: Cell>(self, value: C, moved: usize) -> u64 {
+        // Entering the loop is a step, every iteration executes the body and the jump back.
+        let loop_steps = |iterations: u64, body: u64| 1 + iterations * (body + 1);
+        match self {
+            StepCost::Fixed(steps) => steps,
+            StepCost::Loop {
+                body,
+                increments: false,
+            } => loop_steps(value.to_u64(), body),
+            StepCost::Loop {
+                body,
+                increments: true,
+            } => loop_steps(value.wrapping_neg().to_u64(), body),
+            StepCost::Scan { stride } => loop_steps(moved as u64 / stride, stride),
+        }
+    }
+}
+
+/// The optimization of the [Compiler] that created an instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pass {
+    /// No optimization, the instruction stands for a single character of the source.
+    Unoptimized,
+
+    /// Repetitions of a character folded into one instruction, like `+++` or `,,`.
+    Fold,
+
+    /// A loop lowered to `SetZero`, like `[-]`.
+    Clear,
+
+    /// A loop lowered to `FindZero`, like `[>]`.
+    Scan,
+
+    /// A loop lowered to `AddTo` and `SetZero`, like `[->++<]`.
+    Multiply,
+
+    /// Moves or changes of the cell fused into their net effect, like `>><` or `++-`.
+    Fusion,
+}
+
+impl Pass {
+    /// Returns the name used in explanations.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Unoptimized => "none",
+            Pass::Fold => "fold",
+            Pass::Clear => "clear",
+            Pass::Scan => "scan",
+            Pass::Multiply => "multiply",
+            Pass::Fusion => "fusion",
         }
     }
 }
@@ -147,6 +634,22 @@ pub enum Instruction {
     /// Read a byte from the reader into the byte at the data pointer.
     ReadByte,
 
+    /// Set the byte at the data pointer to zero, compiled from the loops `[-]` and `[+]`, and
+    /// at the end of the loops lowered to `AddTo`.
+    SetZero,
+
+    /// Move the data pointer by the given stride until the byte at the data pointer is zero,
+    /// compiled from scan loops like `[>]` or `[<<]`.
+    FindZero(isize),
+
+    /// Add the byte at the data pointer multiplied by `factor` to the byte at `offset` from
+    /// the data pointer, compiled from loops like `[->+<]` or `[->++>+++<<]` together with a
+    /// `SetZero`. Nothing is accessed if the byte at the data pointer is zero.
+    ///
+    /// The factor is wrapped around to the width of the cells when it is applied, e.g. `-1`
+    /// subtracts the byte.
+    AddTo { offset: isize, factor: i32 },
+
     /// If the byte at the data pointer is zero, jump to the instruction after the matching
     /// `JumpNotZero` instruction.
     JumpZero(usize),
@@ -160,21 +663,87 @@ pub enum Instruction {
 
     /// Used to determine the relative offset to `JumpZero` in the compilation step.
     JumpNotZeroPlaceholder,
+
+    /// Stop the program with the value of the cell at the data pointer as exit code, compiled
+    /// from `!` once the program enabled the `exit` [extension](crate::extensions).
+    Exit,
+
+    /// Select the input that `ReadByte` reads from by the value of the cell at the data
+    /// pointer, 0 for the reader and otherwise one of the [HostInputs](crate::host::HostInputs),
+    /// compiled from `%` once the program enabled the `env` [extension](crate::extensions).
+    SelectInput,
+}
+
+impl Instruction {
+    /// The names of the kinds of instructions, in the order of their
+    /// [opcodes](Instruction::opcode).
+    pub const NAMES: [&'static str; 15] = [
+        "IncDP",
+        "DecDP",
+        "IncByteAtDP",
+        "DecByteAtDP",
+        "WriteByte",
+        "ReadByte",
+        "SetZero",
+        "FindZero",
+        "AddTo",
+        "JumpZero",
+        "JumpZeroPlaceholder",
+        "JumpNotZero",
+        "JumpNotZeroPlaceholder",
+        "Exit",
+        "SelectInput",
+    ];
+
+    /// Returns the index of the kind of the instruction in [NAMES](Instruction::NAMES), e.g. to
+    /// count the instructions of every kind.
+    pub fn opcode(&self) -> usize {
+        match self {
+            Instruction::IncDP(_) => 0,
+            Instruction::DecDP(_) => 1,
+            Instruction::IncByteAtDP(_) => 2,
+            Instruction::DecByteAtDP(_) => 3,
+            Instruction::WriteByte(_) => 4,
+            Instruction::ReadByte => 5,
+            Instruction::SetZero => 6,
+            Instruction::FindZero(_) => 7,
+            Instruction::AddTo { .. } => 8,
+            Instruction::JumpZero(_) => 9,
+            Instruction::JumpZeroPlaceholder => 10,
+            Instruction::JumpNotZero(_) => 11,
+            Instruction::JumpNotZeroPlaceholder => 12,
+            Instruction::Exit => 13,
+            Instruction::SelectInput => 14,
+        }
+    }
+
+    /// Returns the name of the kind of the instruction, without its arguments, e.g. `AddTo`.
+    pub fn name(&self) -> &'static str {
+        Self::NAMES[self.opcode()]
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Compiler, Instruction};
+    use std::path::Path;
+    use std::time::Duration;
+    use std::{env, fs, io};
+
+    use super::{CompileError, Compiler, Feed, IncrementalCompiler, Instruction, Program};
+    use crate::source_map;
+    use crate::timing::{Phase, PhaseTime};
+    use crate::virtual_machine::VirtualMachine;
+    use crate::{EofBehavior, FlushBehavior};
 
     #[test]
-    fn test_remove_repeating_reads() {
-        let instructions = Compiler::new(",,,,,.,,,.,").compile();
+    fn test_keep_repeating_reads() {
+        let instructions = Compiler::new(",,,.,").compile().unwrap();
 
         assert_eq!(
             instructions,
             vec![
                 Instruction::ReadByte,
-                Instruction::WriteByte(1),
+                Instruction::ReadByte,
                 Instruction::ReadByte,
                 Instruction::WriteByte(1),
                 Instruction::ReadByte
@@ -183,75 +752,339 @@ mod tests {
     }
 
     #[test]
-    fn test_program_hello_world() {
-        let instructions = Compiler::new(include_str!("../programs/hello_world.b")).compile();
+    fn test_fold_clear_loops() {
+        let instructions = Compiler::new("+[-]>[+]<[--]").compile().unwrap();
 
         assert_eq!(
             instructions,
             vec![
                 Instruction::IncByteAtDP(1),
-                Instruction::JumpZero(31),
+                Instruction::SetZero,
                 Instruction::IncDP(1),
-                Instruction::JumpZero(26),
+                Instruction::SetZero,
                 Instruction::DecDP(1),
-                Instruction::DecByteAtDP(1),
-                Instruction::JumpZero(2),
-                Instruction::JumpNotZero(0),
+                Instruction::JumpZero(3),
+                Instruction::DecByteAtDP(2),
+                Instruction::JumpNotZero(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lower_multiply_loops() {
+        let instructions = Compiler::new("[->+<]>[>+++>-<<-]>>[+<--<++>>]")
+            .compile()
+            .unwrap();
+
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::AddTo {
+                    offset: 1,
+                    factor: 1
+                },
+                Instruction::SetZero,
                 Instruction::IncDP(1),
+                Instruction::AddTo {
+                    offset: 1,
+                    factor: 3
+                },
+                Instruction::AddTo {
+                    offset: 2,
+                    factor: -1
+                },
+                Instruction::SetZero,
+                Instruction::IncDP(2),
+                Instruction::AddTo {
+                    offset: -1,
+                    factor: 2
+                },
+                Instruction::AddTo {
+                    offset: -2,
+                    factor: -2
+                },
+                Instruction::SetZero,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_program() {
+        let program = Compiler::new("++[>+[.-]<-]").compile_program().unwrap();
+
+        assert_eq!(program.instruction_count(), 11);
+        assert_eq!(program.max_loop_depth(), 2);
+        assert_eq!(program.clone(), program);
+        // The bytecode does not keep the positions in the source.
+        assert_eq!(
+            Program::from_bytes(&program.to_bytes()),
+            Ok(Program::new(program.to_vec()))
+        );
+        assert_eq!(
+            Compiler::new("+>[-]")
+                .compile_program()
+                .unwrap()
+                .max_loop_depth(),
+            0
+        );
+
+        // Engines take the program in place of the instructions.
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        VirtualMachine::new(&program, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+        assert_eq!(writer, [1, 1]);
+    }
+
+    #[test]
+    fn test_positions() {
+        let source = "+++ comment\n  [->+<]é>>,\n[-]";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let positions: Vec<_> = program
+            .positions()
+            .iter()
+            .map(|position| (position.offset, position.line, position.column))
+            .collect();
+
+        // Folded instructions and lowered loops start at their first character, columns count
+        // characters instead of bytes.
+        assert_eq!(
+            positions,
+            [
+                (0, 1, 1),
+                (14, 2, 3),
+                (14, 2, 3),
+                (22, 2, 10),
+                (24, 2, 12),
+                (26, 3, 1)
+            ]
+        );
+        assert_eq!(program.position(3).unwrap().to_string(), "line 2 column 10");
+        assert_eq!(program.position(6), None);
+        let spans = source_map::instruction_spans(source);
+        assert!(program
+            .positions()
+            .iter()
+            .zip(&spans)
+            .all(|(position, span)| (position.line, position.column) == (span.line, span.column)));
+
+        let unoptimized = Compiler::new(source)
+            .without_optimizations()
+            .compile_program()
+            .unwrap();
+        assert_eq!(unoptimized.positions().len(), 15);
+        assert_eq!(unoptimized.position(2).unwrap().offset, 2);
+        assert_eq!(
+            Compiler::new("+[-]>").compile_passes().unwrap()[1]
+                .1
+                .positions()
+                .len(),
+            3
+        );
+        assert!(Program::new(program.to_vec()).positions().is_empty());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let program = Compiler::new(",[>+[.-]<-]").compile_program().unwrap();
+
+        assert_eq!(
+            program.disassemble(),
+            "     0  ReadByte\n\
+             \x20    1  JumpZero(10) -> 11\n\
+             \x20    2    IncDP(1)\n\
+             \x20    3    IncByteAtDP(1)\n\
+             \x20    4    JumpZero(4) -> 8\n\
+             \x20    5      WriteByte(1)\n\
+             \x20    6      DecByteAtDP(1)\n\
+             \x20    7    JumpNotZero(2) -> 5\n\
+             \x20    8    DecDP(1)\n\
+             \x20    9    DecByteAtDP(1)\n\
+             \x20   10  JumpNotZero(8) -> 2\n"
+        );
+    }
+
+    #[test]
+    fn test_lower_scan_loops() {
+        let instructions = Compiler::new(&format!("[>]<[<<]>[{}]", ">".repeat(128)))
+            .compile()
+            .unwrap();
+
+        assert_eq!(instructions[0], Instruction::FindZero(1));
+        assert_eq!(instructions[2], Instruction::FindZero(-2));
+        // Strides that do not fit into a byte are kept as loops.
+        assert_eq!(instructions[4], Instruction::JumpZero(3));
+    }
+
+    #[test]
+    fn test_keep_unbalanced_loops() {
+        let instructions = Compiler::new("[->+]").compile().unwrap();
+
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(instructions[0], Instruction::JumpZero(5));
+    }
+
+    #[test]
+    fn test_without_optimizations() {
+        let instructions = Compiler::new("++[-],,")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::IncByteAtDP(1),
                 Instruction::IncByteAtDP(1),
-                Instruction::JumpZero(18),
-                Instruction::IncDP(1),
-                Instruction::IncByteAtDP(3),
-                Instruction::IncDP(1),
-                Instruction::JumpZero(4),
-                Instruction::IncByteAtDP(11),
-                Instruction::IncDP(1),
-                Instruction::JumpNotZero(2),
                 Instruction::JumpZero(3),
-                Instruction::IncDP(1),
-                Instruction::JumpNotZero(1),
                 Instruction::DecByteAtDP(1),
-                Instruction::JumpZero(3),
-                Instruction::DecDP(1),
                 Instruction::JumpNotZero(1),
-                Instruction::IncDP(1),
-                Instruction::DecByteAtDP(1),
-                Instruction::JumpNotZero(16),
-                Instruction::JumpNotZero(24),
-                Instruction::IncByteAtDP(10),
-                Instruction::DecDP(1),
-                Instruction::JumpNotZero(29),
-                Instruction::IncDP(6),
-                Instruction::DecByteAtDP(4),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(2),
-                Instruction::IncByteAtDP(3),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(1),
-                Instruction::DecByteAtDP(1),
-                Instruction::WriteByte(2),
-                Instruction::IncByteAtDP(3),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(1),
-                Instruction::DecByteAtDP(1),
-                Instruction::WriteByte(1),
-                Instruction::IncDP(3),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(2),
-                Instruction::WriteByte(1),
-                Instruction::IncByteAtDP(3),
-                Instruction::WriteByte(1),
-                Instruction::DecByteAtDP(6),
-                Instruction::WriteByte(1),
-                Instruction::IncDP(1),
-                Instruction::DecByteAtDP(1),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(2),
-                Instruction::IncByteAtDP(1),
-                Instruction::WriteByte(1),
-                Instruction::DecDP(1),
-                Instruction::WriteByte(1)
+                Instruction::ReadByte,
+                Instruction::ReadByte,
             ]
         );
     }
+
+    #[test]
+    fn test_times() {
+        let mut compiler = Compiler::new("++[-]>[->+<]");
+        compiler.compile().unwrap();
+        let times = compiler.times();
+        assert!(times.get(Phase::Compile).wall > Duration::ZERO);
+        assert!(times.get(Phase::Optimize).wall > Duration::ZERO);
+        assert_eq!(times.get(Phase::Execute), PhaseTime::default());
+
+        let mut compiler = Compiler::new("++[-]").without_optimizations();
+        compiler.compile().unwrap();
+        assert!(compiler.times().get(Phase::Compile).wall > Duration::ZERO);
+        assert_eq!(compiler.times().get(Phase::Optimize), PhaseTime::default());
+    }
+
+    #[test]
+    fn test_unbalanced_brackets() {
+        assert_eq!(
+            Compiler::new("+[>\n;; expect \"]\"\n<-]]").compile(),
+            Err(CompileError {
+                bracket: ']',
+                offset: 21,
+                line: 3,
+                column: 4,
+            })
+        );
+        assert_eq!(
+            Compiler::new("ä[[-]").without_optimizations().compile(),
+            Err(CompileError {
+                bracket: '[',
+                offset: 2,
+                line: 1,
+                column: 2,
+            })
+        );
+        assert_eq!(
+            Compiler::new("[[]").compile().unwrap_err().to_string(),
+            "unmatched `[` at line 1 column 1"
+        );
+    }
+
+    #[test]
+    fn test_incremental_compiler() {
+        let mut compiler = IncrementalCompiler::default();
+
+        assert_eq!(compiler.feed("+++[>++"), Feed::Incomplete { depth: 1 });
+        assert_eq!(
+            compiler.feed(";; expect \"[ in a directive\""),
+            Feed::Incomplete { depth: 1 }
+        );
+        assert_eq!(compiler.feed("[-]<-]]"), Feed::Unbalanced);
+        assert_eq!(
+            compiler.feed("<-] > ."),
+            Feed::Complete(Compiler::new("+++[>++<-]>.").compile().unwrap())
+        );
+        assert_eq!(compiler.feed("]"), Feed::Unbalanced);
+
+        assert_eq!(compiler.feed("[[["), Feed::Incomplete { depth: 3 });
+        compiler.reset();
+        assert_eq!(compiler.depth(), 0);
+        assert_eq!(
+            compiler.feed("+"),
+            Feed::Complete(vec![Instruction::IncByteAtDP(1)])
+        );
+    }
+
+    /// Programs whose instructions after every pass are compared with the snapshots in
+    /// `tests/snapshots`, which `UPDATE_SNAPSHOTS=1 cargo test` writes instead.
+    const SNAPSHOTS: &[(&str, &str)] = &[
+        ("hello_world", include_str!("../programs/hello_world.b")),
+        ("bitwidth", include_str!("../programs/bitwidth.b")),
+        (
+            "loops",
+            ",[>+<-]>[-]+[>>]<[<]>>[->++>+++<<]<<[[-]>]++++[>,.<-]",
+        ),
+    ];
+
+    #[test]
+    fn test_pass_snapshots() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+        let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+        for (name, source) in SNAPSHOTS {
+            let mut snapshot = String::new();
+            for (pass, program) in Compiler::new(source).compile_passes().unwrap() {
+                if !snapshot.is_empty() {
+                    snapshot.push('\n');
+                }
+                snapshot.push_str(&format!("; {pass}\n{}", program.disassemble()));
+            }
+
+            let path = dir.join(format!("{name}.ir"));
+            if update {
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(&path, &snapshot).unwrap();
+                continue;
+            }
+            let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+                panic!(
+                    "failed to read {}: {err}, run `UPDATE_SNAPSHOTS=1 cargo test` to write it",
+                    path.display()
+                )
+            });
+            let line = expected
+                .lines()
+                .zip(snapshot.lines())
+                .take_while(|(expected, actual)| expected == actual)
+                .count()
+                + 1;
+            assert!(
+                expected == snapshot,
+                "the instructions of `{name}` differ from {} at line {line}, review the \
+                 difference after `UPDATE_SNAPSHOTS=1 cargo test`",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile_passes() {
+        let passes = Compiler::new("+++[-]").compile_passes().unwrap();
+        let names: Vec<&str> = passes.iter().map(|(name, _)| *name).collect();
+
+        assert_eq!(
+            names,
+            ["fold-runs", "clear-loops", "copy-loops", "scan-loops"]
+        );
+        assert_eq!(
+            passes[3].1,
+            Compiler::new("+++[-]").compile_program().unwrap()
+        );
+        assert_eq!(
+            Compiler::new("+++[-]")
+                .without_optimizations()
+                .compile_passes()
+                .unwrap()[0]
+                .1
+                .instruction_count(),
+            6
+        );
+    }
 }
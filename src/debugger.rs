@@ -0,0 +1,380 @@
+use std::io::{self, BufRead, Read, Write};
+
+use crate::cell_names::CellNames;
+use crate::cell_view::CellView;
+use crate::interpreter::Interpreter;
+use crate::tokenizer::{tokenize, TokenKind};
+use crate::watch::Expr;
+use crate::{EofBehavior, FlushBehavior, RuntimeError};
+
+/// The comment character that pauses the execution before the next instruction.
+pub const BREAKPOINT: char = '#';
+
+/// How many cells on each side of the data pointer are printed at a breakpoint.
+const BREAKPOINT_RADIUS: usize = 4;
+
+const HELP: &str = "\
+commands:
+  step [n], s [n]     execute the next n instructions (default 1, also an empty line)
+  continue, c         execute until the program finishes or reaches a `#` breakpoint
+  watch <expr>, w     show the value of an expression after every stop
+  unwatch <n>         remove the watch with the given number
+  print <expr>, p     print the value of an expression once
+  tape [n], t [n], dump [n]
+                      print the cells around the data pointer (default 8 on each side)
+  examine <start> <n> [view], x
+                      decode n cells from start as u8, u16le, u16be, u32le, u32be,
+                      ascii or digits (default u8), start can be an expression
+  help, h             print this help
+  quit, q             stop debugging
+
+expressions use `cell(i)`, `dp`, integers, `+ - * / %` and parentheses,
+e.g. `cell(dp) + cell(dp + 1) * 256`";
+
+/// An interactive debugger that executes a program step by step with the
+/// [interpreter](crate::interpreter::Interpreter).
+///
+/// A [BREAKPOINT] in a comment of the program stops the execution before the next instruction.
+pub struct Debugger<'a, R, W> {
+    interpreter: Interpreter<'a, R, W>,
+    breakpoints: Vec<usize>,
+    watches: Vec<Expr>,
+    names: CellNames,
+}
+
+impl<'a, R, W> Debugger<'a, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Creates a new debugger for the given code, where the program reads from `reader` and
+    /// writes to `writer`.
+    pub fn new(code: &'a str, reader: &'a mut R, writer: &'a mut W) -> Self {
+        Self {
+            interpreter: Interpreter::new(code, reader, writer),
+            breakpoints: breakpoints(code),
+            watches: Vec::new(),
+            names: CellNames::default(),
+        }
+    }
+
+    /// Shows the given names instead of the indices of cells.
+    pub fn with_cell_names(mut self, names: CellNames) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Reads commands from `commands` until they are exhausted or the user quits, printing the
+    /// state of the program to `output`.
+    pub fn run(&mut self, commands: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "type `help` for a list of commands")?;
+        self.print_state(output)?;
+
+        let mut line = String::new();
+        loop {
+            write!(output, "(bf) ")?;
+            output.flush()?;
+
+            line.clear();
+            if commands.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let line = line.trim();
+            let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+            let argument = argument.trim();
+
+            match command {
+                "" | "s" | "step" => {
+                    let n = if argument.is_empty() {
+                        Ok(1)
+                    } else {
+                        argument.parse::<usize>()
+                    };
+                    match n {
+                        Ok(n) => self.step(n, output)?,
+                        Err(_) => writeln!(output, "invalid number of steps `{argument}`")?,
+                    }
+                }
+                "c" | "continue" => self.step(usize::MAX, output)?,
+                "w" | "watch" => match argument.parse::<Expr>() {
+                    Ok(expr) => {
+                        self.watches.push(expr);
+                        self.print_watches(output)?;
+                    }
+                    Err(err) => writeln!(output, "{err}")?,
+                },
+                "unwatch" => match argument.parse::<usize>() {
+                    Ok(n) if (1..=self.watches.len()).contains(&n) => {
+                        self.watches.remove(n - 1);
+                    }
+                    _ => writeln!(output, "no watch with number `{argument}`")?,
+                },
+                "p" | "print" => match argument
+                    .parse::<Expr>()
+                    .and_then(|expr| expr.evaluate(self.interpreter.data(), self.interpreter.dp()))
+                {
+                    Ok(value) => writeln!(output, "{value}")?,
+                    Err(err) => writeln!(output, "{err}")?,
+                },
+                "t" | "tape" | "dump" => match argument.parse::<usize>() {
+                    Ok(radius) => self.print_tape(output, radius)?,
+                    Err(_) if argument.is_empty() => self.print_tape(output, 8)?,
+                    Err(_) => writeln!(output, "invalid number of cells `{argument}`")?,
+                },
+                "x" | "examine" => match self.examine(argument) {
+                    Ok(text) => writeln!(output, "{text}")?,
+                    Err(err) => writeln!(output, "{err}")?,
+                },
+                "h" | "help" => writeln!(output, "{HELP}")?,
+                "q" | "quit" => return Ok(()),
+                _ => writeln!(output, "unknown command `{command}`, type `help` for help")?,
+            }
+        }
+    }
+
+    /// Executes at most `n` instructions and prints the state, stopping early at a breakpoint
+    /// or an instruction that moves the data pointer outside of the tape.
+    fn step(&mut self, n: usize, output: &mut impl Write) -> io::Result<()> {
+        for _ in 0..n {
+            if self.interpreter.is_finished() {
+                break;
+            }
+            match self
+                .interpreter
+                .step(FlushBehavior::OnWrite, EofBehavior::default())
+            {
+                Ok(_) => {}
+                Err(RuntimeError::Io(err)) => return Err(err),
+                Err(err) => {
+                    writeln!(output, "{err}")?;
+                    break;
+                }
+            }
+            if self
+                .breakpoints
+                .binary_search(&self.interpreter.ip())
+                .is_ok()
+            {
+                writeln!(output, "breakpoint")?;
+                self.print_state(output)?;
+                return self.print_tape(output, BREAKPOINT_RADIUS);
+            }
+        }
+        self.print_state(output)
+    }
+
+    /// Decodes the cells described by the arguments `<start> <n> [view]`.
+    fn examine(&self, arguments: &str) -> Result<String, String> {
+        let usage = || "usage: examine <start> <n> [view]".to_string();
+        let mut arguments = arguments.split_whitespace();
+
+        let start = arguments
+            .next()
+            .ok_or_else(usage)?
+            .parse::<Expr>()
+            .and_then(|expr| expr.evaluate(self.interpreter.data(), self.interpreter.dp()))
+            .map_err(|err| err.to_string())?;
+        let len: usize = arguments
+            .next()
+            .ok_or_else(usage)?
+            .parse()
+            .map_err(|_| usage())?;
+        let view = match arguments.next() {
+            Some(view) => view.parse::<CellView>()?,
+            None => CellView::Decimal,
+        };
+
+        let data = self.interpreter.data();
+        usize::try_from(start)
+            .ok()
+            .and_then(|start| data.get(start..start.checked_add(len)?))
+            .map(|cells| view.decode(cells))
+            .ok_or_else(|| format!("cells {start}..{start}+{len} are outside of the tape"))
+    }
+
+    fn print_state(&self, output: &mut impl Write) -> io::Result<()> {
+        let interpreter = &self.interpreter;
+        let dp = interpreter.dp();
+
+        match interpreter.code().get(interpreter.ip()) {
+            Some(instruction) => {
+                write!(output, "ip {} `{}`", interpreter.ip(), *instruction as char)?
+            }
+            None => write!(output, "ip {} (end)", interpreter.ip())?,
+        }
+        writeln!(
+            output,
+            "  dp {dp}  {}={}",
+            self.names.label(dp),
+            interpreter.data()[dp]
+        )?;
+        self.print_watches(output)?;
+
+        if interpreter.is_finished() {
+            writeln!(output, "program finished")?;
+        }
+        Ok(())
+    }
+
+    fn print_watches(&self, output: &mut impl Write) -> io::Result<()> {
+        for (i, watch) in self.watches.iter().enumerate() {
+            match watch.evaluate(self.interpreter.data(), self.interpreter.dp()) {
+                Ok(value) => writeln!(output, "  #{} {watch} = {value}", i + 1)?,
+                Err(err) => writeln!(output, "  #{} {watch}: {err}", i + 1)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn print_tape(&self, output: &mut impl Write, radius: usize) -> io::Result<()> {
+        let data = self.interpreter.data();
+        let dp = self.interpreter.dp();
+        let start = dp.saturating_sub(radius);
+        let end = (dp + radius + 1).min(data.len());
+
+        for (i, cell) in data.iter().enumerate().take(end).skip(start) {
+            let marker = if i == dp { '>' } else { ' ' };
+            match self.names.get(i) {
+                Some(name) => writeln!(output, "{marker} {i:>5}: {cell:>3}  {name}")?,
+                None => writeln!(output, "{marker} {i:>5}: {cell:>3}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the instruction pointers that a [BREAKPOINT] in the comments of `code` stops at in
+/// ascending order, which is the index of the instruction after it.
+fn breakpoints(code: &str) -> Vec<usize> {
+    let mut breakpoints = Vec::new();
+    let mut ip = 0;
+    for token in tokenize(code) {
+        match token.kind {
+            TokenKind::Instruction => ip += 1,
+            TokenKind::Comment if token.text.contains(BREAKPOINT) => breakpoints.push(ip),
+            TokenKind::Comment | TokenKind::Directive => {}
+        }
+    }
+    breakpoints
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::{breakpoints, Debugger};
+    use crate::cell_names::CellNames;
+
+    fn debug(code: &str, commands: &str) -> String {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let mut output = Vec::new();
+
+        Debugger::new(code, &mut reader, &mut writer)
+            .with_cell_names(CellNames::parse(code).unwrap())
+            .run(&mut Cursor::new(commands), &mut output)
+            .unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_watch_after_steps() {
+        let output = debug("+++>++", "watch cell(0) + cell(1) * 256\nstep 6\n");
+
+        assert!(output.contains("(bf)   #1 cell(0) + cell(1) * 256 = 0\n"));
+        assert!(
+            output.contains("ip 6 (end)  dp 1  cell[1]=2\n  #1 cell(0) + cell(1) * 256 = 515\n")
+        );
+        assert!(output.ends_with("program finished\n(bf) "));
+    }
+
+    #[test]
+    fn test_unwatch_and_print() {
+        let output = debug("++>+", "w dp\nunwatch 1\ns 3\np cell(0) * 10\nq\n");
+
+        assert!(output.contains("ip 3 `+`  dp 1  cell[1]=0\n(bf) 20\n"));
+        assert!(!output.contains("#1 dp = 1"));
+    }
+
+    #[test]
+    fn test_examine() {
+        let output = debug(
+            "++>+>+++",
+            "c
+x 0 2 u16le
+x dp-2 3
+x 0 3 digits
+x 29999 2
+x 0 1 hex
+",
+        );
+
+        assert!(output.contains("(bf) 258\n"));
+        assert!(output.contains("(bf) 2 1 3\n"));
+        assert!(output.contains("(bf) 213\n"));
+        assert!(output.contains("(bf) cells 29999..29999+2 are outside of the tape\n"));
+        assert!(output.contains("(bf) unknown view `hex`"));
+    }
+
+    #[test]
+    fn test_cell_names() {
+        let output = debug(";; name cell 1 counter\n+>+++", "s 5\ntape 1\n");
+
+        assert!(output.contains("dp 1  counter=3\n"));
+        assert!(output.contains("      0:   1\n>     1:   3  counter\n"));
+    }
+
+    #[test]
+    fn test_invalid_watch() {
+        let output = debug("+", "watch cell(\nq\n");
+
+        assert!(output.contains("invalid expression: unexpected end of expression\n"));
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let output = debug(
+            "+<+",
+            "c
+p cell(0)
+",
+        );
+
+        assert!(output.contains(
+            "(bf) instruction 1 moved the data pointer outside of the tape to cell -1
+ip 1 `<`"
+        ));
+        assert!(output.ends_with(
+            "(bf) 1
+(bf) "
+        ));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        assert_eq!(
+            breakpoints("#+ # >## [-]\n;; name cell 0 #\n+#"),
+            [0, 1, 2, 6]
+        );
+    }
+
+    #[test]
+    fn test_continue_to_breakpoint() {
+        let output = debug("+++>++#>+ #.", "c\nc\ndump 1\nc\n");
+
+        assert!(output.contains(
+            "(bf) breakpoint
+ip 6 `>`  dp 1  cell[1]=2
+      0:   3
+>     1:   2
+      2:   0
+      3:   0
+"
+        ));
+        assert!(output.contains("(bf) breakpoint\nip 8 `.`  dp 2  cell[2]=1\n"));
+        assert!(output.contains("(bf)       1:   2\n>     2:   1\n      3:   0\n(bf) "));
+        assert!(output.ends_with("ip 9 (end)  dp 2  cell[2]=1\nprogram finished\n(bf) "));
+    }
+}
@@ -0,0 +1,130 @@
+//! Decodes the output of a program before it is printed, so text with invalid UTF-8 or binary
+//! output does not confuse the terminal.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Number of bytes per line of a [hex] dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// How the output of a program is decoded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Decode {
+    /// As UTF-8, replacing invalid sequences with `U+FFFD`.
+    Utf8,
+    /// As a hex dump with the printable ASCII characters next to it.
+    Hex,
+}
+
+impl FromStr for Decode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8" => Ok(Decode::Utf8),
+            "hex" => Ok(Decode::Hex),
+            _ => Err(format!(
+                "unknown decoding `{s}`, valid values are `utf8` and `hex`"
+            )),
+        }
+    }
+}
+
+/// Output decoded as UTF-8.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utf8 {
+    pub text: String,
+
+    /// Offsets of the invalid sequences that were replaced.
+    pub invalid: Vec<usize>,
+}
+
+impl Utf8 {
+    /// Describes the replaced sequences, or returns `None` if the output was valid.
+    pub fn summary(&self) -> Option<String> {
+        let first = self.invalid.first()?;
+        Some(match self.invalid.len() {
+            1 => format!("replaced 1 invalid UTF-8 sequence at byte {first}"),
+            n => format!("replaced {n} invalid UTF-8 sequences, the first at byte {first}"),
+        })
+    }
+}
+
+/// Decodes `bytes` as UTF-8, replacing every invalid sequence with `U+FFFD`.
+pub fn utf8(bytes: &[u8]) -> Utf8 {
+    let mut decoded = Utf8 {
+        text: String::with_capacity(bytes.len()),
+        invalid: Vec::new(),
+    };
+
+    let mut offset = 0;
+    for chunk in bytes.utf8_chunks() {
+        decoded.text.push_str(chunk.valid());
+        offset += chunk.valid().len();
+
+        if !chunk.invalid().is_empty() {
+            decoded.text.push(char::REPLACEMENT_CHARACTER);
+            decoded.invalid.push(offset);
+            offset += chunk.invalid().len();
+        }
+    }
+
+    decoded
+}
+
+/// Returns a hex dump of `bytes`, with the offset of each line and its printable ASCII
+/// characters.
+pub fn hex(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        write!(dump, "{:08x} ", line * BYTES_PER_LINE).unwrap();
+        for i in 0..BYTES_PER_LINE {
+            if i % 8 == 0 {
+                dump.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => write!(dump, "{byte:02x} ").unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+
+        dump.push_str(" |");
+        for byte in chunk {
+            dump.push(match byte {
+                b' '..=b'~' => *byte as char,
+                _ => '.',
+            });
+        }
+        dump.push_str("|\n");
+    }
+
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hex, utf8};
+
+    #[test]
+    fn test_utf8() {
+        let decoded = utf8(b"ok \xe2\x82\xac \xff\xfe \xe2\x82");
+
+        assert_eq!(decoded.text, "ok € \u{fffd}\u{fffd} \u{fffd}");
+        assert_eq!(decoded.invalid, [7, 8, 10]);
+        assert_eq!(
+            decoded.summary().as_deref(),
+            Some("replaced 3 invalid UTF-8 sequences, the first at byte 7")
+        );
+        assert_eq!(utf8(b"valid").summary(), None);
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(
+            hex(b"Hello World!\n\0\x01\x02\x03"),
+            "00000000  48 65 6c 6c 6f 20 57 6f  72 6c 64 21 0a 00 01 02  |Hello World!....|\n\
+             00000010  03                                                |.|\n"
+        );
+    }
+}
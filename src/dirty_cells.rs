@@ -0,0 +1,108 @@
+//! Notifications about the cells a program changed, so a view of the tape only has to redraw
+//! those cells instead of comparing the whole tape after every slice of execution.
+
+use std::mem;
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+
+/// The ranges of cells changed by one slice of execution, sorted and not overlapping.
+pub type DirtyRanges = Vec<Range<usize>>;
+
+/// Records which cells were written and sends them as [DirtyRanges] to a subscriber.
+///
+/// Marking a cell only sets a bit, the ranges are built when they are sent.
+#[derive(Debug)]
+pub(crate) struct DirtyCells {
+    /// One bit per cell.
+    bits: Vec<u64>,
+
+    /// Indices of the words of `bits` that are not zero.
+    words: Vec<usize>,
+
+    sender: Sender<DirtyRanges>,
+}
+
+impl DirtyCells {
+    pub(crate) fn new(sender: Sender<DirtyRanges>) -> Self {
+        Self {
+            bits: Vec::new(),
+            words: Vec::new(),
+            sender,
+        }
+    }
+
+    /// Marks the cell at `i` as changed.
+    pub(crate) fn mark(&mut self, i: usize) {
+        let word = i / 64;
+        if word >= self.bits.len() {
+            // The tape may have grown since the last write.
+            self.bits.resize(word + 1, 0);
+        }
+        if self.bits[word] == 0 {
+            self.words.push(word);
+        }
+        self.bits[word] |= 1 << (i % 64);
+    }
+
+    /// Sends the ranges of the cells changed since the last call, if there are any.
+    ///
+    /// A subscriber that is gone is not an error, the execution continues without it.
+    pub(crate) fn send(&mut self) {
+        if self.words.is_empty() {
+            return;
+        }
+        let ranges = self.take();
+        let _ = self.sender.send(ranges);
+    }
+
+    fn take(&mut self) -> DirtyRanges {
+        self.words.sort_unstable();
+
+        let mut ranges: DirtyRanges = Vec::new();
+        for word in self.words.drain(..) {
+            let mut bits = mem::take(&mut self.bits[word]);
+            while bits != 0 {
+                let shift = bits.trailing_zeros();
+                let len = (!(bits >> shift)).trailing_zeros();
+                // The run of changed cells may reach the end of the word, so `len` may be 64.
+                let run = u64::MAX >> (64 - len);
+                bits &= !(run << shift);
+
+                let start = word * 64 + shift as usize;
+                let end = start + len as usize;
+                match ranges.last_mut() {
+                    Some(last) if last.end == start => last.end = end,
+                    _ => ranges.push(start..end),
+                }
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::DirtyCells;
+
+    #[test]
+    fn test_dirty_ranges() {
+        let (sender, receiver) = mpsc::channel();
+        let mut dirty = DirtyCells::new(sender);
+
+        for i in [200, 3, 4, 5, 63, 64, 65, 0, 4] {
+            dirty.mark(i);
+        }
+        dirty.send();
+        // Nothing changed since the last slice.
+        dirty.send();
+        (64..192).chain([300]).for_each(|i| dirty.mark(i));
+        dirty.send();
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [vec![0..1, 3..6, 63..66, 200..201], vec![64..192, 300..301]]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use crate::compiler::Instruction;
+use crate::tape::Cell;
+
+/// The net effect of executing a loop until the byte at the data pointer is zero.
+///
+/// Each entry is an offset relative to the data pointer and the value that has to be added to
+/// the byte at that offset. The offset `0` is always part of the effect because a finished loop
+/// leaves the byte at the data pointer at zero.
+pub type Effect<C = u8> = Vec<(isize, C)>;
+
+/// The recorded [Effect] of a loop and how many iterations of its body it stands for, e.g. to
+/// count the steps of a replayed loop.
+#[derive(Debug, PartialEq)]
+pub struct Replay<C = u8> {
+    pub effect: Effect<C>,
+    pub iterations: u64,
+}
+
+/// Memoizes the effect of pure and balanced loops as a function of the byte at the data pointer
+/// when the loop is entered.
+///
+/// A loop is pure if its body only contains data pointer and byte increments or decrements, and
+/// it is balanced if the data pointer is the same after every iteration. The effect of such a
+/// loop only depends on the entry value, so executing the same loop again with the same entry
+/// value can replay the recorded changes instead of iterating over the body.
+#[derive(Debug)]
+pub struct EffectCache<C = u8> {
+    /// Analyzed loop bodies, keyed by the index of their `JumpZero` instruction.
+    /// `None` means the loop is not pure or not balanced.
+    bodies: HashMap<usize, Option<LoopBody<C>>>,
+
+    /// Recorded effects, keyed by the index of the `JumpZero` instruction and the entry value.
+    /// `None` means the loop never terminates for this entry value.
+    effects: HashMap<(usize, C), Option<Replay<C>>>,
+}
+
+// Deriving `Default` would require `C: Default`.
+impl<C> Default for EffectCache<C> {
+    fn default() -> Self {
+        Self {
+            bodies: HashMap::new(),
+            effects: HashMap::new(),
+        }
+    }
+}
+
+/// The changes a single iteration of a pure and balanced loop makes.
+#[derive(Debug)]
+struct LoopBody<C> {
+    /// Bytes added per iteration, relative to the data pointer.
+    deltas: Vec<(isize, C)>,
+
+    /// Smallest offset the data pointer reaches while executing the body.
+    min_offset: isize,
+
+    /// Largest offset the data pointer reaches while executing the body.
+    max_offset: isize,
+}
+
+impl<C: Cell> EffectCache<C> {
+    /// Returns the effect of the loop starting at `ip` for the given entry `value`, with the
+    /// number of iterations it stands for.
+    ///
+    /// `None` is returned if the loop can not be replayed: either it is not pure and balanced,
+    /// it would move the data pointer outside of `0..data_size` when started at `dp`, or it never
+    /// terminates. In that case the loop has to be executed normally.
+    pub fn lookup(
+        &mut self,
+        instructions: &[Instruction],
+        ip: usize,
+        value: C,
+        dp: usize,
+        data_size: usize,
+    ) -> Option<&Replay<C>> {
+        let body = self
+            .bodies
+            .entry(ip)
+            .or_insert_with(|| LoopBody::analyze(instructions, ip))
+            .as_ref()?;
+
+        let lowest = dp as isize + body.min_offset;
+        let highest = dp as isize + body.max_offset;
+        if lowest < 0 || highest >= data_size as isize {
+            return None;
+        }
+
+        self.effects
+            .entry((ip, value))
+            .or_insert_with(|| body.record(value))
+            .as_ref()
+    }
+}
+
+impl<C: Cell> LoopBody<C> {
+    /// Analyzes the body of the loop whose `JumpZero` instruction is at `ip`.
+    fn analyze(instructions: &[Instruction], ip: usize) -> Option<Self> {
+        let end = match instructions[ip] {
+            Instruction::JumpZero(n) => ip + n - 1,
+            _ => return None,
+        };
+
+        let mut deltas: Vec<(isize, C)> = Vec::new();
+        let mut offset: isize = 0;
+        let mut min_offset: isize = 0;
+        let mut max_offset: isize = 0;
+
+        for instruction in &instructions[ip + 1..end] {
+            match *instruction {
+                Instruction::IncDP(n) => offset += n as isize,
+                Instruction::DecDP(n) => offset -= n as isize,
+                Instruction::IncByteAtDP(n) => {
+                    add_delta(&mut deltas, offset, C::truncate(n as u64))
+                }
+                Instruction::DecByteAtDP(n) => {
+                    add_delta(&mut deltas, offset, C::truncate(n as u64).wrapping_neg())
+                }
+                _ => return None,
+            }
+            min_offset = min_offset.min(offset);
+            max_offset = max_offset.max(offset);
+        }
+
+        if offset != 0 {
+            return None;
+        }
+
+        Some(Self {
+            deltas,
+            min_offset,
+            max_offset,
+        })
+    }
+
+    /// Computes how often the loop body runs for the given entry `value` until the byte at
+    /// offset `0` is zero and records the accumulated changes.
+    fn record(&self, value: C) -> Option<Replay<C>> {
+        let step = self
+            .deltas
+            .iter()
+            .find(|(offset, _)| *offset == 0)
+            .map_or(C::ZERO, |(_, delta)| *delta);
+        let iterations = C::truncate(iterations(value.to_u64(), step.to_u64(), C::WIDTH.bits())?);
+
+        let mut effect: Effect<C> = self
+            .deltas
+            .iter()
+            .filter(|(offset, _)| *offset != 0)
+            .map(|(offset, delta)| (*offset, delta.wrapping_mul(iterations)))
+            .filter(|(_, delta)| *delta != C::ZERO)
+            .collect();
+        effect.push((0, value.wrapping_neg()));
+
+        Some(Replay {
+            effect,
+            iterations: iterations.to_u64(),
+        })
+    }
+}
+
+/// Returns the smallest number of times `step` has to be added to `value` to reach zero, modulo
+/// 2 to the power of `bits`, or `None` if it never does.
+fn iterations(value: u64, step: u64, bits: u32) -> Option<u64> {
+    let mask = u64::MAX >> (64 - bits);
+    if value == 0 {
+        return Some(0);
+    }
+    if step == 0 {
+        return None;
+    }
+
+    // Solves `value + iterations * step = 0`. With `step = odd << shift`, a solution exists if
+    // `-value` is divisible by `1 << shift`, then it is `-value >> shift` times the inverse of
+    // `odd`, modulo `1 << (bits - shift)`.
+    let shift = step.trailing_zeros();
+    let target = value.wrapping_neg() & mask;
+    if target.trailing_zeros() < shift {
+        return None;
+    }
+    let odd = step >> shift;
+    // Newton's iteration doubles the number of correct low bits of the inverse, starting with
+    // three as every odd number is its own inverse modulo 8.
+    let mut inverse = odd;
+    for _ in 0..5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(odd.wrapping_mul(inverse)));
+    }
+    Some((target >> shift).wrapping_mul(inverse) & (mask >> shift))
+}
+
+fn add_delta<C: Cell>(deltas: &mut Vec<(isize, C)>, offset: isize, delta: C) {
+    match deltas.iter_mut().find(|(o, _)| *o == offset) {
+        Some((_, d)) => *d = d.wrapping_add(delta),
+        None => deltas.push((offset, delta)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+
+    use super::{iterations, EffectCache};
+
+    // Without optimizations, the compiler keeps the loops it would lower to `AddTo`.
+
+    #[test]
+    fn test_multiply_loop() {
+        let instructions = Compiler::new("[->++>+++<<]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u8>::default();
+
+        let replay = cache.lookup(&instructions, 0, 5, 0, 30_000).unwrap();
+
+        assert_eq!(replay.effect, vec![(1, 10), (2, 15), (0, 251)]);
+        assert_eq!(replay.iterations, 5);
+    }
+
+    #[test]
+    fn test_wide_cells() {
+        let instructions = Compiler::new("[+>-<]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u16>::default();
+
+        let replay = cache.lookup(&instructions, 0, 65_000, 0, 30_000).unwrap();
+
+        assert_eq!(replay.effect, vec![(1, 65_000), (0, 536)]);
+        assert_eq!(replay.iterations, 536);
+    }
+
+    #[test]
+    fn test_iterations() {
+        assert_eq!(iterations(0, 3, 8), Some(0));
+        assert_eq!(iterations(5, 255, 8), Some(5));
+        assert_eq!(iterations(5, 1, 16), Some(65_531));
+        assert_eq!(iterations(9, 3, 8), Some(253));
+        assert_eq!(iterations(12, 252, 8), Some(3));
+        assert_eq!(iterations(6, 4, 32), None);
+        assert_eq!(iterations(1, u32::MAX as u64, 32), Some(1));
+    }
+
+    #[test]
+    fn test_impure_loop_is_not_cached() {
+        let instructions = Compiler::new("[->.<]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u8>::default();
+
+        assert_eq!(cache.lookup(&instructions, 0, 5, 0, 30_000), None);
+    }
+
+    #[test]
+    fn test_unbalanced_loop_is_not_cached() {
+        let instructions = Compiler::new("[->]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u8>::default();
+
+        assert_eq!(cache.lookup(&instructions, 0, 5, 0, 30_000), None);
+    }
+
+    #[test]
+    fn test_loop_leaving_the_tape_is_not_cached() {
+        let instructions = Compiler::new("[-<+>]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u8>::default();
+
+        assert_eq!(cache.lookup(&instructions, 0, 5, 0, 30_000), None);
+        assert!(cache.lookup(&instructions, 0, 5, 1, 30_000).is_some());
+    }
+
+    #[test]
+    fn test_non_terminating_loop_is_not_cached() {
+        // Decrementing an odd value by two never reaches zero.
+        let instructions = Compiler::new("[-->+<]")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        let mut cache = EffectCache::<u8>::default();
+
+        assert_eq!(cache.lookup(&instructions, 0, 3, 0, 30_000), None);
+        assert_eq!(
+            cache
+                .lookup(&instructions, 0, 4, 0, 30_000)
+                .map(|replay| &replay.effect),
+            Some(&vec![(1, 2), (0, 252)])
+        );
+    }
+}
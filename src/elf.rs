@@ -0,0 +1,442 @@
+//! Compiles programs ahead of time to static x86-64 Linux executables, by wrapping the machine
+//! code of the [JIT compiler](crate::jit::JitCompiler) in a minimal ELF file.
+//!
+//! The executable has no dependencies: a small runtime written in machine code buffers the
+//! output and calls the kernel with `syscall` to read, write and exit. Like the
+//! [virtual machine](crate::virtual_machine::VirtualMachine) with its defaults, the cells have
+//! 8 bits, the tape of [DEFAULT_TAPE_SIZE] cells does not grow and the program fails when the
+//! data pointer leaves it or the input ends, printing the error and exiting with 1. The program
+//! has no [host inputs](crate::host::HostInputs), `%` only selects the regular input.
+
+use crate::compiler::Instruction;
+use crate::jit::{self, Callbacks, MachineCode};
+use crate::tape::CellWidth;
+use crate::DEFAULT_TAPE_SIZE;
+
+/// The bytes every ELF file starts with.
+pub const MAGIC: &[u8; 4] = b"\x7fELF";
+
+/// The address the file is loaded at, the default of static executables.
+const BASE: u64 = 0x40_0000;
+
+/// The size of the state, whose first three fields are the output buffer the generated code
+/// appends to: its start, length and capacity.
+const STATE_SIZE: u64 = 32;
+
+/// The size of the output buffer, which is written once it is full, before reading and at
+/// the end.
+const BUFFER_SIZE: u64 = 8192;
+
+const HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const PROGRAM_HEADERS: u64 = 2;
+
+/// The offset of the runtime in the file, after the headers.
+const CODE: u64 = HEADER_SIZE + PROGRAM_HEADERS * PROGRAM_HEADER_SIZE;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+/// The alignment of the segments, the zero initialized data starts on the page after the code.
+const PAGE_SIZE: u64 = 0x1000;
+
+/// The functions the generated code calls, as offsets in [RUNTIME]. They take the arguments
+/// of [Callbacks] and never fail, as they exit the process instead.
+const FLUSH: u64 = 0x00;
+const WRITE: u64 = 0x3f;
+const READ: u64 = 0x7e;
+const EXIT: u64 = 0xb0;
+const SELECT_INPUT: u64 = 0xc1;
+const OUT_OF_BOUNDS: u64 = 0xf7;
+
+/// The runtime, followed by [MESSAGES], which it addresses relative to the instruction
+/// pointer:
+///
+/// ```text
+/// flush:                                  ; flush(state)
+///     push   rbx
+///     mov    rbx,rdi
+///     mov    rsi,QWORD PTR [rbx]
+///     mov    rdx,QWORD PTR [rbx+0x8]
+/// 1:  test   rdx,rdx
+///     je     2f
+///     mov    edi,0x1                      ; write(1, data, len)
+///     mov    eax,0x1
+///     syscall
+///     test   rax,rax
+///     jle    3f
+///     add    rsi,rax
+///     sub    rdx,rax
+///     jmp    1b
+/// 2:  mov    QWORD PTR [rbx+0x8],0x0
+///     pop    rbx
+///     ret
+/// 3:  mov    edi,0x1                      ; exit_group(1)
+///     mov    eax,0xe7
+///     syscall
+///
+/// write:                                  ; write(state, byte, n)
+///     push   rbx
+///     push   rbp
+///     push   r12
+///     mov    rbx,rdi
+///     mov    ebp,esi
+///     mov    r12,rdx
+/// 1:  test   r12,r12
+///     je     3f
+///     mov    rax,QWORD PTR [rbx+0x8]
+///     cmp    rax,QWORD PTR [rbx+0x10]
+///     jb     2f
+///     mov    rdi,rbx
+///     call   flush
+///     xor    eax,eax
+/// 2:  mov    rcx,QWORD PTR [rbx]
+///     mov    BYTE PTR [rcx+rax*1],bpl
+///     inc    rax
+///     mov    QWORD PTR [rbx+0x8],rax
+///     dec    r12
+///     jmp    1b
+/// 3:  pop    r12
+///     pop    rbp
+///     pop    rbx
+///     xor    eax,eax
+///     ret
+///
+/// read:                                   ; read(state, cell)
+///     push   rbx
+///     mov    rbx,rsi
+///     call   flush
+///     xor    edi,edi                      ; read(0, cell, 1)
+///     mov    rsi,rbx
+///     mov    edx,0x1
+///     xor    eax,eax
+///     syscall
+///     pop    rbx
+///     cmp    rax,0x1
+///     jne    1f
+///     xor    eax,eax
+///     ret
+/// 1:  lea    rsi,[rip+input_ended]
+///     mov    edx,0x10
+///     jmp    fail
+///
+/// exit:                                   ; exit(state, cell)
+///     push   rsi
+///     call   flush
+///     pop    rsi
+///     movzx  edi,BYTE PTR [rsi]           ; exit_group(cell)
+///     mov    eax,0xe7
+///     syscall
+///
+/// select_input:                           ; select_input(state, cell)
+///     cmp    BYTE PTR [rsi],0x0
+///     jne    1f
+///     xor    eax,eax
+///     ret
+/// 1:  push   rsi
+///     call   flush
+///     lea    rsi,[rip+unknown_input]
+///     mov    edx,0x2c
+///     call   write_stderr
+///     pop    rax
+///     movzx  eax,BYTE PTR [rax]
+///     call   write_number
+///     lea    rsi,[rip+newline]
+///     mov    edx,0x1
+///     jmp    fail
+///
+/// out_of_bounds:                          ; out_of_bounds(state, ip, offset)
+///     push   rsi
+///     push   rdx
+///     call   flush
+///     lea    rsi,[rip+instruction]
+///     mov    edx,0xc
+///     call   write_stderr
+///     mov    rax,QWORD PTR [rsp+0x8]
+///     call   write_number
+///     lea    rsi,[rip+moved]
+///     mov    edx,0x34
+///     call   write_stderr
+///     mov    rax,QWORD PTR [rsp]
+///     call   write_number
+///     lea    rsi,[rip+newline]
+///     mov    edx,0x1
+///     jmp    fail
+///
+/// write_stderr:                           ; write(2, rsi, rdx)
+///     mov    edi,0x2
+///     mov    eax,0x1
+///     syscall
+///     ret
+///
+/// fail:                                   ; writes rsi and rdx to stderr, exit_group(1)
+///     call   write_stderr
+///     mov    edi,0x1
+///     mov    eax,0xe7
+///     syscall
+///
+/// write_number:                           ; writes rax in decimal to stderr
+///     sub    rsp,0x20
+///     lea    rsi,[rsp+0x20]
+///     mov    r8,rax
+///     test   rax,rax
+///     jns    1f
+///     neg    rax
+/// 1:  mov    ecx,0xa
+/// 2:  xor    edx,edx
+///     div    rcx
+///     add    dl,0x30
+///     dec    rsi
+///     mov    BYTE PTR [rsi],dl
+///     test   rax,rax
+///     jne    2b
+///     test   r8,r8
+///     jns    3f
+///     dec    rsi
+///     mov    BYTE PTR [rsi],0x2d
+/// 3:  lea    rdx,[rsp+0x20]
+///     sub    rdx,rsi
+///     call   write_stderr
+///     add    rsp,0x20
+///     ret
+/// ```
+const RUNTIME: [u8; 423] = [
+    0x53, 0x48, 0x89, 0xfb, 0x48, 0x8b, 0x33, 0x48, 0x8b, 0x53, 0x08, 0x48, 0x85, 0xd2, 0x74, 0x19,
+    0xbf, 0x01, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x85, 0xc0, 0x7e,
+    0x12, 0x48, 0x01, 0xc6, 0x48, 0x29, 0xc2, 0xeb, 0xe2, 0x48, 0xc7, 0x43, 0x08, 0x00, 0x00, 0x00,
+    0x00, 0x5b, 0xc3, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xb8, 0xe7, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x53,
+    0x55, 0x41, 0x54, 0x48, 0x89, 0xfb, 0x89, 0xf5, 0x49, 0x89, 0xd4, 0x4d, 0x85, 0xe4, 0x74, 0x27,
+    0x48, 0x8b, 0x43, 0x08, 0x48, 0x3b, 0x43, 0x10, 0x72, 0x0a, 0x48, 0x89, 0xdf, 0xe8, 0x9e, 0xff,
+    0xff, 0xff, 0x31, 0xc0, 0x48, 0x8b, 0x0b, 0x40, 0x88, 0x2c, 0x01, 0x48, 0xff, 0xc0, 0x48, 0x89,
+    0x43, 0x08, 0x49, 0xff, 0xcc, 0xeb, 0xd4, 0x41, 0x5c, 0x5d, 0x5b, 0x31, 0xc0, 0xc3, 0x53, 0x48,
+    0x89, 0xf3, 0xe8, 0x79, 0xff, 0xff, 0xff, 0x31, 0xff, 0x48, 0x89, 0xde, 0xba, 0x01, 0x00, 0x00,
+    0x00, 0x31, 0xc0, 0x0f, 0x05, 0x5b, 0x48, 0x83, 0xf8, 0x01, 0x75, 0x03, 0x31, 0xc0, 0xc3, 0x48,
+    0x8d, 0x35, 0x01, 0x01, 0x00, 0x00, 0xba, 0x10, 0x00, 0x00, 0x00, 0xe9, 0x9e, 0x00, 0x00, 0x00,
+    0x56, 0xe8, 0x4a, 0xff, 0xff, 0xff, 0x5e, 0x0f, 0xb6, 0x3e, 0xb8, 0xe7, 0x00, 0x00, 0x00, 0x0f,
+    0x05, 0x80, 0x3e, 0x00, 0x75, 0x03, 0x31, 0xc0, 0xc3, 0x56, 0xe8, 0x31, 0xff, 0xff, 0xff, 0x48,
+    0x8d, 0x35, 0xe1, 0x00, 0x00, 0x00, 0xba, 0x2c, 0x00, 0x00, 0x00, 0xe8, 0x61, 0x00, 0x00, 0x00,
+    0x58, 0x0f, 0xb6, 0x00, 0xe8, 0x76, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x33, 0x01, 0x00, 0x00,
+    0xba, 0x01, 0x00, 0x00, 0x00, 0xeb, 0x57, 0x56, 0x52, 0xe8, 0x02, 0xff, 0xff, 0xff, 0x48, 0x8d,
+    0x35, 0xde, 0x00, 0x00, 0x00, 0xba, 0x0c, 0x00, 0x00, 0x00, 0xe8, 0x32, 0x00, 0x00, 0x00, 0x48,
+    0x8b, 0x44, 0x24, 0x08, 0xe8, 0x46, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0xcf, 0x00, 0x00, 0x00,
+    0xba, 0x34, 0x00, 0x00, 0x00, 0xe8, 0x17, 0x00, 0x00, 0x00, 0x48, 0x8b, 0x04, 0x24, 0xe8, 0x2c,
+    0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0xe9, 0x00, 0x00, 0x00, 0xba, 0x01, 0x00, 0x00, 0x00, 0xeb,
+    0x0d, 0xbf, 0x02, 0x00, 0x00, 0x00, 0xb8, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xc3, 0xe8, 0xee,
+    0xff, 0xff, 0xff, 0xbf, 0x01, 0x00, 0x00, 0x00, 0xb8, 0xe7, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48,
+    0x83, 0xec, 0x20, 0x48, 0x8d, 0x74, 0x24, 0x20, 0x49, 0x89, 0xc0, 0x48, 0x85, 0xc0, 0x79, 0x03,
+    0x48, 0xf7, 0xd8, 0xb9, 0x0a, 0x00, 0x00, 0x00, 0x31, 0xd2, 0x48, 0xf7, 0xf1, 0x80, 0xc2, 0x30,
+    0x48, 0xff, 0xce, 0x88, 0x16, 0x48, 0x85, 0xc0, 0x75, 0xee, 0x4d, 0x85, 0xc0, 0x79, 0x06, 0x48,
+    0xff, 0xce, 0xc6, 0x06, 0x2d, 0x48, 0x8d, 0x54, 0x24, 0x20, 0x48, 0x29, 0xf2, 0xe8, 0x9f, 0xff,
+    0xff, 0xff, 0x48, 0x83, 0xc4, 0x20, 0xc3,
+];
+
+/// The messages of the errors, in the order the runtime expects them.
+const MESSAGES: &[u8] = b"the input ended\n\
+    the program selected the unknown host input \
+    instruction \
+    \x20moved the data pointer outside of the tape to cell \
+    \n";
+
+/// Compiles `instructions`, which have to be compiled for a whole program, to an executable.
+pub fn compile(instructions: &[Instruction]) -> Vec<u8> {
+    let address = |offset: u64| (BASE + CODE + offset) as usize;
+    let callbacks = Callbacks {
+        read: address(READ),
+        write: address(WRITE),
+        out_of_bounds: address(OUT_OF_BOUNDS),
+        exit: address(EXIT),
+        // The code only counts instructions for the limits of the JIT compiler.
+        fuel: 0,
+        steps: 0,
+        select_input: address(SELECT_INPUT),
+    };
+    let mut machine_code = MachineCode::new(CellWidth::U8);
+    machine_code.emit_stack_setup(callbacks.out_of_bounds);
+    jit::emit_instructions(&mut machine_code, instructions, &callbacks, None);
+    machine_code.emit_stack_teardown();
+
+    // The program follows the entry point, whose length does not depend on the addresses.
+    let start = (RUNTIME.len() + MESSAGES.len()) as u64;
+    let end =
+        CODE + start + entry_point(start, 0).len() as u64 + machine_code.get_buf().len() as u64;
+    let data = (BASE + end).next_multiple_of(PAGE_SIZE);
+    let data_size = STATE_SIZE + BUFFER_SIZE + DEFAULT_TAPE_SIZE as u64;
+
+    let mut file = Vec::with_capacity(end as usize);
+    file.extend_from_slice(MAGIC);
+    // 64-bit, little endian, version 1, System V ABI.
+    file.extend_from_slice(&[2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    // An executable for x86-64, version 1.
+    file.extend_from_slice(&2u16.to_le_bytes());
+    file.extend_from_slice(&0x3eu16.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes());
+    file.extend_from_slice(&(BASE + CODE + start).to_le_bytes());
+    // The program headers follow, there are no section headers and no flags.
+    file.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    file.extend_from_slice(&0u64.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+    for size in [HEADER_SIZE, PROGRAM_HEADER_SIZE, PROGRAM_HEADERS, 0, 0, 0] {
+        file.extend_from_slice(&(size as u16).to_le_bytes());
+    }
+
+    // The whole file is loaded as code, the data is not in the file and zero initialized.
+    program_header(&mut file, PF_R | PF_X, BASE, end, end);
+    program_header(&mut file, PF_R | PF_W, data, 0, data_size);
+
+    file.extend_from_slice(&RUNTIME);
+    file.extend_from_slice(MESSAGES);
+    file.extend_from_slice(&entry_point(start, data));
+    file.extend_from_slice(machine_code.get_buf());
+    file
+}
+
+/// Appends a program header loading `file_size` bytes from the start of the file to
+/// `address`, followed by zeros up to `memory_size` bytes.
+fn program_header(file: &mut Vec<u8>, flags: u32, address: u64, file_size: u64, memory_size: u64) {
+    file.extend_from_slice(&PT_LOAD.to_le_bytes());
+    file.extend_from_slice(&flags.to_le_bytes());
+    // The offset in the file, the virtual and the physical address.
+    for n in [0, address, address, file_size, memory_size, PAGE_SIZE] {
+        file.extend_from_slice(&n.to_le_bytes());
+    }
+}
+
+/// Returns the entry point at `start` in the code, which sets up the state at `data` and
+/// calls the program following it with the tape, like the JIT compiler.
+fn entry_point(start: u64, data: u64) -> Vec<u8> {
+    let buffer = data + STATE_SIZE;
+    let tape = buffer + BUFFER_SIZE;
+
+    // movabs rdi,<state>
+    // movabs rax,<buffer>
+    // mov    QWORD PTR [rdi],rax
+    // mov    QWORD PTR [rdi+0x10],<buffer size>
+    // movabs rsi,<tape>
+    // mov    edx,<tape size>
+    // call   <program>
+    // movabs rdi,<state>
+    // call   flush
+    // xor    edi,edi
+    // mov    eax,0xe7
+    // syscall
+    //
+    // The program follows the 24 bytes after its call, and exits with `exit_group(0)` once it
+    // returned and the output is flushed.
+    let mut code = [
+        &[0x48, 0xbf][..],
+        &data.to_le_bytes(),
+        &[0x48, 0xb8],
+        &buffer.to_le_bytes(),
+        &[0x48, 0x89, 0x07, 0x48, 0xc7, 0x47, 0x10],
+        &(BUFFER_SIZE as u32).to_le_bytes(),
+        &[0x48, 0xbe],
+        &tape.to_le_bytes(),
+        &[0xba],
+        &(DEFAULT_TAPE_SIZE as u32).to_le_bytes(),
+        &[0xe8, 24, 0, 0, 0, 0x48, 0xbf],
+        &data.to_le_bytes(),
+        &[0xe8],
+    ]
+    .concat();
+    let flush = FLUSH as i64 - (start as i64 + code.len() as i64 + 4);
+    code.extend_from_slice(&(flush as i32).to_le_bytes());
+    code.extend_from_slice(&[0x31, 0xff, 0xb8, 0xe7, 0x00, 0x00, 0x00, 0x0f, 0x05]);
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::{Command, Stdio};
+
+    use super::*;
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn test_header() {
+        let file = compile(&Compiler::new("+[>+]").compile().unwrap());
+        assert!(file.starts_with(MAGIC));
+
+        let entry = u64::from_le_bytes(file[24..32].try_into().unwrap());
+        assert_eq!(entry, BASE + CODE + (RUNTIME.len() + MESSAGES.len()) as u64);
+        // The code is loaded from the start of the file, up to its end.
+        let code_size = u64::from_le_bytes(file[96..104].try_into().unwrap());
+        assert_eq!(code_size, file.len() as u64);
+        // The data starts on the page after the code.
+        let data = u64::from_le_bytes(file[136..144].try_into().unwrap());
+        assert_eq!(data, BASE + PAGE_SIZE);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_run() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("brainfuck-elf-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let run = |name: &str, source: &str, input: &[u8]| {
+            let file = dir.join(name);
+            fs::write(&file, compile(&Compiler::new(source).compile().unwrap())).unwrap();
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+            let mut child = Command::new(&file)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            (
+                output.status.code(),
+                output.stdout,
+                String::from_utf8(output.stderr).unwrap(),
+            )
+        };
+
+        assert_eq!(
+            run("double", ",[->++<]>.+++..[<]", b"!"),
+            (Some(0), b"BEE".to_vec(), String::new())
+        );
+        // The virtual machine reports the same instruction.
+        assert_eq!(
+            run("left", "++++++[>++++++++<-]>+.+.<+++[>>+++<<-]>>.<<<", b""),
+            (
+                Some(1),
+                b"12\x09".to_vec(),
+                "instruction 14 moved the data pointer outside of the tape to cell -1\n"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run("right", "+[>+]", b""),
+            (
+                Some(1),
+                Vec::new(),
+                "instruction 2 moved the data pointer outside of the tape to cell 30000\n"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run("eof", ",.,", b"a"),
+            (Some(1), b"a".to_vec(), "the input ended\n".to_string())
+        );
+        assert_eq!(
+            run("exit", ";; extension exit\n+++!+.", b""),
+            (Some(3), Vec::new(), String::new())
+        );
+        assert_eq!(
+            run("input", ";; extension env\n%++%", b""),
+            (
+                Some(1),
+                Vec::new(),
+                "the program selected the unknown host input 2\n".to_string()
+            )
+        );
+        // More output than fits in the buffer.
+        let (code, output, _) = run("long", "-[>-[.-]<-]", b"");
+        assert_eq!((code, output.len()), (Some(0), 255 * 255));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,903 @@
+//! A common interface over the execution environments, so a program can be executed without a
+//! separate code path for the construction and the errors of each of them.
+
+use std::error;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::capabilities::{Backend, BackendOption, UnsupportedOption};
+use crate::closures::ClosureCompiler;
+use crate::compiler::{CompileError, Compiler, Program};
+use crate::host::HostInputs;
+use crate::interpreter::{Executed, Interpreter};
+#[cfg(jit)]
+use crate::jit::{self, JitCompiler};
+use crate::tape::{Cell, CellWidth, Cells, TapePolicy};
+use crate::virtual_machine::{Status, VirtualMachine};
+use crate::{EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+/// How a program is executed, independent of the engine executing it, e.g.
+/// `ExecOptions::new().with_cell_width(CellWidth::U16).with_eof(EofBehavior::SetZero)`.
+///
+/// Options are only set with the `with_*` methods, so new options do not break callers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExecOptions {
+    tape_size: usize,
+    tape_policy: TapePolicy,
+    cell_width: CellWidth,
+    flush: FlushBehavior,
+    eof: EofBehavior,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    cancel: Option<CancelFlag>,
+    host_inputs: Option<&'static HostInputs>,
+}
+
+/// A flag that stops the execution once it is set, compared by its address.
+#[derive(Debug, Copy, Clone)]
+struct CancelFlag(&'static AtomicBool);
+
+impl PartialEq for CancelFlag {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            tape_size: DEFAULT_TAPE_SIZE,
+            tape_policy: TapePolicy::Fixed,
+            cell_width: CellWidth::U8,
+            flush: FlushBehavior::OnEnd,
+            eof: EofBehavior::default(),
+            max_steps: None,
+            timeout: None,
+            cancel: None,
+            host_inputs: None,
+        }
+    }
+}
+
+impl ExecOptions {
+    /// Creates the default options: a fixed tape of [DEFAULT_TAPE_SIZE] bytes, output flushed
+    /// at the end and reads past the end of the input failing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of cells of the tape, which is [DEFAULT_TAPE_SIZE] by default.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = tape_size;
+        self
+    }
+
+    /// Sets what happens when the data pointer moves past the end of the tape, which is
+    /// [TapePolicy::Fixed] by default.
+    pub fn with_tape_policy(mut self, tape_policy: TapePolicy) -> Self {
+        self.tape_policy = tape_policy;
+        self
+    }
+
+    /// Sets the width of the cells, which is [CellWidth::U8] by default.
+    pub fn with_cell_width(mut self, cell_width: CellWidth) -> Self {
+        self.cell_width = cell_width;
+        self
+    }
+
+    /// Sets when the output is flushed, which is [FlushBehavior::OnEnd] by default.
+    pub fn with_flush(mut self, flush: FlushBehavior) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// Sets what reading past the end of the input does, which is [EofBehavior::Error] by
+    /// default.
+    pub fn with_eof(mut self, eof: EofBehavior) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Stops the execution with [RuntimeError::LimitExceeded] instead of executing more than
+    /// `max` steps of the source, e.g. to execute untrusted programs, which is unlimited
+    /// by default.
+    pub fn with_max_steps(mut self, max: u64) -> Self {
+        self.max_steps = Some(max);
+        self
+    }
+
+    /// Stops the execution with [RuntimeError::LimitExceeded] once it took longer than
+    /// `timeout`, which is unlimited by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops the execution with [RuntimeError::Cancelled] once `flag` is set, e.g. by a signal
+    /// handler on Ctrl-C, so [Engine::state] tells where it stopped.
+    ///
+    /// The flag is checked between batches of instructions. The closures can not be stopped,
+    /// see [BackendOption::Cancellation].
+    pub fn with_cancel_flag(mut self, flag: &'static AtomicBool) -> Self {
+        self.cancel = Some(CancelFlag(flag));
+        self
+    }
+
+    /// Lets programs that enabled the `env` [extension](crate::extensions) read `inputs`, which
+    /// none can by default.
+    ///
+    /// The inputs are borrowed for as long as the options exist, e.g. created once by the
+    /// command line for the whole process.
+    pub fn with_host_inputs(mut self, inputs: &'static HostInputs) -> Self {
+        self.host_inputs = Some(inputs);
+        self
+    }
+
+    /// Returns the number of cells of the tape.
+    pub fn tape_size(&self) -> usize {
+        self.tape_size
+    }
+
+    /// Returns what happens when the data pointer moves past the end of the tape.
+    pub fn tape_policy(&self) -> TapePolicy {
+        self.tape_policy
+    }
+
+    /// Returns the width of the cells.
+    pub fn cell_width(&self) -> CellWidth {
+        self.cell_width
+    }
+
+    /// Returns when the output is flushed.
+    pub fn flush(&self) -> FlushBehavior {
+        self.flush
+    }
+
+    /// Returns what reading past the end of the input does.
+    pub fn eof(&self) -> EofBehavior {
+        self.eof
+    }
+
+    /// Returns the maximum number of steps, if they are limited.
+    pub fn max_steps(&self) -> Option<u64> {
+        self.max_steps
+    }
+
+    /// Returns the maximum time the execution may take, if it is limited.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns the flag that cancels the execution, if it can be cancelled.
+    pub fn cancel_flag(&self) -> Option<&'static AtomicBool> {
+        self.cancel.map(|cancel| cancel.0)
+    }
+
+    /// Returns the inputs programs can select, if any.
+    pub fn host_inputs(&self) -> Option<&'static HostInputs> {
+        self.host_inputs
+    }
+
+    /// Returns the options that only some backends support, e.g. to choose one with
+    /// [Backend::auto].
+    pub fn backend_options(&self) -> Vec<BackendOption> {
+        let limited = self.max_steps.is_some() || self.timeout.is_some();
+        [
+            (limited, BackendOption::Limits),
+            (self.cancel.is_some(), BackendOption::Cancellation),
+        ]
+        .into_iter()
+        .filter_map(|(requested, option)| requested.then_some(option))
+        .collect()
+    }
+}
+
+/// Number of instructions executed between checks whether progress is due, so the clock is
+/// not read after every instruction.
+const PROGRESS_BATCH: u64 = 100_000;
+
+/// How far an execution got, reported by [Engine::run_with_progress].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Progress {
+    /// Number of instructions executed so far.
+    pub steps: u64,
+
+    /// Number of bytes written so far.
+    pub output_bytes: u64,
+
+    /// The data pointer.
+    pub dp: usize,
+
+    /// Time since the execution started.
+    pub elapsed: Duration,
+}
+
+/// Number of cells on each side of the data pointer that a [State] keeps.
+pub const STATE_RADIUS: usize = 8;
+
+/// Where an execution stopped, returned by [Engine::state], e.g. to report how far a
+/// cancelled program got.
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    /// Number of instructions executed.
+    pub steps: u64,
+
+    /// The data pointer.
+    pub dp: usize,
+
+    /// The index of the first cell of `cells`.
+    pub first: usize,
+
+    /// The cells at most [STATE_RADIUS] cells away from the data pointer.
+    pub cells: Vec<u64>,
+}
+
+impl State {
+    /// Keeps the cells around `dp` of a tape of `len` cells, which `cell` returns by index.
+    pub(crate) fn new(steps: u64, dp: usize, len: usize, cell: impl Fn(usize) -> u64) -> Self {
+        let first = dp.saturating_sub(STATE_RADIUS).min(len);
+        let end = dp.saturating_add(STATE_RADIUS + 1).min(len);
+        Self {
+            steps,
+            dp,
+            first,
+            cells: (first..end).map(cell).collect(),
+        }
+    }
+
+    /// Keeps the cells around `dp` of `tape`.
+    pub(crate) fn of_tape<C: Cell>(steps: u64, dp: usize, tape: &[C]) -> Self {
+        Self::new(steps, dp, tape.len(), |index| tape[index].to_u64())
+    }
+}
+
+/// Whether the execution continues after a [Progress] report.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Control {
+    Continue,
+
+    /// Stop the execution with [RuntimeError::Cancelled].
+    Cancel,
+}
+
+/// An execution environment with a program and the input and output to execute it with.
+pub trait Engine {
+    /// Executes the program, handling its input and output according to `options`.
+    ///
+    /// The tape is set up when the engine is created, e.g. by [select], so the options of
+    /// the tape are not applied again.
+    fn run(&mut self, options: &ExecOptions) -> Result<(), RuntimeError>;
+
+    /// Like [run](Engine::run), but calls `callback` about every `interval` with the progress
+    /// of the execution, e.g. to show an activity indicator. The execution stops with
+    /// [RuntimeError::Cancelled] once the callback returns [Control::Cancel].
+    ///
+    /// Engines that can not pause, see [BackendOption::Progress](crate::capabilities::BackendOption::Progress),
+    /// execute without reporting progress.
+    fn run_with_progress(
+        &mut self,
+        options: &ExecOptions,
+        _interval: Duration,
+        _callback: &mut dyn FnMut(&Progress) -> Control,
+    ) -> Result<(), RuntimeError> {
+        self.run(options)
+    }
+
+    /// Returns where the last run stopped, e.g. to report how far it got once it was
+    /// cancelled, or `None` if the engine does not keep its state.
+    ///
+    /// The JIT compiler only keeps it if the run was stopped by a limit or a cancel flag.
+    fn state(&self) -> Option<State> {
+        None
+    }
+}
+
+/// Calls the callback of [Engine::run_with_progress] once its interval passed.
+struct Reporter<'a> {
+    start: Instant,
+    next: Duration,
+    interval: Duration,
+    callback: &'a mut dyn FnMut(&Progress) -> Control,
+}
+
+impl<'a> Reporter<'a> {
+    fn new(interval: Duration, callback: &'a mut dyn FnMut(&Progress) -> Control) -> Self {
+        Self {
+            start: Instant::now(),
+            next: interval,
+            interval,
+            callback,
+        }
+    }
+
+    fn report(&mut self, steps: u64, output_bytes: u64, dp: usize) -> Result<(), RuntimeError> {
+        let elapsed = self.start.elapsed();
+        if elapsed < self.next {
+            return Ok(());
+        }
+        self.next = elapsed + self.interval;
+        let progress = Progress {
+            steps,
+            output_bytes,
+            dp,
+            elapsed,
+        };
+        match (self.callback)(&progress) {
+            Control::Continue => Ok(()),
+            Control::Cancel => Err(RuntimeError::Cancelled),
+        }
+    }
+}
+
+impl<R: Read, W: Write, C: Cell> Engine for Interpreter<'_, R, W, C> {
+    fn run(&mut self, options: &ExecOptions) -> Result<(), RuntimeError> {
+        self.execute(options.flush, options.eof)?;
+        Ok(())
+    }
+
+    fn run_with_progress(
+        &mut self,
+        options: &ExecOptions,
+        interval: Duration,
+        callback: &mut dyn FnMut(&Progress) -> Control,
+    ) -> Result<(), RuntimeError> {
+        let mut reporter = Reporter::new(interval, callback);
+        let (mut steps, mut output_bytes) = (0, 0);
+        self.execute_with(options.flush, options.eof, |interpreter, executed| {
+            if let Executed::Write(_) = executed {
+                output_bytes += 1;
+            }
+            steps += 1;
+            if steps % PROGRESS_BATCH == 0 {
+                reporter.report(steps, output_bytes, interpreter.dp())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn state(&self) -> Option<State> {
+        Some(State::of_tape(self.executed(), self.dp(), self.data()))
+    }
+}
+
+impl<R: Read, W: Write, C: Cell, S: Cells<C>> Engine for VirtualMachine<'_, R, W, C, S> {
+    fn run(&mut self, options: &ExecOptions) -> Result<(), RuntimeError> {
+        self.execute(options.flush, options.eof)?;
+        Ok(())
+    }
+
+    fn run_with_progress(
+        &mut self,
+        options: &ExecOptions,
+        interval: Duration,
+        callback: &mut dyn FnMut(&Progress) -> Control,
+    ) -> Result<(), RuntimeError> {
+        let mut reporter = Reporter::new(interval, callback);
+        let mut steps = 0;
+        loop {
+            let slice = self.run(PROGRESS_BATCH, options.flush, options.eof)?;
+            steps += slice.executed;
+            match slice.status {
+                Status::Finished => return Ok(()),
+                Status::Blocked => return Err(RuntimeError::Io(io::ErrorKind::WouldBlock.into())),
+                Status::Paused => reporter.report(steps, self.written(), self.dp())?,
+            }
+        }
+    }
+
+    fn state(&self) -> Option<State> {
+        Some(State::of_tape(self.executed(), self.dp(), self.data()))
+    }
+}
+
+impl<'a, R: Read + 'a, W: Write + 'a, C: Cell> Engine for ClosureCompiler<'a, R, W, C> {
+    fn run(&mut self, options: &ExecOptions) -> Result<(), RuntimeError> {
+        self.execute(options.flush, options.eof)
+    }
+}
+
+/// Executes the generated code on its own thread with a stack of [jit::DEFAULT_STACK_SIZE]
+/// bytes, emitting it again for every run.
+#[cfg(jit)]
+impl<R: Read + Send, W: Write + Send> Engine for JitCompiler<'_, R, W> {
+    fn run(&mut self, options: &ExecOptions) -> Result<(), RuntimeError> {
+        self.execute_again_on_thread(options.flush, options.eof, jit::DEFAULT_STACK_SIZE)
+    }
+
+    fn state(&self) -> Option<State> {
+        self.state().cloned()
+    }
+}
+
+/// Describes why [run] could not compile or execute a program.
+#[derive(Debug)]
+pub enum Error {
+    /// The program has a bracket without a matching one.
+    Compile(CompileError),
+
+    /// The execution failed, or the program exited.
+    Runtime(RuntimeError),
+
+    /// The backend does not support an option the program is executed with.
+    UnsupportedOption(UnsupportedOption),
+
+    /// The backend is not [available](Backend::is_available) on this platform.
+    Unavailable(Backend),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Compile(err) => err.fmt(f),
+            Error::Runtime(err) => err.fmt(f),
+            Error::UnsupportedOption(err) => err.fmt(f),
+            Error::Unavailable(backend) => write!(
+                f,
+                "the `{}` backend is not available on this platform",
+                backend.name()
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Compile(err) => Some(err),
+            Error::Runtime(err) => Some(err),
+            Error::UnsupportedOption(err) => Some(err),
+            Error::Unavailable(_) => None,
+        }
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        Error::Compile(err)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(err: RuntimeError) -> Self {
+        Error::Runtime(err)
+    }
+}
+
+impl From<UnsupportedOption> for Error {
+    fn from(err: UnsupportedOption) -> Self {
+        Error::UnsupportedOption(err)
+    }
+}
+
+/// Compiles `source` and executes it with the engine of `backend`, e.g.
+/// `run(Backend::Jit, source, &mut io::stdin(), &mut io::stdout(), &ExecOptions::new())`.
+///
+/// Fails with [Error::UnsupportedOption] if `backend` does not support `options`, or with
+/// [Error::Unavailable] if it is not part of this build, instead of executing the program
+/// with another backend. [Backend::auto] chooses one that supports them.
+pub fn run<R, W>(
+    backend: Backend,
+    source: &str,
+    reader: &mut R,
+    writer: &mut W,
+    options: &ExecOptions,
+) -> Result<(), Error>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+    let program = Compiler::new(source).compile_program()?;
+    select(backend, source, &program, reader, writer, options)?.run(options)?;
+    Ok(())
+}
+
+/// Creates the engine of `backend` for the program, whose source the interpreter executes and
+/// whose compiled `program` all other engines execute, with the tape and the limits of
+/// `options`. The steps of the limits are counted with the [step costs](Program::step_costs)
+/// of `program`.
+///
+/// The virtual machine uses its effect cache. Fails like [run] if `backend` does not support
+/// `options` or is not available.
+pub fn select<'a, R, W>(
+    backend: Backend,
+    source: &'a str,
+    program: &'a Program,
+    reader: &'a mut R,
+    writer: &'a mut W,
+    options: &ExecOptions,
+) -> Result<Box<dyn Engine + 'a>, Error>
+where
+    R: Read + Send,
+    W: Write + Send,
+{
+    match options.cell_width {
+        CellWidth::U8 => select_with::<u8, R, W>(backend, source, program, reader, writer, options),
+        CellWidth::U16 => {
+            select_with::<u16, R, W>(backend, source, program, reader, writer, options)
+        }
+        CellWidth::U32 => {
+            select_with::<u32, R, W>(backend, source, program, reader, writer, options)
+        }
+    }
+}
+
+fn select_with<'a, C, R, W>(
+    backend: Backend,
+    source: &'a str,
+    program: &'a Program,
+    reader: &'a mut R,
+    writer: &'a mut W,
+    options: &ExecOptions,
+) -> Result<Box<dyn Engine + 'a>, Error>
+where
+    C: Cell + 'a,
+    R: Read + Send,
+    W: Write + Send,
+{
+    if !backend.is_available() {
+        return Err(Error::Unavailable(backend));
+    }
+    backend.check(&options.backend_options())?;
+
+    let engine: Box<dyn Engine + 'a> = match backend {
+        Backend::Interpreter => {
+            let mut interpreter = Interpreter::new(source, reader, writer)
+                .with_tape_size(options.tape_size)
+                .with_tape_policy(options.tape_policy)
+                .with_cells::<C>();
+            if let Some(max) = options.max_steps {
+                interpreter = interpreter.with_max_steps(max);
+            }
+            if let Some(timeout) = options.timeout {
+                interpreter = interpreter.with_timeout(timeout);
+            }
+            if let Some(cancel) = options.cancel {
+                interpreter = interpreter.with_cancel_flag(cancel.0);
+            }
+            if let Some(inputs) = options.host_inputs {
+                interpreter = interpreter.with_host_inputs(inputs);
+            }
+            Box::new(interpreter)
+        }
+        #[cfg(jit)]
+        Backend::Jit => {
+            let mut jit = JitCompiler::new(program, reader, writer)
+                .with_tape_size(options.tape_size)
+                .with_tape_policy(options.tape_policy)
+                .with_cell_width(C::WIDTH);
+            if let Some(max) = options.max_steps {
+                jit = jit.with_max_steps(max, program.step_costs());
+            }
+            if let Some(timeout) = options.timeout {
+                jit = jit.with_timeout(timeout);
+            }
+            if let Some(cancel) = options.cancel {
+                jit = jit.with_cancel_flag(cancel.0);
+            }
+            if let Some(inputs) = options.host_inputs {
+                jit = jit.with_host_inputs(inputs);
+            }
+            Box::new(jit)
+        }
+        #[cfg(not(jit))]
+        Backend::Jit => unreachable!("the jit compiler is not available"),
+        Backend::VirtualMachine => {
+            let mut vm = VirtualMachine::new(program, reader, writer)
+                .with_tape_size(options.tape_size)
+                .with_tape_policy(options.tape_policy)
+                .with_cells::<C>()
+                .with_effect_cache();
+            if let Some(max) = options.max_steps {
+                vm = vm.with_max_steps(max, program.step_costs());
+            }
+            if let Some(timeout) = options.timeout {
+                vm = vm.with_timeout(timeout);
+            }
+            if let Some(cancel) = options.cancel {
+                vm = vm.with_cancel_flag(cancel.0);
+            }
+            if let Some(inputs) = options.host_inputs {
+                vm = vm.with_host_inputs(inputs);
+            }
+            Box::new(vm)
+        }
+        Backend::Closures => {
+            let mut closures = ClosureCompiler::new(program, reader, writer)
+                .with_tape_size(options.tape_size)
+                .with_tape_policy(options.tape_policy)
+                .with_cells::<C>();
+            if let Some(inputs) = options.host_inputs {
+                closures = closures.with_host_inputs(inputs);
+            }
+            Box::new(closures)
+        }
+    };
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    use once_cell::sync::Lazy;
+
+    use crate::capabilities::{Backend, BackendOption};
+    use crate::compiler::Compiler;
+    use crate::host::HostInputs;
+    use crate::tape::CellWidth;
+    use crate::{Limit, RuntimeError};
+
+    use super::{select, Control, Error, ExecOptions, STATE_RADIUS};
+
+    /// Returns the backends that are part of this build.
+    fn available() -> impl Iterator<Item = Backend> {
+        Backend::ALL
+            .into_iter()
+            .filter(|backend| backend.is_available())
+    }
+
+    #[test]
+    fn test_select() {
+        let source = ",[.-]";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let options = ExecOptions::new().with_cell_width(CellWidth::U16);
+
+        for backend in available() {
+            let mut reader = Cursor::new([3]);
+            let mut writer = Vec::new();
+            let mut engine = select(
+                backend,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            )
+            .unwrap();
+            engine.run(&options).unwrap();
+            drop(engine);
+
+            assert_eq!(writer, [3, 2, 1], "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_select_tape() {
+        let source = "+[>+]";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let options = ExecOptions::new().with_tape_size(10);
+
+        for backend in available() {
+            let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+            let err = select(
+                backend,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            )
+            .unwrap()
+            .run(&options)
+            .unwrap_err();
+
+            assert!(
+                matches!(err, RuntimeError::PointerOutOfBounds { dp: 10, .. }),
+                "{backend:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_limits() {
+        let execute = |backend, source: &str, options: &ExecOptions| {
+            let program = Compiler::new(source).compile_program().unwrap();
+            let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+            let result = select(backend, source, &program, &mut reader, &mut writer, options)
+                .unwrap()
+                .run(options);
+            result
+        };
+
+        for backend in available() {
+            if !backend.supports(BackendOption::Limits) {
+                continue;
+            }
+            let options = ExecOptions::new().with_max_steps(1000);
+            execute(backend, "++[->+<]", &options).unwrap();
+            execute(backend, &"+>".repeat(500), &options).unwrap();
+            for source in ["+[]".to_string(), "+[-]".to_string() + &"+>".repeat(600)] {
+                let err = execute(backend, &source, &options).unwrap_err();
+                assert!(
+                    matches!(err, RuntimeError::LimitExceeded(Limit::Steps(1000))),
+                    "{backend:?}: {err}"
+                );
+            }
+
+            let options = ExecOptions::new().with_timeout(Duration::from_millis(20));
+            let err = execute(backend, "+[]", &options).unwrap_err();
+            assert!(
+                matches!(err, RuntimeError::LimitExceeded(Limit::Timeout(_))),
+                "{backend:?}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_cancel() {
+        static CANCEL: AtomicBool = AtomicBool::new(true);
+        let source = ">+[]";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let options = ExecOptions::new().with_cancel_flag(&CANCEL);
+
+        for backend in available() {
+            if !backend.supports(BackendOption::Cancellation) {
+                continue;
+            }
+            let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+            let mut engine = select(
+                backend,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            )
+            .unwrap();
+            let err = engine.run(&options).unwrap_err();
+            assert!(matches!(err, RuntimeError::Cancelled), "{backend:?}: {err}");
+            let state = engine.state().unwrap();
+            assert_eq!(state.first, 0, "{backend:?}");
+            assert_eq!(
+                state.cells.len(),
+                STATE_RADIUS + 1 + state.dp,
+                "{backend:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_unsupported_options() {
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+        let source = "+[-]";
+        let program = Compiler::new(source).compile_program().unwrap();
+
+        for options in [
+            ExecOptions::new().with_max_steps(1000),
+            ExecOptions::new().with_timeout(Duration::from_secs(1)),
+            ExecOptions::new().with_cancel_flag(&CANCEL),
+        ] {
+            let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+            let result = select(
+                Backend::Closures,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            );
+            assert!(
+                matches!(result, Err(Error::UnsupportedOption(unsupported)) if unsupported.backend == Backend::Closures),
+                "{options:?}"
+            );
+
+            drop(result);
+
+            let backend = Backend::auto(&options.backend_options()).unwrap();
+            assert_ne!(backend, Backend::Closures);
+            super::run(backend, source, &mut reader, &mut writer, &options).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(not(jit))]
+    fn test_select_unavailable() {
+        let options = ExecOptions::new();
+        let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+        let err = super::run(Backend::Jit, "+", &mut reader, &mut writer, &options).unwrap_err();
+        assert!(matches!(err, Error::Unavailable(Backend::Jit)), "{err}");
+    }
+
+    #[test]
+    fn test_select_host_inputs() {
+        static INPUTS: Lazy<HostInputs> = Lazy::new(|| HostInputs::new().with_value("x", "ab"));
+        let source = ";; extension env\n,.[-]+%,.,.[-]%,.[-]++%";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let options = ExecOptions::new().with_host_inputs(&INPUTS);
+
+        for backend in available() {
+            let (mut reader, mut writer) = (Cursor::new(b"xy"), Vec::new());
+            let mut engine = select(
+                backend,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            )
+            .unwrap();
+            let err = engine.run(&options).unwrap_err();
+            assert!(
+                matches!(err, RuntimeError::UnknownInput(2)),
+                "{backend:?}: {err}"
+            );
+            drop(engine);
+            assert_eq!(writer, b"xaby", "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_run_with_progress() {
+        let source = "+[.]";
+        let program = Compiler::new(source).compile_program().unwrap();
+        let options = ExecOptions::new();
+
+        for backend in available() {
+            if !backend.supports(BackendOption::Progress) {
+                continue;
+            }
+            let (mut reader, mut writer) = (Cursor::new([]), Vec::new());
+            let mut reports = Vec::new();
+            let err = select(
+                backend,
+                source,
+                &program,
+                &mut reader,
+                &mut writer,
+                &options,
+            )
+            .unwrap()
+            .run_with_progress(&options, Duration::ZERO, &mut |progress| {
+                reports.push(*progress);
+                if reports.len() < 3 {
+                    Control::Continue
+                } else {
+                    Control::Cancel
+                }
+            })
+            .unwrap_err();
+
+            assert!(matches!(err, RuntimeError::Cancelled), "{backend:?}: {err}");
+            assert_eq!(reports.len(), 3, "{backend:?}");
+            assert!(reports[0].steps < reports[2].steps, "{backend:?}");
+            assert!(reports[2].output_bytes > 0, "{backend:?}");
+            assert_eq!(reports[2].dp, 0, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        use crate::prelude::*;
+
+        let options = ExecOptions::new().with_tape_size(4);
+        for backend in available() {
+            let mut writer = Vec::new();
+            run(
+                backend,
+                ",[.-]",
+                &mut Cursor::new([2]),
+                &mut writer,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(writer, [2, 1], "{backend:?}");
+
+            let err = run(backend, "+[", &mut Cursor::new([]), &mut writer, &options);
+            assert!(matches!(err, Err(Error::Compile(_))), "{backend:?}");
+
+            let err = run(backend, ">>>>", &mut Cursor::new([]), &mut writer, &options);
+            assert!(
+                matches!(
+                    err,
+                    Err(Error::Runtime(RuntimeError::PointerOutOfBounds { .. }))
+                ),
+                "{backend:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Output a program is expected to write, declared in the program with directives like
+//! `;; expect "Hello World!\n"`, so a program can be tested without a separate file.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::tokenizer::{tokenize, TokenKind, DIRECTIVE_PREFIX};
+
+/// The expected output declared in a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expectations {
+    output: Vec<u8>,
+    count: usize,
+}
+
+/// An `expect` directive that could not be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationError {
+    /// Line of the directive, starting at 1.
+    pub line: usize,
+
+    /// Column of the directive, starting at 1.
+    pub column: usize,
+
+    pub message: String,
+}
+
+impl Display for ExpectationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid expected output at line {} column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for ExpectationError {}
+
+impl Expectations {
+    /// Collects the `;; expect "<output>"` directives of `source`, whose outputs are expected
+    /// one after another when the program runs without input.
+    ///
+    /// The output is a quoted string, which may contain the escapes `\n`, `\r`, `\t`, `\0`,
+    /// `\"`, `\\` and `\xNN` for any byte. Directives that start with another word are left for
+    /// other tools.
+    pub fn parse(source: &str) -> Result<Self, ExpectationError> {
+        let mut expectations = Self::default();
+
+        for token in tokenize(source) {
+            if token.kind != TokenKind::Directive {
+                continue;
+            }
+
+            let text = token.text[DIRECTIVE_PREFIX.len()..].trim();
+            let argument = match text.strip_prefix("expect") {
+                Some(argument) if argument.is_empty() || argument.starts_with([' ', '\t']) => {
+                    argument.trim()
+                }
+                _ => continue,
+            };
+
+            let output = unquote(argument).map_err(|message| ExpectationError {
+                line: token.line,
+                column: token.column,
+                message,
+            })?;
+            expectations.output.extend(output);
+            expectations.count += 1;
+        }
+
+        Ok(expectations)
+    }
+
+    /// Returns the output the program is expected to write.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Returns whether the program has no `expect` directives.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// Returns the bytes of the quoted string `s`.
+fn unquote(s: &str) -> Result<Vec<u8>, String> {
+    let usage = || "expected `expect \"<output>\"`".to_string();
+    let mut chars = s.strip_prefix('"').ok_or_else(usage)?.chars();
+    let mut bytes = Vec::new();
+
+    loop {
+        let c = match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    let byte = u8::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("invalid escape `\\x{hex}`"))?;
+                    bytes.push(byte);
+                    continue;
+                }
+                Some(c) => return Err(format!("invalid escape `\\{c}`")),
+                None => return Err("unterminated string".to_string()),
+            },
+            Some(c) => c,
+            None => return Err("unterminated string".to_string()),
+        };
+        bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
+    if !chars.as_str().trim().is_empty() {
+        return Err(usage());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expectations;
+
+    #[test]
+    fn test_parse_expectations() {
+        let expectations = Expectations::parse(
+            ";; expect \"Hello\"\n+++.\n;; name cell 0 x\n;;expect \", \\\"W\\xf6rld\\\"!\\n\"\n",
+        )
+        .unwrap();
+
+        assert!(!expectations.is_empty());
+        assert_eq!(expectations.output(), b"Hello, \"W\xf6rld\"!\n");
+
+        let expectations = Expectations::parse(";; expect \"\"\n;; expected nothing").unwrap();
+        assert!(!expectations.is_empty());
+        assert!(expectations.output().is_empty());
+        assert!(Expectations::parse("+.").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_expectations() {
+        let error = |source| Expectations::parse(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("+\n ;; expect Hello"),
+            "invalid expected output at line 2 column 2: expected `expect \"<output>\"`"
+        );
+        assert_eq!(
+            error(";; expect \"Hello\" World"),
+            "invalid expected output at line 1 column 1: expected `expect \"<output>\"`"
+        );
+        assert_eq!(
+            error(";; expect \"Hello"),
+            "invalid expected output at line 1 column 1: unterminated string"
+        );
+        assert_eq!(
+            error(";; expect \"\\q\""),
+            "invalid expected output at line 1 column 1: invalid escape `\\q`"
+        );
+        assert_eq!(
+            error(";; expect \"\\xg0\""),
+            "invalid expected output at line 1 column 1: invalid escape `\\xg0`"
+        );
+    }
+}
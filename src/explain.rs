@@ -0,0 +1,254 @@
+//! Explains what the optimizing compiler turned a construct of the source into, e.g. to learn
+//! why a loop executes fast or to verify that the optimizer understood it.
+
+use std::str::FromStr;
+
+use std::ops::Range;
+
+use crate::compiler::{CompileError, Compiler, Instruction, Pass};
+use crate::source_map::{self, Span};
+
+/// A position in the source of a program, written as `LINE:COL`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Position {
+    /// Line, starting at 1.
+    pub line: usize,
+
+    /// Column in characters, starting at 1.
+    pub column: usize,
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("expected `LINE:COL`, e.g. `3:14`, found `{s}`");
+        let (line, column) = s.split_once(':').ok_or_else(error)?;
+        match (line.parse(), column.parse()) {
+            (Ok(line), Ok(column)) if line > 0 && column > 0 => Ok(Self { line, column }),
+            _ => Err(error()),
+        }
+    }
+}
+
+/// The instructions a construct of the source was compiled into.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// Span of the construct, e.g. a run of `+` or a whole loop that was lowered.
+    pub span: Span,
+
+    /// Index of the first instruction in the compiled program.
+    pub index: usize,
+
+    pub instructions: Vec<Instruction>,
+
+    /// The optimization that created the instructions.
+    pub pass: Pass,
+
+    /// Indices of the instructions they replace in the unoptimized program.
+    pub original: Range<usize>,
+
+    /// What the instructions do, in words.
+    pub summary: String,
+}
+
+/// Explains the instructions compiled from the construct at `at`, or returns `None` if there is
+/// only a comment at the position.
+pub fn explain(source: &str, at: Position) -> Result<Option<Explanation>, CompileError> {
+    let instructions = Compiler::new(source).compile()?;
+    let provenance = source_map::provenance(source);
+
+    let at = (at.line, at.column);
+    let contains =
+        |span: &Span| (span.line, span.column) <= at && at <= (span.end_line, span.end_column);
+    let index = match provenance.iter().position(|p| contains(&p.span)) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    // The instructions of a lowered loop share the provenance of the loop.
+    let len = provenance[index..]
+        .iter()
+        .take_while(|p| **p == provenance[index])
+        .count();
+    let instructions = instructions[index..index + len].to_vec();
+    let provenance = provenance[index].clone();
+
+    Ok(Some(Explanation {
+        span: provenance.span,
+        index,
+        summary: summary(&instructions, provenance.pass, provenance.original.len()),
+        instructions,
+        pass: provenance.pass,
+        original: provenance.original,
+    }))
+}
+
+/// Describes the instructions that `pass` created from `original` unoptimized instructions.
+fn summary(instructions: &[Instruction], pass: Pass, original: usize) -> String {
+    let text = match instructions {
+        [Instruction::IncDP(n)] => format!("moves the data pointer: dp += {n}"),
+        [Instruction::DecDP(n)] => format!("moves the data pointer: dp -= {n}"),
+        [Instruction::IncByteAtDP(n)] => format!("{} += {n}", cell(0)),
+        [Instruction::DecByteAtDP(n)] => format!("{} -= {n}", cell(0)),
+        [Instruction::WriteByte(n)] => format!("writes {} {n} times", cell(0)),
+        [Instruction::ReadByte] => format!("reads a byte into {}", cell(0)),
+        [Instruction::SetZero] => format!("{} = 0", cell(0)),
+        [Instruction::Exit] => format!("exits with {} as exit code", cell(0)),
+        [Instruction::SelectInput] => {
+            format!("selects the input numbered by {} for later reads", cell(0))
+        }
+        [Instruction::FindZero(stride)] => {
+            let operator = if *stride < 0 { "-=" } else { "+=" };
+            format!(
+                "dp {operator} {} until {} is 0",
+                stride.unsigned_abs(),
+                cell(0)
+            )
+        }
+        [Instruction::JumpZero(n)] => format!(
+            "this loop was not optimized, it executes its {} instructions once per iteration",
+            n - 2
+        ),
+        [Instruction::JumpNotZero(_)] => {
+            format!(
+                "the end of a loop, it jumps back to its start if {} is not 0",
+                cell(0)
+            )
+        }
+        [additions @ .., Instruction::SetZero] => {
+            let additions: Vec<String> = additions
+                .iter()
+                .map(|instruction| match *instruction {
+                    Instruction::AddTo { offset, factor } => {
+                        let (operator, factor) = match factor {
+                            1 => ("+=", String::new()),
+                            -1 => ("-=", String::new()),
+                            _ if factor < 0 => ("-=", format!("{}*", factor.unsigned_abs())),
+                            _ => ("+=", format!("{factor}*")),
+                        };
+                        format!("{} {operator} {factor}{}", cell(offset), cell(0))
+                    }
+                    _ => unreachable!("lowered loops only add to other cells"),
+                })
+                .collect();
+            format!("{}, then {} = 0", additions.join(", "), cell(0))
+        }
+        _ => unreachable!("unexpected instructions {instructions:?}"),
+    };
+
+    match pass {
+        Pass::Unoptimized => text,
+        Pass::Fold => format!("{text}, {original} instructions folded into one"),
+        Pass::Fusion => format!("{text}, {original} instructions fused into one"),
+        Pass::Multiply if matches!(instructions, [Instruction::AddTo { factor: 1, .. }, _]) => {
+            format!("this loop was recognized as move: {text}")
+        }
+        Pass::Clear | Pass::Scan | Pass::Multiply => {
+            format!("this loop was recognized as {}: {text}", pass.name())
+        }
+    }
+}
+
+/// Returns the name of the cell `offset` cells away from the data pointer.
+fn cell(offset: isize) -> String {
+    match offset {
+        0 => "cell[dp]".to_string(),
+        _ if offset > 0 => format!("cell[dp+{offset}]"),
+        _ => format!("cell[dp{offset}]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, Position};
+    use crate::compiler::{Instruction, Pass};
+
+    fn summary(source: &str, at: &str) -> Option<String> {
+        explain(source, at.parse().unwrap())
+            .unwrap()
+            .map(|explanation| explanation.summary)
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(
+            "3:14".parse(),
+            Ok(Position {
+                line: 3,
+                column: 14
+            })
+        );
+        assert!("3".parse::<Position>().is_err());
+        assert!("0:1".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn test_explain_lowered_loops() {
+        let source = "+++ comment\n[->>+++<<<+>]>[-]>[<]";
+        let explanation = explain(source, "2:4".parse().unwrap()).unwrap().unwrap();
+
+        assert_eq!(explanation.index, 1);
+        assert_eq!(explanation.pass, Pass::Multiply);
+        assert_eq!(explanation.original, 3..16);
+        assert_eq!(
+            explanation.instructions,
+            [
+                Instruction::AddTo {
+                    offset: 2,
+                    factor: 3
+                },
+                Instruction::AddTo {
+                    offset: -1,
+                    factor: 1
+                },
+                Instruction::SetZero,
+            ]
+        );
+        assert_eq!(
+            explanation.summary,
+            "this loop was recognized as multiply: cell[dp+2] += 3*cell[dp], \
+            cell[dp-1] += cell[dp], then cell[dp] = 0"
+        );
+        assert_eq!(
+            summary(source, "2:16"),
+            Some("this loop was recognized as clear: cell[dp] = 0".to_string())
+        );
+        assert_eq!(
+            summary(source, "2:20"),
+            Some("this loop was recognized as scan: dp -= 1 until cell[dp] is 0".to_string())
+        );
+        assert_eq!(
+            summary("[->+<]", "1:1"),
+            Some(
+                "this loop was recognized as move: cell[dp+1] += cell[dp], then cell[dp] = 0"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_explain_instructions() {
+        let source = "+++ comment\n[--.]";
+
+        assert_eq!(
+            summary(source, "1:2"),
+            Some("cell[dp] += 3, 3 instructions folded into one".to_string())
+        );
+        assert_eq!(
+            summary(",, comment\n.,", "1:2"),
+            Some("reads a byte into cell[dp]".to_string())
+        );
+        assert_eq!(summary(source, "1:6"), None);
+        assert_eq!(
+            summary(source, "2:1"),
+            Some(
+                "this loop was not optimized, it executes its 2 instructions once per iteration"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            summary(source, "2:5"),
+            Some("the end of a loop, it jumps back to its start if cell[dp] is not 0".to_string())
+        );
+    }
+}
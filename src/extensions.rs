@@ -0,0 +1,108 @@
+//! Instructions beyond the eight of brainfuck, like `!` to exit with the value of the cell at
+//! the data pointer. Their characters are comments in brainfuck, so a program enables them with
+//! directives like `;; extension exit`, which apply from the directive to the end of the
+//! program.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::syntax::{IDENT_EXIT, IDENT_SELECT_INPUT};
+use crate::tokenizer::{tokenize, TokenKind, DIRECTIVE_PREFIX};
+
+/// Names of the extensions a program can enable.
+///
+/// - `exit`: `!` stops the program with the value of the cell at the data pointer, which the
+///   execution environments return as [RuntimeError::Exit](crate::RuntimeError::Exit).
+/// - `env`: `%` selects the input `,` reads from by the value of the cell at the data pointer,
+///   0 for the regular input and otherwise one of the [HostInputs](crate::host::HostInputs).
+pub const EXTENSIONS: &[&str] = &["exit", "env"];
+
+/// Returns the character of the instruction the extension `name` adds.
+pub(crate) fn ident(name: &str) -> Option<u8> {
+    match name {
+        "exit" => Some(IDENT_EXIT),
+        "env" => Some(IDENT_SELECT_INPUT),
+        _ => None,
+    }
+}
+
+/// An `extension` directive that does not name one of the [EXTENSIONS].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionError {
+    /// Line of the directive, starting at 1.
+    pub line: usize,
+
+    /// Column of the directive, starting at 1.
+    pub column: usize,
+
+    pub message: String,
+}
+
+impl Display for ExtensionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid extension at line {} column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for ExtensionError {}
+
+/// Returns the name of the extension the directive `text` enables, if it is an `extension`
+/// directive.
+pub(crate) fn enabled_by(text: &str) -> Option<&str> {
+    let mut words = text[DIRECTIVE_PREFIX.len()..].split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("extension"), Some(name), None) => Some(name),
+        (Some("extension"), ..) => Some(""),
+        _ => None,
+    }
+}
+
+/// Checks that the `;; extension <name>` directives of `source` name one of the
+/// [EXTENSIONS]. Unknown extensions are ignored by the execution environments.
+pub fn check(source: &str) -> Result<(), ExtensionError> {
+    for token in tokenize(source) {
+        if token.kind != TokenKind::Directive {
+            continue;
+        }
+
+        let error = |message: String| ExtensionError {
+            line: token.line,
+            column: token.column,
+            message,
+        };
+        match enabled_by(token.text) {
+            Some("") => return Err(error("expected `extension <name>`".to_string())),
+            Some(name) if !EXTENSIONS.contains(&name) => {
+                return Err(error(format!(
+                    "unknown extension `{name}`, expected one of: {}",
+                    EXTENSIONS.join(", ")
+                )))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check;
+
+    #[test]
+    fn test_check_extensions() {
+        assert_eq!(check(";; extension exit\n+!;; name cell 0 x"), Ok(()));
+        assert_eq!(check(";; extension env\n;; extension exit\n%,!"), Ok(()));
+        assert_eq!(
+            check("+\n;; extension goto").unwrap_err().to_string(),
+            "invalid extension at line 2 column 1: unknown extension `goto`, expected one of: exit, env"
+        );
+        assert_eq!(
+            check(";; extension").unwrap_err().to_string(),
+            "invalid extension at line 1 column 1: expected `extension <name>`"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Flushing of the output shared by the execution environments that write through a
+//! [Write](std::io::Write) implementation.
+
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::FlushBehavior;
+
+/// Flushes a writer according to a [FlushBehavior], remembering what
+/// [FlushBehavior::Adaptive] needs to batch bursts of output.
+#[derive(Debug, Default)]
+pub(crate) struct Flusher {
+    /// Bytes written since the last flush.
+    pending: usize,
+
+    /// When the writer was last flushed, `None` if it has not been flushed yet.
+    last_flush: Option<Instant>,
+}
+
+impl Flusher {
+    /// Called after `n` bytes have been written to `writer`.
+    pub(crate) fn written<W: Write>(
+        &mut self,
+        flush: FlushBehavior,
+        writer: &mut W,
+        n: usize,
+    ) -> io::Result<()> {
+        match flush {
+            FlushBehavior::OnWrite => writer.flush(),
+            FlushBehavior::Adaptive(adaptive) => {
+                self.pending += n;
+                let now = Instant::now();
+                let idle = self
+                    .last_flush
+                    .is_none_or(|last_flush| now - last_flush >= adaptive.idle);
+                if idle || self.pending >= adaptive.max_buffered {
+                    self.pending = 0;
+                    self.last_flush = Some(now);
+                    writer.flush()?;
+                }
+                Ok(())
+            }
+            FlushBehavior::Disabled | FlushBehavior::OnEnd => Ok(()),
+        }
+    }
+
+    /// Called before a byte is read, so a prompt written in a burst is visible while the
+    /// program waits for input.
+    pub(crate) fn reading<W: Write>(
+        &mut self,
+        flush: FlushBehavior,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        if matches!(flush, FlushBehavior::Adaptive(_)) && self.pending > 0 {
+            self.pending = 0;
+            self.last_flush = Some(Instant::now());
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Called after all instructions have been executed.
+    pub(crate) fn finished<W: Write>(
+        &mut self,
+        flush: FlushBehavior,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        match flush {
+            FlushBehavior::OnEnd | FlushBehavior::Adaptive(_) => writer.flush(),
+            FlushBehavior::Disabled | FlushBehavior::OnWrite => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::time::Duration;
+
+    use super::Flusher;
+    use crate::{AdaptiveFlush, FlushBehavior};
+
+    /// Counts the calls to `flush`.
+    #[derive(Default)]
+    struct FlushCounter(usize);
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_adaptive_batches_bursts() {
+        let flush = FlushBehavior::Adaptive(AdaptiveFlush {
+            idle: Duration::from_secs(3600),
+            max_buffered: 4,
+        });
+        let mut writer = FlushCounter::default();
+        let mut flusher = Flusher::default();
+
+        // The first write flushes, the burst after it only every 4 bytes, leaving 1 pending.
+        for _ in 0..10 {
+            flusher.written(flush, &mut writer, 1).unwrap();
+        }
+        assert_eq!(writer.0, 3);
+
+        flusher.reading(flush, &mut writer).unwrap();
+        flusher.reading(flush, &mut writer).unwrap();
+        assert_eq!(writer.0, 4);
+    }
+
+    #[test]
+    fn test_adaptive_flushes_after_idle() {
+        let flush = FlushBehavior::Adaptive(AdaptiveFlush {
+            idle: Duration::ZERO,
+            max_buffered: usize::MAX,
+        });
+        let mut writer = FlushCounter::default();
+        let mut flusher = Flusher::default();
+
+        for _ in 0..3 {
+            flusher.written(flush, &mut writer, 1).unwrap();
+        }
+        assert_eq!(writer.0, 3);
+    }
+}
@@ -0,0 +1,188 @@
+//! Renders how often each cell of the tape was written to.
+
+use std::fmt::Write;
+
+/// Characters used to draw cells, ordered by increasing number of writes.
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+/// Returns the number of cells to render: every cell up to the last one that was written to.
+fn rendered_len(counts: &[u64]) -> usize {
+    counts
+        .iter()
+        .rposition(|count| *count > 0)
+        .map_or(0, |last| last + 1)
+}
+
+/// Scales `count` logarithmically to `0..=max_level`, where only cells that were never written
+/// to are at level zero.
+fn level(count: u64, max_count: u64, max_level: u64) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    let log = |n: u64| (n as f64 + 1.0).ln();
+    let scaled = (log(count) / log(max_count) * (max_level - 1) as f64).round() as u64;
+    1 + scaled.min(max_level - 1)
+}
+
+/// Renders the write counts per cell as text with `width` cells per row.
+///
+/// Each row starts with the index of its first cell, the legend at the end shows the number of
+/// writes of the most written cell.
+pub fn render_text(counts: &[u64], width: usize) -> String {
+    let len = rendered_len(counts);
+    let max_count = counts.iter().copied().max().unwrap_or(0);
+    let mut text = String::new();
+
+    if len == 0 {
+        return "no cells were written to\n".to_string();
+    }
+
+    for (row, cells) in counts[..len].chunks(width).enumerate() {
+        write!(text, "{:>6} │", row * width).unwrap();
+        for count in cells {
+            text.push(SHADES[level(*count, max_count, SHADES.len() as u64 - 1) as usize]);
+        }
+        text.push('\n');
+    }
+
+    writeln!(
+        text,
+        "{:>6}  {} 1 write, {} {max_count} writes",
+        "",
+        SHADES[1],
+        SHADES[SHADES.len() - 1]
+    )
+    .unwrap();
+
+    text
+}
+
+/// Renders the write counts per cell as a grayscale PNG image with `width` cells per row,
+/// where every cell is a square of `scale` pixels.
+#[cfg(feature = "png")]
+pub fn render_png(counts: &[u64], width: usize, scale: usize) -> Vec<u8> {
+    let len = rendered_len(counts).max(1);
+    let max_count = counts.iter().copied().max().unwrap_or(0);
+    let rows = len.div_ceil(width);
+
+    let mut pixels = Vec::with_capacity((width * scale + 1) * rows * scale);
+    for row in 0..rows {
+        let mut line = Vec::with_capacity(width * scale + 1);
+        // Filter type `None` for the scanline.
+        line.push(0);
+        for column in 0..width {
+            let count = counts.get(row * width + column).copied().unwrap_or(0);
+            let shade = level(count, max_count, 255) as u8;
+            line.extend(std::iter::repeat_n(shade, scale));
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+
+    png::encode_grayscale((width * scale) as u32, (rows * scale) as u32, &pixels)
+}
+
+#[cfg(feature = "png")]
+mod png {
+    /// Encodes filtered 8-bit grayscale scanlines as a PNG image using uncompressed deflate
+    /// blocks.
+    pub fn encode_grayscale(width: u32, height: u32, scanlines: &[u8]) -> Vec<u8> {
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        // Bit depth 8, grayscale, deflate, adaptive filtering, no interlacing.
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+
+        write_chunk(&mut png, b"IDAT", &zlib_stored(scanlines));
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let mut zlib = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = data.chunks(u16::MAX as usize).collect();
+
+        if blocks.is_empty() {
+            zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            zlib.push(u8::from(i == blocks.len() - 1));
+            let len = block.len() as u16;
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+
+        zlib.extend_from_slice(&adler32(data).to_be_bytes());
+        zlib
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = u32::MAX;
+        for byte in data {
+            crc ^= *byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u32, 0u32);
+        for byte in data {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        (b << 16) | a
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{adler32, crc32};
+
+        #[test]
+        fn test_checksums() {
+            assert_eq!(crc32(b"IEND"), 0xae42_6082);
+            assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_text;
+
+    #[test]
+    fn test_render_text() {
+        let counts = [1, 0, 100, 10, 0, 0, 0];
+
+        assert_eq!(
+            render_text(&counts, 2),
+            "     0 │░ \n     2 │█▓\n        ░ 1 write, █ 100 writes\n"
+        );
+    }
+
+    #[test]
+    fn test_render_nothing_written() {
+        assert_eq!(render_text(&[0; 4], 2), "no cells were written to\n");
+    }
+}
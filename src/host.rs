@@ -0,0 +1,159 @@
+//! Inputs the host provides besides the regular input, e.g. environment variables or values
+//! given on the command line, so scripts can parameterize a program without generating its
+//! source. A program that enabled the `env` [extension](crate::extensions) selects the input
+//! `,` reads from with `%`.
+//!
+//! Only the inputs added to [HostInputs] can be read, so a program never sees other
+//! environment variables.
+
+use std::io::{Cursor, Read};
+
+use crate::RuntimeError;
+
+/// The inputs a program can select with `%`, numbered from 1 in the order they were added. The
+/// number 0 selects the regular input.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HostInputs {
+    inputs: Vec<(String, Vec<u8>)>,
+}
+
+impl HostInputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an input named `name` with the bytes of `value`.
+    pub fn with_value(mut self, name: impl Into<String>, value: impl Into<Vec<u8>>) -> Self {
+        self.inputs.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds the value of the environment variable `name` as input, which is empty if the
+    /// variable is not set.
+    pub fn with_env_var(self, name: &str) -> Self {
+        let value = std::env::var_os(name).unwrap_or_default();
+        #[cfg(unix)]
+        let value = std::os::unix::ffi::OsStringExt::into_vec(value);
+        #[cfg(not(unix))]
+        let value = value.to_string_lossy().into_owned().into_bytes();
+        self.with_value(name, value)
+    }
+
+    /// Returns the names of the inputs in the order of their numbers.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.inputs.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the bytes of the input with the number `n`, starting at 1.
+    pub fn get(&self, n: usize) -> Option<&[u8]> {
+        let index = n.checked_sub(1)?;
+        self.inputs.get(index).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+/// The input the execution environments read from, which `%` switches between the regular
+/// input and the [HostInputs].
+#[derive(Debug, Default, Clone)]
+pub(crate) struct InputSelector<'a> {
+    inputs: Option<&'a HostInputs>,
+
+    /// The rest of the selected host input, or `None` if the regular input is selected.
+    selected: Option<Cursor<&'a [u8]>>,
+}
+
+impl<'a> InputSelector<'a> {
+    pub fn new(inputs: &'a HostInputs) -> Self {
+        Self {
+            inputs: Some(inputs),
+            selected: None,
+        }
+    }
+
+    /// Selects the host input with the number `n` from its first byte, or the regular input if
+    /// `n` is 0.
+    pub fn select(&mut self, n: u64) -> Result<(), RuntimeError> {
+        if n == 0 {
+            self.selected = None;
+            return Ok(());
+        }
+        let input = usize::try_from(n)
+            .ok()
+            .and_then(|n| self.inputs?.get(n))
+            .ok_or(RuntimeError::UnknownInput(n))?;
+        self.selected = Some(Cursor::new(input));
+        Ok(())
+    }
+
+    /// Returns the selected input, which is `reader` unless a host input is selected.
+    pub fn reader<'r>(&'r mut self, reader: &'r mut dyn Read) -> &'r mut dyn Read {
+        match &mut self.selected {
+            Some(input) => input,
+            None => reader,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let inputs = HostInputs::new()
+            .with_value("name", "ab")
+            .with_value("empty", "");
+        assert_eq!(inputs.names().collect::<Vec<_>>(), ["name", "empty"]);
+        assert_eq!(inputs.get(0), None);
+        assert_eq!(inputs.get(1), Some(b"ab".as_slice()));
+
+        let mut selector = InputSelector::new(&inputs);
+        let mut regular = io::Cursor::new(b"xy");
+        let mut read = |selector: &mut InputSelector| {
+            let mut byte = [0];
+            let n = selector.reader(&mut regular).read(&mut byte).unwrap();
+            (n > 0).then_some(byte[0])
+        };
+
+        assert_eq!(read(&mut selector), Some(b'x'));
+        selector.select(1).unwrap();
+        assert_eq!(read(&mut selector), Some(b'a'));
+        selector.select(0).unwrap();
+        assert_eq!(read(&mut selector), Some(b'y'));
+        // Selecting an input again starts from its first byte.
+        selector.select(1).unwrap();
+        assert_eq!(read(&mut selector), Some(b'a'));
+        assert_eq!(read(&mut selector), Some(b'b'));
+        assert_eq!(read(&mut selector), None);
+        selector.select(2).unwrap();
+        assert_eq!(read(&mut selector), None);
+
+        assert!(matches!(
+            selector.select(3),
+            Err(RuntimeError::UnknownInput(3))
+        ));
+        assert!(matches!(
+            InputSelector::default().select(1),
+            Err(RuntimeError::UnknownInput(1))
+        ));
+    }
+
+    #[test]
+    fn test_env_var() {
+        std::env::set_var("BRAINFUCK_TEST_HOST_INPUT", "value");
+        let inputs = HostInputs::new()
+            .with_env_var("BRAINFUCK_TEST_HOST_INPUT")
+            .with_env_var("BRAINFUCK_TEST_HOST_INPUT_UNSET");
+        assert_eq!(inputs.get(1), Some(b"value".as_slice()));
+        assert_eq!(inputs.get(2), Some(b"".as_slice()));
+    }
+}
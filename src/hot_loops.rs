@@ -0,0 +1,193 @@
+//! Ranks the loops and source positions a program spent its instructions in, from how often
+//! the [virtual machine](crate::virtual_machine::VirtualMachine::with_instruction_counts)
+//! executed each instruction, so the hot loops of a program stand out.
+
+use std::cmp::Reverse;
+use std::fmt::{self, Display};
+
+use crate::compiler::Instruction;
+use crate::source_map::Span;
+
+/// Number of loops and positions a [Profile] describes.
+pub const TOP: usize = 10;
+
+/// How much a loop or the instruction at a source position executed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Entry {
+    /// Span of the instruction, or of the `[` of a loop.
+    pub span: Span,
+
+    /// Number of iterations of a loop, or executions of the instructions at a position.
+    pub executions: u64,
+
+    /// Number of instructions executed, including nested loops, or of all instructions at a
+    /// position, like the instructions a loop was lowered to.
+    pub instructions: u64,
+}
+
+/// The loops and positions of an execution, most executed instructions first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub loops: Vec<Entry>,
+    pub positions: Vec<Entry>,
+
+    /// Number of instructions executed in total.
+    pub total: u64,
+}
+
+impl Profile {
+    /// Creates the profile of an execution of `instructions` with the span of every instruction
+    /// in `spans` and how often it was executed in `counts`. Loops and positions that never
+    /// executed are left out.
+    pub fn new(instructions: &[Instruction], spans: &[Span], counts: &[u64]) -> Self {
+        let mut loops: Vec<Entry> = instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(start, instruction)| match instruction {
+                // The loop ends with the `JumpNotZero` before the target of the jump, which
+                // every iteration executes once.
+                Instruction::JumpZero(n) => Some(Entry {
+                    span: spans[start],
+                    executions: counts[start + n - 1],
+                    instructions: counts[start..start + n].iter().sum(),
+                }),
+                _ => None,
+            })
+            .filter(|entry| entry.instructions > 0)
+            .collect();
+        // The instructions of a lowered loop share its span, so they are one position.
+        let mut positions: Vec<Entry> = Vec::new();
+        for (span, count) in spans.iter().zip(counts) {
+            match positions.last_mut() {
+                Some(last) if last.span == *span => {
+                    last.executions = last.executions.max(*count);
+                    last.instructions += count;
+                }
+                _ => positions.push(Entry {
+                    span: *span,
+                    executions: *count,
+                    instructions: *count,
+                }),
+            }
+        }
+        positions.retain(|entry| entry.instructions > 0);
+
+        // Stable sorts keep entries with as many instructions in the order of the source.
+        loops.sort_by_key(|entry| Reverse(entry.instructions));
+        positions.sort_by_key(|entry| Reverse(entry.instructions));
+        Self {
+            loops,
+            positions,
+            total: counts.iter().sum(),
+        }
+    }
+
+    /// Returns the share of all executed instructions in percent.
+    fn percent(&self, instructions: u64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        instructions as f64 * 100.0 / self.total as f64
+    }
+}
+
+/// Describes the [TOP] loops and positions, one per line, e.g. `loop at line 12 column 4
+/// executed 1.2M times, 68% of instructions`.
+impl Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hot loops:")?;
+        if self.loops.is_empty() {
+            writeln!(f, "  no loop was executed")?;
+        }
+        for entry in self.loops.iter().take(TOP) {
+            writeln!(
+                f,
+                "  loop at line {} column {} executed {}, {:.0}% of instructions",
+                entry.span.line,
+                entry.span.column,
+                times(entry.executions),
+                self.percent(entry.instructions)
+            )?;
+        }
+        writeln!(f, "hot positions:")?;
+        for entry in self.positions.iter().take(TOP) {
+            writeln!(
+                f,
+                "  line {} column {} executed {}, {:.0}% of instructions",
+                entry.span.line,
+                entry.span.column,
+                times(entry.executions),
+                self.percent(entry.instructions)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns how many times something executed, e.g. `once` or `1.2M times`.
+fn times(n: u64) -> String {
+    match n {
+        1 => "once".to_string(),
+        n => format!("{} times", abbreviate(n)),
+    }
+}
+
+/// Returns `n` with one decimal and a suffix for thousands, millions or billions, e.g. `1.2M`.
+fn abbreviate(n: u64) -> String {
+    let (divisor, suffix) = match n {
+        0..=999 => return n.to_string(),
+        1_000..=999_999 => (1e3, "K"),
+        1_000_000..=999_999_999 => (1e6, "M"),
+        _ => (1e9, "G"),
+    };
+    format!("{:.1}{suffix}", n as f64 / divisor)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::source_map;
+    use crate::virtual_machine::VirtualMachine;
+    use crate::{EofBehavior, FlushBehavior};
+
+    #[test]
+    fn test_profile() {
+        let source = "++[>+++\n  [.-]<-]";
+        let instructions = Compiler::new(source).compile().unwrap();
+        let spans = source_map::instruction_spans(source);
+        let (mut reader, mut writer) = (io::empty(), io::sink());
+        let mut vm =
+            VirtualMachine::new(&instructions, &mut reader, &mut writer).with_instruction_counts();
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+        let profile = Profile::new(&instructions, &spans, vm.instruction_counts().unwrap());
+
+        let loops: Vec<_> = profile
+            .loops
+            .iter()
+            .map(|entry| (entry.span.line, entry.span.column, entry.executions))
+            .collect();
+        assert_eq!(loops, [(1, 3, 2), (2, 3, 6)]);
+        assert_eq!(profile.loops[0].instructions, profile.total - 1);
+        // The instructions of the inner loop run as often, the first one ranks first.
+        let hottest = profile.positions[0];
+        assert_eq!((hottest.span.line, hottest.span.column), (2, 4));
+        assert_eq!(hottest.executions, 6);
+        assert!(profile
+            .to_string()
+            .starts_with("hot loops:\n  loop at line 1 column 3 executed 2 times, "));
+    }
+
+    #[test]
+    fn test_abbreviate() {
+        assert_eq!(abbreviate(999), "999");
+        assert_eq!(abbreviate(1_234), "1.2K");
+        assert_eq!(abbreviate(1_200_000), "1.2M");
+        assert_eq!(abbreviate(3_000_000_000), "3.0G");
+        assert_eq!(times(1), "once");
+        assert_eq!(times(2_500), "2.5K times");
+    }
+}
@@ -1,33 +1,88 @@
-use std::io::{self, Read, Write};
-
+use std::io::{Read, Write};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use crate::flush::Flusher;
+use crate::host::{HostInputs, InputSelector};
+use crate::limits::Watchdog;
+use crate::report::{Counter, ExecReport};
+use crate::step::{StepIo, StepResult};
 use crate::syntax::{
-    IDENT_DEC_DATA, IDENT_DEC_DP, IDENT_INC_DATA, IDENT_INC_DP, IDENT_JUMP_NOT_ZERO,
-    IDENT_JUMP_ZERO, IDENT_READ_BYTE, IDENT_WRITE_BYTE,
+    IDENT_DEC_DATA, IDENT_DEC_DP, IDENT_EXIT, IDENT_INC_DATA, IDENT_INC_DP, IDENT_JUMP_NOT_ZERO,
+    IDENT_JUMP_ZERO, IDENT_READ_BYTE, IDENT_SELECT_INPUT, IDENT_WRITE_BYTE,
+};
+use crate::tape::{Cell, Tape, TapePolicy};
+use crate::throttle::Throttle;
+use crate::{remove_non_idents, EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+/// The characters of the instructions, which name them in an [ExecReport].
+const NAMES: [&str; 10] = [">", "<", "+", "-", ".", ",", "[", "]", "!", "%"];
+
+/// The index into [NAMES] of every instruction by its character, so counting an instruction
+/// does not search for it.
+const OPCODES: [u8; 256] = {
+    let mut opcodes = [0; 256];
+    let mut opcode = 0;
+    while opcode < NAMES.len() {
+        opcodes[NAMES[opcode].as_bytes()[0] as usize] = opcode as u8;
+        opcode += 1;
+    }
+    opcodes
 };
-use crate::{remove_non_idents, FlushBehavior};
 
-/// The memory size that is available to a Brainfuck program.
-const DATA_SIZE: usize = 30_000;
+/// What [execute_instruction](Interpreter::execute_instruction) did, the part of a [StepIo]
+/// the execution loops need.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Executed {
+    /// The instruction neither read nor wrote.
+    Other,
+
+    /// The instruction read the byte, or reached the end of the input if it is `None`.
+    Read(Option<u8>),
+
+    /// The instruction wrote the byte.
+    Write(u8),
+}
 
-/// An interpreter that can execute Brainfuck code.
-pub struct Interpreter<'a, R, W> {
+/// An interpreter that can execute Brainfuck code on cells of type `C`.
+pub struct Interpreter<'a, R, W, C = u8> {
     /// Code to execute.
     code: Vec<u8>,
 
+    /// The position of the matching bracket of every bracket in `code`.
+    jumps: Vec<usize>,
+
     /// Instruction pointer into `code`.
     ip: usize,
 
     /// Zero initialized, available memory for `code`.
-    data: Vec<u8>,
+    data: Tape<C>,
 
     /// Data pointer into `data`.
     dp: usize,
 
+    /// Counts the instructions of every kind and the bytes read and written so far.
+    counter: Counter<{ NAMES.len() }>,
+
     /// Reader to read a byte from when the input instruction is encountered.
     reader: &'a mut R,
 
+    /// Switches between `reader` and the host inputs.
+    input: InputSelector<'a>,
+
     /// Writer to write a byte to when the output instruction is encountered.
     writer: &'a mut W,
+
+    /// Decides when `writer` is flushed.
+    flusher: Flusher,
+
+    /// Slows the execution down, if enabled with [with_speed](Interpreter::with_speed).
+    throttle: Option<Throttle>,
+
+    /// Stops the execution, if limited with [with_max_steps](Interpreter::with_max_steps)
+    /// or [with_timeout](Interpreter::with_timeout), or cancellable with
+    /// [with_cancel_flag](Interpreter::with_cancel_flag).
+    watchdog: Option<Watchdog>,
 }
 
 impl<'a, R, W> Interpreter<'a, R, W>
@@ -37,87 +92,393 @@ where
 {
     /// Creates a new interpreter to execute Brainfuck code.
     pub fn new(code: &'a str, reader: &'a mut R, writer: &'a mut W) -> Self {
+        let code = remove_non_idents(code);
         Self {
-            code: remove_non_idents(code),
+            jumps: match_brackets(&code),
+            code,
             ip: 0,
-            data: vec![0; DATA_SIZE],
+            data: Tape::new(DEFAULT_TAPE_SIZE, TapePolicy::Fixed),
             dp: 0,
+            counter: Counter::new(),
             reader,
+            input: InputSelector::default(),
             writer,
+            flusher: Flusher::default(),
+            throttle: None,
+            watchdog: None,
         }
     }
+}
 
-    /// Executes the program, returning an error if reading from the reader
-    /// or writing to the writer fails.
-    pub fn execute(&mut self, flush: FlushBehavior) -> io::Result<()> {
-        while self.ip < self.code.len() {
-            let instruction = self.code[self.ip];
-            match instruction {
-                IDENT_INC_DP => {
-                    self.dp += 1;
-                    assert!(self.dp < DATA_SIZE);
-                }
-                IDENT_DEC_DP => self.dp -= 1,
-                IDENT_INC_DATA => self.data[self.dp] = self.data[self.dp].wrapping_add(1),
-                IDENT_DEC_DATA => self.data[self.dp] = self.data[self.dp].wrapping_sub(1),
-                IDENT_READ_BYTE => self
-                    .reader
-                    .read_exact(&mut self.data[self.dp..self.dp + 1])?,
-                IDENT_WRITE_BYTE => {
-                    self.writer.write_all(&self.data[self.dp..self.dp + 1])?;
-                    if flush == FlushBehavior::OnWrite {
-                        self.writer.flush()?;
-                    }
-                }
-                IDENT_JUMP_ZERO if self.data[self.dp] == 0 => {
-                    let mut brackets = 0;
-                    loop {
-                        match self.code[self.ip] {
-                            IDENT_JUMP_ZERO => brackets += 1,
-                            IDENT_JUMP_NOT_ZERO => brackets -= 1,
-                            _ => {}
-                        };
-                        if brackets == 0 {
-                            break;
-                        }
-                        self.ip += 1;
-                    }
+impl<'a, R, W, C> Interpreter<'a, R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    /// Executes the program on cells of type `C` instead of bytes, e.g.
+    /// `Interpreter::new(code, reader, writer).with_cells::<u16>()`.
+    pub fn with_cells<D: Cell>(self) -> Interpreter<'a, R, W, D> {
+        Interpreter {
+            code: self.code,
+            jumps: self.jumps,
+            ip: self.ip,
+            data: Tape::new(self.data.len(), self.data.policy()),
+            dp: self.dp,
+            counter: self.counter,
+            reader: self.reader,
+            input: self.input,
+            writer: self.writer,
+            flusher: self.flusher,
+            throttle: self.throttle,
+            watchdog: self.watchdog,
+        }
+    }
+
+    /// Sets the number of cells of the tape, which is [DEFAULT_TAPE_SIZE] by default.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.data = Tape::new(tape_size, self.data.policy());
+        self
+    }
+
+    /// Sets what happens when the data pointer moves past the end of the tape.
+    pub fn with_tape_policy(mut self, policy: TapePolicy) -> Self {
+        self.data = Tape::new(self.data.len(), policy);
+        self
+    }
+
+    /// Execute at most `speed` instructions per second by sleeping between batches of
+    /// instructions, e.g. to follow a program in slow motion.
+    pub fn with_speed(mut self, speed: u64) -> Self {
+        self.throttle = Some(Throttle::new(speed));
+        self
+    }
+
+    /// Fails with [RuntimeError::LimitExceeded] instead of executing more than `max` steps,
+    /// one per command of the source, e.g. to stop an untrusted program that loops forever.
+    pub fn with_max_steps(mut self, max: u64) -> Self {
+        self.watchdog
+            .get_or_insert_with(Watchdog::default)
+            .max_steps = Some(max);
+        self
+    }
+
+    /// Fails with [RuntimeError::LimitExceeded] once the execution took longer than `timeout`.
+    ///
+    /// The time is checked between batches of instructions, so a read that blocks is not
+    /// interrupted.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).timeout = Some(timeout);
+        self
+    }
+
+    /// Fails with [RuntimeError::Cancelled] once `flag` is set, e.g. by a signal handler on
+    /// Ctrl-C. Like the time, the flag is checked between batches of instructions.
+    pub fn with_cancel_flag(mut self, flag: &'static AtomicBool) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).cancel = Some(flag);
+        self
+    }
+
+    /// Lets the program read the host inputs once it selected them with `%`, see the `env`
+    /// [extension](crate::extensions).
+    pub fn with_host_inputs(mut self, inputs: &'a HostInputs) -> Self {
+        self.input = InputSelector::new(inputs);
+        self
+    }
+
+    /// Executes the program, returning an error if the data pointer moves outside of the tape
+    /// or reading from the reader or writing to the writer fails, or else what the execution
+    /// did so far.
+    ///
+    /// Reading past the end of the input is handled according to `eof`.
+    pub fn execute(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<ExecReport, RuntimeError> {
+        let start = Instant::now();
+        let result = self.execute_steps(flush, eof);
+        self.counter.elapsed += start.elapsed();
+        result.map(|()| self.report())
+    }
+
+    fn execute_steps(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        // Without a throttle, nothing has to be done after an instruction.
+        if self.throttle.is_none() {
+            return self.execute_with(flush, eof, |_, _| Ok(()));
+        }
+        let mut executed = 0;
+        self.execute_with(flush, eof, |interpreter, _| {
+            if let Some(throttle) = &mut interpreter.throttle {
+                executed += 1;
+                if executed == throttle.batch() {
+                    throttle.wait(executed);
+                    executed = 0;
                 }
-                IDENT_JUMP_NOT_ZERO if self.data[self.dp] != 0 => {
-                    let mut brackets = 0;
-                    loop {
-                        match self.code[self.ip] {
-                            IDENT_JUMP_ZERO => brackets -= 1,
-                            IDENT_JUMP_NOT_ZERO => brackets += 1,
-                            _ => {}
-                        };
-                        if brackets == 0 {
-                            break;
-                        }
-                        self.ip -= 1;
-                    }
+            }
+            Ok(())
+        })
+    }
+
+    /// Executes all remaining instructions, calling `observe` with what every one of them did,
+    /// and flushes the output.
+    ///
+    /// The watchdog counts the instructions in batches of its allowance, so it does not slow
+    /// down every instruction, or of the [throttle](Interpreter::with_speed) if that is smaller.
+    #[inline(always)]
+    pub(crate) fn execute_with(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+        mut observe: impl FnMut(&mut Self, Executed) -> Result<(), RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        while !self.is_finished() {
+            let allowance = match &mut self.watchdog {
+                Some(watchdog) => watchdog.allowance(),
+                None => u64::MAX,
+            };
+            // A slowed down execution is stopped within a batch of the throttle.
+            let allowance = match &self.throttle {
+                Some(throttle) => allowance.min(throttle.batch()),
+                None => allowance,
+            };
+            let mut executed = 0;
+            while executed < allowance && !self.is_finished() {
+                let instruction = self.execute_instruction(flush, eof)?;
+                executed += 1;
+                observe(self, instruction)?;
+            }
+            let finished = self.is_finished();
+            if let (Some(watchdog), false) = (&mut self.watchdog, finished) {
+                watchdog.count(executed)?;
+                if watchdog.allowance() == 0 {
+                    return Err(watchdog.exceeded());
                 }
-                _ => {}
             }
+        }
+
+        self.finish(flush)
+    }
+
+    /// Returns whether all instructions have been executed.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.ip >= self.code.len()
+    }
 
-            self.ip += 1;
+    /// Executes the instruction at the instruction pointer and returns what it did, or `None`
+    /// once all instructions have been executed and the output has been flushed.
+    pub fn step(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<Option<StepResult<char>>, RuntimeError> {
+        if self.is_finished() {
+            self.finish(flush)?;
+            return Ok(None);
+        }
+        if let Some(watchdog) = &mut self.watchdog {
+            if watchdog.allowance() == 0 {
+                return Err(watchdog.exceeded());
+            }
+            watchdog.count(1)?;
         }
 
-        if flush == FlushBehavior::OnEnd {
-            self.writer.flush()
-        } else {
-            Ok(())
+        let instruction = self.code[self.ip];
+        let io = match self.execute_instruction(flush, eof)? {
+            Executed::Other => StepIo::None,
+            Executed::Read(byte) => StepIo::Read(byte),
+            Executed::Write(byte) => StepIo::Write { byte, count: 1 },
+        };
+        Ok(Some(StepResult {
+            instruction: instruction as char,
+            ip: self.ip,
+            dp: self.dp,
+            io,
+        }))
+    }
+
+    /// Executes the instruction at the instruction pointer, which has to be in the code, like
+    /// [step](Interpreter::step) without counting it with the watchdog or describing it, as
+    /// the execution loops run it for every instruction.
+    #[inline(always)]
+    fn execute_instruction(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<Executed, RuntimeError> {
+        let out_of_bounds = |dp| RuntimeError::PointerOutOfBounds { ip: self.ip, dp };
+        let instruction = self.code[self.ip];
+        // The code only contains instructions, which all have an opcode.
+        self.counter.instructions[OPCODES[instruction as usize] as usize] += 1;
+        let mut executed = Executed::Other;
+        match instruction {
+            IDENT_INC_DP => {
+                self.dp = self
+                    .data
+                    .offset(self.dp, 1)
+                    .map_err(|err| out_of_bounds(err.0))?;
+                self.counter.peak_dp = self.counter.peak_dp.max(self.dp);
+            }
+            IDENT_DEC_DP => self.dp = self.dp.checked_sub(1).ok_or(out_of_bounds(-1))?,
+            IDENT_INC_DATA => self.data[self.dp] = self.data[self.dp].wrapping_add(C::truncate(1)),
+            IDENT_DEC_DATA => self.data[self.dp] = self.data[self.dp].wrapping_sub(C::truncate(1)),
+            IDENT_READ_BYTE => {
+                self.flusher.reading(flush, self.writer)?;
+                let reader = self.input.reader(self.reader);
+                let byte = eof.read(reader, &mut self.data[self.dp])?;
+                if byte.is_some() {
+                    self.counter.bytes_read += 1;
+                }
+                executed = Executed::Read(byte);
+            }
+            IDENT_WRITE_BYTE => {
+                let byte = self.data[self.dp].low_byte();
+                self.writer.write_all(&[byte])?;
+                self.flusher.written(flush, self.writer, 1)?;
+                self.counter.bytes_written += 1;
+                executed = Executed::Write(byte);
+            }
+            IDENT_EXIT => {
+                self.flusher.finished(flush, self.writer)?;
+                return Err(RuntimeError::Exit(self.data[self.dp].to_u64() as u32));
+            }
+            IDENT_SELECT_INPUT => self.input.select(self.data[self.dp].to_u64())?,
+            IDENT_JUMP_ZERO if self.data[self.dp] == C::ZERO => self.ip = self.jumps[self.ip],
+            IDENT_JUMP_NOT_ZERO if self.data[self.dp] != C::ZERO => self.ip = self.jumps[self.ip],
+            _ => {}
         }
+
+        self.ip += 1;
+        Ok(executed)
+    }
+
+    /// Flushes the output once all instructions have been executed.
+    fn finish(&mut self, flush: FlushBehavior) -> Result<(), RuntimeError> {
+        Ok(self.flusher.finished(flush, self.writer)?)
+    }
+
+    /// Returns the instruction pointer into the code, which only contains valid instructions.
+    pub(crate) fn ip(&self) -> usize {
+        self.ip
     }
+
+    /// Returns the code without comments.
+    pub(crate) fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Returns the number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.counter.instructions.iter().sum()
+    }
+
+    /// Returns what the execution did so far, with the instructions named by their character.
+    pub fn report(&self) -> ExecReport {
+        self.counter.report(&NAMES)
+    }
+
+    /// Returns the data pointer.
+    pub(crate) fn dp(&self) -> usize {
+        self.dp
+    }
+
+    /// Returns the memory of the program.
+    pub(crate) fn data(&self) -> &[C] {
+        &self.data
+    }
+
+    /// Returns the tape of the program, e.g. to compare it with the tape of another execution.
+    pub fn tape(&self) -> &Tape<C> {
+        &self.data
+    }
+}
+
+/// Returns the position of the matching bracket for every bracket in `code`, so jumps do not
+/// have to search for it. An unmatched `[` jumps past the end of the code and an unmatched `]`
+/// does not jump.
+fn match_brackets(code: &[u8]) -> Vec<usize> {
+    let mut jumps: Vec<usize> = (0..code.len()).collect();
+    let mut open = Vec::new();
+    for (ip, instruction) in code.iter().enumerate() {
+        match *instruction {
+            IDENT_JUMP_ZERO => open.push(ip),
+            IDENT_JUMP_NOT_ZERO => {
+                if let Some(start) = open.pop() {
+                    (jumps[start], jumps[ip]) = (ip, start);
+                }
+            }
+            _ => {}
+        }
+    }
+    for start in open {
+        jumps[start] = code.len();
+    }
+    jumps
 }
 
 #[cfg(test)]
 mod tests {
     use std::io::{self, Cursor};
 
-    use crate::FlushBehavior;
+    use crate::step::{StepIo, StepResult};
+    use crate::{EofBehavior, FlushBehavior, Limit, RuntimeError, DEFAULT_TAPE_SIZE};
+
+    use super::{match_brackets, Interpreter};
+
+    #[test]
+    fn test_max_steps() {
+        let execute = |max| {
+            let (mut reader, mut writer) = (io::empty(), Vec::new());
+            Interpreter::new("+++ comment ++.", &mut reader, &mut writer)
+                .with_max_steps(max)
+                .execute(FlushBehavior::OnEnd, EofBehavior::default())
+                .map(|_| writer)
+        };
+
+        assert_eq!(execute(6).unwrap(), [5]);
+        assert!(matches!(
+            execute(5),
+            Err(RuntimeError::LimitExceeded(Limit::Steps(5)))
+        ));
+    }
 
-    use super::{Interpreter, DATA_SIZE};
+    #[test]
+    fn test_step() {
+        let mut reader = Cursor::new(b"a");
+        let mut writer = Vec::new();
+        let mut interpreter = Interpreter::new(",>,<.", &mut reader, &mut writer);
+        let mut step = || {
+            interpreter
+                .step(FlushBehavior::OnWrite, EofBehavior::SetZero)
+                .unwrap()
+        };
+
+        assert_eq!(
+            step(),
+            Some(StepResult {
+                instruction: ',',
+                ip: 1,
+                dp: 0,
+                io: StepIo::Read(Some(b'a'))
+            })
+        );
+        assert_eq!(step().unwrap().dp, 1);
+        assert_eq!(step().unwrap().io, StepIo::Read(None));
+        step();
+        assert_eq!(
+            step().unwrap().io,
+            StepIo::Write {
+                byte: b'a',
+                count: 1
+            }
+        );
+        assert_eq!(step(), None);
+        assert_eq!(writer, b"a");
+    }
 
     #[test]
     fn test_increment_dp() {
@@ -128,34 +489,58 @@ mod tests {
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
         assert_eq!(interpreter.dp, 0);
 
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
         assert_eq!(interpreter.dp, 1);
     }
 
     #[test]
-    #[should_panic]
     fn test_increment_dp_overflow() {
-        // Incrementing `dp` when `dp` is already the max memory size results in an overflow
-        let code = ">".repeat(DATA_SIZE);
+        // Incrementing `dp` when `dp` is already the last cell moves it outside of the tape.
+        let code = ">".repeat(DEFAULT_TAPE_SIZE);
         let mut reader = io::empty();
         let mut writer = Vec::new();
 
-        Interpreter::new(&code, &mut reader, &mut writer)
-            .execute(FlushBehavior::OnEnd)
+        let err = Interpreter::new(&code, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::PointerOutOfBounds { ip, dp }
+                if ip == DEFAULT_TAPE_SIZE - 1 && dp == DEFAULT_TAPE_SIZE as isize
+        ));
+    }
+
+    #[test]
+    fn test_tape_size() {
+        let code = ">".repeat(DEFAULT_TAPE_SIZE) + "+";
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        let mut interpreter =
+            Interpreter::new(&code, &mut reader, &mut writer).with_tape_size(DEFAULT_TAPE_SIZE + 1);
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
             .unwrap();
+
+        assert_eq!(interpreter.data[DEFAULT_TAPE_SIZE], 1);
     }
 
     #[test]
-    #[should_panic]
     fn test_decrement_dp_overflow() {
-        // Decrementing `dp` when `dp` is already 0 results in an overflow.
-        let code = "<";
+        // Decrementing `dp` when `dp` is already 0 moves it outside of the tape.
+        let code = "+<";
         let mut reader = io::empty();
         let mut writer = Vec::new();
 
-        Interpreter::new(code, &mut reader, &mut writer)
-            .execute(FlushBehavior::OnEnd)
-            .unwrap();
+        let err = Interpreter::new(code, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RuntimeError::PointerOutOfBounds { ip: 1, dp: -1 }
+        ));
     }
 
     #[test]
@@ -165,7 +550,9 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(interpreter.data[0], 1);
         assert_eq!(interpreter.data[1], 2);
@@ -178,13 +565,31 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         // Wrapping overflow because `data` is with 0 initialized.
         assert_eq!(interpreter.data[0], 255);
         assert_eq!(interpreter.data[1], 254);
     }
 
+    #[test]
+    fn test_cell_width() {
+        let code = "-.>+[+]";
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        let mut interpreter = Interpreter::new(code, &mut reader, &mut writer).with_cells::<u16>();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(interpreter.data[0], u16::MAX);
+        assert_eq!(interpreter.data[1], 0);
+        assert_eq!(writer, [255]);
+    }
+
     #[test]
     fn test_output_byte_at_dp() {
         let code = ".+.";
@@ -192,12 +597,30 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(writer[0], 0);
         assert_eq!(writer[1], 1);
     }
 
+    #[test]
+    fn test_exit() {
+        // `!` is a comment until the `exit` extension is enabled.
+        let code = "+!+\n;; extension exit\n+.!+.";
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
+        let err = interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+
+        assert!(matches!(err, RuntimeError::Exit(3)));
+        assert_eq!(writer, [3]);
+    }
+
     #[test]
     fn test_input_byte_at_dp() {
         let code = ",>,>,";
@@ -205,13 +628,42 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(interpreter.data[0], 1);
         assert_eq!(interpreter.data[1], 2);
         assert_eq!(interpreter.data[2], 3);
     }
 
+    #[test]
+    fn test_input_at_eof() {
+        let code = "+,>+,>+,";
+        let cases = [
+            (EofBehavior::SetZero, [1, 0, 0]),
+            (EofBehavior::SetMinusOne, [1, 255, 255]),
+            (EofBehavior::LeaveUnchanged, [1, 1, 1]),
+        ];
+
+        for (eof, data) in cases {
+            let mut reader = Cursor::new([1]);
+            let mut writer = Vec::new();
+
+            let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
+            interpreter.execute(FlushBehavior::OnEnd, eof).unwrap();
+
+            assert_eq!(interpreter.data[..3], data, "{eof:?}");
+        }
+
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let err = Interpreter::new(code, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
     #[test]
     fn test_loop_skip_to_back() {
         // Execute `+` because `data[0]` is 0 then at `]` do not jump back to `+` because `data[0]`
@@ -221,7 +673,9 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(interpreter.data[0], 0);
     }
@@ -235,11 +689,59 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(&writer, &[2, 1]);
     }
 
+    #[test]
+    fn test_match_brackets() {
+        assert_eq!(match_brackets(b"+[[-]>]"), [0, 6, 4, 3, 2, 5, 1]);
+        assert_eq!(match_brackets(b"[]["), [1, 0, 3]);
+        assert_eq!(match_brackets(b"]"), [0]);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let report = Interpreter::new(",[->+<]>.", &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(
+            report.instructions,
+            [
+                (">", 2),
+                ("<", 1),
+                ("+", 1),
+                ("-", 1),
+                (".", 1),
+                (",", 1),
+                ("[", 1),
+                ("]", 1)
+            ]
+        );
+        assert_eq!(report.bytes_read, 1);
+        assert_eq!(report.bytes_written, 1);
+        assert_eq!(report.peak_dp, 1);
+        assert_eq!(writer, [1]);
+    }
+
+    #[test]
+    fn test_report_reads_past_end() {
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let report = Interpreter::new(",,,", &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::SetZero)
+            .unwrap();
+
+        assert_eq!(report.instructions, [(",", 3)]);
+        assert_eq!(report.bytes_read, 1);
+    }
+
     #[test]
     fn test_program_hello_world() {
         let code = include_str!("../programs/hello_world.b");
@@ -247,7 +749,9 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(String::from_utf8(writer), Ok("Hello World!\n".into()));
     }
@@ -259,7 +763,9 @@ mod tests {
         let mut writer = Vec::new();
 
         let mut interpreter = Interpreter::new(code, &mut reader, &mut writer);
-        interpreter.execute(FlushBehavior::OnEnd).unwrap();
+        interpreter
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
 
         assert_eq!(String::from_utf8(writer), Ok("Hello World! 255\n".into()));
     }
@@ -1,293 +1,3136 @@
-use std::io;
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{mem, slice, thread};
 
-use crate::compiler::Instruction;
-use crate::jit::machine_code::MachineCode;
-use crate::mmap::MemoryMap;
+use crate::compiler::{Instruction, StepCost};
+use crate::engine::State;
+use crate::flush::Flusher;
+use crate::host::{HostInputs, InputSelector};
+/// The machine code of the architecture the crate is compiled for.
+#[cfg(target_arch = "aarch64")]
+pub use crate::jit::aarch64::MachineCode;
+/// The machine code of the architecture the crate is compiled for.
+#[cfg(target_arch = "x86_64")]
+pub use crate::jit::x86_64::MachineCode;
+use crate::limits::Watchdog;
+use crate::mmap::{Commit, Executable, MemoryMap};
+use crate::tape::{Cell, CellWidth, TapePolicy};
+use crate::timing::{Phase, PhaseTimes, Stopwatch};
+use crate::{EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+/// Stack size of the thread started by [JitCompiler::execute_on_thread] if none is given.
+pub const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Number of cells reserved for a [growable](TapePolicy::Growable) tape, unless the tape size
+/// is larger. Only the pages that the program touches are allocated.
+pub const GROWABLE_TAPE_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Returned by the generated code after executing all instructions.
+const EXIT_FINISHED: u32 = 0;
+
+/// Returned by the generated code if a callback failed, which keeps the error.
+const EXIT_ERROR: u32 = 1;
+
+/// Number of bytes the generated code writes to its output buffer before it calls back to write
+/// them, unless every write has to be flushed.
+const OUTPUT_BUFFER_SIZE: usize = 8 * 1024;
 
 /// A JIT compiler takes instructions and turns them into machine code which can be
-/// run on x64 Linux machines.
-pub struct JitCompiler<'a> {
+/// run on x64 and AArch64 machines with Linux or macOS, and on x64 machines with Windows.
+///
+/// The generated code reads and writes by calling back into the compiler, which forwards to
+/// the given reader and writer. It checks the data pointer whenever it moves and stops with
+/// [RuntimeError::PointerOutOfBounds] once it leaves the tape.
+pub struct JitCompiler<'a, R, W> {
     instructions: &'a [Instruction],
     machine_code: MachineCode,
+    tape_size: usize,
+    tape_policy: TapePolicy,
+    cell_width: CellWidth,
+    loop_counters: bool,
+    watchdog: Option<Watchdog>,
+
+    /// The costs of the instructions the generated code counts, or one step per instruction if
+    /// they are not known.
+    step_costs: Option<&'a [StepCost]>,
+
+    /// Where the last execution stopped, if a limit or the cancel flag stopped it.
+    state: Option<State>,
+    host_inputs: Option<&'a HostInputs>,
+    reader: &'a mut R,
+    writer: &'a mut W,
 }
 
-impl<'a> JitCompiler<'a> {
-    /// Create a new JIT Compiler that executes the given instructions.
-    pub fn new(instructions: &'a [Instruction]) -> Self {
+impl<'a, R, W> JitCompiler<'a, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Create a new JIT Compiler that executes the given instructions, reading input from
+    /// `reader` and writing output to `writer`.
+    pub fn new(instructions: &'a [Instruction], reader: &'a mut R, writer: &'a mut W) -> Self {
         Self {
             instructions,
             machine_code: MachineCode::default(),
+            tape_size: DEFAULT_TAPE_SIZE,
+            tape_policy: TapePolicy::Fixed,
+            cell_width: CellWidth::U8,
+            loop_counters: false,
+            watchdog: None,
+            step_costs: None,
+            state: None,
+            host_inputs: None,
+            reader,
+            writer,
         }
     }
 
-    /// Emit machine code which will then execute the given instructions.
-    pub fn execute(mut self) -> io::Result<()> {
-        let data = vec![0; 30_000];
+    /// Sets the width of the cells, which is [CellWidth::U8] by default.
+    pub fn with_cell_width(mut self, cell_width: CellWidth) -> Self {
+        self.machine_code = MachineCode::new(cell_width);
+        self.cell_width = cell_width;
+        self
+    }
+
+    /// Sets the number of cells of the tape, which is [DEFAULT_TAPE_SIZE] by default.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = tape_size;
+        self
+    }
+
+    /// Sets what happens when the data pointer moves past the end of the tape.
+    ///
+    /// The generated code can not grow the tape, so instead a growable tape reserves
+    /// [GROWABLE_TAPE_SIZE] cells up front.
+    pub fn with_tape_policy(mut self, policy: TapePolicy) -> Self {
+        self.tape_policy = policy;
+        self
+    }
 
-        self.machine_code.emit_stack_setup(&data[0]);
+    /// Counts the iterations of every loop, which the [compiled](JitCompiler::compile) program
+    /// reports as its [loop counts](CompiledProgram::loop_counts).
+    ///
+    /// The generated code increments a counter at the start of every iteration, without
+    /// calling back.
+    pub fn with_loop_counters(mut self) -> Self {
+        self.loop_counters = true;
+        self
+    }
 
-        for (i, instruction) in self.instructions.iter().enumerate() {
-            match instruction {
-                Instruction::IncDP(n) => self.machine_code.emit_inc_dp(*n),
-                Instruction::DecDP(n) => self.machine_code.emit_dec_dp(*n),
-                Instruction::IncByteAtDP(n) => self.machine_code.emit_inc_byte_at_dp(*n),
-                Instruction::DecByteAtDP(n) => self.machine_code.emit_dec_byte_at_dp(*n),
-                Instruction::WriteByte(n) => self.machine_code.emit_write_byte_at_dp(*n),
-                Instruction::ReadByte => self.machine_code.emit_read_byte_at_dp(),
-                Instruction::JumpZero(n) => {
-                    assert_eq!(
-                        self.instructions[i + n - 1],
-                        Instruction::JumpNotZero(n - 2)
-                    );
+    /// Fail with [RuntimeError::LimitExceeded] instead of executing more than `max` steps,
+    /// e.g. to stop an untrusted program that loops forever. `step_costs` are the costs of the
+    /// instructions as returned by
+    /// [compile_with_step_costs](crate::compiler::Compiler::compile_with_step_costs).
+    ///
+    /// The generated code counts the steps of every iteration of a loop and calls back once
+    /// they are used up. Steps outside of loops are counted before the code up to the next loop
+    /// runs, so such a program stops before it instead of in the middle of it. Lowered loops
+    /// call back to count their steps, which depend on the cells.
+    pub fn with_max_steps(mut self, max: u64, step_costs: &'a [StepCost]) -> Self {
+        assert_eq!(step_costs.len(), self.instructions.len());
+        self.step_costs = Some(step_costs);
+        self.watchdog
+            .get_or_insert_with(Watchdog::default)
+            .max_steps = Some(max);
+        self
+    }
 
-                    let offset: usize = self.instructions[i + 1..i + n]
-                        .iter()
-                        .map(|instruction| self.get_instruction_bytes(instruction))
-                        .sum();
+    /// Fail with [RuntimeError::LimitExceeded] once the execution took longer than `timeout`.
+    ///
+    /// The time is checked when the generated code calls back to count steps, see
+    /// [with_max_steps](JitCompiler::with_max_steps), so a read that blocks is
+    /// not interrupted.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).timeout = Some(timeout);
+        self
+    }
 
-                    self.machine_code.emit_jump_zero(offset as i32)
-                }
-                Instruction::JumpNotZero(n) => {
-                    assert_eq!(self.instructions[i - n - 1], Instruction::JumpZero(n + 2));
+    /// Fail with [RuntimeError::Cancelled] once `flag` is set, e.g. by a signal handler on
+    /// Ctrl-C.
+    ///
+    /// Like the time, the flag is checked when the generated code calls back to count steps,
+    /// so the steps are counted even without a limit.
+    pub fn with_cancel_flag(mut self, flag: &'static AtomicBool) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).cancel = Some(flag);
+        self
+    }
+
+    /// Lets the program read the host inputs once it selected them with `%`, see the `env`
+    /// [extension](crate::extensions).
+    pub fn with_host_inputs(mut self, inputs: &'a HostInputs) -> Self {
+        self.host_inputs = Some(inputs);
+        self
+    }
+
+    /// Returns where the last execution stopped, if a limit or the cancel flag stopped it.
+    pub fn state(&self) -> Option<&State> {
+        self.state.as_ref()
+    }
+
+    /// Emit machine code which will then execute the given instructions, handling reads past
+    /// the end of the input according to `eof`.
+    pub fn execute(mut self, flush: FlushBehavior, eof: EofBehavior) -> Result<(), RuntimeError> {
+        self.execute_again(flush, eof)
+    }
+
+    /// Like [execute](JitCompiler::execute), but keeps the compiler, emitting the machine code
+    /// again on every call.
+    pub(crate) fn execute_again(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        self.machine_code = MachineCode::new(self.cell_width);
+        let program = self.emit()?;
+        let result = program.run(self.reader, self.writer, flush, eof);
+        self.state = program.state();
+        result
+    }
+
+    /// Emits the machine code without executing it, so it can be [run](CompiledProgram::run)
+    /// many times with any reader and writer.
+    pub fn compile(mut self) -> io::Result<CompiledProgram> {
+        self.emit()
+    }
+
+    fn emit(&mut self) -> io::Result<CompiledProgram> {
+        let stopwatch = Stopwatch::start();
+        let callbacks = Callbacks::new(self.cell_width);
+        let counters = self
+            .loop_counters
+            .then(|| LoopCounters::new(self.instructions));
+        let step_costs = self.watchdog.is_some().then(|| match self.step_costs {
+            Some(step_costs) => step_costs.to_vec(),
+            None => self.instructions.iter().map(StepCost::of).collect(),
+        });
+        self.machine_code.emit_stack_setup(callbacks.out_of_bounds);
+        emit_each(
+            &mut self.machine_code,
+            self.instructions,
+            &callbacks,
+            counters.as_ref(),
+            step_costs.as_deref(),
+            |_, _| {},
+        );
+        self.machine_code.emit_stack_teardown();
+
+        // SAFETY: The code was emitted for a whole program, between the setup and the teardown,
+        // and the counters it increments are moved into the program.
+        let mut program = unsafe {
+            CompiledProgram::from_machine_code(
+                &self.machine_code,
+                self.tape_size,
+                self.tape_policy,
+            )?
+        };
+        program.counters = counters;
+        program.watchdog = self.watchdog.clone();
+        if let Some(step_costs) = step_costs {
+            program.lowered_loops = lowered_loops(self.instructions, &step_costs);
+        }
+        program.host_inputs = self.host_inputs.cloned();
+        program.times.add(Phase::Codegen, stopwatch.elapsed());
+        Ok(program)
+    }
 
-                    let offset: usize = self.instructions[i - n..i]
-                        .iter()
-                        .map(|instruction| self.get_instruction_bytes(instruction))
-                        .sum();
+    /// Like [execute](JitCompiler::execute), but on a new thread with a stack of `stack_size`
+    /// bytes, so the generated code does not run on the stack of the caller.
+    ///
+    /// A panic while compiling or executing is returned as an error instead of unwinding
+    /// into the caller. Faults of the generated code, like segmentation faults, still abort the
+    /// whole process.
+    pub fn execute_on_thread(
+        mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+        stack_size: usize,
+    ) -> Result<(), RuntimeError>
+    where
+        R: Send,
+        W: Send,
+    {
+        self.execute_again_on_thread(flush, eof, stack_size)
+    }
+
+    /// Like [execute_on_thread](JitCompiler::execute_on_thread), but keeps the compiler like
+    /// [execute_again](JitCompiler::execute_again).
+    pub(crate) fn execute_again_on_thread(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+        stack_size: usize,
+    ) -> Result<(), RuntimeError>
+    where
+        R: Send,
+        W: Send,
+    {
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .name("brainfuck-jit".to_string())
+                .stack_size(stack_size)
+                .spawn_scoped(scope, || self.execute_again(flush, eof))?
+                .join()
+                .unwrap_or_else(|panic| {
+                    Err(RuntimeError::Io(io::Error::other(format!(
+                        "the jit compiled program panicked: {}",
+                        panic_message(&*panic)
+                    ))))
+                })
+        })
+    }
+}
+
+/// Emits the code of `instructions` without a setup and a teardown, so custom code can be
+/// emitted around it, returning its length. The instructions have to be compiled for a whole
+/// program, so every loop is closed.
+///
+/// The code calls the given `callbacks`, which have to be created for the cell width of
+/// `machine_code`, and increments `counters` if given, which have to be created for
+/// `instructions` and outlive the code.
+pub fn emit_instructions(
+    machine_code: &mut MachineCode,
+    instructions: &[Instruction],
+    callbacks: &Callbacks,
+    counters: Option<&LoopCounters>,
+) -> usize {
+    emit_each(
+        machine_code,
+        instructions,
+        callbacks,
+        counters,
+        None,
+        |_, _| {},
+    )
+}
+
+/// Returns the machine code of a whole program as hex bytes, preceded by the instruction they
+/// were emitted for, e.g. to find wrong jump displacements.
+///
+/// Bytes that keep the cell cached between instructions belong to the instruction whose code
+/// emitted them.
+pub fn dump_machine_code(instructions: &[Instruction], cell_width: CellWidth) -> String {
+    let callbacks = Callbacks::new(cell_width);
+    let mut machine_code = MachineCode::new(cell_width);
+    let mut sections = vec![("setup".to_string(), 0)];
+    machine_code.emit_stack_setup(callbacks.out_of_bounds);
+    emit_each(
+        &mut machine_code,
+        instructions,
+        &callbacks,
+        None,
+        None,
+        |i, start| sections.push((format!("{i}  {:?}", instructions[i]), start)),
+    );
+    sections.push(("teardown".to_string(), machine_code.get_buf().len()));
+    machine_code.emit_stack_teardown();
+
+    let code = machine_code.get_buf();
+    let ends = sections.iter().skip(1).map(|(_, start)| *start);
+    let mut dump = String::new();
+    for ((name, start), end) in sections.iter().zip(ends.chain([code.len()])) {
+        dump.push_str(&format!("; {name}\n"));
+        for (line, bytes) in code[*start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+            dump.push_str(&format!("{:08x}  {}\n", start + line * 16, hex.join(" ")));
+        }
+    }
+    dump
+}
+
+/// Like [emit_instructions], calling `on_instruction` with the index of every instruction and
+/// the offset of its code before emitting it. If the `fuel` costs of the instructions are set,
+/// every iteration of a loop subtracts its [cost](iteration_cost) from the steps the program may
+/// still execute.
+fn emit_each(
+    machine_code: &mut MachineCode,
+    instructions: &[Instruction],
+    callbacks: &Callbacks,
+    counters: Option<&LoopCounters>,
+    fuel: Option<&[StepCost]>,
+    mut on_instruction: impl FnMut(usize, usize),
+) -> usize {
+    let checks = bounds_checks(instructions);
+    let check = |i: usize| checks[i].then_some(i);
+
+    let mut loops = Vec::new();
+    let mut counter = 0;
+    let mut len = 0;
+    for (i, instruction) in instructions.iter().enumerate() {
+        on_instruction(i, machine_code.get_buf().len());
+        let starts_segment = i == 0 || matches!(instructions[i - 1], Instruction::JumpNotZero(_));
+        if let Some(costs) = fuel {
+            if loops.is_empty() && starts_segment {
+                let cost = straight_line_cost(instructions, costs, i);
+                len += emit_fuel(machine_code, cost, callbacks.fuel);
+            }
+            if !matches!(costs[i], StepCost::Fixed(_)) {
+                len += machine_code.emit_steps(i, callbacks.steps);
+            }
+        }
+        len += match instruction {
+            Instruction::IncDP(n) => machine_code.emit_inc_dp(*n, check(i)),
+            Instruction::DecDP(n) => machine_code.emit_dec_dp(*n, check(i)),
+            Instruction::IncByteAtDP(n) => machine_code.emit_inc_byte_at_dp(*n),
+            Instruction::DecByteAtDP(n) => machine_code.emit_dec_byte_at_dp(*n),
+            Instruction::WriteByte(n) => machine_code.emit_write_byte_at_dp(*n, callbacks.write),
+            Instruction::ReadByte => machine_code.emit_read_byte_at_dp(callbacks.read),
+            Instruction::Exit => machine_code.emit_exit(callbacks.exit),
+            Instruction::SelectInput => machine_code.emit_select_input(callbacks.select_input),
+            Instruction::SetZero => machine_code.emit_set_zero(),
+            Instruction::FindZero(stride) => machine_code.emit_find_zero(*stride, i),
+            Instruction::AddTo { offset, factor } => {
+                machine_code.emit_add_to(*offset, *factor, check(i))
+            }
+            Instruction::JumpZero(n) => {
+                assert_eq!(instructions[i + n - 1], Instruction::JumpNotZero(n - 2));
 
-                    self.machine_code.emit_jump_not_zero(offset)
+                // The body starts after the jump, the loop ends after the jump back.
+                let body = machine_code.new_label();
+                let end = machine_code.new_label();
+                let len = machine_code.emit_jump_zero(end);
+                machine_code.bind_label(body);
+                loops.push((body, end));
+                let len = match counters {
+                    Some(counters) => {
+                        counter += 1;
+                        len + machine_code.emit_count(counters.address(counter - 1))
+                    }
+                    None => len,
+                };
+                match fuel {
+                    Some(costs) => {
+                        let cost = iteration_cost(instructions, costs, i);
+                        len + emit_fuel(machine_code, cost, callbacks.fuel)
+                    }
+                    None => len,
                 }
-                _ => unreachable!(),
+            }
+            Instruction::JumpNotZero(n) => {
+                assert_eq!(instructions[i - n - 1], Instruction::JumpZero(n + 2));
+
+                let (body, end) = loops.pop().expect("every loop has been opened");
+                let len = machine_code.emit_jump_not_zero(body);
+                machine_code.bind_label(end);
+                len
+            }
+            _ => unreachable!(),
+        };
+    }
+    len
+}
+
+/// The most steps a single subtraction from the fuel counts, so its cost fits into an immediate
+/// on every architecture.
+const MAX_FUEL_COST: u64 = (1 << 12) - 1;
+
+/// Emits code that subtracts `cost` from the fuel, in as many subtractions as it takes.
+fn emit_fuel(machine_code: &mut MachineCode, mut cost: u64, callback: usize) -> usize {
+    let mut len = 0;
+    while cost > 0 {
+        let chunk = cost.min(MAX_FUEL_COST);
+        len += machine_code.emit_fuel(chunk as u32, callback);
+        cost -= chunk;
+    }
+    len
+}
+
+/// Returns the steps an instruction counts before it runs, which are all of its steps unless
+/// they depend on the cells and it [calls back](MachineCode::emit_steps) to count them.
+fn fixed_cost(cost: StepCost) -> u64 {
+    match cost {
+        StepCost::Fixed(steps) => steps,
+        _ => 0,
+    }
+}
+
+/// Returns the fixed steps of an iteration of the loop that starts at `start`, its body and
+/// the jump back. A nested loop counts as its first instruction, as its iterations count
+/// themselves.
+fn iteration_cost(instructions: &[Instruction], costs: &[StepCost], start: usize) -> u64 {
+    let Instruction::JumpZero(n) = instructions[start] else {
+        unreachable!("a loop starts with a JumpZero");
+    };
+    let end = start + n - 1;
+    let (mut cost, mut i) = (fixed_cost(costs[end]), start + 1);
+    while i < end {
+        cost += fixed_cost(costs[i]);
+        i += match instructions[i] {
+            Instruction::JumpZero(n) => n,
+            _ => 1,
+        };
+    }
+    cost
+}
+
+/// Returns the fixed steps from `start`, which is outside of any loop, up to the start of the
+/// next loop or the end of the program, which run once each time the code gets there.
+fn straight_line_cost(instructions: &[Instruction], costs: &[StepCost], start: usize) -> u64 {
+    let len = instructions[start..]
+        .iter()
+        .position(|instruction| matches!(instruction, Instruction::JumpZero(_)))
+        .map_or(instructions.len() - start, |n| n + 1);
+    costs[start..start + len]
+        .iter()
+        .copied()
+        .map(fixed_cost)
+        .sum()
+}
+
+/// Returns the lowered loops that [call back](MachineCode::emit_steps) to count their steps,
+/// by the index of their instruction.
+fn lowered_loops(instructions: &[Instruction], costs: &[StepCost]) -> HashMap<usize, LoweredLoop> {
+    instructions
+        .iter()
+        .zip(costs)
+        .enumerate()
+        .filter(|(_, (_, cost))| !matches!(cost, StepCost::Fixed(_)))
+        .map(|(ip, (instruction, cost))| {
+            let stride = match instruction {
+                Instruction::FindZero(stride) => *stride,
+                _ => 0,
             };
+            (
+                ip,
+                LoweredLoop {
+                    cost: *cost,
+                    stride,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Returns for every instruction whether the generated code has to check the data pointer it
+/// moves to or adds to, which is not the case for a cell between cells that were already
+/// checked since the last jump, as the tape has no holes.
+fn bounds_checks(instructions: &[Instruction]) -> Vec<bool> {
+    // The cells relative to the data pointer that are known to be inside of the tape.
+    let (mut first, mut last) = (0, 0);
+    instructions
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::IncDP(n) | Instruction::DecDP(n) => {
+                let n = match instruction {
+                    Instruction::IncDP(_) => *n as isize,
+                    _ => -(*n as isize),
+                };
+                let checked = !(first..=last).contains(&n);
+                (first, last) = (first.min(n) - n, last.max(n) - n);
+                checked
+            }
+            // The cell is only accessed if the cell at the data pointer is not zero, so it is
+            // not known to be inside of the tape afterwards.
+            Instruction::AddTo { offset, .. } => !(first..=last).contains(offset),
+            Instruction::FindZero(_) => {
+                (first, last) = (0, 0);
+                true
+            }
+            Instruction::JumpZero(_) | Instruction::JumpNotZero(_) => {
+                (first, last) = (0, 0);
+                false
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+/// Machine code emitted by [JitCompiler::compile], which can be run many times, each time on
+/// a fresh tape.
+pub struct CompiledProgram {
+    code: MemoryMap<Executable>,
+    tape_size: usize,
+    tape_policy: TapePolicy,
+    cell_width: CellWidth,
+    times: PhaseTimes,
+    counters: Option<LoopCounters>,
+
+    /// Counts the steps of every run, if the program was compiled with limits.
+    watchdog: Option<Watchdog>,
+
+    /// The lowered loops whose steps the generated code counts by calling back.
+    lowered_loops: HashMap<usize, LoweredLoop>,
+
+    /// Where the last run stopped, if a limit or the cancel flag stopped it.
+    state: Mutex<Option<State>>,
+
+    /// The inputs the program can select with `%`.
+    host_inputs: Option<HostInputs>,
+}
+
+/// A lowered loop whose steps depend on the cells, which [steps_callback] counts.
+#[derive(Debug, Copy, Clone)]
+struct LoweredLoop {
+    cost: StepCost,
+
+    /// The cells a scan moves per iteration, zero for other loops.
+    stride: isize,
+}
+
+/// The iteration counters of the loops of a program, which the generated code increments.
+#[derive(Debug)]
+pub struct LoopCounters {
+    /// The indices of the `JumpZero` instructions of the loops, in the order of the counters.
+    loops: Vec<usize>,
+
+    /// Incremented without synchronization, which only loses counts if a program runs on
+    /// several threads at once.
+    counts: Box<[AtomicU64]>,
+}
+
+impl LoopCounters {
+    /// Creates a counter for every loop of `instructions`, which starts at zero.
+    pub fn new(instructions: &[Instruction]) -> Self {
+        let loops: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| matches!(instruction, Instruction::JumpZero(_)))
+            .map(|(i, _)| i)
+            .collect();
+        let counts = loops.iter().map(|_| AtomicU64::new(0)).collect();
+        Self { loops, counts }
+    }
+
+    /// Returns the address of the `n`th counter.
+    fn address(&self, n: usize) -> usize {
+        self.counts[n].as_ptr() as usize
+    }
+
+    /// Returns the index of the `JumpZero` instruction and the number of iterations of every
+    /// loop, in the order of the instructions.
+    pub fn counts(&self) -> Vec<(usize, u64)> {
+        self.loops
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(ip, count)| (*ip, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
         }
+    }
+}
 
-        self.machine_code.emit_stack_teardown();
+impl CompiledProgram {
+    /// Maps `machine_code` into executable memory to run it on a tape of `tape_size` cells of
+    /// the width of `machine_code`, e.g. after emitting custom code around the
+    /// [instructions](emit_instructions) of a program.
+    ///
+    /// # Safety
+    ///
+    /// The code is executed as is, so it has to start with the
+    /// [setup](MachineCode::emit_stack_setup), end with the
+    /// [teardown](MachineCode::emit_stack_teardown), bind every label it jumps to and call
+    /// only [callbacks](Callbacks) of its cell width.
+    pub unsafe fn from_machine_code(
+        machine_code: &MachineCode,
+        tape_size: usize,
+        tape_policy: TapePolicy,
+    ) -> io::Result<Self> {
+        let mut mmap = MemoryMap::new(machine_code.get_buf().len())?;
+        mmap.get_mut().copy_from_slice(machine_code.get_buf());
+        Ok(Self {
+            code: mmap.set_executable()?,
+            tape_size,
+            tape_policy,
+            cell_width: machine_code.cell_width(),
+            times: PhaseTimes::default(),
+            counters: None,
+            watchdog: None,
+            lowered_loops: HashMap::new(),
+            state: Mutex::new(None),
+            host_inputs: None,
+        })
+    }
+
+    /// Lets every run read the host inputs once the program selected them with `%`, see the
+    /// `env` [extension](crate::extensions).
+    pub fn with_host_inputs(mut self, inputs: HostInputs) -> Self {
+        self.host_inputs = Some(inputs);
+        self
+    }
+
+    /// Returns the time spent emitting the machine code in the [Codegen](Phase::Codegen) phase.
+    pub fn times(&self) -> PhaseTimes {
+        self.times
+    }
+
+    /// Returns the iterations of every loop in the last run, as the index of its `JumpZero`
+    /// instruction and the count, if the program was compiled
+    /// [with loop counters](JitCompiler::with_loop_counters).
+    pub fn loop_counts(&self) -> Option<Vec<(usize, u64)>> {
+        self.counters.as_ref().map(LoopCounters::counts)
+    }
+
+    /// Returns the number of steps counted, the data pointer and the cells around it when the
+    /// last run stopped, if a limit or the cancel flag stopped it.
+    pub fn state(&self) -> Option<State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Runs the program on a zero initialized tape, reading input from `reader` and writing
+    /// output to `writer`, and handling reads past the end of the input according to `eof`.
+    ///
+    /// The tape lies between guard pages, so an access outside of it
+    /// that the bounds checks miss faults instead of corrupting other memory.
+    pub fn run<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        let bytes = self.cell_width.bytes();
+        let mut tape = match self.tape_policy {
+            TapePolicy::Fixed => MemoryMap::guarded(self.tape_size * bytes, Commit::Eager)?,
+            TapePolicy::Growable => {
+                MemoryMap::guarded(self.tape_size.max(GROWABLE_TAPE_SIZE) * bytes, Commit::Lazy)?
+            }
+        };
+
+        self.run_on(tape.get_mut(), reader, writer, flush, eof)
+    }
+
+    /// Runs the program on `cells` of the caller instead of a fresh tape, e.g. on a slice of
+    /// shared memory so other threads or processes can observe the cells while the program
+    /// runs. The cells keep their values and are not copied.
+    ///
+    /// Fails if the cells are not as wide as the compiled program expects.
+    pub fn run_on_cells<C: Cell, R: Read, W: Write>(
+        &self,
+        cells: &mut [C],
+        reader: &mut R,
+        writer: &mut W,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        if C::WIDTH != self.cell_width {
+            return Err(RuntimeError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the program was compiled for {}-bit cells, not {}-bit cells",
+                    self.cell_width.bits(),
+                    C::WIDTH.bits()
+                ),
+            )));
+        }
+        // SAFETY: Cells are integers, so their memory can be accessed as bytes.
+        let data = unsafe {
+            slice::from_raw_parts_mut(cells.as_mut_ptr().cast::<u8>(), mem::size_of_val(cells))
+        };
+        self.run_on(data, reader, writer, flush, eof)
+    }
 
-        let mut mmap = MemoryMap::new(self.machine_code.get_buf().len())?;
-        mmap.get_mut().copy_from_slice(self.machine_code.get_buf());
-        let mmap = mmap.set_executable()?;
+    fn run_on<R: Read, W: Write>(
+        &self,
+        data: &mut [u8],
+        reader: &mut R,
+        writer: &mut W,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        if let Some(counters) = &self.counters {
+            counters.reset();
+        }
 
+        // The flusher has to see every write to flush it or to notice idle output.
+        let capacity = match flush {
+            FlushBehavior::Disabled | FlushBehavior::OnEnd => OUTPUT_BUFFER_SIZE,
+            FlushBehavior::OnWrite | FlushBehavior::Adaptive(_) => 0,
+        };
+        let mut buffer = vec![0; capacity];
+        let mut watchdog = self.watchdog.clone();
+        let allowed = watchdog.as_mut().map_or(0, Watchdog::allowance);
+        let mut io = Io {
+            output: Output {
+                data: buffer.as_mut_ptr(),
+                len: 0,
+                capacity,
+            },
+            fuel: allowed,
+            allowed,
+            watchdog,
+            lowered_loops: &self.lowered_loops,
+            tape: data.as_ptr(),
+            tape_len: data.len(),
+            stopped_at: None,
+            reader,
+            input: self
+                .host_inputs
+                .as_ref()
+                .map_or_else(InputSelector::default, InputSelector::new),
+            writer,
+            flush,
+            eof,
+            flusher: Flusher::default(),
+            error: None,
+        };
         // SAFETY: We wrote the machine code to the memory mapped region;
-        // and the machine code is valid.
-        unsafe { mmap.execute() }
+        // and the machine code is valid. It receives the state of the callbacks, which
+        // outlives the execution, and the tape with its length, which it does not leave.
+        let status = unsafe {
+            self.code.execute(
+                &mut io as *mut Io as *mut c_void,
+                data.as_mut_ptr(),
+                data.len(),
+            )
+        };
+        let state = match (io.stopped_at, &io.watchdog) {
+            (Some(offset), Some(watchdog)) => {
+                let width = self.cell_width.bytes();
+                Some(State::new(
+                    watchdog.executed(),
+                    offset / width,
+                    data.len() / width,
+                    |index| {
+                        let mut cell = [0; 8];
+                        cell[..width].copy_from_slice(&data[index * width..][..width]);
+                        u64::from_le_bytes(cell)
+                    },
+                ))
+            }
+            _ => None,
+        };
+        *self.state.lock().unwrap_or_else(|err| err.into_inner()) = state;
+        match status {
+            EXIT_ERROR => {
+                // The output written before the error is kept, like in the other environments.
+                let error = io.error.take();
+                io.drain()?;
+                if let Some(RuntimeError::Exit(_)) = error {
+                    io.flusher.finished(flush, &mut io.writer)?;
+                }
+                Err(error.expect("a callback that fails stores its error"))
+            }
+            _ => {
+                io.drain()?;
+                Ok(io.flusher.finished(flush, &mut io.writer)?)
+            }
+        }
+    }
+}
+
+/// Addresses of the functions the generated code calls to read and write, and to stop when
+/// the data pointer leaves the tape or the program exits.
+#[derive(Debug, Copy, Clone)]
+pub struct Callbacks {
+    /// Passed to [emit_read_byte_at_dp](MachineCode::emit_read_byte_at_dp).
+    pub read: usize,
+
+    /// Passed to [emit_write_byte_at_dp](MachineCode::emit_write_byte_at_dp).
+    pub write: usize,
 
+    /// Passed to [emit_stack_setup](MachineCode::emit_stack_setup).
+    pub out_of_bounds: usize,
+
+    /// Passed to [emit_exit](MachineCode::emit_exit).
+    pub exit: usize,
+
+    /// Passed to [emit_fuel](MachineCode::emit_fuel).
+    pub fuel: usize,
+
+    /// Passed to [emit_steps](MachineCode::emit_steps).
+    pub steps: usize,
+
+    /// Passed to [emit_select_input](MachineCode::emit_select_input).
+    pub select_input: usize,
+}
+
+impl Callbacks {
+    /// Returns the callbacks for cells of the given width.
+    pub fn new(cell_width: CellWidth) -> Self {
+        Self {
+            read: match cell_width {
+                CellWidth::U8 => read_callback::<u8> as *const () as usize,
+                CellWidth::U16 => read_callback::<u16> as *const () as usize,
+                CellWidth::U32 => read_callback::<u32> as *const () as usize,
+            },
+            write: write_callback as *const () as usize,
+            out_of_bounds: match cell_width {
+                CellWidth::U8 => out_of_bounds_callback::<u8> as *const () as usize,
+                CellWidth::U16 => out_of_bounds_callback::<u16> as *const () as usize,
+                CellWidth::U32 => out_of_bounds_callback::<u32> as *const () as usize,
+            },
+            exit: match cell_width {
+                CellWidth::U8 => exit_callback::<u8> as *const () as usize,
+                CellWidth::U16 => exit_callback::<u16> as *const () as usize,
+                CellWidth::U32 => exit_callback::<u32> as *const () as usize,
+            },
+            fuel: fuel_callback as *const () as usize,
+            steps: match cell_width {
+                CellWidth::U8 => steps_callback::<u8> as *const () as usize,
+                CellWidth::U16 => steps_callback::<u16> as *const () as usize,
+                CellWidth::U32 => steps_callback::<u32> as *const () as usize,
+            },
+            select_input: match cell_width {
+                CellWidth::U8 => select_input_callback::<u8> as *const () as usize,
+                CellWidth::U16 => select_input_callback::<u16> as *const () as usize,
+                CellWidth::U32 => select_input_callback::<u32> as *const () as usize,
+            },
+        }
+    }
+}
+
+/// The state the generated code passes to the callbacks.
+///
+/// The generated code appends to `output` and subtracts from `fuel` without calling back, so
+/// they come first.
+#[repr(C)]
+struct Io<'a> {
+    output: Output,
+
+    /// The steps the program may still execute, which the generated code accesses at offset 24
+    /// if it was compiled with limits. It is below zero once it calls back.
+    fuel: u64,
+
+    /// The steps the program was allowed when `fuel` was last set.
+    allowed: u64,
+    watchdog: Option<Watchdog>,
+    lowered_loops: &'a HashMap<usize, LoweredLoop>,
+
+    /// The first cell of the tape and its length in bytes.
+    tape: *const u8,
+    tape_len: usize,
+
+    /// The offset in bytes of the data pointer from `tape`, once counting the steps stopped
+    /// the execution.
+    stopped_at: Option<usize>,
+    reader: &'a mut dyn Read,
+    input: InputSelector<'a>,
+    writer: &'a mut dyn Write,
+    flush: FlushBehavior,
+    eof: EofBehavior,
+    flusher: Flusher,
+
+    /// The error of the callback that stopped the execution.
+    error: Option<RuntimeError>,
+}
+
+/// The buffer the generated code writes its output to, whose fields it accesses at offsets 0, 8
+/// and 16. Once a write does not fit, it calls back to write the buffer and the bytes.
+#[repr(C)]
+struct Output {
+    data: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+impl Io<'_> {
+    /// Writes the bytes the generated code buffered.
+    fn drain(&mut self) -> io::Result<()> {
+        // SAFETY: The generated code only appends to the buffer, up to its capacity.
+        let buffered = unsafe { slice::from_raw_parts(self.output.data, self.output.len) };
+        self.output.len = 0;
+        self.writer.write_all(buffered)
+    }
+
+    /// Returns the status the generated code expects from a callback, keeping the error.
+    fn status(&mut self, result: io::Result<()>) -> u32 {
+        match result {
+            Ok(()) => EXIT_FINISHED,
+            Err(err) => {
+                self.error = Some(RuntimeError::Io(err));
+                EXIT_ERROR
+            }
+        }
+    }
+}
+
+/// Defines a function the generated code calls, which follows the System V calling convention on
+/// x86_64 on every platform, like the generated code itself.
+macro_rules! callback {
+    ($(#[$attribute:meta])* fn $($function:tt)*) => {
+        #[cfg(target_arch = "x86_64")]
+        $(#[$attribute])*
+        extern "sysv64" fn $($function)*
+
+        #[cfg(not(target_arch = "x86_64"))]
+        $(#[$attribute])*
+        extern "C" fn $($function)*
+    };
+}
+
+callback! {
+/// Reads a byte into the cell at `cell`, which is not necessarily aligned.
+fn read_callback<C: Cell>(io: *mut Io, cell: *mut u8) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run` and a pointer into the tape.
+    let (io, cell) = unsafe { (&mut *io, cell.cast::<C>()) };
+    let result = io.drain().and_then(|()| io.flusher.reading(io.flush, &mut io.writer)).and_then(|()| {
+        // SAFETY: See above.
+        let mut value = unsafe { cell.read_unaligned() };
+        io.eof.read(io.input.reader(io.reader), &mut value)?;
+        unsafe { cell.write_unaligned(value) };
         Ok(())
+    });
+    io.status(result)
+}
+}
+
+callback! {
+/// Writes the buffered output and `byte` `n` times, which did not fit into the buffer.
+fn write_callback(io: *mut Io, byte: u8, n: usize) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run`.
+    let io = unsafe { &mut *io };
+    let result = io
+        .drain()
+        .and_then(|()| (0..n).try_for_each(|_| io.writer.write_all(&[byte])))
+        .and_then(|()| io.flusher.written(io.flush, &mut io.writer, n));
+    io.status(result)
+}
+}
+
+callback! {
+/// Stops the execution because the instruction at `ip` moved the data pointer `offset` bytes
+/// away from the first cell, outside of the tape.
+fn out_of_bounds_callback<C: Cell>(io: *mut Io, ip: usize, offset: isize) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run`.
+    let io = unsafe { &mut *io };
+    io.error = Some(RuntimeError::PointerOutOfBounds {
+        ip,
+        dp: offset / C::WIDTH.bytes() as isize,
+    });
+    EXIT_ERROR
+}
+}
+
+callback! {
+/// Stops the execution with the value of the cell at `cell` as exit code, which is not
+/// necessarily aligned.
+fn exit_callback<C: Cell>(io: *mut Io, cell: *const u8) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run` and a pointer into the tape.
+    let (io, value) = unsafe { (&mut *io, cell.cast::<C>().read_unaligned()) };
+    io.error = Some(RuntimeError::Exit(value.to_u64() as u32));
+    EXIT_ERROR
+}
+}
+
+callback! {
+/// Selects the input by the value of the cell at `cell`, which is not necessarily aligned,
+/// stopping the execution if there is no such input.
+fn select_input_callback<C: Cell>(io: *mut Io, cell: *const u8) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run` and a pointer into the tape.
+    let (io, value) = unsafe { (&mut *io, cell.cast::<C>().read_unaligned()) };
+    match io.input.select(value.to_u64()) {
+        Ok(()) => EXIT_FINISHED,
+        Err(err) => {
+            io.error = Some(err);
+            EXIT_ERROR
+        }
     }
+}
+}
 
-    fn get_instruction_bytes(&mut self, instruction: &Instruction) -> usize {
-        self.machine_code.get_only_len(|mc| match instruction {
-            Instruction::IncDP(n) => mc.emit_inc_dp(*n),
-            Instruction::DecDP(n) => mc.emit_dec_dp(*n),
-            Instruction::IncByteAtDP(n) => mc.emit_inc_byte_at_dp(*n),
-            Instruction::DecByteAtDP(n) => mc.emit_dec_byte_at_dp(*n),
-            Instruction::WriteByte(n) => mc.emit_write_byte_at_dp(*n),
-            Instruction::ReadByte => mc.emit_read_byte_at_dp(),
-            Instruction::JumpZero(_) => mc.emit_jump_zero(0),
-            Instruction::JumpNotZero(_) => mc.emit_jump_not_zero(0),
-            _ => unreachable!(),
-        })
+callback! {
+/// Counts the steps executed since the last call, stopping the execution at the cell at `cell`
+/// if they exceed a limit or it was cancelled, and allows the next ones.
+fn fuel_callback(io: *mut Io, cell: *const u8) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run`.
+    refuel(unsafe { &mut *io }, cell)
+}
+}
+
+callback! {
+/// Subtracts the steps of the lowered loop at `ip` from the fuel before it runs on the cell at
+/// `cell`, which is not necessarily aligned, refueling once it is used up.
+fn steps_callback<C: Cell>(io: *mut Io, cell: *const u8, ip: usize) -> u32 {
+    // SAFETY: The generated code passes the `Io` given to `run` and a pointer into the tape.
+    let (io, value) = unsafe { (&mut *io, cell.cast::<C>().read_unaligned()) };
+    let lowered = io.lowered_loops[&ip];
+    let moved = match lowered.stride {
+        0 => 0,
+        stride => scan_distance::<C>(io, cell, stride),
+    };
+    let (fuel, used_up) = io.fuel.overflowing_sub(lowered.cost.steps(value, moved));
+    io.fuel = fuel;
+    if used_up {
+        refuel(io, cell)
+    } else {
+        EXIT_FINISHED
+    }
+}
+}
+
+/// Returns how many cells a scan by `stride` cells starting at `cell` moves until it finds a
+/// zero cell, or until the end of the tape, where the scan itself fails.
+fn scan_distance<C: Cell>(io: &Io, cell: *const u8, stride: isize) -> usize {
+    let width = C::WIDTH.bytes() as isize;
+    let mut offset = cell as isize - io.tape as isize;
+    let mut moved = 0;
+    loop {
+        // SAFETY: `offset` is inside of the tape, which holds cells of type `C`.
+        let value = unsafe { io.tape.offset(offset).cast::<C>().read_unaligned() };
+        let next = offset + stride * width;
+        if value == C::ZERO || next < 0 || next >= io.tape_len as isize {
+            return moved;
+        }
+        offset = next;
+        moved += stride.unsigned_abs();
+    }
+}
+
+/// Counts the steps executed since the fuel was last set, stopping the execution at the cell
+/// at `cell` if they exceed a limit or it was cancelled, and allows the next ones.
+fn refuel(io: &mut Io, cell: *const u8) -> u32 {
+    let watchdog = io
+        .watchdog
+        .as_mut()
+        .expect("limited programs run with a watchdog");
+    if let Err(err) = watchdog.count(io.allowed.wrapping_sub(io.fuel)) {
+        io.stopped_at = Some(cell as usize - io.tape as usize);
+        io.error = Some(err);
+        return EXIT_ERROR;
+    }
+    io.allowed = watchdog.allowance();
+    io.fuel = io.allowed;
+    EXIT_FINISHED
+}
+
+/// Returns the message of a panic, if it has one.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
+}
+
+/// A position in the machine code that jumps can target before the position is known.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// The labels of machine code, and the jumps to labels that are not bound to a position yet.
+#[derive(Debug, Default)]
+struct Labels {
+    positions: Vec<Option<usize>>,
+
+    /// The jumps to unbound labels, as the position of the jump and its target.
+    unresolved: Vec<(usize, Label)>,
+}
+
+impl Labels {
+    fn create(&mut self) -> Label {
+        self.positions.push(None);
+        Label(self.positions.len() - 1)
+    }
+
+    /// Returns the position `label` is bound to, if it is bound.
+    fn position(&self, label: Label) -> Option<usize> {
+        self.positions[label.0]
+    }
+
+    /// Remembers the jump at `at` to patch it once `label` is bound.
+    fn refer(&mut self, at: usize, label: Label) {
+        self.unresolved.push((at, label));
+    }
+
+    /// Binds `label` to `position`, returning the positions of the jumps to patch.
+    fn bind(&mut self, label: Label, position: usize) -> Vec<usize> {
+        assert_eq!(self.positions[label.0], None, "a label is only bound once");
+        self.positions[label.0] = Some(position);
+        let (resolved, unresolved) = std::mem::take(&mut self.unresolved)
+            .into_iter()
+            .partition(|(_, target)| *target == label);
+        self.unresolved = unresolved;
+        resolved.into_iter().map(|(at, _)| at).collect::<Vec<_>>()
     }
 }
 
-mod machine_code {
+// Both emitters are compiled on every supported architecture, so their encodings are tested
+// everywhere.
+/// The encoder of x86_64 machine code, which follows the System V calling convention on every
+/// platform.
+#[cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+#[cfg_attr(not(feature = "internals"), doc(hidden))]
+pub mod x86_64 {
+    use crate::jit::{Label, Labels, EXIT_FINISHED};
+    use crate::tape::CellWidth;
+
+    /// The numbers of the general purpose registers, as encoded in instructions.
+    const RAX: u8 = 0;
+    const RDX: u8 = 2;
+    const RSI: u8 = 6;
+    const RDI: u8 = 7;
+
+    /// The register holding the data pointer.
+    const DP: u8 = 12;
+
+    /// The register holding the state passed to the callbacks.
+    const STATE: u8 = 13;
+
+    /// The register holding the start of the tape.
+    const TAPE: u8 = 14;
+
+    /// The registers the callbacks take their arguments in, which follow the System V calling
+    /// convention, like the callbacks are defined. The state is always the first argument.
+    const ARGUMENTS: [u8; 3] = [RDI, RSI, RDX];
+
+    /// mov rsp,rbp
+    /// pop r15
+    /// pop r14
+    /// pop r13
+    /// pop r12
+    /// pop rbp
+    /// ret
+    const TEARDOWN: [u8; 13] = [
+        0x48, 0x89, 0xec, 0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5d, 0xc3,
+    ];
+
+    /// The length of a bounds check, a `cmp` and a conditional jump to its handler.
+    const CHECK_LEN: usize = 9;
 
     /// Encapsulates machine code instructions.
     #[derive(Debug, Default)]
     pub struct MachineCode {
         buf: Vec<u8>,
         suspend_write: bool,
+        cell_width: CellWidth,
+
+        /// The function failed bounds checks call.
+        out_of_bounds: usize,
+
+        /// The bounds checks whose failure is handled after the teardown, as the position of the
+        /// jump to the handler, the instruction pointer it reports and whether it checks the
+        /// data pointer.
+        checks: Vec<(usize, usize, bool)>,
+
+        /// The targets of the jumps between instructions.
+        labels: Labels,
+
+        /// Whether `eax` holds the cell at the data pointer, zero extended. Arithmetic and
+        /// jumps only update the register, so the cell is stored, or spilled, before anything
+        /// else accesses the tape.
+        cached: bool,
     }
 
     impl MachineCode {
-        pub fn emit_stack_setup(&mut self, data_start: *const u8) -> usize {
+        /// Creates machine code operating on cells of the given width.
+        pub fn new(cell_width: CellWidth) -> Self {
+            Self {
+                cell_width,
+                ..Self::default()
+            }
+        }
+
+        /// Emits the setup, remembering the function that failed bounds checks call.
+        pub fn emit_stack_setup(&mut self, out_of_bounds: usize) -> usize {
             // push rbp
             // push r12
-            // mov  r12,<data_start>
+            // push r13
+            // push r14
+            // push r15
+            // mov  r13,rdi
+            // mov  r12,rsi
+            // mov  r14,rsi
+            // lea  r15,[rsi+rdx]
             // mov  rbp,rsp
-            let data_start = (data_start as usize).to_le_bytes();
+            //
+            // `rdi` is the state passed to the callbacks, `rsi` the start of the tape and `rdx`
+            // its length in bytes.
+            self.out_of_bounds = out_of_bounds;
             self.write(&[
-                0x55,
-                0x41,
-                0x54,
-                0x49,
-                0xbc,
-                data_start[0],
-                data_start[1],
-                data_start[2],
-                data_start[3],
-                data_start[4],
-                data_start[5],
-                data_start[6],
-                data_start[7],
-                0x48,
-                0x89,
-                0xe5,
+                0x55, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57, 0x49, 0x89, 0xfd, 0x49, 0x89,
+                0xf4, 0x49, 0x89, 0xf6, 0x4c, 0x8d, 0x3c, 0x16, 0x48, 0x89, 0xe5,
             ])
         }
 
+        /// Emits the teardown, followed by the handlers of the failed bounds checks, so they
+        /// are out of the way of the instructions that are executed.
         pub fn emit_stack_teardown(&mut self) -> usize {
-            // mov rsp,rbp
-            // pop r12
-            // pop rbp
-            // ret
-            self.write(&[0x48, 0x89, 0xec, 0x41, 0x5c, 0x5d, 0xc3])
+            let mut len = self.spill() + self.emit_return(EXIT_FINISHED);
+            for (jump, ip, dp) in std::mem::take(&mut self.checks) {
+                let offset = (self.buf.len() - jump - 4) as u32;
+                self.buf[jump..jump + 4].copy_from_slice(&offset.to_le_bytes());
+
+                // mov    rdx,r12 (if it checks the data pointer)
+                // sub    rdx,r14
+                // mov    esi,<ip>
+                // mov    rdi,r13
+                // movabs rax,<out_of_bounds>
+                // call   rax
+                // <teardown>
+                //
+                // Otherwise the checked pointer is already in `rdx`, the third argument.
+                let load: &[u8] = if dp { &mov(ARGUMENTS[2], DP) } else { &[] };
+                len += self.write(load)
+                    + self.write(&register_op(0x29, ARGUMENTS[2], TAPE))
+                    + self.write(&mov_imm32(ARGUMENTS[1], ip as u32))
+                    + self.write(&mov(ARGUMENTS[0], STATE))
+                    + self.write(&movabs(RAX, self.out_of_bounds as u64))
+                    + self.write(&[0xff, 0xd0])
+                    + self.write(&TEARDOWN);
+            }
+            len
         }
 
-        pub fn emit_inc_dp(&mut self, n: usize) -> usize {
-            let n = n as u8;
-            match n {
-                1 => {
-                    // inc r12
-                    self.write(&[0x49, 0xff, 0xc4])
-                }
-                2..=127 => {
-                    // add r12,<n>
-                    self.write(&[0x49, 0x83, 0xc4, n])
-                }
-                128..=255 => {
-                    // add r12,<n>
-                    self.write(&[0x49, 0x81, 0xc4, n, 0x00, 0x00, 0x00])
-                }
-                _ => 0,
+        fn emit_return(&mut self, status: u32) -> usize {
+            // mov eax,<status>
+            // <teardown>
+            let status = status.to_le_bytes();
+            self.write(&[0xb8, status[0], status[1], status[2], status[3]]) + self.write(&TEARDOWN)
+        }
+
+        /// Moves the data pointer and, unless `check` is `None`, checks it, reporting `check`
+        /// as the instruction pointer if it is outside of the tape.
+        pub fn emit_inc_dp(&mut self, n: usize, check: Option<usize>) -> usize {
+            let n = n * self.cell_width.bytes();
+            let len = self.spill();
+            let len = len
+                + match n {
+                    0 => 0,
+                    1 => {
+                        // inc r12
+                        self.write(&[0x49, 0xff, 0xc4])
+                    }
+                    2..=127 => {
+                        // add r12,<n>
+                        self.write(&[0x49, 0x83, 0xc4, n as u8])
+                    }
+                    0x80..=0x7fff_ffff => {
+                        // add r12,<n>
+                        let n = (n as u32).to_le_bytes();
+                        self.write(&[0x49, 0x81, 0xc4, n[0], n[1], n[2], n[3]])
+                    }
+                    _ => {
+                        // movabs rax,<n>
+                        // add    r12,rax
+                        self.write(&[0x48, 0xb8])
+                            + self.write(&(n as u64).to_le_bytes())
+                            + self.write(&[0x49, 0x01, 0xc4])
+                    }
+                };
+            len + self.emit_check_dp(n, true, check)
+        }
+
+        /// Moves the data pointer back like [emit_inc_dp](MachineCode::emit_inc_dp).
+        pub fn emit_dec_dp(&mut self, n: usize, check: Option<usize>) -> usize {
+            let n = n * self.cell_width.bytes();
+            let len = self.spill();
+            let len = len
+                + match n {
+                    0 => 0,
+                    1 => {
+                        // dec r12
+                        self.write(&[0x49, 0xff, 0xcc])
+                    }
+                    2..=127 => {
+                        // sub r12,<n>
+                        self.write(&[0x49, 0x83, 0xec, n as u8])
+                    }
+                    0x80..=0x7fff_ffff => {
+                        // sub r12,<n>
+                        let n = (n as u32).to_le_bytes();
+                        self.write(&[0x49, 0x81, 0xec, n[0], n[1], n[2], n[3]])
+                    }
+                    _ => {
+                        // movabs rax,<n>
+                        // sub    r12,rax
+                        self.write(&[0x48, 0xb8])
+                            + self.write(&(n as u64).to_le_bytes())
+                            + self.write(&[0x49, 0x29, 0xc4])
+                    }
+                };
+            len + self.emit_check_dp(n, false, check)
+        }
+
+        /// Checks the data pointer after it moved `n` bytes `forward` or backward, unless it did
+        /// not move or does not have to be checked.
+        fn emit_check_dp(&mut self, n: usize, forward: bool, check: Option<usize>) -> usize {
+            match (n, check) {
+                (0, _) | (_, None) => 0,
+                (_, Some(ip)) => self.emit_check(true, forward, ip),
             }
         }
 
-        pub fn emit_dec_dp(&mut self, n: usize) -> usize {
-            let n = n as u8;
-            match n {
-                1 => {
-                    // dec r12
-                    self.write(&[0x49, 0xff, 0xcc])
-                }
-                2..=127 => {
-                    // sub r12,<n>
-                    self.write(&[0x49, 0x83, 0xec, n])
-                }
-                128..=255 => {
-                    // sub r12,<n>
-                    self.write(&[0x49, 0x81, 0xec, n, 0x00, 0x00, 0x00])
-                }
-                _ => 0,
+        /// Emits a check that the data pointer if `dp`, or otherwise the pointer in `rdx`, is
+        /// inside of the tape, which starts at `r14` and ends at `r15`. Otherwise it jumps to a
+        /// handler after the teardown, which reports the pointer and `ip`.
+        ///
+        /// Pointers only move into the tape from inside of it, so a pointer that moved
+        /// `forward` is only compared with the end, and one that moved backward with the start.
+        fn emit_check(&mut self, dp: bool, forward: bool, ip: usize) -> usize {
+            // cmp <r12 or rdx>,<r15 or r14>
+            // jae <handler> (or jb)
+            let cmp = match (dp, forward) {
+                (true, true) => [0x4d, 0x39, 0xfc],
+                (true, false) => [0x4d, 0x39, 0xf4],
+                (false, true) => [0x4c, 0x39, 0xfa],
+                (false, false) => [0x4c, 0x39, 0xf2],
+            };
+            let jump_outside = if forward { 0x83 } else { 0x82 };
+            let len = self.write(&cmp) + self.write(&[0x0f, jump_outside]);
+            if !self.suspend_write {
+                self.checks.push((self.buf.len(), ip, dp));
             }
+            len + self.write(&[0, 0, 0, 0])
         }
 
+        /// Adds `n` to the cell at the data pointer, wrapping around.
         pub fn emit_inc_byte_at_dp(&mut self, n: usize) -> usize {
-            let n = n as u8;
-            match n {
+            match n as u64 & self.cell_mask() {
+                0 => 0,
                 1 => {
-                    // inc BYTE PTR [r12]
-                    self.write(&[0x41, 0xfe, 0x04, 0x24])
+                    // inc <al, ax or eax>
+                    self.cache() + self.write_eax_op(0xfe, 0xff, &[0xc0])
                 }
-                2..=255 => {
-                    // add BYTE PTR [r12],<n>
-                    self.write(&[0x41, 0x80, 0x04, 0x24, n])
+                n => {
+                    // add <al, ax or eax>,<n>
+                    let n = self.cell_immediate(n);
+                    self.cache() + self.write_eax_op(0x04, 0x05, &n)
                 }
-                _ => 0,
             }
         }
 
+        /// Subtracts `n` from the cell at the data pointer, wrapping around.
         pub fn emit_dec_byte_at_dp(&mut self, n: usize) -> usize {
-            let n = n as u8;
-            match n {
+            match n as u64 & self.cell_mask() {
+                0 => 0,
                 1 => {
-                    // dec BYTE PTR [r12]
-                    self.write(&[0x41, 0xfe, 0x0c, 0x24])
+                    // dec <al, ax or eax>
+                    self.cache() + self.write_eax_op(0xfe, 0xff, &[0xc8])
                 }
-                2..=255 => {
-                    // sub BYTE PTR [r12],<n>
-                    self.write(&[0x41, 0x80, 0x2c, 0x24, n])
+                n => {
+                    // sub <al, ax or eax>,<n>
+                    let n = self.cell_immediate(n);
+                    self.cache() + self.write_eax_op(0x2c, 0x2d, &n)
                 }
-                _ => 0,
             }
         }
 
-        pub fn emit_write_byte_at_dp(&mut self, n: usize) -> usize {
-            (0..n)
-                .map(|_| {
-                    // mov     eax,0x1
-                    // mov     edi,0x1
-                    // mov     rsi,r12
-                    // mov     edx,0x1
-                    // syscall
-                    self.write(&[
-                        0xb8, 0x01, 0x00, 0x00, 0x00, 0xbf, 0x01, 0x00, 0x00, 0x00, 0x4c, 0x89,
-                        0xe6, 0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05,
-                    ])
-                })
-                .sum()
+        /// Appends the byte at the data pointer `n` times to the output buffer, or calls
+        /// `callback` if it does not fit.
+        pub fn emit_write_byte_at_dp(&mut self, n: usize, callback: usize) -> usize {
+            // mov    rdi,r13
+            // movzx  esi,BYTE PTR [r12]
+            // movabs rdx,<n>
+            // <call callback>
+            let arguments = [movzx_byte(ARGUMENTS[1], DP), movabs(ARGUMENTS[2], n as u64)].concat();
+            let call = |mc: &mut Self| mc.emit_call(callback, &arguments);
+            let Ok(n) = i32::try_from(n) else {
+                return self.spill() + call(self);
+            };
+
+            // mov    rdi,QWORD PTR [r13+0x8]
+            // lea    rax,[rdi+<n>]
+            // cmp    rax,QWORD PTR [r13+0x10]
+            // ja     <slow>
+            // mov    QWORD PTR [r13+0x8],rax
+            // add    rdi,QWORD PTR [r13+0x0]
+            // mov    al,BYTE PTR [r12]
+            // mov    ecx,<n>
+            // rep stos BYTE PTR es:[rdi],al
+            // jmp    <done>
+            // slow:
+            // <call callback>
+            // done:
+            //
+            // `r13` points to the length and the capacity of the output buffer after its data.
+            let n = n.to_le_bytes();
+            let slow = self.get_only_len(call) as u8;
+            self.spill()
+                + self.write(&[0x49, 0x8b, 0x7d, 0x08, 0x48, 0x8d, 0x87])
+                + self.write(&n)
+                + self.write(&[0x49, 0x3b, 0x45, 0x10, 0x77, 21])
+                + self.write(&[0x49, 0x89, 0x45, 0x08, 0x49, 0x03, 0x7d, 0x00])
+                + self.write(&[0x41, 0x8a, 0x04, 0x24, 0xb9])
+                + self.write(&n)
+                + self.write(&[0xf3, 0xaa, 0xeb, slow])
+                + call(self)
         }
 
-        pub fn emit_read_byte_at_dp(&mut self) -> usize {
-            // mov     eax,0x0
-            // mov     edi,0x0
-            // mov     rsi,r12
-            // mov     edx,0x1
-            // syscall
-            self.write(&[
-                0xb8, 0x00, 0x00, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x00, 0x00, 0x4c, 0x89, 0xe6, 0xba,
-                0x01, 0x00, 0x00, 0x00, 0x0f, 0x05,
-            ])
+        /// Reads into the cell at the data pointer by calling `callback`, stopping the execution
+        /// if it fails.
+        pub fn emit_read_byte_at_dp(&mut self, callback: usize) -> usize {
+            // mov    rdi,r13
+            // mov    rsi,r12
+            // <call callback>
+            self.spill() + self.emit_call(callback, &mov(ARGUMENTS[1], DP))
         }
 
-        pub fn emit_jump_zero(&mut self, skip_bytes: i32) -> usize {
-            // cmp BYTE PTR [r12],0x0
-            // je  <skip_bytes>
-            let jump = skip_bytes.to_le_bytes();
-            self.write(&[
-                0x41, 0x80, 0x3c, 0x24, 0x00, 0x0f, 0x84, jump[0], jump[1], jump[2], jump[3],
-            ])
+        /// Stops the execution with the cell at the data pointer as exit code by calling
+        /// `callback`.
+        pub fn emit_exit(&mut self, callback: usize) -> usize {
+            // mov    rdi,r13
+            // mov    rsi,r12
+            // <call callback>
+            self.spill() + self.emit_call(callback, &mov(ARGUMENTS[1], DP))
         }
 
-        pub fn emit_jump_not_zero(&mut self, skip_bytes: usize) -> usize {
-            // cmp BYTE PTR [r12],0x0
-            // jne <skip_bytes>
+        /// Selects the input by the cell at the data pointer by calling `callback`, stopping
+        /// the execution if it fails.
+        pub fn emit_select_input(&mut self, callback: usize) -> usize {
+            // mov    rdi,r13
+            // mov    rsi,r12
+            // <call callback>
+            self.spill() + self.emit_call(callback, &mov(ARGUMENTS[1], DP))
+        }
 
-            // The current instruction is 11 bytes long.
-            let jump = ((skip_bytes + 11) as i32).wrapping_neg().to_le_bytes();
-            self.write(&[
-                0x41, 0x80, 0x3c, 0x24, 0x00, 0x0f, 0x85, jump[0], jump[1], jump[2], jump[3],
-            ])
+        /// Counts the steps of the lowered loop at `ip` by calling `callback` with the cell at
+        /// the data pointer, stopping the execution if they exceed a limit.
+        pub fn emit_steps(&mut self, ip: usize, callback: usize) -> usize {
+            // mov    rdi,r13
+            // mov    rsi,r12
+            // movabs rdx,<ip>
+            // <call callback>
+            let arguments = [&mov(ARGUMENTS[1], DP)[..], &movabs(ARGUMENTS[2], ip as u64)].concat();
+            self.spill() + self.emit_call(callback, &arguments)
         }
 
-        pub fn get_only_len(&mut self, f: impl Fn(&mut Self) -> usize) -> usize {
-            self.suspend_write = true;
-            let len = f(self);
-            self.suspend_write = false;
-            len
+        /// Calls the `extern "C"` function at `callback` with the state in `rdi` and the
+        /// remaining arguments set up by `arguments`, returning its status if it fails.
+        fn emit_call(&mut self, callback: usize, arguments: &[u8]) -> usize {
+            // mov    rdi,r13
+            // <arguments>
+            // movabs rax,<callback>
+            // call   rax
+            // test   eax,eax
+            // je     <done>
+            // <teardown>
+            // done:
+            //
+            // The stack is aligned to 16 bytes as the setup pushed five registers.
+            self.write(&mov(ARGUMENTS[0], STATE))
+                + self.write(arguments)
+                + self.write(&movabs(RAX, callback as u64))
+                + self.write(&[0xff, 0xd0, 0x85, 0xc0, 0x74, TEARDOWN.len() as u8])
+                + self.write(&TEARDOWN)
         }
 
-        pub fn get_buf(&self) -> &[u8] {
-            &self.buf
+        /// Sets the cell at the data pointer to zero.
+        pub fn emit_set_zero(&mut self) -> usize {
+            // xor eax,eax
+            self.cached = true;
+            self.write(&[0x31, 0xc0])
         }
 
-        fn write(&mut self, code: &[u8]) -> usize {
+        /// Moves the data pointer `stride` cells at a time until it is at a zero cell, reporting
+        /// `ip` as the instruction pointer if it leaves the tape.
+        pub fn emit_find_zero(&mut self, stride: isize, ip: usize) -> usize {
+            // loop:
+            // cmp <cell> PTR [r12],0x0
+            // je  <done>
+            // add r12,<stride>
+            // <check r12>
+            // jmp <loop>
+            // done:
+            let stride = stride as i32 * self.cell_width.bytes() as i32;
+            let add = match i8::try_from(stride) {
+                Ok(stride) => vec![0x49, 0x83, 0xc4, stride as u8],
+                Err(_) => [&[0x49, 0x81, 0xc4][..], &stride.to_le_bytes()].concat(),
+            };
+            let cmp = self.cmp_zero();
+            let len = cmp.len() + 2 + add.len() + CHECK_LEN + 2;
+            self.spill()
+                + self.write(&cmp)
+                + self.write(&[0x74, (add.len() + CHECK_LEN + 2) as u8])
+                + self.write(&add)
+                + self.emit_check(true, stride > 0, ip)
+                + self.write(&[0xeb, (len as i8).wrapping_neg() as u8])
+        }
+
+        /// Adds the cell at the data pointer times `factor` to the cell `offset` cells away from
+        /// it, checking that cell unless `check` is `None`.
+        pub fn emit_add_to(&mut self, offset: isize, factor: i32, check: Option<usize>) -> usize {
+            // movzx eax,<cell> PTR [r12] (unless it is cached)
+            // test  eax,eax
+            // je    <skip imul, check and add>
+            // imul  eax,eax,<factor>
+            // lea   rdx,[r12+<offset>] (unless `check` is `None`)
+            // <check rdx>            (unless `check` is `None`)
+            // add   <cell> PTR [r12+<offset>],<eax>
+            let load = self.cell_load();
+            let forward = offset > 0;
+            let factor = factor.to_le_bytes();
+            let offset = (offset as i32 * self.cell_width.bytes() as i32).to_le_bytes();
+            let lea = [&[0x49, 0x8d, 0x94, 0x24][..], &offset].concat();
+            let add = [
+                self.cell_prefix(),
+                &[self.cell_opcode(0x00, 0x01), 0x84, 0x24],
+                &offset,
+            ]
+            .concat();
+            let skip = 6 + check.map_or(0, |_| lea.len() + CHECK_LEN) + add.len();
+            let load = if self.cached { &[][..] } else { load };
+            let len = self.spill()
+                + self.write(load)
+                + self.write(&[0x85, 0xc0, 0x74, skip as u8])
+                + self.write(&[0x69, 0xc0, factor[0], factor[1], factor[2], factor[3]]);
+            let len = match check {
+                Some(ip) => len + self.write(&lea) + self.emit_check(false, forward, ip),
+                None => len,
+            };
+            len + self.write(&add)
+        }
+
+        /// Increments the `u64` at `address`, e.g. to count how often the code runs.
+        pub fn emit_count(&mut self, address: usize) -> usize {
+            // movabs rcx,<address>
+            // inc    QWORD PTR [rcx]
+            //
+            // `rcx` does not hold the cached cell.
+            self.write(&[0x48, 0xb9])
+                + self.write(&(address as u64).to_le_bytes())
+                + self.write(&[0x48, 0xff, 0x01])
+        }
+
+        /// Subtracts `cost` from the instructions the program may still execute and calls
+        /// `callback` once they are used up, stopping the execution if it fails.
+        pub fn emit_fuel(&mut self, cost: u32, callback: usize) -> usize {
+            // sub QWORD PTR [r13+0x18],<cost>
+            // jae <done>
+            // mov rsi,r12
+            // <call callback>
+            // done:
+            //
+            // `r13` points to the remaining instructions after the output buffer.
+            let call = |mc: &mut Self| mc.emit_call(callback, &mov(ARGUMENTS[1], DP));
+            let done = self.get_only_len(call) as u8;
+            self.spill()
+                + self.write(&[0x49, 0x81, 0x6d, 0x18])
+                + self.write(&cost.to_le_bytes())
+                + self.write(&[0x73, done])
+                + call(self)
+        }
+
+        /// Returns a label to jump to, which has to be [bound](MachineCode::bind_label) before
+        /// the code is executed.
+        pub fn new_label(&mut self) -> Label {
+            self.labels.create()
+        }
+
+        /// Binds `label` to the end of the code, patching the jumps emitted to it so far.
+        ///
+        /// Jumps leave the cell cached, so it is cached at their targets too, and so it has to
+        /// be cached where the label is bound.
+        pub fn bind_label(&mut self, label: Label) {
+            debug_assert!(self.cached, "the cell is cached at the targets of jumps");
+            let target = self.buf.len();
+            for jump in self.labels.bind(label, target) {
+                let offset = (target as i32 - jump as i32 - 4).to_le_bytes();
+                self.buf[jump..jump + 4].copy_from_slice(&offset);
+            }
+        }
+
+        /// Jumps to `target` if the cell at the data pointer is zero.
+        pub fn emit_jump_zero(&mut self, target: Label) -> usize {
+            // test <al, ax or eax>,<al, ax or eax>
+            // je   <target>
+            self.cache()
+                + self.write_eax_op(0x84, 0x85, &[0xc0])
+                + self.write(&[0x0f, 0x84])
+                + self.write_jump_offset(target)
+        }
+
+        /// Jumps to `target` unless the cell at the data pointer is zero.
+        pub fn emit_jump_not_zero(&mut self, target: Label) -> usize {
+            // test <al, ax or eax>,<al, ax or eax>
+            // jne  <target>
+            self.cache()
+                + self.write_eax_op(0x84, 0x85, &[0xc0])
+                + self.write(&[0x0f, 0x85])
+                + self.write_jump_offset(target)
+        }
+
+        /// Loads the cell at the data pointer into `eax`, unless it is already cached.
+        fn cache(&mut self) -> usize {
+            if std::mem::replace(&mut self.cached, true) {
+                return 0;
+            }
+            // movzx eax,<cell> PTR [r12] (or mov)
+            self.write(self.cell_load())
+        }
+
+        /// Stores the cell cached in `eax` at the data pointer, so `eax` can be overwritten.
+        fn spill(&mut self) -> usize {
+            if !std::mem::replace(&mut self.cached, false) {
+                return 0;
+            }
+            // mov <cell> PTR [r12],<al, ax or eax>
+            let code = [
+                self.cell_prefix(),
+                &[self.cell_opcode(0x88, 0x89), 0x04, 0x24],
+            ]
+            .concat();
+            self.write(&code)
+        }
+
+        fn cell_load(&self) -> &'static [u8] {
+            match self.cell_width {
+                CellWidth::U8 => &[0x41, 0x0f, 0xb6, 0x04, 0x24],
+                CellWidth::U16 => &[0x41, 0x0f, 0xb7, 0x04, 0x24],
+                CellWidth::U32 => &[0x41, 0x8b, 0x04, 0x24],
+            }
+        }
+
+        /// Writes an instruction on the cell cached in `eax`, with `opcode_8` for bytes and
+        /// `opcode` for wider cells, followed by `operands`.
+        fn write_eax_op(&mut self, opcode_8: u8, opcode: u8, operands: &[u8]) -> usize {
+            let prefix: &[u8] = match self.cell_width {
+                CellWidth::U16 => &[0x66],
+                CellWidth::U8 | CellWidth::U32 => &[],
+            };
+            let code = [prefix, &[self.cell_opcode(opcode_8, opcode)], operands].concat();
+            self.write(&code)
+        }
+
+        /// Writes the 32-bit offset of a jump to `target`, which is relative to the end of the
+        /// offset, or a placeholder that is patched once `target` is bound.
+        fn write_jump_offset(&mut self, target: Label) -> usize {
+            let jump = self.buf.len();
+            let offset = match self.labels.position(target) {
+                Some(target) => target as i32 - jump as i32 - 4,
+                None => {
+                    if !self.suspend_write {
+                        self.labels.refer(jump, target);
+                    }
+                    0
+                }
+            };
+            self.write(&offset.to_le_bytes())
+        }
+
+        /// Returns `cmp <cell> PTR [r12],0x0`.
+        fn cmp_zero(&self) -> Vec<u8> {
+            [
+                self.cell_prefix(),
+                &[self.cell_opcode(0x80, 0x83), 0x3c, 0x24, 0x00],
+            ]
+            .concat()
+        }
+
+        /// Returns the prefixes of an instruction on a cell, which selects the 16-bit operand
+        /// size and `r12` as base register.
+        fn cell_prefix(&self) -> &'static [u8] {
+            match self.cell_width {
+                CellWidth::U16 => &[0x66, 0x41],
+                CellWidth::U8 | CellWidth::U32 => &[0x41],
+            }
+        }
+
+        fn cell_opcode(&self, opcode_8: u8, opcode: u8) -> u8 {
+            match self.cell_width {
+                CellWidth::U8 => opcode_8,
+                CellWidth::U16 | CellWidth::U32 => opcode,
+            }
+        }
+
+        fn cell_mask(&self) -> u64 {
+            u64::MAX >> (64 - self.cell_width.bits())
+        }
+
+        /// Returns `n` as immediate of the width of a cell.
+        fn cell_immediate(&self, n: u64) -> Vec<u8> {
+            n.to_le_bytes()[..self.cell_width.bytes()].to_vec()
+        }
+
+        /// Returns the length of the code `f` emits, without emitting it.
+        pub fn get_only_len(&mut self, f: impl Fn(&mut Self) -> usize) -> usize {
+            // Instructions measure their parts while they are measured themselves.
+            let (suspend_write, cached) = (self.suspend_write, self.cached);
+            self.suspend_write = true;
+            let len = f(self);
+            (self.suspend_write, self.cached) = (suspend_write, cached);
+            len
+        }
+
+        /// Returns the code emitted so far.
+        pub fn get_buf(&self) -> &[u8] {
+            &self.buf
+        }
+
+        /// Returns the width of the cells the code operates on.
+        pub fn cell_width(&self) -> CellWidth {
+            self.cell_width
+        }
+
+        fn write(&mut self, code: &[u8]) -> usize {
             if !self.suspend_write {
                 self.buf.extend_from_slice(code);
             }
             code.len()
         }
     }
+
+    /// Returns the REX prefix of a 64-bit instruction with `reg` in the reg field and `rm` in
+    /// the r/m field of the ModRM byte, which extends them to the registers 8 to 15.
+    fn rex_w(reg: u8, rm: u8) -> u8 {
+        0x48 | (reg >> 3) << 2 | rm >> 3
+    }
+
+    /// Returns the instruction `opcode` on the registers `dst` and `src`, e.g. `sub` for
+    /// `0x29`.
+    fn register_op(opcode: u8, dst: u8, src: u8) -> [u8; 3] {
+        [rex_w(src, dst), opcode, 0xc0 | (src & 7) << 3 | dst & 7]
+    }
+
+    /// Returns `mov <dst>,<src>`.
+    fn mov(dst: u8, src: u8) -> [u8; 3] {
+        register_op(0x89, dst, src)
+    }
+
+    /// Returns `mov <dst>,<n>` on the lower 32 bits of `dst`, which zero extends `n`.
+    fn mov_imm32(dst: u8, n: u32) -> Vec<u8> {
+        let rex: &[u8] = if dst >= 8 { &[0x41] } else { &[] };
+        [rex, &[0xb8 | dst & 7], &n.to_le_bytes()].concat()
+    }
+
+    /// Returns `movabs <dst>,<n>`, which is always 10 bytes long, so the length of the code
+    /// does not depend on `n`.
+    fn movabs(dst: u8, n: u64) -> Vec<u8> {
+        [&[rex_w(0, dst), 0xb8 | dst & 7][..], &n.to_le_bytes()].concat()
+    }
+
+    /// Returns `movzx <dst>,BYTE PTR [<base>]` on the lower 32 bits of `dst`.
+    fn movzx_byte(dst: u8, base: u8) -> Vec<u8> {
+        let rex = 0x40 | (dst >> 3) << 2 | base >> 3;
+        let rex: &[u8] = if rex != 0x40 { &[rex] } else { &[] };
+        let modrm = (dst & 7) << 3 | base & 7;
+        // `rsp` and `r12` as base need a SIB byte, `rbp` and `r13` a displacement.
+        let address: &[u8] = match base & 7 {
+            4 => &[modrm, 0x24],
+            5 => &[0x40 | modrm, 0x00],
+            _ => &[modrm],
+        };
+        [rex, &[0x0f, 0xb6], address].concat()
+    }
+}
+
+/// The encoder of AArch64 machine code.
+#[cfg_attr(not(target_arch = "aarch64"), allow(dead_code))]
+#[cfg_attr(not(feature = "internals"), doc(hidden))]
+pub mod aarch64 {
+    use crate::jit::{Label, Labels, EXIT_FINISHED};
+    use crate::tape::CellWidth;
+
+    /// The register holding the data pointer.
+    const DP: u32 = 19;
+
+    /// The register holding the state passed to the callbacks.
+    const STATE: u32 = 20;
+
+    /// The register holding the start of the tape.
+    const TAPE: u32 = 21;
+
+    /// The register holding the end of the tape.
+    const TAPE_END: u32 = 22;
+
+    /// ldp x21,x22,[sp,#32]
+    /// ldp x19,x20,[sp,#16]
+    /// ldp x29,x30,[sp],#48
+    /// ret
+    const TEARDOWN: [u32; 4] = [0xa9425bf5, 0xa94153f3, 0xa8c37bfd, 0xd65f03c0];
+
+    /// Encapsulates machine code instructions, which are all 4 bytes long.
+    #[derive(Debug, Default)]
+    pub struct MachineCode {
+        buf: Vec<u8>,
+        suspend_write: bool,
+        cell_width: CellWidth,
+
+        /// Where the code that stops the execution on a failed bounds check starts.
+        out_of_bounds: usize,
+
+        /// The targets of the jumps between instructions.
+        labels: Labels,
+    }
+
+    impl MachineCode {
+        /// Creates machine code operating on cells of the given width.
+        pub fn new(cell_width: CellWidth) -> Self {
+            Self {
+                cell_width,
+                ..Self::default()
+            }
+        }
+
+        /// Emits the setup and the code every failed bounds check jumps to, which calls
+        /// `out_of_bounds` and returns its status.
+        pub fn emit_stack_setup(&mut self, out_of_bounds: usize) -> usize {
+            // stp x29,x30,[sp,#-48]!
+            // stp x19,x20,[sp,#16]
+            // stp x21,x22,[sp,#32]
+            // mov x29,sp
+            // mov x20,x0
+            // mov x19,x1
+            // mov x21,x1
+            // add x22,x1,x2
+            // b   <start>
+            // out_of_bounds:
+            // mov x0,x20
+            // mov x16,<out_of_bounds>
+            // blr x16
+            // <teardown>
+            // start:
+            //
+            // `x0` is the state passed to the callbacks, `x1` the start of the tape and `x2` its
+            // length in bytes.
+            let stub = [
+                &[0xaa0003e0 | STATE << 16][..],
+                &mov_x(16, out_of_bounds as u64),
+                &[0xd63f0200],
+                &TEARDOWN,
+            ]
+            .concat();
+            let len = self.write(&[
+                0xa9bd7bfd, 0xa90153f3, 0xa9025bf5, 0x910003fd, 0xaa0003f4, 0xaa0103f3, 0xaa0103f5,
+                0x8b020036,
+            ]) + self.write(&[branch(1 + stub.len() as i32)]);
+            self.out_of_bounds = self.buf.len();
+            len + self.write(&stub)
+        }
+
+        /// Emits the teardown, which returns that the program finished.
+        pub fn emit_stack_teardown(&mut self) -> usize {
+            // mov w0,<status>
+            // <teardown>
+            self.write(&[movz_w(0, EXIT_FINISHED as u16)]) + self.write(&TEARDOWN)
+        }
+
+        /// Moves the data pointer and, unless `check` is `None`, checks it, reporting `check`
+        /// as the instruction pointer if it is outside of the tape.
+        pub fn emit_inc_dp(&mut self, n: usize, check: Option<usize>) -> usize {
+            // add x19,x19,<n> (or x9 with `n`)
+            // <check x19>
+            match self.emit_move_dp(n, 0x91000000, 0x8b090000) {
+                0 => 0,
+                len => len + self.emit_check_dp(true, check),
+            }
+        }
+
+        /// Moves the data pointer back like [emit_inc_dp](MachineCode::emit_inc_dp).
+        pub fn emit_dec_dp(&mut self, n: usize, check: Option<usize>) -> usize {
+            // sub x19,x19,<n> (or x9 with `n`)
+            // <check x19>
+            match self.emit_move_dp(n, 0xd1000000, 0xcb090000) {
+                0 => 0,
+                len => len + self.emit_check_dp(false, check),
+            }
+        }
+
+        /// Moves the data pointer `n` cells with `immediate` or, if the distance does not fit
+        /// into 12 bits, `register` with the distance in `x9`.
+        fn emit_move_dp(&mut self, n: usize, immediate: u32, register: u32) -> usize {
+            match n * self.cell_width.bytes() {
+                0 => 0,
+                n @ 1..=0xfff => self.write(&[immediate | (n as u32) << 10 | DP << 5 | DP]),
+                n => self.write(&mov_x(9, n as u64)) + self.write(&[register | DP << 5 | DP]),
+            }
+        }
+
+        fn emit_check_dp(&mut self, forward: bool, check: Option<usize>) -> usize {
+            match check {
+                Some(ip) => self.write(&self.check(DP, forward, ip, self.buf.len())),
+                None => 0,
+            }
+        }
+
+        /// Returns a check, written at `at`, that the pointer in `x<rn>` is inside of the tape,
+        /// which starts at `x21` and ends at `x22`. Otherwise it jumps to the code emitted by the
+        /// setup with the instruction pointer and the offset of the pointer.
+        ///
+        /// Pointers only move into the tape from inside of it, so a pointer that moved
+        /// `forward` is only compared with the end, and one that moved backward with the start.
+        fn check(&self, rn: u32, forward: bool, ip: usize, at: usize) -> Vec<u32> {
+            // cmp  x<rn>,<x22 or x21>
+            // b.lo <done> (or b.hs)
+            // sub  x2,x<rn>,x21
+            // mov  w1,<ip>
+            // b    <out_of_bounds>
+            // done:
+            let (bound, inside) = match forward {
+                true => (TAPE_END, 0x3),
+                false => (TAPE, 0x2),
+            };
+            let jump = (self.out_of_bounds as i32).wrapping_sub(at as i32 + 20) / 4;
+            [
+                &[
+                    0xeb00001f | bound << 16 | rn << 5,
+                    0x54000000 | 5 << 5 | inside,
+                    0xcb000002 | TAPE << 16 | rn << 5,
+                ],
+                &mov_w(1, ip as u32)[..],
+                &[branch(jump)],
+            ]
+            .concat()
+        }
+
+        /// Adds `n` to the cell at the data pointer, wrapping around.
+        pub fn emit_inc_byte_at_dp(&mut self, n: usize) -> usize {
+            // add w0,w0,<n>
+            self.emit_cell_arithmetic(n, 0x11000000, 0x0b010000)
+        }
+
+        /// Subtracts `n` from the cell at the data pointer, wrapping around.
+        pub fn emit_dec_byte_at_dp(&mut self, n: usize) -> usize {
+            // sub w0,w0,<n>
+            self.emit_cell_arithmetic(n, 0x51000000, 0x4b010000)
+        }
+
+        /// Loads the cell at `[x19]` into `w0`, applies `immediate` or, if `n` does not fit
+        /// into 12 bits, `register` with `n` in `w1`, and stores `w0` back into the cell.
+        fn emit_cell_arithmetic(&mut self, n: usize, immediate: u32, register: u32) -> usize {
+            let n = (n as u64 & self.cell_mask()) as u32;
+            let operation = match n {
+                0 => return 0,
+                1..=0xfff => vec![immediate | n << 10],
+                _ => [&mov_w(1, n)[..], &[register]].concat(),
+            };
+            self.write(&[self.load_cell(0, DP)])
+                + self.write(&operation)
+                + self.write(&[self.store_cell(0, DP)])
+        }
+
+        /// Appends the byte at the data pointer `n` times to the output buffer, or calls
+        /// `callback` if it does not fit.
+        pub fn emit_write_byte_at_dp(&mut self, n: usize, callback: usize) -> usize {
+            // mov  x0,x20
+            // ldrb w1,[x19]
+            // mov  x2,<n>
+            // <call callback>
+            let arguments = [&[0x39400261][..], &mov_x(2, n as u64)].concat();
+            let call = |mc: &mut Self| mc.emit_call(callback, &arguments);
+            if n >= 1 << 12 {
+                return call(self);
+            }
+
+            // ldp  x9,x10,[x20,#8]
+            // add  x11,x9,#<n>
+            // cmp  x11,x10
+            // b.hi <slow>
+            // str  x11,[x20,#8]
+            // ldr  x10,[x20]
+            // add  x10,x10,x9
+            // ldrb w12,[x19]
+            // mov  x13,#<n>
+            // loop:
+            // strb w12,[x10],#1
+            // subs x13,x13,#1
+            // b.ne <loop>
+            // b    <done>
+            // slow:
+            // <call callback>
+            // done:
+            //
+            // `x20` points to the length and the capacity of the output buffer after its data.
+            let n = n as u32;
+            let slow = (self.get_only_len(call) / 4) as i32;
+            self.write(&[0xa940aa89, 0x9100012b | n << 10, 0xeb0a017f, 0x54000148])
+                + self.write(&[0xf900068b, 0xf940028a, 0x8b09014a, 0x3940026c])
+                + self.write(&[0xd280000d | n << 5, 0x3800154c, 0xf10005ad, 0x54ffffc1])
+                + self.write(&[branch(1 + slow)])
+                + call(self)
+        }
+
+        /// Reads into the cell at the data pointer by calling `callback`, stopping the execution
+        /// if it fails.
+        pub fn emit_read_byte_at_dp(&mut self, callback: usize) -> usize {
+            // mov x0,x20
+            // mov x1,x19
+            // <call callback>
+            self.emit_call(callback, &[0xaa1303e1])
+        }
+
+        /// Stops the execution with the cell at the data pointer as exit code by calling
+        /// `callback`.
+        pub fn emit_exit(&mut self, callback: usize) -> usize {
+            // mov x0,x20
+            // mov x1,x19
+            // <call callback>
+            self.emit_call(callback, &[0xaa1303e1])
+        }
+
+        /// Selects the input by the cell at the data pointer by calling `callback`, stopping
+        /// the execution if it fails.
+        pub fn emit_select_input(&mut self, callback: usize) -> usize {
+            // mov x0,x20
+            // mov x1,x19
+            // <call callback>
+            self.emit_call(callback, &[0xaa1303e1])
+        }
+
+        /// Counts the steps of the lowered loop at `ip` by calling `callback` with the cell at
+        /// the data pointer, stopping the execution if they exceed a limit.
+        pub fn emit_steps(&mut self, ip: usize, callback: usize) -> usize {
+            // mov x0,x20
+            // mov x1,x19
+            // mov x2,<ip>
+            // <call callback>
+            self.emit_call(
+                callback,
+                &[&[0xaa1303e1][..], &mov_x(2, ip as u64)].concat(),
+            )
+        }
+
+        /// Calls the `extern "C"` function at `callback` with the state in `x0` and the
+        /// remaining arguments set up by `arguments`, returning its status if it fails.
+        fn emit_call(&mut self, callback: usize, arguments: &[u32]) -> usize {
+            // mov x0,x20
+            // <arguments>
+            // mov x16,<callback>
+            // blr x16
+            // cbz w0,<done>
+            // <teardown>
+            // done:
+            //
+            // The stack stays aligned to 16 bytes as the setup reserved 48 bytes.
+            let done = 1 + TEARDOWN.len() as i32;
+            self.write(&[0xaa0003e0 | STATE << 16])
+                + self.write(arguments)
+                + self.write(&mov_x(16, callback as u64))
+                + self.write(&[0xd63f0200, cbz(0, done)])
+                + self.write(&TEARDOWN)
+        }
+
+        /// Sets the cell at the data pointer to zero.
+        pub fn emit_set_zero(&mut self) -> usize {
+            // str<cell> wzr,[x19]
+            self.write(&[self.store_cell(31, DP)])
+        }
+
+        /// Moves the data pointer `stride` cells at a time until it is at a zero cell, reporting
+        /// `ip` as the instruction pointer if it leaves the tape.
+        pub fn emit_find_zero(&mut self, stride: isize, ip: usize) -> usize {
+            // loop:
+            // ldr<cell> w0,[x19]
+            // cbz       w0,<done>
+            // add       x19,x19,<stride>
+            // <check x19>
+            // b         <loop>
+            // done:
+            let stride = stride as i64 * self.cell_width.bytes() as i64;
+            let add = match stride.unsigned_abs() {
+                n @ 0..=0xfff if stride < 0 => vec![0xd1000000 | (n as u32) << 10 | DP << 5 | DP],
+                n @ 0..=0xfff => vec![0x91000000 | (n as u32) << 10 | DP << 5 | DP],
+                // add x19,x19,x1
+                _ => [&mov_x(1, stride as u64)[..], &[0x8b010273]].concat(),
+            };
+            let at = self.buf.len() + 8 + add.len() * 4;
+            let add = [add, self.check(DP, stride > 0, ip, at)].concat();
+            let len = 2 + add.len() as i32;
+            self.write(&[self.load_cell(0, DP), cbz(0, len)])
+                + self.write(&add)
+                + self.write(&[branch(-len)])
+        }
+
+        /// Adds the cell at the data pointer times `factor` to the cell `offset` cells away from
+        /// it, checking that cell unless `check` is `None`.
+        pub fn emit_add_to(&mut self, offset: isize, factor: i32, check: Option<usize>) -> usize {
+            // ldr<cell> w0,[x19]
+            // cbz       w0,<done>
+            // mov       w1,<factor>
+            // mul       w0,w0,w1
+            // mov       x2,<offset>
+            // add       x2,x19,x2
+            // <check x2> (unless `check` is `None`)
+            // ldr<cell> w3,[x2]
+            // add       w3,w3,w0
+            // str<cell> w3,[x2]
+            // done:
+            let offset = offset as i64 * self.cell_width.bytes() as i64;
+            let add = [
+                &mov_w(1, factor as u32)[..],
+                &[0x1b017c00],
+                &mov_x(2, offset as u64),
+                &[0x8b020262],
+                &check.map_or(Vec::new(), |ip| {
+                    self.check(2, offset > 0, ip, self.buf.len() + 40)
+                }),
+                &[self.load_cell(3, 2), 0x0b000063, self.store_cell(3, 2)],
+            ]
+            .concat();
+            self.write(&[self.load_cell(0, DP), cbz(0, 1 + add.len() as i32)]) + self.write(&add)
+        }
+
+        /// Increments the `u64` at `address`, e.g. to count how often the code runs.
+        pub fn emit_count(&mut self, address: usize) -> usize {
+            // mov x9,<address>
+            // ldr x10,[x9]
+            // add x10,x10,#1
+            // str x10,[x9]
+            self.write(&mov_x(9, address as u64))
+                + self.write(&[0xf940012a, 0x9100054a, 0xf900012a])
+        }
+
+        /// Subtracts `cost` from the instructions the program may still execute and calls
+        /// `callback` once they are used up, stopping the execution if it fails.
+        pub fn emit_fuel(&mut self, cost: u32, callback: usize) -> usize {
+            // ldr  x9,[x20,#24]
+            // subs x9,x9,#<cost>
+            // str  x9,[x20,#24]
+            // b.hs <done>
+            // mov  x1,x19
+            // <call callback>
+            // done:
+            //
+            // `x20` points to the remaining instructions after the output buffer.
+            debug_assert!(cost < 1 << 12, "the cost fits into an immediate");
+            let call = |mc: &mut Self| mc.emit_call(callback, &[0xaa1303e1]);
+            let done = 1 + self.get_only_len(call) as u32 / 4;
+            self.write(&[0xf9400e89, 0xf1000129 | cost << 10, 0xf9000e89])
+                + self.write(&[0x54000002 | done << 5])
+                + call(self)
+        }
+
+        /// Returns a label to jump to, which has to be [bound](MachineCode::bind_label) before
+        /// the code is executed.
+        pub fn new_label(&mut self) -> Label {
+            self.labels.create()
+        }
+
+        /// Binds `label` to the end of the code, patching the jumps emitted to it so far.
+        pub fn bind_label(&mut self, label: Label) {
+            let target = self.buf.len();
+            for jump in self.labels.bind(label, target) {
+                let offset = (target as i32 - jump as i32) / 4;
+                self.buf[jump..jump + 4].copy_from_slice(&branch(offset).to_le_bytes());
+            }
+        }
+
+        /// Jumps to `target` if the cell at the data pointer is zero.
+        pub fn emit_jump_zero(&mut self, target: Label) -> usize {
+            // ldr<cell> w0,[x19]
+            // cbnz      w0,<done>
+            // b         <target>
+            // done:
+            //
+            // Unlike `b`, `cbnz` can only jump 1 MiB.
+            self.write(&[self.load_cell(0, DP), 0x35000000 | 2 << 5]) + self.write_branch(target)
+        }
+
+        /// Jumps to `target` unless the cell at the data pointer is zero.
+        pub fn emit_jump_not_zero(&mut self, target: Label) -> usize {
+            // ldr<cell> w0,[x19]
+            // cbz       w0,<done>
+            // b         <target>
+            // done:
+            self.write(&[self.load_cell(0, DP), cbz(0, 2)]) + self.write_branch(target)
+        }
+
+        /// Writes a `b` to `target`, which is relative to the start of the `b`, or a placeholder
+        /// that is patched once `target` is bound.
+        fn write_branch(&mut self, target: Label) -> usize {
+            let jump = self.buf.len();
+            let offset = match self.labels.position(target) {
+                Some(target) => (target as i32 - jump as i32) / 4,
+                None => {
+                    if !self.suspend_write {
+                        self.labels.refer(jump, target);
+                    }
+                    0
+                }
+            };
+            self.write(&[branch(offset)])
+        }
+
+        /// Returns `ldr<cell> w<rt>,[x<rn>]`.
+        fn load_cell(&self, rt: u32, rn: u32) -> u32 {
+            let opcode = match self.cell_width {
+                CellWidth::U8 => 0x39400000,
+                CellWidth::U16 => 0x79400000,
+                CellWidth::U32 => 0xb9400000,
+            };
+            opcode | rn << 5 | rt
+        }
+
+        /// Returns `str<cell> w<rt>,[x<rn>]`, where register 31 is `wzr`.
+        fn store_cell(&self, rt: u32, rn: u32) -> u32 {
+            let opcode = match self.cell_width {
+                CellWidth::U8 => 0x39000000,
+                CellWidth::U16 => 0x79000000,
+                CellWidth::U32 => 0xb9000000,
+            };
+            opcode | rn << 5 | rt
+        }
+
+        fn cell_mask(&self) -> u64 {
+            u64::MAX >> (64 - self.cell_width.bits())
+        }
+
+        /// Returns the length of the code `f` emits, without emitting it.
+        pub fn get_only_len(&mut self, f: impl Fn(&mut Self) -> usize) -> usize {
+            // Instructions measure their parts while they are measured themselves.
+            let suspend_write = std::mem::replace(&mut self.suspend_write, true);
+            let len = f(self);
+            self.suspend_write = suspend_write;
+            len
+        }
+
+        /// Returns the code emitted so far.
+        pub fn get_buf(&self) -> &[u8] {
+            &self.buf
+        }
+
+        /// Returns the width of the cells the code operates on.
+        pub fn cell_width(&self) -> CellWidth {
+            self.cell_width
+        }
+
+        fn write(&mut self, code: &[u32]) -> usize {
+            if !self.suspend_write {
+                for instruction in code {
+                    self.buf.extend_from_slice(&instruction.to_le_bytes());
+                }
+            }
+            code.len() * 4
+        }
+    }
+
+    /// Returns `movz w<rd>,<n>`.
+    fn movz_w(rd: u32, n: u16) -> u32 {
+        0x52800000 | (n as u32) << 5 | rd
+    }
+
+    /// Returns `movz` and `movk` moving `n` into `w<rd>`, always two instructions.
+    fn mov_w(rd: u32, n: u32) -> [u32; 2] {
+        [movz_w(rd, n as u16), 0x72a00000 | (n >> 16) << 5 | rd]
+    }
+
+    /// Returns `movz` and `movk` moving `n` into `x<rd>`, always four instructions, so the
+    /// length of the code does not depend on `n`.
+    fn mov_x(rd: u32, n: u64) -> [u32; 4] {
+        let part = |hw: u64| ((n >> (16 * hw)) & 0xffff) as u32;
+        [
+            0xd2800000 | part(0) << 5 | rd,
+            0xf2a00000 | part(1) << 5 | rd,
+            0xf2c00000 | part(2) << 5 | rd,
+            0xf2e00000 | part(3) << 5 | rd,
+        ]
+    }
+
+    /// Returns `cbz w<rt>,<offset>`, with `offset` in instructions.
+    fn cbz(rt: u32, offset: i32) -> u32 {
+        0x34000000 | (offset as u32 & 0x7ffff) << 5 | rt
+    }
+
+    /// Returns `b <offset>`, with `offset` in instructions.
+    fn branch(offset: i32) -> u32 {
+        0x14000000 | (offset as u32 & 0x3ffffff)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // TODO: Test output must be manually checked as the generated machine code writes directly
-    // to stdout.
+    use std::env;
+    use std::io::{self, Cursor, Read, Write};
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    use crate::compiler::{Compiler, Instruction};
+    use crate::interpreter::Interpreter;
+    use crate::jit::{
+        aarch64, bounds_checks, dump_machine_code, emit_instructions, x86_64, Callbacks,
+        CompiledProgram, JitCompiler, MachineCode, DEFAULT_STACK_SIZE,
+    };
+    use crate::tape::{CellWidth, TapePolicy};
+    use crate::timing::{Phase, PhaseTime};
+    use crate::{EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
+
+    /// The environment variable that tells a test it runs in the child process of
+    /// [with_piped_stdio].
+    const CHILD: &str = "BRAINFUCK_JIT_TEST_CHILD";
 
-    use crate::compiler::Compiler;
-    use crate::jit::JitCompiler;
+    /// Encloses the output of the code under test in the output of the child process, which
+    /// also contains the output of the test harness.
+    const MARKER: &[u8] = b"\x01jit test output\x01";
+
+    /// Runs `f` on the standard input and output of a child process, where both are pipes,
+    /// by running the test `test` of this binary again, and returns what `f` wrote to the
+    /// standard output after reading `input`.
+    ///
+    /// Returns `None` in the child process, where the test has to return after this call.
+    fn with_piped_stdio(
+        test: &str,
+        input: &[u8],
+        f: impl FnOnce(&mut dyn Read, &mut dyn Write),
+    ) -> Option<Vec<u8>> {
+        if env::var_os(CHILD).is_some() {
+            let mut stdout = io::stdout();
+            stdout.write_all(MARKER).unwrap();
+            f(&mut io::stdin().lock(), &mut stdout);
+            stdout.write_all(MARKER).unwrap();
+            stdout.flush().unwrap();
+            return None;
+        }
+
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args([test, "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let start = output
+            .stdout
+            .windows(MARKER.len())
+            .position(|window| window == MARKER)
+            .expect("the child process ran the test")
+            + MARKER.len();
+        let len = output.stdout[start..]
+            .windows(MARKER.len())
+            .position(|window| window == MARKER)
+            .expect("the test finished in the child process");
+        Some(output.stdout[start..start + len].to_vec())
+    }
 
     #[test]
     fn test_program_hello_world() {
-        let instructions = Compiler::new(include_str!("../programs/hello_world.b")).compile();
-        JitCompiler::new(&instructions).execute().unwrap();
-        // Output must be `Hello World!`.
+        let instructions = Compiler::new(include_str!("../programs/hello_world.b"))
+            .compile()
+            .unwrap();
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(writer), Ok("Hello World!\n".into()));
     }
 
     #[test]
     fn test_program_bitwidth() {
-        let instructions = Compiler::new(include_str!("../programs/bitwidth.b")).compile();
-        JitCompiler::new(&instructions).execute().unwrap();
-        // Output must be `Hello World! 255`.
+        let instructions = Compiler::new(include_str!("../programs/bitwidth.b"))
+            .compile()
+            .unwrap();
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+
+        JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(writer), Ok("Hello World! 255\n".into()));
+    }
+
+    #[test]
+    fn test_read_at_eof() {
+        // Writes the low byte of every cell after reading into it.
+        let instructions = Compiler::new("+,.>+,.>+,.").compile().unwrap();
+        let cases = [
+            (EofBehavior::SetZero, [1, 0, 0]),
+            (EofBehavior::SetMinusOne, [1, 255, 255]),
+            (EofBehavior::LeaveUnchanged, [1, 1, 1]),
+        ];
+
+        for (eof, output) in cases {
+            let mut reader = Cursor::new([1]);
+            let mut writer = Vec::new();
+
+            JitCompiler::new(&instructions, &mut reader, &mut writer)
+                .with_cell_width(CellWidth::U16)
+                .execute(FlushBehavior::OnEnd, eof)
+                .unwrap();
+
+            assert_eq!(writer, output, "{eof:?}");
+        }
+
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let err = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof));
+        assert_eq!(writer, [1]);
+    }
+
+    /// Keeps the bytes written to it and counts the calls to `write`.
+    #[derive(Default)]
+    struct WriteCounter {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl io::Write for WriteCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_buffered_output() {
+        // Writes 4096 runs of 5 bytes, more than fit into the output buffer.
+        let program = "++++++++++++++++[>++++++++++++++++[>++++++++++++++++[>+.....<-]<-]<-]";
+        let instructions = Compiler::new(program).compile().unwrap();
+        let mut expected = Vec::new();
+        Interpreter::new(program, &mut io::empty(), &mut expected)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+        assert_eq!(expected.len(), 4096 * 5);
+
+        for (flush, buffered) in [
+            (FlushBehavior::OnEnd, true),
+            (FlushBehavior::OnWrite, false),
+        ] {
+            let mut reader = io::empty();
+            let mut writer = WriteCounter::default();
+            JitCompiler::new(&instructions, &mut reader, &mut writer)
+                .execute(flush, EofBehavior::Error)
+                .unwrap();
+
+            assert!(writer.bytes == expected, "{flush:?}");
+            assert_eq!(
+                writer.writes < 100,
+                buffered,
+                "{flush:?}: {}",
+                writer.writes
+            );
+        }
+    }
+
+    #[test]
+    fn test_piped_stdio() {
+        let instructions = Compiler::new(",[.,]").compile().unwrap();
+        let output = with_piped_stdio(
+            "jit::tests::test_piped_stdio",
+            b"Hello",
+            |mut reader, mut writer| {
+                JitCompiler::new(&instructions, &mut reader, &mut writer)
+                    .execute(FlushBehavior::OnEnd, EofBehavior::SetZero)
+                    .unwrap();
+            },
+        );
+
+        if let Some(output) = output {
+            assert_eq!(output, b"Hello");
+        }
+    }
+
+    #[test]
+    fn test_piped_stdio_flushes_every_write() {
+        let instructions = Compiler::new(include_str!("../programs/hello_world.b"))
+            .compile()
+            .unwrap();
+        let output = with_piped_stdio(
+            "jit::tests::test_piped_stdio_flushes_every_write",
+            b"",
+            |mut reader, mut writer| {
+                JitCompiler::new(&instructions, &mut reader, &mut writer)
+                    .execute(FlushBehavior::OnWrite, EofBehavior::Error)
+                    .unwrap();
+            },
+        );
+
+        if let Some(output) = output {
+            assert_eq!(output, b"Hello World!\n");
+        }
+    }
+
+    #[test]
+    fn test_long_runs() {
+        // Runs longer than 255 instructions need wider immediates, also in the loop offsets.
+        let program = format!(
+            "{}[{}{}.{}-]",
+            "+".repeat(1000),
+            ">".repeat(5000),
+            "+".repeat(300),
+            "<".repeat(5000)
+        );
+        let instructions = Compiler::new(&program).compile().unwrap();
+        assert!(instructions.contains(&Instruction::IncDP(5000)));
+
+        // The first cell counts down from 1000 wrapped to the cell, and each iteration writes
+        // the low byte of the 300 added to the cell 5000 cells further.
+        for (width, iterations) in [(CellWidth::U8, 232), (CellWidth::U16, 1000)] {
+            let expected: Vec<u8> = (1..=iterations).map(|i: u32| (300 * i) as u8).collect();
+
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+            JitCompiler::new(&instructions, &mut reader, &mut writer)
+                .with_cell_width(width)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+            assert!(writer == expected, "{width:?}");
+        }
+    }
+
+    #[test]
+    fn test_custom_prologue() {
+        let instructions = Compiler::new(include_str!("../programs/hello_world.b"))
+            .compile()
+            .unwrap();
+        let callbacks = Callbacks::new(CellWidth::U16);
+        let mut machine_code = MachineCode::new(CellWidth::U16);
+        machine_code.emit_stack_setup(callbacks.out_of_bounds);
+        // Writes `>` before the output of the program.
+        machine_code.emit_inc_byte_at_dp(b'>' as usize);
+        machine_code.emit_write_byte_at_dp(1, callbacks.write);
+        machine_code.emit_set_zero();
+        emit_instructions(&mut machine_code, &instructions, &callbacks, None);
+        machine_code.emit_stack_teardown();
+
+        // SAFETY: The code is emitted between the setup and the teardown.
+        let program = unsafe {
+            CompiledProgram::from_machine_code(&machine_code, DEFAULT_TAPE_SIZE, TapePolicy::Fixed)
+        }
+        .unwrap();
+        let mut writer = Vec::new();
+        program
+            .run(
+                &mut io::empty(),
+                &mut writer,
+                FlushBehavior::OnEnd,
+                EofBehavior::Error,
+            )
+            .unwrap();
+
+        assert_eq!(String::from_utf8(writer), Ok(">Hello World!\n".into()));
+    }
+
+    #[test]
+    fn test_dump_machine_code() {
+        let instructions = Compiler::new("+[>]").compile().unwrap();
+        let dump = dump_machine_code(&instructions, CellWidth::U8);
+
+        let names: Vec<&str> = dump.lines().filter(|line| line.starts_with(';')).collect();
+        assert_eq!(
+            names,
+            [
+                "; setup",
+                "; 0  IncByteAtDP(1)",
+                "; 1  FindZero(1)",
+                "; teardown"
+            ]
+        );
+
+        // The dump contains all of the code, with the offset of every line.
+        let callbacks = Callbacks::new(CellWidth::U8);
+        let mut machine_code = MachineCode::new(CellWidth::U8);
+        machine_code.emit_stack_setup(callbacks.out_of_bounds);
+        emit_instructions(&mut machine_code, &instructions, &callbacks, None);
+        machine_code.emit_stack_teardown();
+        let mut offset = 0;
+        let mut code = Vec::new();
+        for line in dump.lines().filter(|line| !line.starts_with(';')) {
+            let (at, hex) = line.split_once("  ").unwrap();
+            assert_eq!(usize::from_str_radix(at, 16), Ok(offset));
+            for byte in hex.split(' ') {
+                code.push(u8::from_str_radix(byte, 16).unwrap());
+                offset += 1;
+            }
+        }
+        assert_eq!(code, machine_code.get_buf());
+    }
+
+    #[test]
+    fn test_codegen_time() {
+        let instructions = Compiler::new("+[>+<-]").compile().unwrap();
+        let mut reader = io::empty();
+        let mut writer = io::sink();
+        let program = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .compile()
+            .unwrap();
+
+        let times = program.times();
+        assert!(times.get(Phase::Codegen).wall > Duration::ZERO);
+        assert_eq!(times.get(Phase::Execute), PhaseTime::default());
+    }
+
+    #[test]
+    fn test_run_compiled_program_twice() {
+        let instructions = Compiler::new("+.,+.").compile().unwrap();
+        let mut reader = io::empty();
+        let mut writer = io::sink();
+        let program = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .compile()
+            .unwrap();
+
+        for input in [1, 7] {
+            let mut reader = Cursor::new([input]);
+            let mut writer = Vec::new();
+            program
+                .run(
+                    &mut reader,
+                    &mut writer,
+                    FlushBehavior::OnEnd,
+                    EofBehavior::Error,
+                )
+                .unwrap();
+
+            // Every run starts with a zero tape.
+            assert_eq!(writer, [1, input + 1]);
+        }
+    }
+
+    #[test]
+    fn test_loop_counters() {
+        let instructions = Compiler::new(",[>+++[.-]<-]").compile().unwrap();
+        let mut reader = io::empty();
+        let mut writer = io::sink();
+        let program = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .with_loop_counters()
+            .compile()
+            .unwrap();
+
+        for input in [2, 1] {
+            let mut reader = Cursor::new([input]);
+            program
+                .run(
+                    &mut reader,
+                    &mut io::sink(),
+                    FlushBehavior::OnEnd,
+                    EofBehavior::Error,
+                )
+                .unwrap();
+
+            // Every run starts counting from zero.
+            let outer = input as u64;
+            assert_eq!(
+                program.loop_counts(),
+                Some(vec![(1, outer), (4, 3 * outer)])
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_on_cells() {
+        let instructions = Compiler::new("[>]+>-.").compile().unwrap();
+        let mut reader = io::empty();
+        let mut writer = io::sink();
+        let program = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .with_cell_width(CellWidth::U16)
+            .compile()
+            .unwrap();
+
+        let mut cells = [7u16, 0, 0];
+        let mut writer = Vec::new();
+        program
+            .run_on_cells(
+                &mut cells,
+                &mut reader,
+                &mut writer,
+                FlushBehavior::OnEnd,
+                EofBehavior::Error,
+            )
+            .unwrap();
+        assert_eq!(cells, [7, 1, u16::MAX]);
+        assert_eq!(writer, [255]);
+
+        let err = program
+            .run_on_cells(
+                &mut [0u8; 3],
+                &mut reader,
+                &mut writer,
+                FlushBehavior::OnEnd,
+                EofBehavior::Error,
+            )
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::Io(err) if err.kind() == io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let cases = [
+            ("+.<", CellWidth::U8, TapePolicy::Fixed, 2, -1),
+            (">>>>", CellWidth::U16, TapePolicy::Fixed, 0, 4),
+            ("+[<]", CellWidth::U8, TapePolicy::Fixed, 1, -1),
+            ("+>>+[>>]", CellWidth::U32, TapePolicy::Fixed, 3, 4),
+            ("+[->>>>+<<<<]", CellWidth::U16, TapePolicy::Fixed, 1, 4),
+            ("<", CellWidth::U8, TapePolicy::Growable, 0, -1),
+        ];
+
+        for (program, cell_width, policy, expected_ip, expected_dp) in cases {
+            let instructions = Compiler::new(program).compile().unwrap();
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+            let err = JitCompiler::new(&instructions, &mut reader, &mut writer)
+                .with_cell_width(cell_width)
+                .with_tape_size(4)
+                .with_tape_policy(policy)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap_err();
+
+            assert!(
+                matches!(
+                    err,
+                    RuntimeError::PointerOutOfBounds { ip, dp } if ip == expected_ip && dp == expected_dp
+                ),
+                "{program}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        // Sets a cell to 300 and writes it before exiting, the output has to be kept.
+        let program = ";; extension exit\n+++[>++++++++++<-]>[>++++++++++<-]>.!.";
+        let instructions = Compiler::new(program).compile().unwrap();
+
+        for (width, code) in [(CellWidth::U8, 44), (CellWidth::U16, 300)] {
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+            let err = JitCompiler::new(&instructions, &mut reader, &mut writer)
+                .with_cell_width(width)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap_err();
+
+            assert!(
+                matches!(err, RuntimeError::Exit(c) if c == code),
+                "{width:?}: {err:?}"
+            );
+            assert_eq!(writer, [44]);
+        }
+    }
+
+    #[test]
+    fn test_bounds_checks() {
+        let instructions = [
+            Instruction::IncDP(2),
+            Instruction::IncByteAtDP(1),
+            Instruction::DecDP(3),
+            Instruction::IncDP(1),
+            Instruction::AddTo {
+                offset: 1,
+                factor: 1,
+            },
+            Instruction::AddTo {
+                offset: 3,
+                factor: 1,
+            },
+            Instruction::JumpZero(4),
+            Instruction::IncDP(1),
+            Instruction::JumpNotZero(1),
+            Instruction::DecDP(1),
+            Instruction::FindZero(1),
+            Instruction::DecDP(1),
+        ];
+        assert_eq!(
+            bounds_checks(&instructions),
+            [
+                true,  // >>
+                false, // +
+                true,  // <<<
+                false, // >, back to a checked cell
+                false, // a cell between checked cells
+                true,  // a cell past the checked cells
+                false, // [
+                true,  // > after a jump
+                false, // ]
+                true,  // < after a jump
+                true,  // [>]
+                true,  // < after a scan
+            ]
+        );
+    }
+
+    #[test]
+    fn test_execute_on_thread_catches_panics() {
+        // A placeholder is never emitted by the compiler and makes the code generation panic.
+        let instructions = [Instruction::JumpZeroPlaceholder];
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let err = JitCompiler::new(&instructions, &mut reader, &mut writer)
+            .execute_on_thread(FlushBehavior::OnEnd, EofBehavior::Error, DEFAULT_STACK_SIZE)
+            .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .starts_with("the jit compiled program panicked: "));
+    }
+
+    /// Returns the instructions emitted by `f` on AArch64.
+    fn aarch64(cell_width: CellWidth, f: impl Fn(&mut aarch64::MachineCode) -> usize) -> Vec<u32> {
+        let mut machine_code = aarch64::MachineCode::new(cell_width);
+        let len = f(&mut machine_code);
+        let buf = machine_code.get_buf();
+        assert_eq!(len, buf.len());
+        buf.chunks(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Returns the code `f` emits on a new [x86_64::MachineCode], checking the length it
+    /// returns.
+    fn x86_64(cell_width: CellWidth, f: impl Fn(&mut x86_64::MachineCode) -> usize) -> Vec<u8> {
+        let mut machine_code = x86_64::MachineCode::new(cell_width);
+        let len = f(&mut machine_code);
+        assert_eq!(len, machine_code.get_buf().len());
+        machine_code.get_buf().to_vec()
+    }
+
+    #[test]
+    fn test_x86_64_encoding() {
+        /// movabs rax,0x80000000
+        const MOVABS: [u8; 10] = [0x48, 0xb8, 0, 0, 0, 0x80, 0, 0, 0, 0];
+        let cases: [(&str, _, &[u8]); 14] = [
+            ("inc r12", (CellWidth::U8, 1), &[0x49, 0xff, 0xc4]),
+            ("add r12,2", (CellWidth::U8, 2), &[0x49, 0x83, 0xc4, 0x02]),
+            (
+                "add r12,127",
+                (CellWidth::U8, 127),
+                &[0x49, 0x83, 0xc4, 0x7f],
+            ),
+            (
+                "add r12,128",
+                (CellWidth::U8, 128),
+                &[0x49, 0x81, 0xc4, 0x80, 0, 0, 0],
+            ),
+            (
+                "add r12,255",
+                (CellWidth::U8, 255),
+                &[0x49, 0x81, 0xc4, 0xff, 0, 0, 0],
+            ),
+            (
+                "add r12,256",
+                (CellWidth::U8, 256),
+                &[0x49, 0x81, 0xc4, 0, 0x01, 0, 0],
+            ),
+            (
+                "add r12,126",
+                (CellWidth::U16, 63),
+                &[0x49, 0x83, 0xc4, 0x7e],
+            ),
+            (
+                "add r12,128",
+                (CellWidth::U16, 64),
+                &[0x49, 0x81, 0xc4, 0x80, 0, 0, 0],
+            ),
+            (
+                "add r12,0x7fffffff",
+                (CellWidth::U8, i32::MAX as usize),
+                &[0x49, 0x81, 0xc4, 0xff, 0xff, 0xff, 0x7f],
+            ),
+            ("add r12,rax", (CellWidth::U8, 1 << 31), &[0x49, 0x01, 0xc4]),
+            (
+                "add r12,rax",
+                (CellWidth::U16, 1 << 30),
+                &[0x49, 0x01, 0xc4],
+            ),
+            ("dec r12", (CellWidth::U8, 1), &[0x49, 0xff, 0xcc]),
+            (
+                "sub r12,128",
+                (CellWidth::U8, 128),
+                &[0x49, 0x81, 0xec, 0x80, 0, 0, 0],
+            ),
+            ("sub r12,rax", (CellWidth::U8, 1 << 31), &[0x49, 0x29, 0xc4]),
+        ];
+        for (mnemonic, (cell_width, n), expected) in cases {
+            let code = if mnemonic.starts_with("add") || mnemonic.starts_with("inc") {
+                x86_64(cell_width, |mc| mc.emit_inc_dp(n, None))
+            } else {
+                x86_64(cell_width, |mc| mc.emit_dec_dp(n, None))
+            };
+            let code = code.strip_prefix(&MOVABS[..]).unwrap_or(&code);
+            assert_eq!(code, expected, "{mnemonic}");
+        }
+
+        // The cell is loaded into eax before the first operation on it.
+        let cases: [(&str, _, &[u8]); 10] = [
+            ("inc al", (CellWidth::U8, 1), &[0xfe, 0xc0]),
+            ("add al,127", (CellWidth::U8, 127), &[0x04, 0x7f]),
+            ("add al,128", (CellWidth::U8, 128), &[0x04, 0x80]),
+            ("add al,255", (CellWidth::U8, 255), &[0x04, 0xff]),
+            ("", (CellWidth::U8, 256), &[]),
+            ("add ax,256", (CellWidth::U16, 256), &[0x66, 0x05, 0, 0x01]),
+            (
+                "add ax,65535",
+                (CellWidth::U16, 65535),
+                &[0x66, 0x05, 0xff, 0xff],
+            ),
+            ("", (CellWidth::U16, 65536), &[]),
+            (
+                "add eax,0x7fffffff",
+                (CellWidth::U32, i32::MAX as usize),
+                &[0x05, 0xff, 0xff, 0xff, 0x7f],
+            ),
+            (
+                "sub eax,0x7fffffff",
+                (CellWidth::U32, i32::MAX as usize),
+                &[0x2d, 0xff, 0xff, 0xff, 0x7f],
+            ),
+        ];
+        for (mnemonic, (cell_width, n), expected) in cases {
+            let code = if mnemonic.starts_with("sub") {
+                x86_64(cell_width, |mc| mc.emit_dec_byte_at_dp(n))
+            } else {
+                x86_64(cell_width, |mc| mc.emit_inc_byte_at_dp(n))
+            };
+            let load: &[u8] = match (expected.is_empty(), cell_width) {
+                (true, _) => &[],
+                (false, CellWidth::U8) => &[0x41, 0x0f, 0xb6, 0x04, 0x24],
+                (false, CellWidth::U16) => &[0x41, 0x0f, 0xb7, 0x04, 0x24],
+                (false, CellWidth::U32) => &[0x41, 0x8b, 0x04, 0x24],
+            };
+            assert_eq!(code, [load, expected].concat(), "{mnemonic}");
+        }
+
+        // cmp BYTE PTR [r12],0x0; je <done>; add r12,<stride>; ...
+        let cases: [(&str, isize, &[u8]); 4] = [
+            ("add r12,127", 127, &[0x49, 0x83, 0xc4, 0x7f]),
+            ("add r12,128", 128, &[0x49, 0x81, 0xc4, 0x80, 0, 0, 0]),
+            ("add r12,-128", -128, &[0x49, 0x83, 0xc4, 0x80]),
+            (
+                "add r12,-129",
+                -129,
+                &[0x49, 0x81, 0xc4, 0x7f, 0xff, 0xff, 0xff],
+            ),
+        ];
+        for (mnemonic, stride, expected) in cases {
+            let code = x86_64(CellWidth::U8, |mc| mc.emit_find_zero(stride, 0));
+            assert_eq!(code[7..7 + expected.len()], *expected, "{mnemonic}");
+        }
+    }
+
+    #[test]
+    fn test_x86_64_cached_cell() {
+        // movzx eax,BYTE PTR [r12]; add al,2; dec al; xor eax,eax; inc al
+        // mov BYTE PTR [r12],al; inc r12
+        assert_eq!(
+            x86_64(CellWidth::U8, |mc| {
+                mc.emit_inc_byte_at_dp(2)
+                    + mc.emit_dec_byte_at_dp(1)
+                    + mc.emit_set_zero()
+                    + mc.emit_inc_byte_at_dp(1)
+                    + mc.emit_inc_dp(1, None)
+            }),
+            [
+                0x41, 0x0f, 0xb6, 0x04, 0x24, 0x04, 0x02, 0xfe, 0xc8, 0x31, 0xc0, 0xfe, 0xc0, 0x41,
+                0x88, 0x04, 0x24, 0x49, 0xff, 0xc4
+            ]
+        );
+    }
+
+    #[test]
+    fn test_x86_64_calls() {
+        // mov rdi,r13; mov rsi,r12; movabs rax,0x1122; call rax
+        assert_eq!(
+            x86_64(CellWidth::U8, |mc| mc.emit_read_byte_at_dp(0x1122))[..18],
+            [
+                0x4c, 0x89, 0xef, 0x4c, 0x89, 0xe6, 0x48, 0xb8, 0x22, 0x11, 0, 0, 0, 0, 0, 0, 0xff,
+                0xd0
+            ]
+        );
+        // mov rdi,r13; movzx esi,BYTE PTR [r12]; movabs rdx,1<<40
+        assert_eq!(
+            x86_64(CellWidth::U8, |mc| mc
+                .emit_write_byte_at_dp(1 << 40, 0x1122))[..18],
+            [0x4c, 0x89, 0xef, 0x41, 0x0f, 0xb6, 0x34, 0x24, 0x48, 0xba, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_aarch64_encoding() {
+        // add x19,x19,#2
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| mc.emit_inc_dp(2, Some(3)))[..1],
+            [0x91000a73]
+        );
+        // sub x19,x19,#8
+        assert_eq!(
+            aarch64(CellWidth::U32, |mc| mc.emit_dec_dp(2, Some(3)))[..1],
+            [0xd1002273]
+        );
+        // mov x9,#4400; movk x9,#0,lsl #16; ...; add x19,x19,x9
+        assert_eq!(
+            aarch64(CellWidth::U32, |mc| mc.emit_inc_dp(1100, None)),
+            [0xd2822609, 0xf2a00009, 0xf2c00009, 0xf2e00009, 0x8b090273]
+        );
+        // ldrh w0,[x19]; add w0,w0,#3; strh w0,[x19]
+        assert_eq!(
+            aarch64(CellWidth::U16, |mc| mc.emit_inc_byte_at_dp(3)),
+            [0x79400260, 0x11000c00, 0x79000260]
+        );
+        // A byte wraps around, so nothing is emitted.
+        assert_eq!(aarch64(CellWidth::U8, |mc| mc.emit_dec_byte_at_dp(256)), []);
+        // strb wzr,[x19]
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| mc.emit_set_zero()),
+            [0x3900027f]
+        );
+        // ldrb w0,[x19]; cbz w0,#36; sub x19,x19,#1; <check x19>; b #-36
+        let find_zero = aarch64(CellWidth::U8, |mc| mc.emit_find_zero(-1, 3));
+        assert_eq!(find_zero[..3], [0x39400260, 0x34000120, 0xd1000673]);
+        assert_eq!(find_zero[9..], [0x17fffff7]);
+        // ldp x9,x10,[x20,#8]; add x11,x9,#3; cmp x11,x10; b.hi #40; ...; b #68; <call>
+        let write = aarch64(CellWidth::U8, |mc| mc.emit_write_byte_at_dp(3, 0));
+        assert_eq!(write[..4], [0xa940aa89, 0x91000d2b, 0xeb0a017f, 0x54000148]);
+        assert_eq!(write[12..14], [0x14000011, 0xaa1403e0]);
+        // mov x0,x20; ... without a buffer for runs that do not fit into an immediate.
+        let write = aarch64(CellWidth::U8, |mc| mc.emit_write_byte_at_dp(4096, 0));
+        assert_eq!(write[0], 0xaa1403e0);
+    }
+
+    #[test]
+    fn test_aarch64_bounds_check() {
+        // add x19,x19,#1; cmp x19,x22; b.lo #20; sub x2,x19,x21; mov w1,#3;
+        // movk w1,#0,lsl #16; b #-24
+        //
+        // The failed check jumps to the code emitted by the setup, which is at 0 here.
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| mc.emit_inc_dp(1, Some(3))),
+            [0x91000673, 0xeb16027f, 0x540000a3, 0xcb150262, 0x52800061, 0x72a00001, 0x17fffffa]
+        );
+        // sub x19,x19,#1; cmp x19,x21; b.hs #20; ...
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| mc.emit_dec_dp(1, Some(3)))[..3],
+            [0xd1000673, 0xeb15027f, 0x540000a2]
+        );
+
+        // Moving the data pointer by zero cells neither moves nor checks it.
+        assert_eq!(aarch64(CellWidth::U8, |mc| mc.emit_dec_dp(0, Some(3))), []);
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| mc.emit_dec_dp(1, None)),
+            [0xd1000673]
+        );
+
+        // b #44; mov x0,x20; <mov x16,0>; blr x16; <teardown>
+        let setup = aarch64(CellWidth::U8, |mc| mc.emit_stack_setup(0));
+        assert_eq!(setup[8..11], [0x1400000b, 0xaa1403e0, 0xd2800010]);
+        assert_eq!(setup.len(), 8 + 1 + 10);
+    }
+
+    #[test]
+    fn test_aarch64_jumps() {
+        // ldrb w0,[x19]; cbnz w0,#8; b #28
+        // ldrb w0,[x19]; add w0,w0,#1; strb w0,[x19]
+        // ldrb w0,[x19]; cbz w0,#8; b #-20
+        assert_eq!(
+            aarch64(CellWidth::U8, |mc| {
+                let (body, end) = (mc.new_label(), mc.new_label());
+                let len = mc.emit_jump_zero(end);
+                mc.bind_label(body);
+                let len = len + mc.emit_inc_byte_at_dp(1) + mc.emit_jump_not_zero(body);
+                mc.bind_label(end);
+                len
+            }),
+            [
+                0x39400260, 0x35000040, 0x14000007, 0x39400260, 0x11000400, 0x39000260, 0x39400260,
+                0x34000040, 0x17fffffb
+            ]
+        );
+
+        // The length of the code does not depend on the operands, which are not known when
+        // measuring the fast path of a write.
+        let len = |f: &dyn Fn(&mut aarch64::MachineCode) -> usize| {
+            aarch64::MachineCode::default().get_only_len(f)
+        };
+        assert_eq!(
+            len(&|mc| mc.emit_write_byte_at_dp(1, 0)),
+            len(&|mc| mc.emit_write_byte_at_dp(1, usize::MAX))
+        );
     }
 }
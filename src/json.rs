@@ -0,0 +1,70 @@
+//! Minimal JSON serialization for the machine readable output of the crate.
+
+use std::fmt::Write;
+
+/// Returns `s` as a quoted and escaped JSON string.
+pub fn string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Returns a JSON array containing the already serialized `values`.
+pub fn array<I>(values: I) -> String
+where
+    I: IntoIterator<Item = String>,
+{
+    format!("[{}]", values.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// Builds a JSON object from already serialized values.
+#[derive(Debug, Default)]
+pub struct Object {
+    fields: Vec<String>,
+}
+
+impl Object {
+    /// Adds a field with an already serialized `value`.
+    pub fn field(mut self, name: &str, value: impl ToString) -> Self {
+        self.fields
+            .push(format!("{}:{}", string(name), value.to_string()));
+        self
+    }
+
+    /// Returns the serialized object.
+    pub fn finish(self) -> String {
+        format!("{{{}}}", self.fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{array, string, Object};
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(string("a\"b\\c\n\u{1}"), r#""a\"b\\c\n\u0001""#);
+    }
+
+    #[test]
+    fn test_object() {
+        let json = Object::default()
+            .field("name", string("bf"))
+            .field("sizes", array([1, 2].map(|n: i32| n.to_string())))
+            .finish();
+
+        assert_eq!(json, r#"{"name":"bf","sizes":[1,2]}"#);
+    }
+}
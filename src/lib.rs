@@ -1,13 +1,79 @@
-use syntax::IDENTS;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::time::Duration;
 
+use tape::Cell;
+use tokenizer::{tokenize, TokenKind};
+
+pub mod bytecode;
+pub mod capabilities;
+pub mod cell_names;
+pub mod cell_view;
+#[cfg_attr(not(feature = "internals"), doc(hidden))]
+pub mod cli;
+pub mod closures;
 pub mod compiler;
+pub mod debugger;
+pub mod decode;
+pub mod dirty_cells;
+#[cfg(all(jit, target_arch = "x86_64"))]
+pub mod elf;
+pub mod engine;
+pub mod expectations;
+pub mod explain;
+pub mod extensions;
+pub mod heatmap;
+pub mod host;
+pub mod hot_loops;
 pub mod interpreter;
-#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+#[cfg(jit)]
 pub mod jit;
+pub mod loop_assertions;
+pub mod manifest;
+pub mod newline;
+pub mod observer;
+pub mod passes;
+#[cfg(unix)]
+pub mod poll_writer;
+pub mod prelude;
+pub mod preprocessor;
+pub mod profiler;
+pub mod recording;
+pub mod repl;
+pub mod report;
+pub mod sanitizer;
+pub mod scheduler;
+pub mod snapshot;
+pub mod source_map;
+pub mod spec;
+pub mod step;
+pub mod tape;
+pub mod timing;
+pub mod tokenizer;
+pub mod transpile;
+#[cfg_attr(not(feature = "internals"), doc(hidden))]
+pub mod verifier;
 pub mod virtual_machine;
+pub mod wasm;
+pub mod watch;
 
+mod bench;
+mod effect_cache;
+mod flush;
+mod json;
+mod limits;
+#[cfg(unix)]
+mod line_editor;
+#[cfg(any(unix, windows))]
 mod mmap;
 mod syntax;
+mod throttle;
+mod toml;
+
+/// Number of cells of the tape if no other size is configured.
+pub const DEFAULT_TAPE_SIZE: usize = 30_000;
 
 /// Describes when the [writer](std::io::Write) where bytes are written to is flushed.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -19,14 +85,215 @@ pub enum FlushBehavior {
     /// Call [flush](std::io::Write::flush) once at the end, after all instructions have been
     /// executed.
     OnEnd,
+    /// Call [flush](std::io::Write::flush) after writes that follow idle output, before
+    /// reading input, and at the end, while batching bursts of writes.
+    Adaptive(AdaptiveFlush),
+}
+
+/// Thresholds of [FlushBehavior::Adaptive].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveFlush {
+    /// A write is flushed if the output has not been flushed for at least this long, so
+    /// occasional output appears immediately and a burst is flushed at least this often.
+    pub idle: Duration,
+
+    /// A write is flushed once this many bytes have been written since the last flush.
+    pub max_buffered: usize,
+}
+
+impl Default for AdaptiveFlush {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_millis(10),
+            max_buffered: 8 * 1024,
+        }
+    }
+}
+
+/// Describes what the read instruction does when the input has ended.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum EofBehavior {
+    /// Set the cell at the data pointer to zero.
+    SetZero,
+    /// Set the cell at the data pointer to -1, which is the largest value of the cell.
+    SetMinusOne,
+    /// Leave the cell at the data pointer unchanged.
+    LeaveUnchanged,
+    /// Fail with an [UnexpectedEof](io::ErrorKind::UnexpectedEof) error.
+    #[default]
+    Error,
+}
+
+impl EofBehavior {
+    /// All behaviors.
+    pub const ALL: [EofBehavior; 4] = [
+        EofBehavior::SetZero,
+        EofBehavior::SetMinusOne,
+        EofBehavior::LeaveUnchanged,
+        EofBehavior::Error,
+    ];
+
+    /// Returns the name used on the command line and in recordings.
+    pub fn name(self) -> &'static str {
+        match self {
+            EofBehavior::SetZero => "zero",
+            EofBehavior::SetMinusOne => "minus-one",
+            EofBehavior::LeaveUnchanged => "unchanged",
+            EofBehavior::Error => "error",
+        }
+    }
+
+    /// Reads a byte from `reader` into `cell`, handling the end of the input according to this
+    /// behavior. Returns the byte, or `None` at the end of the input.
+    pub(crate) fn read<C: Cell>(
+        self,
+        reader: &mut (impl Read + ?Sized),
+        cell: &mut C,
+    ) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match reader.read_exact(&mut byte) {
+            Ok(()) => {
+                *cell = C::from_byte(byte[0]);
+                return Ok(Some(byte[0]));
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => match self {
+                EofBehavior::SetZero => *cell = C::ZERO,
+                EofBehavior::SetMinusOne => *cell = C::ZERO.wrapping_sub(C::truncate(1)),
+                EofBehavior::LeaveUnchanged => {}
+                EofBehavior::Error => return Err(err),
+            },
+            Err(err) => return Err(err),
+        }
+        Ok(None)
+    }
+}
+
+impl FromStr for EofBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EofBehavior::ALL
+            .into_iter()
+            .find(|behavior| behavior.name() == s)
+            .ok_or_else(|| {
+                "valid values are `zero`, `minus-one`, `unchanged` and `error`".to_string()
+            })
+    }
+}
+
+/// A limit of an execution, see [ExecOptions::with_max_steps](engine::ExecOptions::with_max_steps)
+/// and [ExecOptions::with_timeout](engine::ExecOptions::with_timeout).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Limit {
+    /// The maximum number of steps.
+    Steps(u64),
+
+    /// The maximum time the execution may take.
+    Timeout(Duration),
+}
+
+/// Describes why the execution of a program failed.
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The instruction at `ip` moved the data pointer outside of the tape to the cell `dp`,
+    /// which is negative if it is before the first cell.
+    PointerOutOfBounds { ip: usize, dp: isize },
+
+    /// The loop that starts at `ip` repeated its body more than the `max` times it was
+    /// asserted to.
+    LoopLimitExceeded { ip: usize, max: u64 },
+
+    /// The program stopped at an `exit` [extension](crate::extensions) instruction with the
+    /// value of the cell at the data pointer as exit code, after flushing its output. It is not
+    /// a failure, but returned as an error so every execution environment stops right away.
+    Exit(u32),
+
+    /// The program selected an input with `%` that is not one of its
+    /// [HostInputs](host::HostInputs).
+    UnknownInput(u64),
+
+    /// The execution was cancelled by the callback of
+    /// [run_with_progress](engine::Engine::run_with_progress), or because its cancel flag was
+    /// set, see [ExecOptions::with_cancel_flag](engine::ExecOptions::with_cancel_flag).
+    Cancelled,
+
+    /// The execution was stopped because it exceeded the limit, e.g. of an untrusted program
+    /// that loops forever.
+    LimitExceeded(Limit),
+
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+}
+
+impl RuntimeError {
+    /// Returns the index of the instruction the error happened at, if it is known, which
+    /// [Program::position](compiler::Program::position) maps to the source.
+    pub fn ip(&self) -> Option<usize> {
+        match self {
+            RuntimeError::PointerOutOfBounds { ip, .. }
+            | RuntimeError::LoopLimitExceeded { ip, .. } => Some(*ip),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::PointerOutOfBounds { ip, dp } => write!(
+                f,
+                "instruction {ip} moved the data pointer outside of the tape to cell {dp}"
+            ),
+            RuntimeError::LoopLimitExceeded { ip, max } => {
+                write!(
+                    f,
+                    "the loop at instruction {ip} ran more than {max} iterations"
+                )
+            }
+            RuntimeError::Exit(code) => write!(f, "the program exited with code {code}"),
+            RuntimeError::UnknownInput(n) => {
+                write!(f, "the program selected the unknown host input {n}")
+            }
+            RuntimeError::Cancelled => f.write_str("the execution was cancelled"),
+            RuntimeError::LimitExceeded(Limit::Steps(max)) => {
+                write!(f, "the program exceeded the limit of {max} steps")
+            }
+            RuntimeError::LimitExceeded(Limit::Timeout(timeout)) => {
+                write!(f, "the program exceeded the timeout of {timeout:?}")
+            }
+            RuntimeError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RuntimeError::PointerOutOfBounds { .. }
+            | RuntimeError::LoopLimitExceeded { .. }
+            | RuntimeError::Exit(_)
+            | RuntimeError::UnknownInput(_)
+            | RuntimeError::Cancelled
+            | RuntimeError::LimitExceeded(_) => None,
+            RuntimeError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        RuntimeError::Io(err)
+    }
 }
 
 /// Returns the source as a vector containing only identifiers.
 ///
-/// This way, UTF-8 comments for example are filtered out.
+/// This way, UTF-8 comments for example are filtered out, as well as directives like
+/// `;; name cell 3 my-counter` that may contain instructions.
 fn remove_non_idents(code: &str) -> Vec<u8> {
-    code.chars()
-        .filter(|c| c.is_ascii() && IDENTS.contains(&(*c as u8)))
-        .map(|c| c as u8)
+    tokenize(code)
+        .iter()
+        .filter(|token| token.kind == TokenKind::Instruction)
+        .map(|token| token.text.as_bytes()[0])
         .collect()
 }
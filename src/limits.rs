@@ -0,0 +1,132 @@
+//! Counting of executed steps and of the elapsed time, so engines can stop programs
+//! that run too long, e.g. untrusted ones, with [RuntimeError::LimitExceeded], or once they
+//! are cancelled with [RuntimeError::Cancelled].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{Limit, RuntimeError};
+
+/// Number of steps executed between checks whether the timeout passed, so the clock is not read
+/// after every instruction. Engines that execute instructions in batches execute at most this
+/// many steps at once, unless a single instruction stands for more.
+///
+/// A step is a character of the unoptimized program, see [StepCost](crate::compiler::StepCost),
+/// so the limits are the same for every engine and optimization level.
+pub(crate) const CLOCK_BATCH: u64 = 100_000;
+
+/// Fails once more than `max_steps` steps are executed, `timeout` passed or `cancel` is set.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Watchdog {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub cancel: Option<&'static AtomicBool>,
+
+    /// When the first instruction was allowed.
+    start: Option<Instant>,
+    executed: u64,
+
+    /// The number of executed steps at which the clock is read next.
+    next_check: u64,
+}
+
+impl Watchdog {
+    /// Returns how many steps may be executed before the next [count](Watchdog::count),
+    /// at most [CLOCK_BATCH], starting the clock on the first call.
+    pub fn allowance(&mut self) -> u64 {
+        self.start.get_or_insert_with(Instant::now);
+        self.remaining().min(CLOCK_BATCH)
+    }
+
+    /// Returns how many more steps may be executed without exceeding the limit.
+    pub fn remaining(&self) -> u64 {
+        self.max_steps
+            .map_or(u64::MAX, |max| max.saturating_sub(self.executed))
+    }
+
+    /// Counts `n` executed steps, failing if they are more than allowed, if the
+    /// timeout passed or if the execution was cancelled.
+    pub fn count(&mut self, n: u64) -> Result<(), RuntimeError> {
+        self.executed = self.executed.saturating_add(n);
+        if let Some(max) = self.max_steps {
+            if self.executed > max {
+                return Err(self.exceeded());
+            }
+        }
+        if let Some(cancel) = self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(RuntimeError::Cancelled);
+            }
+        }
+        if let (Some(timeout), Some(start)) = (self.timeout, self.start) {
+            if self.executed >= self.next_check {
+                self.next_check = self.executed + CLOCK_BATCH;
+                if start.elapsed() > timeout {
+                    return Err(RuntimeError::LimitExceeded(Limit::Timeout(timeout)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of steps counted so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Returns the error for an instruction that would exceed the maximum number of steps.
+    pub fn exceeded(&self) -> RuntimeError {
+        RuntimeError::LimitExceeded(Limit::Steps(self.max_steps.unwrap_or(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_steps() {
+        let mut watchdog = Watchdog {
+            max_steps: Some(3),
+            ..Watchdog::default()
+        };
+        assert_eq!(watchdog.allowance(), 3);
+        watchdog.count(2).unwrap();
+        assert_eq!(watchdog.allowance(), 1);
+        assert_eq!(watchdog.remaining(), 1);
+        watchdog.count(1).unwrap();
+        assert_eq!(watchdog.allowance(), 0);
+        assert!(matches!(
+            watchdog.count(1),
+            Err(RuntimeError::LimitExceeded(Limit::Steps(3)))
+        ));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut watchdog = Watchdog {
+            timeout: Some(Duration::ZERO),
+            ..Watchdog::default()
+        };
+        assert_eq!(watchdog.allowance(), CLOCK_BATCH);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(matches!(
+            watchdog.count(1),
+            Err(RuntimeError::LimitExceeded(Limit::Timeout(Duration::ZERO)))
+        ));
+    }
+
+    #[test]
+    fn test_cancel() {
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+        let mut watchdog = Watchdog {
+            cancel: Some(&CANCEL),
+            ..Watchdog::default()
+        };
+        watchdog.allowance();
+        watchdog.count(2).unwrap();
+        CANCEL.store(true, Ordering::Relaxed);
+        assert!(matches!(watchdog.count(1), Err(RuntimeError::Cancelled)));
+        assert_eq!(watchdog.executed(), 3);
+    }
+}
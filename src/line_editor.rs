@@ -0,0 +1,355 @@
+//! Line editing with history and completion for interactive sessions in a terminal.
+//!
+//! Supported keys are the arrow keys, Home and End, Backspace and Delete, Tab to complete
+//! commands, Ctrl-A and Ctrl-E to jump to the start and end of the line, Ctrl-K and Ctrl-U to
+//! delete up to the end and start of the line, Ctrl-C to discard the line and Ctrl-D to end
+//! the session on an empty line.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+/// Maximum number of lines kept in the history.
+const HISTORY_SIZE: usize = 1000;
+
+/// A key pressed by the user.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Tab,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    Eof,
+    /// A key or escape sequence without a meaning for the editor.
+    Ignored,
+}
+
+/// Reads lines from a terminal, letting the user edit them and recall previous ones.
+pub(crate) struct LineEditor {
+    history: Vec<String>,
+
+    /// File the history is loaded from and appended to.
+    file: Option<PathBuf>,
+
+    /// Words completed by Tab at the start of the line.
+    completions: &'static [&'static str],
+}
+
+impl LineEditor {
+    /// Creates an editor that completes `completions`, with the history of `file` if given.
+    pub fn new(completions: &'static [&'static str], file: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|history| history.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        let excess = history.len().saturating_sub(HISTORY_SIZE);
+        history.drain(..excess);
+
+        Self {
+            history,
+            file,
+            completions,
+        }
+    }
+
+    /// Reads a line from the terminal on stdin, returning `None` at the end of the input.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let raw_mode = raw_mode::RawMode::enable()?;
+        let line = self.edit(prompt, &mut io::stdin().lock(), &mut io::stdout().lock());
+        drop(raw_mode);
+        println!();
+
+        if let Ok(Some(line)) = &line {
+            self.add_history(line)?;
+        }
+        line
+    }
+
+    /// Edits a line, reading keys from `keys` and drawing the line on `terminal`.
+    fn edit(
+        &self,
+        prompt: &str,
+        keys: &mut impl Read,
+        terminal: &mut impl Write,
+    ) -> io::Result<Option<String>> {
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        // Index into the history of the recalled line, the history length for a new line.
+        let mut recalled = self.history.len();
+        // The new line while a line of the history is recalled.
+        let mut draft = Vec::new();
+
+        loop {
+            // Redraw the whole line, then move the cursor back to its position.
+            let text: String = line.iter().collect();
+            write!(terminal, "\r{prompt}{text}\x1b[K")?;
+            if cursor < line.len() {
+                write!(terminal, "\x1b[{}D", line.len() - cursor)?;
+            }
+            terminal.flush()?;
+
+            match read_key(keys)? {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => return Ok(Some(line.into_iter().collect())),
+                Key::Backspace if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = line.len(),
+                Key::Up if recalled > 0 => {
+                    if recalled == self.history.len() {
+                        draft = line.clone();
+                    }
+                    recalled -= 1;
+                    line = self.history[recalled].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down if recalled < self.history.len() => {
+                    recalled += 1;
+                    line = match self.history.get(recalled) {
+                        Some(recalled) => recalled.chars().collect(),
+                        None => draft.clone(),
+                    };
+                    cursor = line.len();
+                }
+                Key::Tab => {
+                    let text: String = line.iter().collect();
+                    let candidates: Vec<&str> = self
+                        .completions
+                        .iter()
+                        .copied()
+                        .filter(|completion| completion.starts_with(&text))
+                        .collect();
+                    match candidates[..] {
+                        [] => {}
+                        [completion] => {
+                            line = format!("{completion} ").chars().collect();
+                            cursor = line.len();
+                        }
+                        _ => write!(terminal, "\r\n{}\r\n", candidates.join("  "))?,
+                    }
+                }
+                Key::KillToEnd => line.truncate(cursor),
+                Key::KillToStart => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Interrupt => return Ok(Some(String::new())),
+                Key::Eof if line.is_empty() => return Ok(None),
+                Key::Backspace | Key::Delete | Key::Up | Key::Down | Key::Eof | Key::Ignored => {}
+            }
+        }
+    }
+
+    /// Adds `line` to the history and appends it to the history file.
+    fn add_history(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return Ok(());
+        }
+
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+
+        match &self.file {
+            Some(file) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(file)?;
+                writeln!(file, "{line}")
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads a key, decoding escape sequences and UTF-8 characters.
+fn read_key(keys: &mut impl Read) -> io::Result<Key> {
+    let byte = match read_byte(keys)? {
+        Some(byte) => byte,
+        None => return Ok(Key::Eof),
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x05 => Key::End,
+        0x0b => Key::KillToEnd,
+        0x15 => Key::KillToStart,
+        0x03 => Key::Interrupt,
+        0x04 => Key::Eof,
+        0x1b => match (read_byte(keys)?, read_byte(keys)?) {
+            (Some(b'['), Some(b'A')) => Key::Up,
+            (Some(b'['), Some(b'B')) => Key::Down,
+            (Some(b'['), Some(b'C')) => Key::Right,
+            (Some(b'['), Some(b'D')) => Key::Left,
+            (Some(b'['), Some(b'H')) | (Some(b'O'), Some(b'H')) => Key::Home,
+            (Some(b'['), Some(b'F')) | (Some(b'O'), Some(b'F')) => Key::End,
+            (Some(b'['), Some(b'3')) => match read_byte(keys)? {
+                Some(b'~') => Key::Delete,
+                _ => Key::Ignored,
+            },
+            _ => Key::Ignored,
+        },
+        byte if byte < 0x20 => Key::Ignored,
+        byte => {
+            let len = match byte.leading_ones() {
+                0 => 1,
+                n => n as usize,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(keys)?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Ignored,
+            }
+        }
+    };
+    Ok(key)
+}
+
+fn read_byte(keys: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = 0;
+    match keys.read(std::slice::from_mut(&mut byte))? {
+        0 => Ok(None),
+        _ => Ok(Some(byte)),
+    }
+}
+
+mod raw_mode {
+    use std::io::{self, Error};
+    use std::mem::MaybeUninit;
+
+    use libc::{
+        termios, ECHO, ICANON, ICRNL, IEXTEN, ISIG, IXON, STDIN_FILENO, TCSANOW, VMIN, VTIME,
+    };
+
+    /// Puts the terminal on stdin into raw mode, where keys are read one by one without being
+    /// echoed, until it is dropped.
+    pub struct RawMode {
+        original: termios,
+    }
+
+    impl RawMode {
+        pub fn enable() -> io::Result<Self> {
+            let mut original = MaybeUninit::<termios>::uninit();
+            // SAFETY: `tcgetattr` initializes `original` if it succeeds.
+            let original = unsafe {
+                if libc::tcgetattr(STDIN_FILENO, original.as_mut_ptr()) == -1 {
+                    return Err(Error::last_os_error());
+                }
+                original.assume_init()
+            };
+
+            let mut raw = original;
+            raw.c_lflag &= !(ICANON | ECHO | ISIG | IEXTEN);
+            raw.c_iflag &= !(ICRNL | IXON);
+            raw.c_cc[VMIN] = 1;
+            raw.c_cc[VTIME] = 0;
+            // SAFETY: `raw` is a valid configuration obtained from `tcgetattr`.
+            if unsafe { libc::tcsetattr(STDIN_FILENO, TCSANOW, &raw) } == -1 {
+                return Err(Error::last_os_error());
+            }
+
+            Ok(Self { original })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: `original` is the configuration of the terminal before it was changed.
+            unsafe { libc::tcsetattr(STDIN_FILENO, TCSANOW, &self.original) };
+        }
+    }
+}
+
+/// Returns whether stdin and stdout are terminals, so lines can be edited.
+pub(crate) fn is_terminal() -> bool {
+    use std::io::IsTerminal;
+
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::LineEditor;
+
+    fn edit(editor: &LineEditor, keys: &str) -> Option<String> {
+        editor
+            .edit("> ", &mut Cursor::new(keys), &mut Vec::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_editing_keys() {
+        let editor = LineEditor::new(&[], None);
+
+        assert_eq!(edit(&editor, "ab\x1b[Dc\r"), Some("acb".to_string()));
+        assert_eq!(
+            edit(&editor, "abc\x01\x1b[3~\x05\x7fä\r"),
+            Some("bä".to_string())
+        );
+        assert_eq!(
+            edit(&editor, "abcd\x1b[D\x1b[D\x0b\r"),
+            Some("ab".to_string())
+        );
+        assert_eq!(edit(&editor, "abcd\x1b[D\x15\r"), Some("d".to_string()));
+        assert_eq!(edit(&editor, "abc\x03"), Some(String::new()));
+        assert_eq!(edit(&editor, "a\x04\x7f\x04"), None);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = LineEditor::new(&[], None);
+        for line in ["+", "-", "-", " "] {
+            editor.add_history(line).unwrap();
+        }
+
+        assert_eq!(editor.history, ["+", "-"]);
+        assert_eq!(
+            edit(&editor, "\x1b[A\x1b[A\x1b[A.\r"),
+            Some("+.".to_string())
+        );
+        assert_eq!(
+            edit(&editor, ">\x1b[A\x1b[B\x1b[B\r"),
+            Some(">".to_string())
+        );
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        let editor = LineEditor::new(&[":tape", ":reset", ":run"], None);
+
+        assert_eq!(edit(&editor, ":t\t1\r"), Some(":tape 1".to_string()));
+        assert_eq!(edit(&editor, ":r\t\r"), Some(":r".to_string()));
+        assert_eq!(edit(&editor, "+\t\r"), Some("+".to_string()));
+    }
+}
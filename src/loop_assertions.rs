@@ -0,0 +1,250 @@
+//! Assertions on the number of iterations of a loop, declared in the program with directives
+//! like `;; assert loop<=1000` before the `[` of the loop.
+//!
+//! The [virtual machine](crate::virtual_machine::VirtualMachine) checks them with
+//! [with_loop_limits](crate::virtual_machine::VirtualMachine::with_loop_limits).
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::compiler::Instruction;
+use crate::source_map::Span;
+use crate::tokenizer::{tokenize, TokenKind, DIRECTIVE_PREFIX};
+
+/// An assertion that a loop repeats its body at most `max` times every time it is entered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoopAssertion {
+    /// The maximum number of iterations.
+    pub max: u64,
+
+    /// Line of the directive, starting at 1.
+    pub line: usize,
+
+    /// Column of the directive, starting at 1.
+    pub column: usize,
+
+    /// Line of the `[` the directive is attached to.
+    pub loop_line: usize,
+
+    /// Column of the `[` the directive is attached to.
+    pub loop_column: usize,
+}
+
+/// An `assert` directive that could not be parsed, or that is not followed by a loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopAssertionError {
+    /// Line of the directive, starting at 1.
+    pub line: usize,
+
+    /// Column of the directive, starting at 1.
+    pub column: usize,
+
+    pub message: String,
+}
+
+impl Display for LoopAssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid loop assertion at line {} column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for LoopAssertionError {}
+
+impl Display for LoopAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loop<={}", self.max)
+    }
+}
+
+/// The loop assertions of a program, ordered by their position.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LoopAssertions {
+    assertions: Vec<LoopAssertion>,
+}
+
+impl LoopAssertions {
+    /// Collects the `;; assert loop<=<max>` directives of `source`, each attached to the first
+    /// instruction after it, which has to be a `[`.
+    ///
+    /// Directives that start with another word are left for other tools, and a loop may only
+    /// have one assertion.
+    pub fn parse(source: &str) -> Result<Self, LoopAssertionError> {
+        let mut assertions = Vec::new();
+        let mut pending: Option<(usize, usize, u64)> = None;
+
+        for token in tokenize(source) {
+            match token.kind {
+                TokenKind::Comment => continue,
+                TokenKind::Instruction => {
+                    if let Some((line, column, max)) = pending.take() {
+                        if token.text != "[" {
+                            return Err(LoopAssertionError {
+                                line,
+                                column,
+                                message: format!("expected a loop, found `{}`", token.text),
+                            });
+                        }
+                        assertions.push(LoopAssertion {
+                            max,
+                            line,
+                            column,
+                            loop_line: token.line,
+                            loop_column: token.column,
+                        });
+                    }
+                    continue;
+                }
+                TokenKind::Directive => {}
+            }
+
+            let mut words = token.text[DIRECTIVE_PREFIX.len()..].split_whitespace();
+            if words.next() != Some("assert") {
+                continue;
+            }
+
+            let error = |message: String| LoopAssertionError {
+                line: token.line,
+                column: token.column,
+                message,
+            };
+
+            // Allow spaces around the operator, like `loop <= 1000`.
+            let condition: String = words.collect();
+            let max = condition
+                .strip_prefix("loop<=")
+                .ok_or_else(|| error("expected `assert loop<=<max>`".to_string()))?;
+            let max: u64 = max
+                .parse()
+                .map_err(|_| error(format!("`{max}` is not a number of iterations")))?;
+
+            if pending.is_some() {
+                return Err(error("the loop already has an assertion".to_string()));
+            }
+            pending = Some((token.line, token.column, max));
+        }
+
+        match pending {
+            Some((line, column, _)) => Err(LoopAssertionError {
+                line,
+                column,
+                message: "expected a loop, found the end of the program".to_string(),
+            }),
+            None => Ok(Self { assertions }),
+        }
+    }
+
+    /// Returns the assertions ordered by their position.
+    pub fn iter(&self) -> impl Iterator<Item = &LoopAssertion> {
+        self.assertions.iter()
+    }
+
+    /// Returns whether the program has no assertions.
+    pub fn is_empty(&self) -> bool {
+        self.assertions.is_empty()
+    }
+
+    /// Returns the maximum number of iterations of every instruction in `instructions`, which
+    /// is only set for the [JumpZero](Instruction::JumpZero) of an asserted loop, and the
+    /// assertions whose loop has been lowered to instructions without a loop.
+    ///
+    /// `spans` are the spans of the instructions as returned by
+    /// [instruction_spans](crate::source_map::instruction_spans).
+    pub fn limits(
+        &self,
+        instructions: &[Instruction],
+        spans: &[Span],
+    ) -> (Vec<Option<u64>>, Vec<LoopAssertion>) {
+        let mut limits = vec![None; instructions.len()];
+        let mut lowered = Vec::new();
+
+        for assertion in &self.assertions {
+            let ip = instructions
+                .iter()
+                .zip(spans)
+                .position(|(instruction, span)| {
+                    matches!(instruction, Instruction::JumpZero(_))
+                        && (span.line, span.column) == (assertion.loop_line, assertion.loop_column)
+                });
+            match ip {
+                Some(ip) => limits[ip] = Some(assertion.max),
+                None => lowered.push(*assertion),
+            }
+        }
+
+        (limits, lowered)
+    }
+
+    /// Returns the assertion of the loop that starts at `span`.
+    pub fn get(&self, span: Span) -> Option<&LoopAssertion> {
+        self.assertions.iter().find(|assertion| {
+            (assertion.loop_line, assertion.loop_column) == (span.line, span.column)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoopAssertions;
+    use crate::compiler::Compiler;
+    use crate::source_map;
+
+    #[test]
+    fn test_parse_assertions() {
+        let source = "+\n;; assert loop<=3\n;; name cell 0 x\n  [->+<]\n;; assert loop <= 0\n[-]";
+        let assertions = LoopAssertions::parse(source).unwrap();
+        let assertions: Vec<_> = assertions
+            .iter()
+            .map(|a| (a.max, a.line, a.column, a.loop_line, a.loop_column))
+            .collect();
+
+        assert_eq!(assertions, vec![(3, 2, 1, 4, 3), (0, 5, 1, 6, 1)]);
+    }
+
+    #[test]
+    fn test_invalid_assertions() {
+        let error = |source| LoopAssertions::parse(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("+\n ;; assert loop<=x\n["),
+            "invalid loop assertion at line 2 column 2: `x` is not a number of iterations"
+        );
+        assert_eq!(
+            error(";; assert loop>=3\n["),
+            "invalid loop assertion at line 1 column 1: expected `assert loop<=<max>`"
+        );
+        assert_eq!(
+            error(";; assert loop<=3\n+["),
+            "invalid loop assertion at line 1 column 1: expected a loop, found `+`"
+        );
+        assert_eq!(
+            error(";; assert loop<=3\n;; assert loop<=4\n["),
+            "invalid loop assertion at line 2 column 1: the loop already has an assertion"
+        );
+        assert_eq!(
+            error("+\n;; assert loop<=3"),
+            "invalid loop assertion at line 2 column 1: \
+             expected a loop, found the end of the program"
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let source = ";; assert loop<=2\n[-]+++\n;; assert loop<=5\n[>.<-]";
+        let assertions = LoopAssertions::parse(source).unwrap();
+        let instructions = Compiler::new(source).compile().unwrap();
+        let spans = source_map::instruction_spans(source);
+
+        let (limits, lowered) = assertions.limits(&instructions, &spans);
+
+        // `[-]` is lowered to `SetZero`, so only the second loop has a limit.
+        let mut expected = vec![None; instructions.len()];
+        expected[2] = Some(5);
+        assert_eq!(limits, expected);
+        assert_eq!(lowered.len(), 1);
+        assert_eq!((lowered[0].loop_line, lowered[0].loop_column), (2, 1));
+    }
+}
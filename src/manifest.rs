@@ -0,0 +1,486 @@
+//! Project manifests (`bf.toml`) that describe the main program of a project, how it is
+//! executed, its tests and what is built from it, so commands work without long flag lists.
+//!
+//! ```toml
+//! [package]
+//! name = "hello"
+//! main = "src/hello.b"
+//! dialect = "brainfuck"
+//! cell-width = 8
+//! tape = "fixed"
+//! preprocess = true
+//!
+//! [build]
+//! targets = ["instructions"]
+//! out-dir = "target"
+//! source-maps = true
+//!
+//! [[test]]
+//! name = "greets"
+//! input = ""
+//! output-file = "tests/greeting.txt"
+//! ```
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use crate::tape::{CellWidth, TapePolicy};
+use crate::toml::{self, Table, Value};
+
+/// File name of the manifest in the root directory of a project.
+pub const FILE_NAME: &str = "bf.toml";
+
+/// Dialects a program can be written in.
+pub const DIALECTS: &[&str] = &["brainfuck"];
+
+/// Artifacts that can be built from a program.
+///
+/// - `instructions`: the instructions generated by the compiler, as printed by `compile`
+pub const TARGETS: &[&str] = &["instructions"];
+
+/// Returns the manifest of a new project called `name`, whose main program is `src/main.b`
+/// and whose test compares its output with `tests/hello.txt`.
+///
+/// The name has to be valid in a TOML string without escapes.
+pub fn template(name: &str) -> String {
+    format!(
+        r#"[package]
+name = "{name}"
+main = "src/main.b"
+
+[build]
+targets = ["instructions"]
+
+[[test]]
+name = "hello"
+output-file = "tests/hello.txt"
+"#
+    )
+}
+
+/// A parsed project manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Name of the project, used for the names of built artifacts.
+    pub name: String,
+
+    /// Path of the main program.
+    pub main: PathBuf,
+
+    pub dialect: String,
+
+    /// Width of a cell.
+    pub cell_width: CellWidth,
+
+    /// Behavior of the tape when the data pointer moves.
+    pub tape: TapePolicy,
+
+    /// Whether `;;include` directives are expanded.
+    pub preprocess: bool,
+
+    /// Artifacts built by the `build` command, out of [TARGETS].
+    pub targets: Vec<String>,
+
+    /// Directory the artifacts are written to.
+    pub out_dir: PathBuf,
+
+    /// Whether a [source map](crate::source_map::SourceMap) is written next to every
+    /// artifact.
+    pub source_maps: bool,
+
+    pub tests: Vec<TestCase>,
+}
+
+/// A test that runs the main program with an input and compares its output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub input: Data,
+    pub output: Data,
+}
+
+/// Bytes given either inline in the manifest or as the path of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    Inline(String),
+    File(PathBuf),
+}
+
+/// Describes why a manifest is not valid.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestError {
+    /// The manifest is not valid TOML.
+    Syntax { line: usize, message: String },
+
+    /// The manifest is valid TOML but does not describe a project.
+    Invalid(String),
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::Syntax { line, message } => {
+                write!(f, "invalid syntax at line {line}: {message}")
+            }
+            ManifestError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+impl Manifest {
+    /// Returns the path of the manifest in `dir` or the closest of its ancestors.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    /// Parses a manifest, where relative paths are resolved against `dir`, the directory of
+    /// the manifest.
+    pub fn parse(source: &str, dir: &Path) -> Result<Self, ManifestError> {
+        let mut root = toml::parse(source).map_err(|err| ManifestError::Syntax {
+            line: err.line,
+            message: err.message,
+        })?;
+
+        let mut package = Fields::new("package", take_table(&mut root, "package")?);
+        let name = package.string("name")?.unwrap_or_else(|| {
+            dir.file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("main")
+                .to_string()
+        });
+        let main = dir.join(
+            package
+                .string("main")?
+                .ok_or_else(|| invalid("`package.main` is missing"))?,
+        );
+        let dialect = package
+            .string("dialect")?
+            .unwrap_or_else(|| DIALECTS[0].to_string());
+        let cell_width = match package.integer("cell-width")? {
+            Some(width) => u32::try_from(width)
+                .ok()
+                .and_then(CellWidth::from_bits)
+                .ok_or_else(|| {
+                    let widths = CellWidth::ALL.map(|width| width.bits());
+                    unsupported("cell width", width, &widths)
+                })?,
+            None => CellWidth::U8,
+        };
+        let tape = match package.string("tape")? {
+            Some(tape) => {
+                let policies = TapePolicy::ALL.map(TapePolicy::name);
+                tape.parse()
+                    .map_err(|_| unsupported("tape", &tape, &policies))?
+            }
+            None => TapePolicy::Fixed,
+        };
+        let preprocess = package.boolean("preprocess")?.unwrap_or(false);
+        package.finish()?;
+
+        let mut build = Fields::new("build", take_table(&mut root, "build")?);
+        let targets = build.strings("targets")?.unwrap_or_default();
+        let out_dir = dir.join(
+            build
+                .string("out-dir")?
+                .unwrap_or_else(|| "target".to_string()),
+        );
+        let source_maps = build.boolean("source-maps")?.unwrap_or(false);
+        build.finish()?;
+
+        let tests = match root.remove("test") {
+            None => Vec::new(),
+            Some(Value::Array(tables)) => tables
+                .into_iter()
+                .enumerate()
+                .map(|(i, table)| match table {
+                    Value::Table(table) => TestCase::parse(i, table, dir),
+                    value => Err(invalid(format!(
+                        "`test` must be an array of tables, not {}",
+                        value.type_name()
+                    ))),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(value) => {
+                return Err(invalid(format!(
+                    "`test` must be an array of tables, not {}",
+                    value.type_name()
+                )))
+            }
+        };
+
+        if let Some(key) = root.keys().next() {
+            return Err(invalid(format!("unknown key `{key}`")));
+        }
+
+        let manifest = Self {
+            name,
+            main,
+            dialect,
+            cell_width,
+            tape,
+            preprocess,
+            targets,
+            out_dir,
+            source_maps,
+            tests,
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Checks that this build supports what the manifest asks for.
+    fn validate(&self) -> Result<(), ManifestError> {
+        if !DIALECTS.contains(&self.dialect.as_str()) {
+            return Err(unsupported("dialect", &self.dialect, DIALECTS));
+        }
+        if let Some(target) = self
+            .targets
+            .iter()
+            .find(|target| !TARGETS.contains(&target.as_str()))
+        {
+            return Err(unsupported("target", target, TARGETS));
+        }
+
+        Ok(())
+    }
+}
+
+impl TestCase {
+    fn parse(index: usize, table: Table, dir: &Path) -> Result<Self, ManifestError> {
+        let mut fields = Fields::new(&format!("test[{index}]"), table);
+        let name = fields
+            .string("name")?
+            .unwrap_or_else(|| format!("test {}", index + 1));
+        let input = fields
+            .data("input", dir)?
+            .unwrap_or_else(|| Data::Inline(String::new()));
+        let output = fields
+            .data("output", dir)?
+            .ok_or_else(|| invalid(format!("`test[{index}]` needs `output` or `output-file`")))?;
+        fields.finish()?;
+
+        Ok(Self {
+            name,
+            input,
+            output,
+        })
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ManifestError {
+    ManifestError::Invalid(message.into())
+}
+
+fn unsupported<T: Display>(what: &str, value: impl Display, supported: &[T]) -> ManifestError {
+    let supported: Vec<String> = supported.iter().map(T::to_string).collect();
+    invalid(format!(
+        "unsupported {what} `{value}`, supported are `{}`",
+        supported.join("`, `")
+    ))
+}
+
+/// Removes the table `name` from `root`, returning an empty table if it does not exist.
+fn take_table(root: &mut Table, name: &str) -> Result<Table, ManifestError> {
+    match root.remove(name) {
+        None => Ok(Table::new()),
+        Some(Value::Table(table)) => Ok(table),
+        Some(value) => Err(invalid(format!(
+            "`{name}` must be a table, not {}",
+            value.type_name()
+        ))),
+    }
+}
+
+/// Takes typed fields out of a table, so the remaining ones can be reported as unknown.
+struct Fields {
+    path: String,
+    table: Table,
+}
+
+impl Fields {
+    fn new(path: &str, table: Table) -> Self {
+        Self {
+            path: path.to_string(),
+            table,
+        }
+    }
+
+    fn wrong_type(&self, key: &str, expected: &str, value: &Value) -> ManifestError {
+        invalid(format!(
+            "`{}.{key}` must be {expected}, not {}",
+            self.path,
+            value.type_name()
+        ))
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<String>, ManifestError> {
+        match self.table.remove(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(value) => Err(self.wrong_type(key, "a string", &value)),
+        }
+    }
+
+    fn integer(&mut self, key: &str) -> Result<Option<i64>, ManifestError> {
+        match self.table.remove(key) {
+            None => Ok(None),
+            Some(Value::Integer(n)) => Ok(Some(n)),
+            Some(value) => Err(self.wrong_type(key, "an integer", &value)),
+        }
+    }
+
+    fn boolean(&mut self, key: &str) -> Result<Option<bool>, ManifestError> {
+        match self.table.remove(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(b)),
+            Some(value) => Err(self.wrong_type(key, "a boolean", &value)),
+        }
+    }
+
+    fn strings(&mut self, key: &str) -> Result<Option<Vec<String>>, ManifestError> {
+        match self.table.remove(key) {
+            None => Ok(None),
+            Some(Value::Array(values)) => values
+                .into_iter()
+                .map(|value| match value {
+                    Value::String(s) => Ok(s),
+                    value => Err(self.wrong_type(key, "an array of strings", &value)),
+                })
+                .collect::<Result<_, _>>()
+                .map(Some),
+            Some(value) => Err(self.wrong_type(key, "an array of strings", &value)),
+        }
+    }
+
+    /// Takes either `key` with inline data or `key-file` with the path of a file.
+    fn data(&mut self, key: &str, dir: &Path) -> Result<Option<Data>, ManifestError> {
+        let inline = self.string(key)?;
+        let file = self.string(&format!("{key}-file"))?;
+        match (inline, file) {
+            (Some(_), Some(_)) => Err(invalid(format!(
+                "`{0}` can only have one of `{key}` and `{key}-file`",
+                self.path
+            ))),
+            (Some(data), None) => Ok(Some(Data::Inline(data))),
+            (None, Some(file)) => Ok(Some(Data::File(dir.join(file)))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Returns an error if the table contains keys that were not taken.
+    fn finish(self) -> Result<(), ManifestError> {
+        match self.table.keys().next() {
+            Some(key) => Err(invalid(format!("unknown key `{}.{key}`", self.path))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{template, Data, Manifest, TestCase};
+    use crate::tape::TapePolicy;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = Manifest::parse(
+            r#"
+[package]
+name = "hello"
+main = "src/hello.b"
+cell-width = 8
+
+[build]
+targets = ["instructions"]
+
+[[test]]
+output = "Hello World!\n"
+
+[[test]]
+name = "echo"
+input-file = "tests/echo.in"
+output = "abc"
+"#,
+            Path::new("/project"),
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "hello");
+        assert_eq!(manifest.main, Path::new("/project/src/hello.b"));
+        assert_eq!(manifest.dialect, "brainfuck");
+        assert_eq!(manifest.tape, TapePolicy::Fixed);
+        assert!(!manifest.preprocess);
+        assert_eq!(manifest.targets, ["instructions"]);
+        assert_eq!(manifest.out_dir, Path::new("/project/target"));
+        assert_eq!(
+            manifest.tests,
+            [
+                TestCase {
+                    name: "test 1".to_string(),
+                    input: Data::Inline(String::new()),
+                    output: Data::Inline("Hello World!\n".to_string()),
+                },
+                TestCase {
+                    name: "echo".to_string(),
+                    input: Data::File(Path::new("/project/tests/echo.in").to_path_buf()),
+                    output: Data::Inline("abc".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let manifest = Manifest::parse(&template("greeter"), Path::new("/project")).unwrap();
+
+        assert_eq!(manifest.name, "greeter");
+        assert_eq!(manifest.main, Path::new("/project/src/main.b"));
+        assert_eq!(
+            manifest.tests,
+            [TestCase {
+                name: "hello".to_string(),
+                input: Data::Inline(String::new()),
+                output: Data::File(Path::new("/project/tests/hello.txt").to_path_buf()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        let error = |source| {
+            Manifest::parse(source, Path::new("/project"))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(error("[package]"), "`package.main` is missing");
+        assert_eq!(
+            error("[package]\nmain = 'a.b'\ncell-width = 64"),
+            "unsupported cell width `64`, supported are `8`, `16`, `32`"
+        );
+        assert_eq!(
+            error("[package]\nmain = 'a.b'\ntape = 'infinite'"),
+            "unsupported tape `infinite`, supported are `fixed`, `growable`"
+        );
+        assert_eq!(
+            error("[package]\nmain = 'a.b'\nmian = 'b.b'"),
+            "unknown key `package.mian`"
+        );
+        assert_eq!(
+            error("[package]\nmain = 1"),
+            "`package.main` must be a string, not an integer"
+        );
+        assert_eq!(
+            error("[package]\nmain = 'a.b'\n[[test]]\ninput = ''"),
+            "`test[0]` needs `output` or `output-file`"
+        );
+        assert_eq!(error("[package"), "invalid syntax at line 1: expected `]`");
+    }
+}
@@ -1,76 +1,398 @@
-use std::io::{self, Error};
-use std::marker::PhantomData;
-use std::{mem, ptr, slice};
+//! A wrapper around `mmap(2)` on unix platforms and `VirtualAlloc` on Windows to execute
+//! generated machine code, and to hold the tape it runs on between guard pages.
+//!
+//! On macOS, code is written to a `MAP_JIT` region that is toggled between writable and
+//! executable with `pthread_jit_write_protect_np`, as a region can not be both at once.
+
+// Only the JIT compiler uses the wrapper, which is not compiled on every platform.
+#![cfg_attr(not(jit), allow(dead_code))]
 
-use libc::{c_void, MAP_ANONYMOUS, MAP_FAILED, MAP_PRIVATE, PROT_EXEC, PROT_READ, PROT_WRITE};
+#[cfg(not(any(unix, windows)))]
+compile_error!(
+    "`mmap.rs` wraps `mmap(2)` or `VirtualAlloc` and can only be compiled on unix or Windows"
+);
+
+use std::ffi::c_void;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::{mem, slice};
 
 pub struct ReadWritable;
 pub struct Executable;
 
-/// A wrapper around the `mmap(2)` syscall.
+/// The signature of the generated code, which follows the System V calling convention on
+/// x86_64 on every platform.
+#[cfg(target_arch = "x86_64")]
+type Function = extern "sysv64" fn(*mut c_void, *mut u8, usize) -> u32;
+#[cfg(not(target_arch = "x86_64"))]
+type Function = extern "C" fn(*mut c_void, *mut u8, usize) -> u32;
+
+/// Size of the inaccessible regions before and after a [guarded](MemoryMap::guarded) region,
+/// which is a multiple of the page size on every supported platform.
+pub const GUARD_SIZE: usize = 64 * 1024;
+
+/// When the memory of a [guarded](MemoryMap::guarded) region is set aside.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Commit {
+    /// Memory is reserved for the whole region when it is mapped, so touching it later can not
+    /// run out of memory.
+    Eager,
+
+    /// No memory is reserved for the region, so physical pages are only allocated once they
+    /// are touched, e.g. for a large tape of which a program may only use the beginning.
+    /// Windows commits the whole region, so it counts against the commit limit, but still
+    /// only allocates the touched pages.
+    Lazy,
+}
+
+/// A wrapper around the `mmap(2)` syscall, or `VirtualAlloc` on Windows.
 pub struct MemoryMap<Mode = ReadWritable> {
     addr: *mut c_void,
     len: usize,
+    /// The bytes of the mapped region that are accessible, which excludes guard pages.
+    usable: Range<usize>,
     mode: PhantomData<Mode>,
 }
 
 impl MemoryMap<ReadWritable> {
-    /// Create a new readable and writable memory mapped region.
+    /// Create a new readable and writable memory mapped region for code, which is made
+    /// executable by [set_executable](MemoryMap::set_executable).
     pub fn new(len: usize) -> io::Result<Self> {
-        // SAFETY: This call is according to the man pages.
-        let addr = unsafe {
-            libc::mmap(
-                ptr::null_mut(),
-                len,
-                PROT_READ | PROT_WRITE,
-                MAP_PRIVATE | MAP_ANONYMOUS,
-                -1,
-                0,
-            )
-        };
+        Ok(Self::from_addr(sys::map_code(len)?, len))
+    }
 
-        if addr == MAP_FAILED {
-            return Err(Error::last_os_error());
+    /// Create a new readable and writable, zero initialized memory mapped region of `len`
+    /// bytes for data between [GUARD_SIZE] bytes that can not be accessed, so accessing memory
+    /// just before or after the region faults instead of corrupting other memory. Unlike the
+    /// regions for code, it is never executable, including on macOS.
+    ///
+    /// The region ends right at the guard after it, while up to a page of accessible memory
+    /// may lie between the guard before it and its start.
+    ///
+    /// `commit` decides whether memory is reserved for the region up front.
+    pub fn guarded(len: usize, commit: Commit) -> io::Result<Self> {
+        let pages = len.div_ceil(GUARD_SIZE) * GUARD_SIZE;
+        let len_with_guards = GUARD_SIZE + pages + GUARD_SIZE;
+        let mut map = Self::from_addr(sys::map_data(len_with_guards, commit)?, len_with_guards);
+        // SAFETY: Both guards are inside of the region returned by `map_data`.
+        unsafe {
+            sys::protect_none(map.addr, GUARD_SIZE)?;
+            sys::protect_none(map.addr.add(GUARD_SIZE + pages), GUARD_SIZE)?;
         }
+        map.usable = GUARD_SIZE + pages - len..GUARD_SIZE + pages;
+        Ok(map)
+    }
 
-        Ok(Self {
+    fn from_addr(addr: *mut c_void, len: usize) -> Self {
+        Self {
             addr,
             len,
+            usable: 0..len,
             mode: PhantomData,
-        })
+        }
     }
 
-    /// Returns a mutable reference to the memory mapped region.
+    /// Returns a mutable reference to the accessible part of the memory mapped region.
     pub fn get_mut(&mut self) -> &mut [u8] {
-        // SAFETY: `mmap` returns a mutable pointer to the mapped area that is `len` bytes long.
-        unsafe { slice::from_raw_parts_mut(self.addr as *mut u8, self.len) }
+        // SAFETY: The usable part of the mapped area is readable and writable.
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.addr.cast::<u8>().add(self.usable.start),
+                self.usable.len(),
+            )
+        }
     }
 
     /// Changes the permissions of the memory mapped region from readable and writable
     /// to only executable.
     pub fn set_executable(self) -> io::Result<MemoryMap<Executable>> {
-        // SAFETY: `mprotect` can only be called once and only after a successful call to `mmap`.
-        if unsafe { libc::mprotect(self.addr, self.len, PROT_EXEC) } == -1 {
-            Err(Error::last_os_error())
-        } else {
-            Ok(MemoryMap {
-                addr: self.addr,
-                len: self.len,
-                mode: PhantomData,
-            })
-        }
+        // SAFETY: The permissions can only be changed once and only after a successful call
+        // to `map_code`.
+        unsafe { sys::set_executable(self.addr, self.len) }?;
+        let executable = MemoryMap {
+            addr: self.addr,
+            len: self.len,
+            usable: self.usable.clone(),
+            mode: PhantomData,
+        };
+        // The region is now owned by `executable`.
+        mem::forget(self);
+        Ok(executable)
     }
 }
 
 impl MemoryMap<Executable> {
-    /// Casts the first byte of the memory mapped region into a function pointer and calls it.
+    /// Casts the first byte of the memory mapped region into a function pointer and calls it
+    /// with `state`, `data` and the length of `data` in bytes, returning the value the function
+    /// returns.
     ///
     /// # Safety
     ///
     /// The method is unsafe because the caller can write arbitrary values to the memory mapped
     /// region by calling [get_mut](crate::mmap::MemoryMap::get_mut).
-    pub unsafe fn execute(self) {
-        let function = mem::transmute::<_, fn() -> ()>(self.addr);
-        function();
+    pub unsafe fn execute(&self, state: *mut c_void, data: *mut u8, len: usize) -> u32 {
+        // SAFETY: The region holds code generated for the signature of `Function`. The types
+        // are spelled out because the pointer alone does not say what it is transmuted to.
+        let function = mem::transmute::<*mut c_void, Function>(self.addr);
+        function(state, data, len)
+    }
+}
+
+/// Makes the instructions written to `len` bytes at `addr` visible to the instruction fetch, as
+/// AArch64 does not keep the data and instruction caches coherent.
+///
+/// # Safety
+///
+/// The bytes have to be mapped and readable.
+#[cfg(all(target_arch = "aarch64", not(target_os = "macos")))]
+unsafe fn clear_instruction_cache(addr: usize, len: usize) {
+    use std::arch::asm;
+
+    let ctr: u64;
+    asm!("mrs {}, ctr_el0", out(reg) ctr, options(nomem, nostack));
+    let data_line = 4usize << ((ctr >> 16) & 0xf);
+    let instruction_line = 4usize << (ctr & 0xf);
+
+    for line in (addr & !(data_line - 1)..addr + len).step_by(data_line) {
+        asm!("dc cvau, {}", in(reg) line, options(nostack));
+    }
+    asm!("dsb ish", options(nostack));
+    for line in (addr & !(instruction_line - 1)..addr + len).step_by(instruction_line) {
+        asm!("ic ivau, {}", in(reg) line, options(nostack));
+    }
+    asm!("dsb ish", "isb", options(nostack));
+}
+
+impl<Mode> Drop for MemoryMap<Mode> {
+    fn drop(&mut self) {
+        // SAFETY: `addr` and `len` describe a region returned by `map_code` or `map_data`
+        // that is only unmapped here.
+        unsafe { sys::unmap(self.addr, self.len) };
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_void;
+    use std::io::{self, Error};
+    use std::ptr;
+
+    #[cfg(target_os = "macos")]
+    use libc::MAP_JIT;
+
+    use super::Commit;
+    use libc::{
+        MAP_ANONYMOUS, MAP_FAILED, MAP_NORESERVE, MAP_PRIVATE, PROT_EXEC, PROT_NONE, PROT_READ,
+        PROT_WRITE,
+    };
+
+    #[cfg(target_os = "macos")]
+    extern "C" {
+        fn sys_icache_invalidate(start: *mut c_void, len: usize);
+    }
+
+    /// Maps a region for code, which [set_executable] makes executable.
+    pub fn map_code(len: usize) -> io::Result<*mut c_void> {
+        #[cfg(target_os = "macos")]
+        let addr = mmap(
+            len,
+            PROT_READ | PROT_WRITE | PROT_EXEC,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_JIT,
+        )?;
+        #[cfg(not(target_os = "macos"))]
+        let addr = mmap(len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS)?;
+
+        // SAFETY: Makes `MAP_JIT` regions writable for the current thread until the code is
+        // made executable.
+        #[cfg(target_os = "macos")]
+        unsafe {
+            libc::pthread_jit_write_protect_np(0);
+        }
+        Ok(addr)
+    }
+
+    /// Maps a readable and writable region for data, reserving memory for it unless `commit`
+    /// is [lazy](Commit::Lazy).
+    pub fn map_data(len: usize, commit: Commit) -> io::Result<*mut c_void> {
+        let flags = match commit {
+            Commit::Eager => MAP_PRIVATE | MAP_ANONYMOUS,
+            Commit::Lazy => MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+        };
+        mmap(len, PROT_READ | PROT_WRITE, flags)
+    }
+
+    fn mmap(len: usize, protection: i32, flags: i32) -> io::Result<*mut c_void> {
+        // SAFETY: This call is according to the man pages.
+        let addr = unsafe { libc::mmap(ptr::null_mut(), len, protection, flags, -1, 0) };
+
+        if addr == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(addr)
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe a region returned by [map_code].
+    #[cfg(target_os = "macos")]
+    pub unsafe fn set_executable(addr: *mut c_void, len: usize) -> io::Result<()> {
+        libc::pthread_jit_write_protect_np(1);
+        sys_icache_invalidate(addr, len);
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe a region returned by [map_code].
+    #[cfg(not(target_os = "macos"))]
+    pub unsafe fn set_executable(addr: *mut c_void, len: usize) -> io::Result<()> {
+        #[cfg(target_arch = "aarch64")]
+        super::clear_instruction_cache(addr as usize, len);
+
+        match libc::mprotect(addr, len, PROT_EXEC) {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe pages of a region returned by [map_data].
+    pub unsafe fn protect_none(addr: *mut c_void, len: usize) -> io::Result<()> {
+        match libc::mprotect(addr, len, PROT_NONE) {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe a region returned by [map_code] or [map_data], which
+    /// is not used afterwards.
+    pub unsafe fn unmap(addr: *mut c_void, len: usize) {
+        libc::munmap(addr, len);
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::io::{self, Error};
+    use std::ptr;
+
+    use super::Commit;
+
+    const MEM_COMMIT: u32 = 0x1000;
+    const MEM_RESERVE: u32 = 0x2000;
+    const MEM_RELEASE: u32 = 0x8000;
+    const PAGE_NOACCESS: u32 = 0x01;
+    const PAGE_READWRITE: u32 = 0x04;
+    const PAGE_EXECUTE_READ: u32 = 0x20;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn VirtualAlloc(
+            address: *mut c_void,
+            size: usize,
+            allocation_type: u32,
+            protect: u32,
+        ) -> *mut c_void;
+        fn VirtualProtect(
+            address: *mut c_void,
+            size: usize,
+            new_protect: u32,
+            old_protect: *mut u32,
+        ) -> i32;
+        fn VirtualFree(address: *mut c_void, size: usize, free_type: u32) -> i32;
+        fn FlushInstructionCache(process: *mut c_void, address: *const c_void, size: usize) -> i32;
+        fn GetCurrentProcess() -> *mut c_void;
+    }
+
+    /// Maps a region for code, which [set_executable] makes executable.
+    pub fn map_code(len: usize) -> io::Result<*mut c_void> {
+        map(len)
+    }
+
+    /// Committed pages are zero initialized and only allocated once they are touched, so
+    /// `commit` makes no difference.
+    pub fn map_data(len: usize, _commit: Commit) -> io::Result<*mut c_void> {
+        map(len)
+    }
+
+    fn map(len: usize) -> io::Result<*mut c_void> {
+        // SAFETY: This call is according to the documentation.
+        let addr = unsafe {
+            VirtualAlloc(
+                ptr::null_mut(),
+                len,
+                MEM_COMMIT | MEM_RESERVE,
+                PAGE_READWRITE,
+            )
+        };
+
+        if addr.is_null() {
+            return Err(Error::last_os_error());
+        }
+        Ok(addr)
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe a region returned by [map_code].
+    pub unsafe fn set_executable(addr: *mut c_void, len: usize) -> io::Result<()> {
+        let mut old_protect = 0;
+        if VirtualProtect(addr, len, PAGE_EXECUTE_READ, &mut old_protect) == 0
+            || FlushInstructionCache(GetCurrentProcess(), addr, len) == 0
+        {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `addr` and `len` have to describe pages of a region returned by [map_data].
+    pub unsafe fn protect_none(addr: *mut c_void, len: usize) -> io::Result<()> {
+        let mut old_protect = 0;
+        if VirtualProtect(addr, len, PAGE_NOACCESS, &mut old_protect) == 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `addr` has to be a region returned by [map_code] or [map_data], which is not used
+    /// afterwards.
+    pub unsafe fn unmap(addr: *mut c_void, _len: usize) {
+        VirtualFree(addr, 0, MEM_RELEASE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Commit, MemoryMap, GUARD_SIZE};
+
+    #[test]
+    fn test_guarded() {
+        for (len, commit) in [
+            (0, Commit::Eager),
+            (30_000, Commit::Eager),
+            (GUARD_SIZE, Commit::Lazy),
+        ] {
+            let mut map = MemoryMap::guarded(len, commit).unwrap();
+            let addr = map.addr as usize;
+            let data = map.get_mut();
+
+            assert_eq!(data.len(), len);
+            assert!(data.iter().all(|&byte| byte == 0));
+            data.fill(1);
+
+            // The region ends right at the guard after it.
+            let end = data.as_ptr() as usize + len - addr;
+            assert_eq!(end % GUARD_SIZE, 0);
+            assert_eq!(end, GUARD_SIZE + len.div_ceil(GUARD_SIZE) * GUARD_SIZE);
+        }
     }
 }
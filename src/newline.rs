@@ -0,0 +1,229 @@
+//! Translation of line endings on the input and output of a program, for terminals and
+//! programs that disagree on what a newline is, e.g. classic programs that expect `,` to read
+//! 13 when Enter is pressed.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// What a newline is translated to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Newline {
+    /// Bytes are passed through unchanged.
+    Raw,
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+    /// `\r`
+    Cr,
+}
+
+impl Newline {
+    fn bytes(self) -> Option<&'static [u8]> {
+        match self {
+            Newline::Raw => None,
+            Newline::Lf => Some(b"\n"),
+            Newline::CrLf => Some(b"\r\n"),
+            Newline::Cr => Some(b"\r"),
+        }
+    }
+}
+
+impl FromStr for Newline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Newline::Raw),
+            "lf" => Ok(Newline::Lf),
+            "crlf" => Ok(Newline::CrLf),
+            "cr" => Ok(Newline::Cr),
+            _ => Err(format!(
+                "unknown newline `{s}`, valid values are `raw`, `lf`, `crlf` and `cr`"
+            )),
+        }
+    }
+}
+
+/// How newlines are translated on input and output.
+///
+/// Parsed from either a single newline used for both directions, e.g. `crlf`, or from the
+/// input and output newline separated by a comma, e.g. `cr,lf`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NewlineMode {
+    /// What newlines read by the program are translated to.
+    pub input: Newline,
+
+    /// What newlines written by the program are translated to.
+    pub output: Newline,
+}
+
+impl Default for NewlineMode {
+    fn default() -> Self {
+        Self {
+            input: Newline::Raw,
+            output: Newline::Raw,
+        }
+    }
+}
+
+impl FromStr for NewlineMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (input, output) = s.split_once(',').unwrap_or((s, s));
+        Ok(Self {
+            input: input.parse()?,
+            output: output.parse()?,
+        })
+    }
+}
+
+/// Replaces `\r\n`, `\r` and `\n` with a newline, remembering a trailing `\r` so a `\r\n`
+/// split across two reads or writes is still recognized.
+#[derive(Debug)]
+struct Translator {
+    /// The newline to translate to, `None` to pass bytes through unchanged.
+    newline: Option<&'static [u8]>,
+    after_cr: bool,
+}
+
+impl Translator {
+    fn new(newline: Newline) -> Self {
+        Self {
+            newline: newline.bytes(),
+            after_cr: false,
+        }
+    }
+
+    fn translate(&mut self, bytes: &[u8], translated: &mut impl Extend<u8>) {
+        let newline = match self.newline {
+            Some(newline) => newline,
+            None => return translated.extend(bytes.iter().copied()),
+        };
+
+        for &byte in bytes {
+            match byte {
+                b'\n' if self.after_cr => {}
+                b'\n' | b'\r' => translated.extend(newline.iter().copied()),
+                byte => translated.extend([byte]),
+            }
+            self.after_cr = byte == b'\r';
+        }
+    }
+}
+
+/// A reader that translates the newlines read from `R`.
+pub struct NewlineReader<R> {
+    reader: R,
+    translator: Translator,
+
+    /// Translated bytes that did not fit into the buffer of the last read.
+    pending: VecDeque<u8>,
+}
+
+impl<R: Read> NewlineReader<R> {
+    /// Creates a reader that translates the newlines read from `reader` to `newline`.
+    pub fn new(reader: R, newline: Newline) -> Self {
+        Self {
+            reader,
+            translator: Translator::new(newline),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: Read> Read for NewlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; 512];
+        while self.pending.is_empty() {
+            let n = self.reader.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.translator.translate(&chunk[..n], &mut self.pending);
+        }
+
+        let n = buf.len().min(self.pending.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *byte = pending;
+        }
+        Ok(n)
+    }
+}
+
+/// A writer that translates the newlines written to `W`.
+pub struct NewlineWriter<W> {
+    writer: W,
+    translator: Translator,
+}
+
+impl<W: Write> NewlineWriter<W> {
+    /// Creates a writer that translates the newlines written to `writer` to `newline`.
+    pub fn new(writer: W, newline: Newline) -> Self {
+        Self {
+            writer,
+            translator: Translator::new(newline),
+        }
+    }
+}
+
+impl<W: Write> Write for NewlineWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut translated = Vec::with_capacity(buf.len());
+        self.translator.translate(buf, &mut translated);
+        self.writer.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::{Newline, NewlineMode, NewlineReader, NewlineWriter};
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            "cr,lf".parse(),
+            Ok(NewlineMode {
+                input: Newline::Cr,
+                output: Newline::Lf,
+            })
+        );
+        assert_eq!(
+            "crlf".parse::<NewlineMode>().map(|mode| mode.output),
+            Ok(Newline::CrLf)
+        );
+        assert!("lf,unix".parse::<NewlineMode>().is_err());
+    }
+
+    #[test]
+    fn test_reader_translates_byte_by_byte() {
+        let mut reader = NewlineReader::new(&b"a\r\nb\nc\rd"[..], Newline::Cr);
+        let mut byte = [0];
+        let mut read = Vec::new();
+        while reader.read(&mut byte).unwrap() == 1 {
+            read.push(byte[0]);
+        }
+
+        assert_eq!(read, b"a\rb\rc\rd");
+    }
+
+    #[test]
+    fn test_writer_handles_split_crlf() {
+        let mut written = Vec::new();
+        let mut writer = NewlineWriter::new(&mut written, Newline::CrLf);
+        for byte in b"a\r\nb\n" {
+            writer.write_all(&[*byte]).unwrap();
+        }
+
+        assert_eq!(written, b"a\r\nb\r\n");
+    }
+}
@@ -0,0 +1,27 @@
+//! Hooks into the execution of the [virtual machine](crate::virtual_machine::VirtualMachine),
+//! so tracing, visualizations and other instrumentation do not need their own execution loop.
+
+use crate::compiler::Instruction;
+
+/// Receives the events of an execution, enabled with
+/// [VirtualMachine::with_observer](crate::virtual_machine::VirtualMachine::with_observer).
+///
+/// Every method does nothing by default, so an observer only implements the events it needs.
+pub trait ExecutionObserver {
+    /// Called before the `instruction` at `ip` is executed, with the data pointer `dp`.
+    fn on_instruction(&mut self, _ip: usize, _instruction: &Instruction, _dp: usize) {}
+
+    /// Called after a read instruction read `byte` into the cell at `dp`, or reached the end of
+    /// the input if it is `None`.
+    fn on_read(&mut self, _dp: usize, _byte: Option<u8>) {}
+
+    /// Called after a write instruction wrote `byte` `count` times.
+    fn on_write(&mut self, _byte: u8, _count: usize) {}
+
+    /// Called before the first iteration of the loop whose `JumpZero` is at `ip`.
+    fn on_loop_enter(&mut self, _ip: usize) {}
+
+    /// Called when the loop whose `JumpZero` is at `ip` ends after its last iteration, which
+    /// it does not if the program stops inside of it.
+    fn on_loop_exit(&mut self, _ip: usize) {}
+}
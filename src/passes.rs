@@ -0,0 +1,703 @@
+//! The passes of the optimizer, which the [Compiler](crate::compiler::Compiler) runs one after
+//! the other on the unoptimized program, as selected by an [OptLevel] or given one by one with
+//! [with_passes](crate::compiler::Compiler::with_passes).
+//!
+//! Every pass takes and returns a list of [Lowered] instructions, which keep the range of the
+//! unoptimized program they replace, so each pass can be tested on its own and the passes can
+//! be combined in any order:
+//!
+//! ```
+//! use brainfuck::compiler::{Compiler, Instruction};
+//! use brainfuck::passes::{self, Optimization};
+//!
+//! let unoptimized = Compiler::new("+++[-]").without_optimizations().compile().unwrap();
+//! let lowered = passes::run(&[Optimization::ClearLoops], &unoptimized);
+//! assert_eq!(lowered[3].instruction, Instruction::SetZero);
+//! ```
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::compiler::{self, Instruction, Pass, StepCost};
+
+/// How much the [Compiler](crate::compiler::Compiler) optimizes, selecting the passes it runs.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum OptLevel {
+    /// No optimizations, every character of the program is one instruction.
+    O0,
+
+    /// Repeated characters are folded into one instruction.
+    O1,
+
+    /// Loops that clear, copy or scan are also lowered to instructions without a loop.
+    #[default]
+    O2,
+
+    /// Moves and changes of the cell are also fused, and loops that can never run are removed.
+    ///
+    /// The fused instructions skip the intermediate positions of the data pointer, so a data
+    /// pointer that only leaves the tape between two moves, like `<>` on the first cell, is
+    /// not reported. The instructions that are removed are not counted as executed steps.
+    O3,
+}
+
+impl OptLevel {
+    /// All levels, from no optimizations to the most.
+    pub const ALL: [OptLevel; 4] = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3];
+
+    /// Returns the number of the level, as used on the command line.
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the passes of the level, in the order they run.
+    pub fn passes(self) -> &'static [Optimization] {
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 => &[Optimization::FoldRuns],
+            OptLevel::O2 => &[
+                Optimization::FoldRuns,
+                Optimization::ClearLoops,
+                Optimization::CopyLoops,
+                Optimization::ScanLoops,
+            ],
+            OptLevel::O3 => &[
+                Optimization::FoldRuns,
+                Optimization::OffsetFusion,
+                Optimization::ClearLoops,
+                Optimization::CopyLoops,
+                Optimization::ScanLoops,
+                Optimization::DeadLoopElimination,
+            ],
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OptLevel::ALL
+            .into_iter()
+            .find(|level| level.number().to_string() == s)
+            .ok_or_else(|| "valid values are `0`, `1`, `2` and `3`".to_string())
+    }
+}
+
+/// A pass of the optimizer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Optimization {
+    /// See [fold_runs].
+    FoldRuns,
+
+    /// See [offset_fusion].
+    OffsetFusion,
+
+    /// See [clear_loops].
+    ClearLoops,
+
+    /// See [copy_loops].
+    CopyLoops,
+
+    /// See [scan_loops].
+    ScanLoops,
+
+    /// See [dead_loop_elimination].
+    DeadLoopElimination,
+}
+
+impl Optimization {
+    /// All passes, in the order [OptLevel::O3] runs them.
+    pub const ALL: [Optimization; 6] = [
+        Optimization::FoldRuns,
+        Optimization::OffsetFusion,
+        Optimization::ClearLoops,
+        Optimization::CopyLoops,
+        Optimization::ScanLoops,
+        Optimization::DeadLoopElimination,
+    ];
+
+    /// Returns the name of the pass, like `fold-runs`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Optimization::FoldRuns => "fold-runs",
+            Optimization::OffsetFusion => "offset-fusion",
+            Optimization::ClearLoops => "clear-loops",
+            Optimization::CopyLoops => "copy-loops",
+            Optimization::ScanLoops => "scan-loops",
+            Optimization::DeadLoopElimination => "dead-loop-elimination",
+        }
+    }
+
+    /// Runs the pass on `lowered`.
+    pub fn run(self, lowered: Vec<Lowered>) -> Vec<Lowered> {
+        match self {
+            Optimization::FoldRuns => fold_runs(lowered),
+            Optimization::OffsetFusion => offset_fusion(lowered),
+            Optimization::ClearLoops => clear_loops(lowered),
+            Optimization::CopyLoops => copy_loops(lowered),
+            Optimization::ScanLoops => scan_loops(lowered),
+            Optimization::DeadLoopElimination => dead_loop_elimination(lowered),
+        }
+    }
+}
+
+impl FromStr for Optimization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Optimization::ALL
+            .into_iter()
+            .find(|pass| pass.name() == s)
+            .ok_or_else(|| format!("unknown pass `{s}`"))
+    }
+}
+
+/// An instruction of a program that is being optimized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lowered {
+    /// The instruction, with placeholders for its jumps.
+    pub instruction: Instruction,
+
+    /// The instructions of the unoptimized program it replaces, one per character of the
+    /// source. The instructions of a lowered loop share the range of the loop.
+    pub original: Range<usize>,
+
+    /// How many steps of the unoptimized program it stands for.
+    pub cost: StepCost,
+
+    /// The optimization that created it.
+    pub pass: Pass,
+}
+
+/// Returns the unoptimized instructions, one per character, as the input of the first pass.
+/// Resolved jumps are turned back into placeholders.
+pub fn unoptimized(instructions: &[Instruction]) -> Vec<Lowered> {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            let instruction = match instruction {
+                Instruction::JumpZero(_) => Instruction::JumpZeroPlaceholder,
+                Instruction::JumpNotZero(_) => Instruction::JumpNotZeroPlaceholder,
+                instruction => *instruction,
+            };
+            Lowered {
+                instruction,
+                original: i..i + 1,
+                cost: StepCost::of(&instruction),
+                pass: Pass::Unoptimized,
+            }
+        })
+        .collect()
+}
+
+/// Runs `passes` in order on the unoptimized `instructions`.
+pub fn run(passes: &[Optimization], instructions: &[Instruction]) -> Vec<Lowered> {
+    apply(passes, unoptimized(instructions))
+}
+
+/// Runs `passes` in order on `lowered`.
+pub fn apply(passes: &[Optimization], lowered: Vec<Lowered>) -> Vec<Lowered> {
+    passes
+        .iter()
+        .fold(lowered, |lowered, pass| pass.run(lowered))
+}
+
+/// Returns the instructions of `lowered` with their jumps resolved, ready to be executed.
+pub fn instructions(lowered: &[Lowered]) -> Vec<Instruction> {
+    let mut instructions: Vec<_> = lowered.iter().map(|lowered| lowered.instruction).collect();
+    compiler::resolve_jumps(&mut instructions);
+    instructions
+}
+
+/// Folds repetitions of an instruction into one, like `+++` to `IncByteAtDP(3)`. Reads are
+/// never folded, as every read consumes a byte of the input.
+pub fn fold_runs(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    merge(lowered, Pass::Fold, |first, second| {
+        let folded = match (first.instruction, second.instruction) {
+            (Instruction::IncDP(a), Instruction::IncDP(b)) => Instruction::IncDP(a + b),
+            (Instruction::DecDP(a), Instruction::DecDP(b)) => Instruction::DecDP(a + b),
+            (Instruction::IncByteAtDP(a), Instruction::IncByteAtDP(b)) => {
+                Instruction::IncByteAtDP(a + b)
+            }
+            (Instruction::DecByteAtDP(a), Instruction::DecByteAtDP(b)) => {
+                Instruction::DecByteAtDP(a + b)
+            }
+            (Instruction::WriteByte(a), Instruction::WriteByte(b)) => Instruction::WriteByte(a + b),
+            (Instruction::Exit, Instruction::Exit)
+            | (Instruction::SelectInput, Instruction::SelectInput) => {
+                return Some((Some(first.instruction), first.cost));
+            }
+            _ => return None,
+        };
+        Some((Some(folded), sum(first.cost, second.cost)))
+    })
+}
+
+/// Fuses adjacent moves of the data pointer into their net move, and adjacent changes of the
+/// cell at the data pointer into their net change, like `>><` to `IncDP(1)` or `+-` to
+/// nothing. See [OptLevel::O3] for what the fused instructions do not report.
+pub fn offset_fusion(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    merge(lowered, Pass::Fusion, |first, second| {
+        let (first_cost, second_cost) = (first.cost, second.cost);
+        let (first, second) = (first.instruction, second.instruction);
+        let (net, moves) = match (first, second) {
+            (
+                Instruction::IncDP(_) | Instruction::DecDP(_),
+                Instruction::IncDP(_) | Instruction::DecDP(_),
+            ) => (signed(first) + signed(second), true),
+            (
+                Instruction::IncByteAtDP(_) | Instruction::DecByteAtDP(_),
+                Instruction::IncByteAtDP(_) | Instruction::DecByteAtDP(_),
+            ) => (signed(first) + signed(second), false),
+            _ => return None,
+        };
+        let n = net.unsigned_abs();
+        let fused = match (net.signum(), moves) {
+            (0, _) => None,
+            (1, true) => Some(Instruction::IncDP(n)),
+            (_, true) => Some(Instruction::DecDP(n)),
+            (1, false) => Some(Instruction::IncByteAtDP(n)),
+            (_, false) => Some(Instruction::DecByteAtDP(n)),
+        };
+        Some((fused, sum(first_cost, second_cost)))
+    })
+}
+
+/// Lowers loops that only count the cell at the data pointer down or up to zero, like `[-]`
+/// or `[+>-<]`, to `SetZero`.
+pub fn clear_loops(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    lower(lowered, |instructions, lowered| {
+        let found = multiply_loop(instructions).filter(|found| found.targets.is_empty())?;
+        let cost = loop_cost(&lowered[..found.len], found.increments);
+        Some((found.len, vec![(Instruction::SetZero, cost)], Pass::Clear))
+    })
+}
+
+/// Lowers loops that add multiples of the cell at the data pointer to other cells and count
+/// it down or up to zero, like `[->+<]` or `[->++>+++<<]`, to `AddTo` and `SetZero`.
+pub fn copy_loops(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    lower(lowered, |instructions, lowered| {
+        let found = multiply_loop(instructions).filter(|found| !found.targets.is_empty())?;
+        let mut replacement: Vec<_> = found
+            .targets
+            .iter()
+            .map(|&(offset, factor)| {
+                let instruction = Instruction::AddTo { offset, factor };
+                (instruction, StepCost::of(&instruction))
+            })
+            .collect();
+        let cost = loop_cost(&lowered[..found.len], found.increments);
+        replacement.push((Instruction::SetZero, cost));
+        Some((found.len, replacement, Pass::Multiply))
+    })
+}
+
+/// Lowers loops that only move the data pointer, like `[>]` or `[<<]`, to `FindZero`.
+pub fn scan_loops(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    lower(lowered, |instructions, _| {
+        let stride = scan_loop(instructions)?;
+        let cost = StepCost::Scan {
+            stride: stride.unsigned_abs() as u64,
+        };
+        Some((3, vec![(Instruction::FindZero(stride), cost)], Pass::Scan))
+    })
+}
+
+/// Removes loops that start where the cell at the data pointer is known to be zero, so they
+/// never run: right after another loop, like the second loop of `[-][>+<-]`, or after
+/// `SetZero` or `FindZero`.
+///
+/// Loops at the start of the program are kept, as the tape may not be empty when a program is
+/// executed in pieces, like in the [REPL](crate::repl::Repl).
+pub fn dead_loop_elimination(lowered: Vec<Lowered>) -> Vec<Lowered> {
+    let mut kept: Vec<Lowered> = Vec::with_capacity(lowered.len());
+    let mut i = 0;
+
+    while i < lowered.len() {
+        let zero = matches!(
+            kept.last().map(|lowered| lowered.instruction),
+            Some(
+                Instruction::JumpNotZeroPlaceholder
+                    | Instruction::SetZero
+                    | Instruction::FindZero(_)
+            )
+        );
+        if zero && lowered[i].instruction == Instruction::JumpZeroPlaceholder {
+            let mut depth = 0;
+            while {
+                match lowered[i].instruction {
+                    Instruction::JumpZeroPlaceholder => depth += 1,
+                    Instruction::JumpNotZeroPlaceholder => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+                depth > 0
+            } {}
+            continue;
+        }
+        kept.push(lowered[i].clone());
+        i += 1;
+    }
+
+    kept
+}
+
+/// Merges each instruction with the one before it if `merge` returns the merged instruction,
+/// or `None` if they cancel each other out, and its cost.
+fn merge(
+    lowered: Vec<Lowered>,
+    pass: Pass,
+    merge: impl Fn(&Lowered, &Lowered) -> Option<(Option<Instruction>, StepCost)>,
+) -> Vec<Lowered> {
+    let mut merged: Vec<Lowered> = Vec::with_capacity(lowered.len());
+
+    for next in lowered {
+        let last = match merged.last_mut() {
+            Some(last) => last,
+            None => {
+                merged.push(next);
+                continue;
+            }
+        };
+        match merge(last, &next) {
+            Some((Some(instruction), cost)) => {
+                *last = Lowered {
+                    instruction,
+                    original: last.original.start..next.original.end,
+                    cost,
+                    pass,
+                };
+            }
+            Some((None, _)) => {
+                merged.pop();
+            }
+            None => merged.push(next),
+        }
+    }
+
+    merged
+}
+
+/// Replaces the loops at the start of which `lower` returns their length, the instructions
+/// that replace them with their costs and the pass, called with the instructions and the
+/// lowered instructions from there on.
+fn lower(
+    lowered: Vec<Lowered>,
+    lower: impl Fn(&[Instruction], &[Lowered]) -> Option<(usize, Vec<(Instruction, StepCost)>, Pass)>,
+) -> Vec<Lowered> {
+    let instructions: Vec<Instruction> =
+        lowered.iter().map(|lowered| lowered.instruction).collect();
+    let mut result = Vec::with_capacity(lowered.len());
+    let mut i = 0;
+
+    while i < lowered.len() {
+        match lower(&instructions[i..], &lowered[i..]) {
+            Some((len, replacement, pass)) => {
+                let original = lowered[i].original.start..lowered[i + len - 1].original.end;
+                result.extend(replacement.into_iter().map(|(instruction, cost)| Lowered {
+                    instruction,
+                    original: original.clone(),
+                    cost,
+                    pass,
+                }));
+                i += len;
+            }
+            None => {
+                result.push(lowered[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the cost of the lowered loop `lowered`, including its brackets.
+fn loop_cost(lowered: &[Lowered], increments: bool) -> StepCost {
+    let body = lowered[1..lowered.len() - 1]
+        .iter()
+        .map(|lowered| match lowered.cost {
+            StepCost::Fixed(steps) => steps,
+            _ => unreachable!("the body of a lowered loop only moves and changes cells"),
+        })
+        .sum();
+    StepCost::Loop { body, increments }
+}
+
+/// Returns the cost of two merged moves or changes of the cell.
+fn sum(first: StepCost, second: StepCost) -> StepCost {
+    match (first, second) {
+        (StepCost::Fixed(a), StepCost::Fixed(b)) => StepCost::Fixed(a + b),
+        _ => unreachable!("moves, changes of the cell and writes have a fixed cost"),
+    }
+}
+
+/// Returns how far a move moves the data pointer, or how much a change of the cell adds to it.
+fn signed(instruction: Instruction) -> isize {
+    match instruction {
+        Instruction::IncDP(n) | Instruction::IncByteAtDP(n) => n as isize,
+        Instruction::DecDP(n) | Instruction::DecByteAtDP(n) => -(n as isize),
+        _ => unreachable!("only moves and changes of the cell are fused"),
+    }
+}
+
+/// Returns the stride of the scan loop at the start of `instructions`, like `[>]`, `[<]` or
+/// `[>>]`, if its stride fits into a signed byte.
+fn scan_loop(instructions: &[Instruction]) -> Option<isize> {
+    let stride = match instructions {
+        [Instruction::JumpZeroPlaceholder, Instruction::IncDP(n), Instruction::JumpNotZeroPlaceholder, ..] => {
+            *n as isize
+        }
+        [Instruction::JumpZeroPlaceholder, Instruction::DecDP(n), Instruction::JumpNotZeroPlaceholder, ..] => {
+            -(*n as isize)
+        }
+        _ => return None,
+    };
+    (-128..=127).contains(&stride).then_some(stride)
+}
+
+/// A loop recognized by [multiply_loop].
+struct MultiplyLoop {
+    /// Offsets and factors of the cells the cell at the data pointer is added to.
+    targets: Vec<(isize, i32)>,
+
+    /// Number of instructions of the loop.
+    len: usize,
+
+    /// Whether the loop increments the byte at the data pointer instead of decrementing it.
+    increments: bool,
+}
+
+/// Analyzes the loop at the start of `instructions`.
+///
+/// Only loops without nested loops or I/O, that end at the data pointer they started at and
+/// change the cell at the data pointer by exactly one per iteration are recognized, like the
+/// clear loop `[-]`, the move loop `[->+<]` or the multiply loop `[->++>+++<<]`.
+///
+/// The changes are not wrapped around, so the loop has the same effect for every cell width.
+fn multiply_loop(instructions: &[Instruction]) -> Option<MultiplyLoop> {
+    if instructions.first() != Some(&Instruction::JumpZeroPlaceholder) {
+        return None;
+    }
+
+    // Values added per iteration, relative to the data pointer.
+    let mut deltas: Vec<(isize, i64)> = Vec::new();
+    let mut offset: isize = 0;
+    let mut add = |offset: isize, delta: i64| match deltas.iter_mut().find(|(o, _)| *o == offset) {
+        Some((_, d)) => *d += delta,
+        None => deltas.push((offset, delta)),
+    };
+
+    for (i, instruction) in instructions.iter().enumerate().skip(1) {
+        match *instruction {
+            Instruction::IncDP(n) => offset += n as isize,
+            Instruction::DecDP(n) => offset -= n as isize,
+            Instruction::IncByteAtDP(n) => add(offset, n as i64),
+            Instruction::DecByteAtDP(n) => add(offset, -(n as i64)),
+            Instruction::JumpNotZeroPlaceholder if offset == 0 => {
+                let step = deltas
+                    .iter()
+                    .find(|(offset, _)| *offset == 0)
+                    .map(|(_, delta)| *delta)?;
+                // The loop iterates `value` times if it decrements, and `-value` times if it
+                // increments the cell at the data pointer.
+                let sign = match step {
+                    -1 => 1,
+                    1 => -1,
+                    _ => return None,
+                };
+
+                let targets = deltas
+                    .into_iter()
+                    .filter(|(offset, delta)| *offset != 0 && *delta != 0)
+                    .map(|(offset, delta)| Some((offset, i32::try_from(delta * sign).ok()?)))
+                    .collect::<Option<_>>()?;
+                return Some(MultiplyLoop {
+                    targets,
+                    len: i + 1,
+                    increments: step == 1,
+                });
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clear_loops, copy_loops, dead_loop_elimination, fold_runs, offset_fusion, scan_loops,
+        unoptimized, Lowered, OptLevel, Optimization,
+    };
+    use crate::compiler::{Compiler, Instruction, Pass, StepCost};
+
+    fn lowered(source: &str) -> Vec<Lowered> {
+        let instructions = Compiler::new(source)
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        unoptimized(&instructions)
+    }
+
+    fn instructions(lowered: &[Lowered]) -> Vec<Instruction> {
+        lowered.iter().map(|lowered| lowered.instruction).collect()
+    }
+
+    #[test]
+    fn test_opt_level() {
+        assert!(OptLevel::O0.passes().is_empty());
+        assert_eq!(OptLevel::default(), OptLevel::O2);
+        assert_eq!("3".parse(), Ok(OptLevel::O3));
+        assert_eq!(
+            "4".parse::<OptLevel>(),
+            Err("valid values are `0`, `1`, `2` and `3`".to_string())
+        );
+
+        for pass in Optimization::ALL {
+            assert_eq!(pass.name().parse(), Ok(pass));
+        }
+        assert_eq!(OptLevel::O3.passes(), Optimization::ALL);
+    }
+
+    #[test]
+    fn test_fold_runs() {
+        let folded = fold_runs(lowered("+++>>,,"));
+
+        assert_eq!(
+            instructions(&folded),
+            [
+                Instruction::IncByteAtDP(3),
+                Instruction::IncDP(2),
+                Instruction::ReadByte,
+                Instruction::ReadByte
+            ]
+        );
+        assert_eq!(folded[0].original, 0..3);
+        assert_eq!(folded[2].original, 5..6);
+        assert_eq!(folded[3].original, 6..7);
+        assert_eq!(folded[0].cost, StepCost::Fixed(3));
+        assert_eq!(folded[2].cost, StepCost::Fixed(1));
+        assert_eq!(folded[0].pass, Pass::Fold);
+        assert_eq!(folded[2].pass, Pass::Unoptimized);
+    }
+
+    #[test]
+    fn test_offset_fusion() {
+        let fused = offset_fusion(fold_runs(lowered(">><+-.")));
+
+        assert_eq!(
+            instructions(&fused),
+            [Instruction::IncDP(1), Instruction::WriteByte(1)]
+        );
+        assert_eq!(fused[0].original, 0..3);
+        assert_eq!(fused[0].cost, StepCost::Fixed(3));
+        assert_eq!(fused[0].pass, Pass::Fusion);
+        assert_eq!(fused[1].pass, Pass::Unoptimized);
+    }
+
+    #[test]
+    fn test_clear_loops() {
+        let lowered = clear_loops(fold_runs(lowered("+[-][->+<]")));
+
+        assert_eq!(lowered[1].instruction, Instruction::SetZero);
+        assert_eq!(lowered[1].original, 1..4);
+        assert_eq!(
+            lowered[1].cost,
+            StepCost::Loop {
+                body: 1,
+                increments: false
+            }
+        );
+        assert_eq!(lowered[1].pass, Pass::Clear);
+        // Loops that copy the cell are left to `copy_loops`.
+        assert_eq!(lowered[2].instruction, Instruction::JumpZeroPlaceholder);
+    }
+
+    #[test]
+    fn test_copy_loops() {
+        let lowered = copy_loops(fold_runs(lowered("[-][->++<]")));
+
+        assert_eq!(
+            instructions(&lowered[3..]),
+            [
+                Instruction::AddTo {
+                    offset: 1,
+                    factor: 2
+                },
+                Instruction::SetZero
+            ]
+        );
+        assert!(lowered[3..]
+            .iter()
+            .all(|lowered| lowered.original == (3..10)));
+        assert_eq!(lowered[4].pass, Pass::Multiply);
+        // Loops that only clear the cell are left to `clear_loops`.
+        assert_eq!(lowered[0].instruction, Instruction::JumpZeroPlaceholder);
+    }
+
+    #[test]
+    fn test_scan_loops() {
+        let lowered = scan_loops(fold_runs(lowered("[>>][<][>+]")));
+
+        assert_eq!(
+            instructions(&lowered[..2]),
+            [Instruction::FindZero(2), Instruction::FindZero(-1)]
+        );
+        assert_eq!(lowered[0].cost, StepCost::Scan { stride: 2 });
+        assert_eq!(lowered[2].instruction, Instruction::JumpZeroPlaceholder);
+    }
+
+    #[test]
+    fn test_dead_loop_elimination() {
+        use Instruction::{JumpNotZeroPlaceholder as Close, JumpZeroPlaceholder as Open};
+
+        let cases = [
+            ("[.][.]", vec![Open, Instruction::WriteByte(1), Close]),
+            ("[-][.]", vec![Instruction::SetZero]),
+            (
+                "[.][.[.]]+[.]",
+                vec![
+                    Open,
+                    Instruction::WriteByte(1),
+                    Close,
+                    Instruction::IncByteAtDP(1),
+                    Open,
+                    Instruction::WriteByte(1),
+                    Close,
+                ],
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let lowered = dead_loop_elimination(clear_loops(fold_runs(lowered(source))));
+            assert_eq!(instructions(&lowered), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn test_o3() {
+        let source = "[-]+-[.>]>><.";
+        let compile = |level| {
+            Compiler::new(source)
+                .with_opt_level(level)
+                .compile()
+                .unwrap()
+        };
+
+        assert_eq!(
+            compile(OptLevel::O3),
+            [
+                Instruction::SetZero,
+                Instruction::IncDP(1),
+                Instruction::WriteByte(1)
+            ]
+        );
+        assert_eq!(compile(OptLevel::O2).len(), 10);
+        assert_eq!(compile(OptLevel::O1).len(), 12);
+        assert_eq!(compile(OptLevel::O0).len(), source.len());
+    }
+}
@@ -0,0 +1,146 @@
+//! Writes to file descriptors that can stall, like pipes to a slow consumer, without blocking
+//! indefinitely: a write waits with `poll(2)` until the descriptor accepts output, so it can be
+//! cancelled while it waits, and the time it waited is measured.
+
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a write waits for the descriptor before it checks for cancellation again.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A writer that waits for `W` to accept output before every write.
+///
+/// A pipe that signals that it accepts output takes at least [libc::PIPE_BUF] bytes without
+/// blocking, so writes are split into chunks of that size.
+#[derive(Debug)]
+pub struct PollWriter<W> {
+    inner: W,
+    cancel: Arc<AtomicBool>,
+    stalled: Duration,
+}
+
+impl<W: Write + AsRawFd> PollWriter<W> {
+    /// Wraps `inner`, which stays in blocking mode.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            cancel: Arc::new(AtomicBool::new(false)),
+            stalled: Duration::ZERO,
+        }
+    }
+
+    /// Returns a flag that cancels the current and all future writes once it is set, e.g. from
+    /// another thread, which then fail with [io::ErrorKind::Other].
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// Returns how long the writes waited for the descriptor to accept output, which is not
+    /// spent executing the program.
+    pub fn stalled(&self) -> Duration {
+        self.stalled
+    }
+
+    /// Waits until the descriptor accepts output or the writes are cancelled.
+    fn wait_writable(&mut self) -> io::Result<()> {
+        let mut fd = libc::pollfd {
+            fd: self.inner.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        let start = Instant::now();
+        let result = loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                break Err(io::Error::other("the write was cancelled"));
+            }
+            // SAFETY: This call is according to the documentation, `fd` is one descriptor.
+            match unsafe { libc::poll(&mut fd, 1, POLL_INTERVAL.as_millis() as libc::c_int) } {
+                0 => continue,
+                -1 => match io::Error::last_os_error() {
+                    err if err.kind() == io::ErrorKind::Interrupted => continue,
+                    err => break Err(err),
+                },
+                // Errors of the descriptor, like a closed pipe, are reported by the write.
+                _ => break Ok(()),
+            }
+        };
+        self.stalled += start.elapsed();
+        result
+    }
+}
+
+impl<W: Write + AsRawFd> Write for PollWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.wait_writable()?;
+        self.inner.write(&buf[..buf.len().min(libc::PIPE_BUF)])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::PollWriter;
+
+    /// Returns the ends of a new pipe to read from and to write to.
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // SAFETY: This call is according to the documentation.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: The descriptors are open and owned by nothing else.
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn test_slow_reader() {
+        let (mut reader, writer) = pipe();
+        let consumer = thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let mut buf = [0; 16 * 1024];
+            loop {
+                thread::sleep(Duration::from_millis(10));
+                match reader.read(&mut buf).unwrap() {
+                    0 => return bytes,
+                    n => bytes.extend_from_slice(&buf[..n]),
+                }
+            }
+        });
+
+        let bytes: Vec<u8> = (0..256 * 1024).map(|i| i as u8).collect();
+        let mut writer = PollWriter::new(writer);
+        writer.write_all(&bytes).unwrap();
+        let stalled = writer.stalled();
+        drop(writer);
+
+        assert!(consumer.join().unwrap() == bytes);
+        assert!(stalled > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_cancel_stalled_write() {
+        // Nothing reads from the pipe, so it is full after a few writes.
+        let (_reader, writer) = pipe();
+        let mut writer = PollWriter::new(writer);
+        let cancel = writer.cancel_handle();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancel.store(true, Ordering::Relaxed);
+        });
+
+        let err = writer.write_all(&vec![0; 1024 * 1024]).unwrap_err();
+        assert_eq!(err.to_string(), "the write was cancelled");
+        assert!(writer.stalled() >= Duration::from_millis(50));
+    }
+}
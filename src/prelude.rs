@@ -0,0 +1,12 @@
+//! The stable surface of the crate, e.g. `use brainfuck::prelude::*;` to compile a program and
+//! execute it with [run] on any [Backend].
+//!
+//! Modules that are not re-exported here can change in any release. The lowest level ones, like
+//! the machine code encoders of the JIT compiler, are hidden from the documentation unless the
+//! `internals` feature is enabled.
+
+pub use crate::capabilities::Backend;
+pub use crate::compiler::{CompileError, Compiler, Program, SourcePosition};
+pub use crate::engine::{run, Engine, Error, ExecOptions};
+pub use crate::tape::{CellWidth, TapePolicy};
+pub use crate::{EofBehavior, FlushBehavior, Limit, RuntimeError};
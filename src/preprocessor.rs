@@ -0,0 +1,277 @@
+//! Expands `;;include "lib.b"` directives, so larger programs can be split across files.
+//!
+//! The included file is inserted after the line of the directive, which is kept as it is
+//! ignored by the execution environments anyway. Every line of the result remembers the file
+//! and line it came from, so errors can point to the original source.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::tokenizer::{tokenize, TokenKind, DIRECTIVE_PREFIX};
+
+/// A program with all includes expanded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocessed {
+    /// The expanded source, which can be passed to the compiler or the interpreter.
+    pub source: String,
+
+    /// Files that contributed to `source`, the first one is the main program.
+    files: Vec<PathBuf>,
+
+    /// Index into `files` and line in that file for every line of `source`.
+    lines: Vec<(usize, usize)>,
+}
+
+/// A position in one of the files of a preprocessed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location<'a> {
+    pub file: &'a Path,
+
+    /// Line in `file`, starting at 1.
+    pub line: usize,
+
+    /// Column in characters, starting at 1.
+    pub column: usize,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// Describes why the includes of a program could not be expanded.
+#[derive(Debug)]
+pub enum PreprocessError {
+    /// An included file could not be read.
+    Read {
+        path: PathBuf,
+        line: usize,
+        file: PathBuf,
+        error: io::Error,
+    },
+
+    /// An include directive is not of the form `;;include "path"`.
+    InvalidInclude { file: PathBuf, line: usize },
+
+    /// A file includes itself, directly or through other files listed in `chain`.
+    Cycle { chain: Vec<PathBuf> },
+}
+
+impl Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::Read {
+                path,
+                line,
+                file,
+                error,
+            } => write!(
+                f,
+                "failed to include {} at {}:{line}: {error}",
+                path.display(),
+                file.display()
+            ),
+            PreprocessError::InvalidInclude { file, line } => write!(
+                f,
+                "invalid include at {}:{line}, expected `;;include \"path\"`",
+                file.display()
+            ),
+            PreprocessError::Cycle { chain } => {
+                let chain: Vec<String> = chain
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                write!(f, "cyclic include: {}", chain.join(" -> "))
+            }
+        }
+    }
+}
+
+impl Error for PreprocessError {}
+
+impl Preprocessed {
+    /// Maps a line and column of the expanded source back to the file it came from.
+    pub fn locate(&self, line: usize, column: usize) -> Option<Location<'_>> {
+        let (file, line) = *self.lines.get(line.checked_sub(1)?)?;
+        Some(Location {
+            file: &self.files[file],
+            line,
+            column,
+        })
+    }
+}
+
+/// Expands the includes of `source`, the program at `path`.
+///
+/// Included paths are relative to the directory of the including file, and are read with
+/// `read`.
+pub fn preprocess<F>(
+    source: &str,
+    path: &Path,
+    mut read: F,
+) -> Result<Preprocessed, PreprocessError>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    let mut preprocessed = Preprocessed {
+        source: String::new(),
+        files: Vec::new(),
+        lines: Vec::new(),
+    };
+    let mut stack = vec![normalize(path)];
+    expand(source, &mut stack, &mut read, &mut preprocessed)?;
+    Ok(preprocessed)
+}
+
+fn expand<F>(
+    source: &str,
+    stack: &mut Vec<PathBuf>,
+    read: &mut F,
+    preprocessed: &mut Preprocessed,
+) -> Result<(), PreprocessError>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    let path = stack.last().unwrap().clone();
+    let file = preprocessed.files.len();
+    preprocessed.files.push(path.clone());
+
+    // Included paths by the line of their directive.
+    let mut includes = Vec::new();
+    for token in tokenize(source) {
+        if token.kind != TokenKind::Directive {
+            continue;
+        }
+        let directive = token.text[DIRECTIVE_PREFIX.len()..].trim();
+        if let Some(argument) = directive
+            .strip_prefix("include")
+            .filter(|argument| argument.is_empty() || argument.starts_with([' ', '\t', '"']))
+        {
+            let included = argument
+                .trim()
+                .strip_prefix('"')
+                .and_then(|argument| argument.strip_suffix('"'))
+                .filter(|included| !included.is_empty())
+                .ok_or_else(|| PreprocessError::InvalidInclude {
+                    file: path.clone(),
+                    line: token.line,
+                })?;
+            includes.push((token.line, included));
+        }
+    }
+
+    let mut includes = includes.into_iter().peekable();
+    for (i, text) in source.lines().enumerate() {
+        preprocessed.source.push_str(text);
+        preprocessed.source.push('\n');
+        preprocessed.lines.push((file, i + 1));
+
+        while let Some((_, included)) = includes.next_if(|(line, _)| *line == i + 1) {
+            let included = normalize(&path.parent().unwrap_or(Path::new("")).join(included));
+            if let Some(start) = stack.iter().position(|file| *file == included) {
+                let mut chain = stack[start..].to_vec();
+                chain.push(included);
+                return Err(PreprocessError::Cycle { chain });
+            }
+
+            let source = read(&included).map_err(|error| PreprocessError::Read {
+                path: included.clone(),
+                line: i + 1,
+                file: path.clone(),
+                error,
+            })?;
+            stack.push(included);
+            expand(&source, stack, read, preprocessed)?;
+            stack.pop();
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `.` and resolvable `..` components, so the same file included through different
+/// paths is recognized when detecting cycles.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::Path;
+
+    use super::{preprocess, Location, PreprocessError, Preprocessed};
+
+    fn run(files: &[(&str, &str)]) -> Result<Preprocessed, PreprocessError> {
+        let files: HashMap<&str, &str> = files.iter().copied().collect();
+        let main = files["main.b"];
+        preprocess(main, Path::new("main.b"), |path| {
+            files
+                .get(path.to_str().unwrap())
+                .map(|source| source.to_string())
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        })
+    }
+
+    #[test]
+    fn test_expand_includes() {
+        let preprocessed = run(&[
+            ("main.b", "+\n;;include \"lib/a.b\"\n."),
+            ("lib/a.b", ">\n;;include \"../b.b\""),
+            ("b.b", "<-"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            preprocessed.source,
+            "+\n;;include \"lib/a.b\"\n>\n;;include \"../b.b\"\n<-\n.\n"
+        );
+        assert_eq!(
+            preprocessed.locate(5, 2),
+            Some(Location {
+                file: Path::new("b.b"),
+                line: 1,
+                column: 2,
+            })
+        );
+        assert_eq!(preprocessed.locate(6, 1).unwrap().to_string(), "main.b:3:1");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |files: &[(&str, &str)]| run(files).unwrap_err().to_string();
+
+        assert_eq!(
+            error(&[
+                ("main.b", ";;include \"a.b\""),
+                ("a.b", "+\n;;include \"./main.b\"")
+            ]),
+            "cyclic include: main.b -> a.b -> main.b"
+        );
+        assert_eq!(
+            error(&[("main.b", "+\n;;include lib.b")]),
+            "invalid include at main.b:2, expected `;;include \"path\"`"
+        );
+        assert!(error(&[("main.b", ";;include \"missing.b\"")])
+            .starts_with("failed to include missing.b at main.b:1: "));
+    }
+}
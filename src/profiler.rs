@@ -0,0 +1,193 @@
+//! A sampling profiler that records which loops of a program were active over time and
+//! exports them as a [Chrome trace](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU),
+//! viewable in `chrome://tracing` or Perfetto.
+
+use std::time::{Duration, Instant};
+
+use crate::compiler::Instruction;
+use crate::json;
+use crate::source_map::Span;
+
+/// The state of the program at one point in time.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    /// Time since the execution started.
+    pub time: Duration,
+
+    /// Index of the instruction that is executed next.
+    pub ip: usize,
+
+    pub dp: usize,
+}
+
+/// Takes a [Sample] every `interval` executed instructions.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    interval: u64,
+    countdown: u64,
+    start: Instant,
+    samples: Vec<Sample>,
+}
+
+impl Sampler {
+    pub(crate) fn new(interval: u64) -> Self {
+        Self {
+            interval: interval.max(1),
+            countdown: 0,
+            start: Instant::now(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Called before every executed instruction.
+    pub(crate) fn tick(&mut self, ip: usize, dp: usize) {
+        if self.countdown == 0 {
+            self.countdown = self.interval;
+            self.samples.push(Sample {
+                time: self.start.elapsed(),
+                ip,
+                dp,
+            });
+        }
+        self.countdown -= 1;
+    }
+
+    pub(crate) fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+}
+
+/// Returns the loops containing the instruction at `ip` as the indices of their `JumpZero`
+/// instructions, from the outermost to the innermost loop.
+fn active_loops(instructions: &[Instruction], ip: usize) -> Vec<usize> {
+    instructions
+        .iter()
+        .enumerate()
+        .take(ip + 1)
+        .filter_map(|(start, instruction)| match instruction {
+            // The loop ends with the `JumpNotZero` before the target of the jump.
+            Instruction::JumpZero(n) if ip < start + n => Some(start),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Converts `samples` taken while executing `instructions` into Chrome trace events.
+///
+/// Every loop becomes a slice spanning the consecutive samples it was active in, nested
+/// loops are nested slices. The data pointer is exported as a counter. Loops are named after
+/// their position in the source if `spans` contains the span of every instruction, and after
+/// their instruction index otherwise.
+pub fn chrome_trace(instructions: &[Instruction], spans: &[Span], samples: &[Sample]) -> String {
+    let name = |start: usize| match spans.get(start) {
+        Some(span) => format!("loop at {}:{}", span.line, span.column),
+        None => format!("loop at instruction {start}"),
+    };
+    let micros = |time: Duration| time.as_micros();
+
+    let mut events = Vec::new();
+    let mut slice = |start: usize, begin: Duration, end: Duration| {
+        events.push(
+            json::Object::default()
+                .field("name", json::string(&name(start)))
+                .field("ph", json::string("X"))
+                .field("ts", micros(begin))
+                .field("dur", micros(end.saturating_sub(begin)))
+                .field("pid", 1)
+                .field("tid", 1)
+                .finish(),
+        );
+    };
+
+    // The currently open slices with the time they started.
+    let mut open: Vec<(usize, Duration)> = Vec::new();
+    for sample in samples {
+        let active = active_loops(instructions, sample.ip);
+        let common = open
+            .iter()
+            .zip(&active)
+            .take_while(|((open, _), active)| open == *active)
+            .count();
+
+        for (start, begin) in open.drain(common..).rev() {
+            slice(start, begin, sample.time);
+        }
+        open.extend(active[common..].iter().map(|start| (*start, sample.time)));
+    }
+
+    let end = samples.last().map_or(Duration::ZERO, |sample| sample.time);
+    for (start, begin) in open.drain(..).rev() {
+        slice(start, begin, end);
+    }
+
+    for sample in samples {
+        events.push(
+            json::Object::default()
+                .field("name", json::string("dp"))
+                .field("ph", json::string("C"))
+                .field("ts", micros(sample.time))
+                .field("pid", 1)
+                .field(
+                    "args",
+                    json::Object::default().field("dp", sample.dp).finish(),
+                )
+                .finish(),
+        );
+    }
+
+    json::Object::default()
+        .field("traceEvents", json::array(events))
+        .field("displayTimeUnit", json::string("ms"))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{active_loops, chrome_trace, Sample, Sampler};
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn test_active_loops() {
+        // 0: +  1: [  2: >  3: [  4: --  5: ]  6: <  7: ]  8: .
+        let instructions = Compiler::new("+[>[--]<].").compile().unwrap();
+
+        assert_eq!(active_loops(&instructions, 0), [] as [usize; 0]);
+        assert_eq!(active_loops(&instructions, 2), [1]);
+        assert_eq!(active_loops(&instructions, 4), [1, 3]);
+        assert_eq!(active_loops(&instructions, 6), [1]);
+        assert_eq!(active_loops(&instructions, 8), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn test_sampler_interval() {
+        let mut sampler = Sampler::new(3);
+        for ip in 0..7 {
+            sampler.tick(ip, 0);
+        }
+
+        let ips: Vec<usize> = sampler.samples().iter().map(|sample| sample.ip).collect();
+        assert_eq!(ips, [0, 3, 6]);
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        let instructions = Compiler::new("[>[--]<]").compile().unwrap();
+        let sample = |micros, ip| Sample {
+            time: Duration::from_micros(micros),
+            ip,
+            dp: 0,
+        };
+        let trace = chrome_trace(
+            &instructions,
+            &[],
+            &[sample(0, 1), sample(10, 3), sample(20, 5), sample(30, 7)],
+        );
+
+        assert!(trace.starts_with(
+            r#"{"traceEvents":[{"name":"loop at instruction 2","ph":"X","ts":10,"dur":10,"pid":1,"tid":1},{"name":"loop at instruction 0","ph":"X","ts":0,"dur":30,"pid":1,"tid":1},{"name":"dp","ph":"C","ts":0,"pid":1,"args":{"dp":0}},"#
+        ));
+        assert!(trace.ends_with(r#"],"displayTimeUnit":"ms"}"#));
+    }
+}
@@ -0,0 +1,356 @@
+//! Recordings (`.bfrun` files) of a single execution, bundling the program, the input it read,
+//! the output it wrote and the options it was executed with, so the execution can be replayed
+//! exactly, e.g. to reproduce a bug report.
+//!
+//! ```toml
+//! format-version = 1
+//! crate-version = "0.1.0"
+//! source = ",[.,]"
+//! input = [104,105]
+//! output = [104,105]
+//!
+//! [options]
+//! env = "vm"
+//! tape-size = 30000
+//! tape = "fixed"
+//! cell-width = 8
+//! eof = "error"
+//! no-opt = false
+//! opt-level = 2
+//! ```
+//!
+//! The input and output are the bytes read and written by the program itself, before newlines
+//! are translated. Recordings made before cells could be wider than a byte have no
+//! `cell-width`, which means 8, and recordings made before the behavior at the end of the input
+//! was configurable have no `eof`, which means `"error"`. Recordings made before the
+//! optimizations could be selected have no `opt-level`, which means 0 with `no-opt` and 2
+//! without.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::capabilities::Backend;
+use crate::json;
+use crate::passes::OptLevel;
+use crate::tape::{CellWidth, TapePolicy};
+use crate::toml::{self, Table, Value};
+use crate::EofBehavior;
+
+/// A recorded execution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    /// Version of the crate that made the recording.
+    pub crate_version: String,
+
+    /// Source of the program, with includes already expanded.
+    pub source: String,
+
+    /// Bytes read by the program.
+    pub input: Vec<u8>,
+
+    /// Bytes written by the program.
+    pub output: Vec<u8>,
+
+    /// Backend the program was executed with.
+    pub backend: Backend,
+
+    /// Number of cells of the tape.
+    pub tape_size: usize,
+
+    /// Behavior of the tape when the data pointer moves past its end.
+    pub tape_policy: TapePolicy,
+
+    /// Width of the cells.
+    pub cell_width: CellWidth,
+
+    /// Behavior of the read instruction at the end of the input.
+    pub eof: EofBehavior,
+
+    /// Whether the program was compiled without optimizations.
+    pub no_opt: bool,
+
+    /// Level of the optimizations the program was compiled with.
+    pub opt_level: OptLevel,
+}
+
+/// Describes why a recording could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordingError {
+    /// The recording is not valid TOML.
+    Syntax { line: usize, message: String },
+
+    /// The recording is valid TOML but does not describe an execution.
+    Invalid(String),
+}
+
+impl Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Syntax { line, message } => {
+                write!(f, "invalid syntax at line {line}: {message}")
+            }
+            RecordingError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for RecordingError {}
+
+impl Recording {
+    /// Version of the format written by [to_toml](Recording::to_toml).
+    /// It is increased whenever fields are removed or change their meaning.
+    pub const FORMAT_VERSION: i64 = 1;
+
+    /// Serializes the recording in the `.bfrun` format.
+    pub fn to_toml(&self) -> String {
+        let bytes = |bytes: &[u8]| json::array(bytes.iter().map(u8::to_string));
+
+        // JSON strings are valid TOML basic strings.
+        format!(
+            "format-version = {}\n\
+            crate-version = {}\n\
+            source = {}\n\
+            input = {}\n\
+            output = {}\n\
+            \n\
+            [options]\n\
+            env = {}\n\
+            tape-size = {}\n\
+            tape = {}\n\
+            cell-width = {}\n\
+            eof = {}\n\
+            no-opt = {}\n\
+            opt-level = {}\n",
+            Self::FORMAT_VERSION,
+            json::string(&self.crate_version),
+            json::string(&self.source),
+            bytes(&self.input),
+            bytes(&self.output),
+            json::string(self.backend.name()),
+            self.tape_size,
+            json::string(self.tape_policy.name()),
+            self.cell_width.bits(),
+            json::string(self.eof.name()),
+            self.no_opt,
+            self.opt_level.number(),
+        )
+    }
+
+    /// Parses a recording in the `.bfrun` format.
+    pub fn parse(source: &str) -> Result<Self, RecordingError> {
+        let mut root = toml::parse(source).map_err(|err| RecordingError::Syntax {
+            line: err.line,
+            message: err.message,
+        })?;
+
+        let format_version = match root.remove("format-version") {
+            Some(Value::Integer(version)) => version,
+            _ => return Err(invalid("`format-version` must be an integer")),
+        };
+        if format_version != Self::FORMAT_VERSION {
+            return Err(invalid(format!(
+                "unsupported format version {format_version}, supported is {}",
+                Self::FORMAT_VERSION
+            )));
+        }
+
+        let crate_version = string(&mut root, "crate-version")?;
+        let source = string(&mut root, "source")?;
+        let input = bytes(&mut root, "input")?;
+        let output = bytes(&mut root, "output")?;
+
+        let mut options = match root.remove("options") {
+            Some(Value::Table(table)) => table,
+            _ => return Err(invalid("`options` must be a table")),
+        };
+        let env = string(&mut options, "env")?;
+        let backend = Backend::ALL
+            .into_iter()
+            .find(|backend| backend.name() == env)
+            .ok_or_else(|| invalid(format!("unknown env `{env}`")))?;
+        let tape_size = match options.remove("tape-size") {
+            Some(Value::Integer(size)) if size > 0 => size as usize,
+            _ => return Err(invalid("`tape-size` must be a positive integer")),
+        };
+        let tape = string(&mut options, "tape")?;
+        let tape_policy = tape
+            .parse()
+            .map_err(|_| invalid(format!("unknown tape `{tape}`")))?;
+        let cell_width =
+            match options.remove("cell-width") {
+                Some(Value::Integer(bits)) => u32::try_from(bits)
+                    .ok()
+                    .and_then(CellWidth::from_bits)
+                    .ok_or_else(|| invalid(format!("unsupported cell width {bits}")))?,
+                None => CellWidth::U8,
+                Some(_) => return Err(invalid("`cell-width` must be an integer")),
+            };
+        let eof = match options.remove("eof") {
+            Some(Value::String(eof)) => eof
+                .parse()
+                .map_err(|_| invalid(format!("unknown eof behavior `{eof}`")))?,
+            None => EofBehavior::Error,
+            Some(_) => return Err(invalid("`eof` must be a string")),
+        };
+        let no_opt = match options.remove("no-opt") {
+            Some(Value::Boolean(no_opt)) => no_opt,
+            _ => return Err(invalid("`no-opt` must be a boolean")),
+        };
+        let opt_level = match options.remove("opt-level") {
+            Some(Value::Integer(level)) => level
+                .to_string()
+                .parse()
+                .map_err(|_| invalid(format!("unsupported opt level {level}")))?,
+            None if no_opt => OptLevel::O0,
+            None => OptLevel::default(),
+            Some(_) => return Err(invalid("`opt-level` must be an integer")),
+        };
+
+        if let Some(key) = root.keys().chain(options.keys()).next() {
+            return Err(invalid(format!("unknown key `{key}`")));
+        }
+
+        Ok(Self {
+            crate_version,
+            source,
+            input,
+            output,
+            backend,
+            tape_size,
+            tape_policy,
+            cell_width,
+            eof,
+            no_opt,
+            opt_level,
+        })
+    }
+}
+
+fn invalid(message: impl Into<String>) -> RecordingError {
+    RecordingError::Invalid(message.into())
+}
+
+fn string(table: &mut Table, key: &str) -> Result<String, RecordingError> {
+    match table.remove(key) {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(invalid(format!("`{key}` must be a string"))),
+    }
+}
+
+fn bytes(table: &mut Table, key: &str) -> Result<Vec<u8>, RecordingError> {
+    let error = || invalid(format!("`{key}` must be an array of bytes"));
+    match table.remove(key) {
+        Some(Value::Array(values)) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::Integer(n) => u8::try_from(n).map_err(|_| error()),
+                _ => Err(error()),
+            })
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recording, RecordingError};
+    use crate::capabilities::Backend;
+    use crate::passes::OptLevel;
+    use crate::tape::{CellWidth, TapePolicy};
+    use crate::EofBehavior;
+
+    fn recording() -> Recording {
+        Recording {
+            crate_version: "1.2.3".to_string(),
+            source: ",[.,]\n\"quoted\" \\ \u{1}".to_string(),
+            input: vec![0, 104, 255],
+            output: vec![104, 255],
+            backend: Backend::Closures,
+            tape_size: 100,
+            tape_policy: TapePolicy::Growable,
+            cell_width: CellWidth::U16,
+            eof: EofBehavior::SetMinusOne,
+            no_opt: true,
+            opt_level: OptLevel::O0,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let recording = recording();
+
+        assert_eq!(Recording::parse(&recording.to_toml()), Ok(recording));
+    }
+
+    #[test]
+    fn test_cell_width_defaults_to_8() {
+        let toml = recording().to_toml().replace("cell-width = 16\n", "");
+
+        assert_eq!(
+            Recording::parse(&toml).map(|recording| recording.cell_width),
+            Ok(CellWidth::U8)
+        );
+    }
+
+    #[test]
+    fn test_eof_defaults_to_error() {
+        let toml = recording().to_toml().replace("eof = \"minus-one\"\n", "");
+
+        assert_eq!(
+            Recording::parse(&toml).map(|recording| recording.eof),
+            Ok(EofBehavior::Error)
+        );
+    }
+
+    #[test]
+    fn test_opt_level_defaults_to_no_opt() {
+        let toml = recording().to_toml().replace("opt-level = 0\n", "");
+        let level = |toml: &str| Recording::parse(toml).map(|recording| recording.opt_level);
+
+        assert_eq!(level(&toml), Ok(OptLevel::O0));
+        assert_eq!(
+            level(&toml.replace("no-opt = true", "no-opt = false")),
+            Ok(OptLevel::O2)
+        );
+    }
+
+    #[test]
+    fn test_invalid_recordings() {
+        let toml = recording().to_toml();
+        let cases = [
+            (
+                toml.replace("format-version = 1", "format-version = 2"),
+                "unsupported format version 2, supported is 1",
+            ),
+            (
+                toml.replace("input = [0,", "input = [256,"),
+                "`input` must be an array of bytes",
+            ),
+            (toml.replace("\"closures\"", "\"gpu\""), "unknown env `gpu`"),
+            (
+                toml.replace("tape-size = 100", "tape-size = 0"),
+                "`tape-size` must be a positive integer",
+            ),
+            (
+                toml.replace("cell-width = 16", "cell-width = 12"),
+                "unsupported cell width 12",
+            ),
+            (
+                toml.replace("\"minus-one\"", "\"retry\""),
+                "unknown eof behavior `retry`",
+            ),
+            (
+                toml.replace("opt-level = 0", "opt-level = 4"),
+                "unsupported opt level 4",
+            ),
+            (toml.clone() + "seed = 1\n", "unknown key `seed`"),
+        ];
+
+        for (toml, message) in cases {
+            assert_eq!(
+                Recording::parse(&toml),
+                Err(RecordingError::Invalid(message.to_string()))
+            );
+        }
+    }
+}
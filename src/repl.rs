@@ -0,0 +1,217 @@
+//! An interactive session that executes brainfuck code line by line on the same tape.
+
+use std::io::{self, BufRead, Write};
+use std::mem;
+#[cfg(unix)]
+use std::{env, path::Path};
+
+use crate::compiler::{Feed, IncrementalCompiler};
+#[cfg(unix)]
+use crate::line_editor::{self, LineEditor};
+use crate::tape::{Tape, TapePolicy};
+use crate::virtual_machine::VirtualMachine;
+use crate::{EofBehavior, FlushBehavior, DEFAULT_TAPE_SIZE};
+
+const HELP: &str = "\
+every line is executed on the same tape, a loop can span several lines
+
+commands:
+  :tape [n], :t [n]   print the cells around the data pointer (default 8 on each side)
+  :reset              clear the tape and discard unfinished loops
+  :help, :h           print this help
+  :quit, :q           stop the session";
+
+/// Prompt for a line that starts new code.
+const PROMPT: &str = "bf> ";
+
+/// Commands completed by Tab.
+#[cfg(unix)]
+const COMMANDS: &[&str] = &[":tape", ":reset", ":help", ":quit"];
+
+/// Name of the history file in the home directory.
+#[cfg(unix)]
+const HISTORY_FILE: &str = ".brainfuck_history";
+
+/// An interactive session, where lines are executed on the
+/// [virtual machine](crate::virtual_machine::VirtualMachine) as soon as all of their loops are
+/// closed.
+pub struct Repl {
+    compiler: IncrementalCompiler,
+    data: Tape,
+    dp: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    /// Creates a session with an empty tape, which grows as needed.
+    pub fn new() -> Self {
+        Self {
+            compiler: IncrementalCompiler::default(),
+            data: Tape::new(DEFAULT_TAPE_SIZE, TapePolicy::Growable),
+            dp: 0,
+        }
+    }
+
+    /// Reads lines from `input` until it is exhausted or the user quits, writing prompts and
+    /// the output of the code to `output`.
+    ///
+    /// Code that reads input reads the bytes following its line from `input`.
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "type `:help` for a list of commands")?;
+
+        let mut line = String::new();
+        loop {
+            write!(output, "{}", self.prompt())?;
+            output.flush()?;
+
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !self.eval(line.trim_end_matches(['\r', '\n']), input, output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs the session on stdin and stdout.
+    ///
+    /// If both are a terminal, lines can be edited, previous lines recalled with the arrow keys
+    /// and commands completed with Tab. The history is kept in `~/.brainfuck_history`.
+    pub fn run_interactive(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        if line_editor::is_terminal() {
+            let file = env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE));
+            let mut editor = LineEditor::new(COMMANDS, file);
+            let mut output = io::stdout();
+
+            writeln!(output, "type `:help` for a list of commands")?;
+            while let Some(line) = editor.read_line(&self.prompt())? {
+                if !self.eval(&line, &mut io::stdin().lock(), &mut output)? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        self.run(&mut io::stdin().lock(), &mut io::stdout())
+    }
+
+    /// Returns the prompt for the next line, which shows the number of unclosed loops.
+    fn prompt(&self) -> String {
+        match self.compiler.depth() {
+            0 => PROMPT.to_string(),
+            depth => format!("{depth:.>3} "),
+        }
+    }
+
+    /// Executes a line of code or a command, returning `false` if the user quits.
+    fn eval(
+        &mut self,
+        line: &str,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<bool> {
+        if let Some(command) = line.trim().strip_prefix(':') {
+            let (command, argument) = command.split_once(' ').unwrap_or((command, ""));
+            let argument = argument.trim();
+            match command {
+                "t" | "tape" => match argument.parse::<usize>() {
+                    Ok(radius) => self.print_tape(output, radius)?,
+                    Err(_) if argument.is_empty() => self.print_tape(output, 8)?,
+                    Err(_) => writeln!(output, "invalid number of cells `{argument}`")?,
+                },
+                "reset" => {
+                    self.compiler.reset();
+                    self.data = Tape::new(DEFAULT_TAPE_SIZE, TapePolicy::Growable);
+                    self.dp = 0;
+                }
+                "h" | "help" => writeln!(output, "{HELP}")?,
+                "q" | "quit" => return Ok(false),
+                _ => writeln!(
+                    output,
+                    "unknown command `:{command}`, type `:help` for help"
+                )?,
+            }
+            return Ok(true);
+        }
+
+        match self.compiler.feed(line) {
+            Feed::Complete(instructions) => {
+                let data = mem::replace(&mut self.data, Tape::new(0, TapePolicy::Growable));
+                let mut vm = VirtualMachine::new(&instructions, &mut *input, &mut *output)
+                    .with_tape(data, self.dp);
+                let result = vm.execute(FlushBehavior::OnWrite, EofBehavior::default());
+                (self.data, self.dp) = vm.into_tape();
+
+                if let Err(err) = result {
+                    writeln!(output, "failed to execute the line: {err}")?;
+                }
+            }
+            Feed::Incomplete { .. } => {}
+            Feed::Unbalanced => writeln!(output, "unmatched `]`, the line was discarded")?,
+        }
+        Ok(true)
+    }
+
+    fn print_tape(&self, output: &mut impl Write, radius: usize) -> io::Result<()> {
+        let start = self.dp.saturating_sub(radius);
+        let end = (self.dp + radius + 1).min(self.data.len());
+
+        for (i, cell) in self.data.iter().enumerate().take(end).skip(start) {
+            let marker = if i == self.dp { '>' } else { ' ' };
+            writeln!(output, "{marker} {i:>5}: {cell:>3}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Repl;
+
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        Repl::new()
+            .run(&mut Cursor::new(input), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_loop_spanning_lines() {
+        let output = session("++++++++[>++++++++\n<-]>+.\n:t 1\n");
+
+        assert_eq!(
+            output,
+            "type `:help` for a list of commands\n\
+            bf> ..1 Abf>       0:   0\n\
+            >     1:  65\n      \
+            2:   0\n\
+            bf> "
+        );
+    }
+
+    #[test]
+    fn test_reads_input_after_line() {
+        let output = session(",+.\nb\n:reset\n:tape 0\n:quit\n+\n");
+
+        assert!(output.contains("bf> cbf> bf> bf> >     0:   0\nbf> "));
+        assert!(output.ends_with(":   0\nbf> "));
+    }
+
+    #[test]
+    fn test_unmatched_bracket() {
+        let output = session("+]\n[\n:reset\n:tape 0\n");
+
+        assert!(output.contains("unmatched `]`, the line was discarded\nbf> ..1 bf> "));
+        assert!(output.ends_with(">     0:   0\nbf> "));
+    }
+}
@@ -0,0 +1,113 @@
+//! A summary of what an execution did, e.g. to profile a program or to compare execution
+//! environments, returned by `Interpreter::execute` and `VirtualMachine::execute`.
+
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+/// What an execution did so far.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExecReport {
+    /// Number of executed instructions of every kind that was executed, named like the
+    /// instructions of the execution environment, e.g. `AddTo` or `+`.
+    pub instructions: Vec<(&'static str, u64)>,
+
+    /// Number of bytes read, including reads past the end of the input.
+    pub bytes_read: u64,
+
+    /// Number of bytes written.
+    pub bytes_written: u64,
+
+    /// The highest data pointer.
+    pub peak_dp: usize,
+
+    /// Time spent executing, including reading and writing.
+    pub elapsed: Duration,
+}
+
+impl ExecReport {
+    /// Returns the number of executed instructions of all kinds.
+    pub fn total_instructions(&self) -> u64 {
+        self.instructions.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Describes the report on several lines, with a line for every kind of instruction.
+impl Display for ExecReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "instructions: {}", self.total_instructions())?;
+        for (name, count) in &self.instructions {
+            writeln!(f, "  {name}: {count}")?;
+        }
+        writeln!(f, "bytes read: {}", self.bytes_read)?;
+        writeln!(f, "bytes written: {}", self.bytes_written)?;
+        writeln!(f, "peak dp: {}", self.peak_dp)?;
+        writeln!(f, "elapsed: {:.3?}", self.elapsed)
+    }
+}
+
+/// Counts what an execution with `N` kinds of instructions did, for an [ExecReport].
+#[derive(Debug, Clone)]
+pub(crate) struct Counter<const N: usize> {
+    /// The number of executed instructions of every kind, by opcode.
+    pub instructions: [u64; N],
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub peak_dp: usize,
+    pub elapsed: Duration,
+}
+
+impl<const N: usize> Counter<N> {
+    pub fn new() -> Self {
+        Self {
+            instructions: [0; N],
+            bytes_read: 0,
+            bytes_written: 0,
+            peak_dp: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Returns the report, with the kinds of instructions named by `names`.
+    pub fn report(&self, names: &[&'static str; N]) -> ExecReport {
+        ExecReport {
+            instructions: named_counts(names, &self.instructions),
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+            peak_dp: self.peak_dp,
+            elapsed: self.elapsed,
+        }
+    }
+}
+
+/// Names the counts of the kinds of instructions in `names`, leaving out kinds that were not
+/// executed.
+fn named_counts(names: &[&'static str], counts: &[u64]) -> Vec<(&'static str, u64)> {
+    names
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| **count > 0)
+        .map(|(name, count)| (*name, *count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let report = ExecReport {
+            instructions: named_counts(&["IncDP", "SetZero", "ReadByte"], &[3, 0, 1]),
+            bytes_read: 1,
+            bytes_written: 0,
+            peak_dp: 3,
+            elapsed: Duration::from_millis(2),
+        };
+        assert_eq!(report.total_instructions(), 4);
+        assert_eq!(
+            report.to_string(),
+            "instructions: 4\n  IncDP: 3\n  ReadByte: 1\nbytes read: 1\nbytes written: 0\n\
+             peak dp: 3\nelapsed: 2.000ms\n"
+        );
+    }
+}
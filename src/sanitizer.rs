@@ -0,0 +1,113 @@
+//! Detects behavior that differs among brainfuck implementations while a program runs on the
+//! [virtual machine](crate::virtual_machine::VirtualMachine), the dynamic counterpart of the
+//! static checks of `check`.
+
+use std::collections::HashSet;
+
+use crate::DEFAULT_TAPE_SIZE;
+
+/// Behavior that other brainfuck implementations do differently, or not at all.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Issue {
+    /// A cell wrapped around below zero or above its largest value, which depends on the width
+    /// of the cells.
+    Wraparound,
+
+    /// The data pointer moved past the first [DEFAULT_TAPE_SIZE] cells, which many
+    /// implementations do not have.
+    BeyondTape,
+
+    /// A read reached the end of the input, which implementations handle differently.
+    ReadAtEof,
+
+    /// A byte that is not ASCII was written.
+    NonAsciiOutput,
+}
+
+impl Issue {
+    /// Returns what happened, for messages like "{description} at line 1 column 3".
+    pub fn description(self) -> &'static str {
+        match self {
+            Issue::Wraparound => "cell wraparound",
+            Issue::BeyondTape => "data pointer beyond the first 30000 cells",
+            Issue::ReadAtEof => "read at the end of the input",
+            Issue::NonAsciiOutput => "output of a non-ASCII byte",
+        }
+    }
+}
+
+/// An occurrence of an [Issue] at the instruction `ip`, with the data pointer `dp` before the
+/// instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Finding {
+    pub issue: Issue,
+    pub ip: usize,
+    pub dp: usize,
+}
+
+/// Collects the issues of an execution, enabled with
+/// [VirtualMachine::with_sanitizer](crate::virtual_machine::VirtualMachine::with_sanitizer).
+///
+/// Only the first occurrence of an issue at an instruction is a finding, so a loop that wraps a
+/// cell on every iteration is reported once.
+///
+/// Optimized programs clear cells without wrapping them, so the clear loop `[+]` is only
+/// detected without optimizations.
+#[derive(Default)]
+pub struct Sanitizer {
+    findings: Vec<Finding>,
+    reported: HashSet<(Issue, usize)>,
+    occurrences: u64,
+    on_finding: Option<Callback>,
+}
+
+/// Called for every [Finding] as soon as it occurs.
+type Callback = Box<dyn FnMut(&Finding)>;
+
+impl Sanitizer {
+    /// Creates a sanitizer that collects the findings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `on_finding` for every finding as soon as it occurs, e.g. to print it.
+    pub fn with_callback(mut self, on_finding: impl FnMut(&Finding) + 'static) -> Self {
+        self.on_finding = Some(Box::new(on_finding));
+        self
+    }
+
+    /// Returns the findings in the order they occurred.
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    /// Returns how often issues occurred, including the ones that were not reported again.
+    pub fn occurrences(&self) -> u64 {
+        self.occurrences
+    }
+
+    pub(crate) fn flag(&mut self, issue: Issue, ip: usize, dp: usize) {
+        self.occurrences += 1;
+        if self.reported.insert((issue, ip)) {
+            let finding = Finding { issue, ip, dp };
+            if let Some(on_finding) = &mut self.on_finding {
+                on_finding(&finding);
+            }
+            self.findings.push(finding);
+        }
+    }
+    /// Flags the data pointer `dp` if it is beyond the tape of most implementations.
+    pub(crate) fn check_dp(&mut self, ip: usize, from: usize, dp: usize) {
+        if dp >= DEFAULT_TAPE_SIZE {
+            self.flag(Issue::BeyondTape, ip, from);
+        }
+    }
+
+    /// Flags `value + delta` if it is outside of the cells, which hold values up to `max`.
+    pub(crate) fn check_wrap(&mut self, ip: usize, dp: usize, value: u64, delta: i128, max: u64) {
+        let result = i128::from(value) + delta;
+        if result < 0 || result > i128::from(max) {
+            self.flag(Issue::Wraparound, ip, dp);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Runs several programs on virtual machines that take turns on a single thread, connected by
+//! channels so the output of one program can be the input of another.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use crate::virtual_machine::{Slice, Status, VirtualMachine};
+use crate::{EofBehavior, FlushBehavior, RuntimeError};
+
+/// Default number of instructions a program executes before the next program takes its turn.
+pub const DEFAULT_SLICE: u64 = 10_000;
+
+/// Creates a channel, bytes written to the [ChannelWriter] can be read from the
+/// [ChannelReader].
+///
+/// Reading from an empty channel fails with [WouldBlock](io::ErrorKind::WouldBlock), which
+/// makes the [Scheduler] continue with other programs until bytes are available.
+pub fn channel() -> (ChannelWriter, ChannelReader) {
+    let buffer = Rc::new(RefCell::new(VecDeque::new()));
+    (ChannelWriter(Rc::clone(&buffer)), ChannelReader(buffer))
+}
+
+/// The writing end of a [channel].
+#[derive(Debug)]
+pub struct ChannelWriter(Rc<RefCell<VecDeque<u8>>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reading end of a [channel].
+#[derive(Debug)]
+pub struct ChannelReader(Rc<RefCell<VecDeque<u8>>>);
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.0.borrow_mut();
+        if buffer.is_empty() && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = buf.len().min(buffer.len());
+        for (byte, buffered) in buf.iter_mut().zip(buffer.drain(..n)) {
+            *byte = buffered;
+        }
+        Ok(n)
+    }
+}
+
+/// Describes why the programs of a [Scheduler] could not run to completion.
+#[derive(Debug)]
+pub enum SchedulerError {
+    /// The program with the given index moved its data pointer outside of the tape, or
+    /// reading or writing failed.
+    Runtime { process: usize, error: RuntimeError },
+
+    /// The programs that have not finished all wait for input that no other program
+    /// provides.
+    Deadlock { blocked: Vec<usize> },
+}
+
+impl Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulerError::Runtime { process, error } => {
+                write!(f, "failed to execute process {process}: {error}")
+            }
+            SchedulerError::Deadlock { blocked } => {
+                let blocked: Vec<String> = blocked.iter().map(usize::to_string).collect();
+                write!(
+                    f,
+                    "deadlock, processes {} are waiting for input",
+                    blocked.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl Error for SchedulerError {}
+
+/// A program that can be executed in slices.
+trait Process {
+    fn run(&mut self, budget: u64, flush: FlushBehavior) -> Result<Slice, RuntimeError>;
+}
+
+impl<R: Read, W: Write> Process for VirtualMachine<'_, R, W> {
+    fn run(&mut self, budget: u64, flush: FlushBehavior) -> Result<Slice, RuntimeError> {
+        VirtualMachine::run(self, budget, flush, EofBehavior::default())
+    }
+}
+
+/// Runs virtual machines round-robin, each one executing a slice of instructions before the
+/// next one continues.
+pub struct Scheduler<'a> {
+    processes: Vec<Box<dyn Process + 'a>>,
+    slice: u64,
+}
+
+impl Default for Scheduler<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Scheduler<'a> {
+    /// Creates a scheduler without processes, using the [DEFAULT_SLICE].
+    pub fn new() -> Self {
+        Self {
+            processes: Vec::new(),
+            slice: DEFAULT_SLICE,
+        }
+    }
+
+    /// Sets the number of instructions a process executes per turn.
+    pub fn with_slice(mut self, slice: u64) -> Self {
+        self.slice = slice.max(1);
+        self
+    }
+
+    /// Adds a virtual machine as a process, returning the index used in errors.
+    pub fn spawn<R, W>(&mut self, vm: VirtualMachine<'a, R, W>) -> usize
+    where
+        R: Read + 'a,
+        W: Write + 'a,
+    {
+        self.processes.push(Box::new(vm));
+        self.processes.len() - 1
+    }
+
+    /// Runs the processes until all of them finished.
+    pub fn run(&mut self, flush: FlushBehavior) -> Result<(), SchedulerError> {
+        let mut finished = vec![false; self.processes.len()];
+
+        while finished.contains(&false) {
+            let mut progress = false;
+            let mut blocked = Vec::new();
+
+            for (process, vm) in self.processes.iter_mut().enumerate() {
+                if finished[process] {
+                    continue;
+                }
+
+                let slice = vm
+                    .run(self.slice, flush)
+                    .map_err(|error| SchedulerError::Runtime { process, error })?;
+                progress |= slice.executed > 0;
+                match slice.status {
+                    Status::Finished => finished[process] = true,
+                    Status::Blocked => blocked.push(process),
+                    Status::Paused => {}
+                }
+            }
+
+            if !progress {
+                return Err(SchedulerError::Deadlock { blocked });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{channel, Scheduler, SchedulerError};
+    use crate::compiler::Compiler;
+    use crate::virtual_machine::VirtualMachine;
+    use crate::FlushBehavior;
+
+    #[test]
+    fn test_pipeline() {
+        // Writes 1, 2 and 3, the second program increments every byte it reads.
+        let producer = Compiler::new("+.+.+.").compile().unwrap();
+        let incrementer = Compiler::new(",+.,+.,+.").compile().unwrap();
+        let (mut tx, mut rx) = channel();
+        let mut input = io::empty();
+        let mut output = Vec::new();
+
+        let mut scheduler = Scheduler::new().with_slice(1);
+        // The consumer is spawned first, so it has to wait for the producer.
+        scheduler.spawn(VirtualMachine::new(&incrementer, &mut rx, &mut output));
+        scheduler.spawn(VirtualMachine::new(&producer, &mut input, &mut tx));
+        scheduler.run(FlushBehavior::OnEnd).unwrap();
+        drop(scheduler);
+
+        assert_eq!(output, [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let program = Compiler::new(",.").compile().unwrap();
+        let (mut tx_a, mut rx_a) = channel();
+        let (mut tx_b, mut rx_b) = channel();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(VirtualMachine::new(&program, &mut rx_a, &mut tx_b));
+        scheduler.spawn(VirtualMachine::new(&program, &mut rx_b, &mut tx_a));
+        let err = scheduler.run(FlushBehavior::OnEnd).unwrap_err();
+
+        assert!(matches!(err, SchedulerError::Deadlock { .. }));
+        assert_eq!(
+            err.to_string(),
+            "deadlock, processes 0, 1 are waiting for input"
+        );
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let program = Compiler::new("+.<").compile().unwrap();
+        let (mut tx, mut rx) = channel();
+        let mut input = io::empty();
+        let mut output = Vec::new();
+
+        let mut scheduler = Scheduler::new();
+        scheduler.spawn(VirtualMachine::new(&program, &mut rx, &mut output));
+        scheduler.spawn(VirtualMachine::new(&program, &mut input, &mut tx));
+        let err = scheduler.run(FlushBehavior::OnEnd).unwrap_err();
+
+        assert!(matches!(err, SchedulerError::Runtime { process: 0, .. }));
+        assert_eq!(
+            err.to_string(),
+            "failed to execute process 0: \
+            instruction 2 moved the data pointer outside of the tape to cell -1"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Snapshots of the state of the [virtual machine](crate::virtual_machine::VirtualMachine), so
+//! long-running programs can be checkpointed to disk and resumed, and executions can be compared.
+//!
+//! The format starts with [MAGIC] and the [VERSION] byte, followed by the number of bits of a
+//! cell, the tape policy, the instruction pointer, the data pointer and the number of cells as
+//! little endian 64 bit integers, and the cells, each little endian in the bytes of its width.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::tape::{Cell, TapePolicy};
+
+/// The bytes every snapshot starts with.
+pub const MAGIC: &[u8; 4] = b"BFS\0";
+
+/// Version of the format written by [VmState::to_bytes].
+pub const VERSION: u8 = 1;
+
+/// The instruction pointer, the data pointer and the tape of a virtual machine.
+///
+/// A snapshot only makes sense for the instructions it was taken of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmState<C> {
+    ip: usize,
+    dp: usize,
+    policy: TapePolicy,
+    cells: Vec<C>,
+}
+
+impl<C: Cell> VmState<C> {
+    /// The data pointer must be on the tape.
+    pub(crate) fn new(ip: usize, dp: usize, policy: TapePolicy, cells: Vec<C>) -> Self {
+        debug_assert!(dp < cells.len());
+        Self {
+            ip,
+            dp,
+            policy,
+            cells,
+        }
+    }
+
+    /// Returns the index of the next instruction.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// Returns the index of the current cell.
+    pub fn dp(&self) -> usize {
+        self.dp
+    }
+
+    /// Returns what happens when the data pointer moves past the end of the tape.
+    pub fn tape_policy(&self) -> TapePolicy {
+        self.policy
+    }
+
+    /// Returns the cells of the tape.
+    pub fn cells(&self) -> &[C] {
+        &self.cells
+    }
+
+    pub(crate) fn into_parts(self) -> (usize, usize, TapePolicy, Vec<C>) {
+        (self.ip, self.dp, self.policy, self.cells)
+    }
+
+    /// Serializes the snapshot, which [from_bytes](VmState::from_bytes) turns back into the
+    /// same snapshot.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let width = C::WIDTH.bytes();
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 3 + 3 * 8 + self.cells.len() * width);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(C::WIDTH.bits() as u8);
+        bytes.push(match self.policy {
+            TapePolicy::Fixed => 0,
+            TapePolicy::Growable => 1,
+        });
+        for n in [self.ip, self.dp, self.cells.len()] {
+            bytes.extend_from_slice(&(n as u64).to_le_bytes());
+        }
+        for cell in &self.cells {
+            bytes.extend_from_slice(&cell.to_u64().to_le_bytes()[..width]);
+        }
+        bytes
+    }
+
+    /// Deserializes a snapshot written by [to_bytes](VmState::to_bytes) with cells of the
+    /// same width.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let Some(rest) = bytes.strip_prefix(MAGIC.as_slice()) else {
+            return Err(SnapshotError::NotSnapshot);
+        };
+        let mut reader = Reader { bytes: rest };
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let bits = reader.take(1)?[0];
+        if u32::from(bits) != C::WIDTH.bits() {
+            return Err(SnapshotError::CellWidth {
+                found: bits,
+                expected: C::WIDTH.bits() as u8,
+            });
+        }
+        let policy = match reader.take(1)?[0] {
+            0 => TapePolicy::Fixed,
+            1 => TapePolicy::Growable,
+            policy => return Err(SnapshotError::UnknownTapePolicy(policy)),
+        };
+        let ip = reader.usize()?;
+        let dp = reader.usize()?;
+        let len = reader.usize()?;
+
+        let width = C::WIDTH.bytes();
+        let data = reader.take(len.checked_mul(width).ok_or(SnapshotError::Truncated)?)?;
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        if dp >= len {
+            return Err(SnapshotError::PointerOutOfBounds { dp, len });
+        }
+        let cells = data
+            .chunks_exact(width)
+            .map(|chunk| {
+                let mut buf = [0; 8];
+                buf[..width].copy_from_slice(chunk);
+                C::truncate(u64::from_le_bytes(buf))
+            })
+            .collect();
+        Ok(Self::new(ip, dp, policy, cells))
+    }
+}
+
+/// Describes why bytes could not be decoded into a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// The bytes do not start with [MAGIC].
+    NotSnapshot,
+
+    /// The bytes were written by a different version of the format.
+    UnsupportedVersion(u8),
+
+    /// The snapshot was taken with cells of a different number of bits.
+    CellWidth { found: u8, expected: u8 },
+
+    /// The byte describing the tape policy is unknown.
+    UnknownTapePolicy(u8),
+
+    /// The bytes end before all cells were read.
+    Truncated,
+
+    /// There are bytes after the last cell.
+    TrailingBytes,
+
+    /// The data pointer is not on the tape.
+    PointerOutOfBounds { dp: usize, len: usize },
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotSnapshot => f.write_str("not a snapshot of a brainfuck program"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported snapshot version {version}, expected version {VERSION}"
+            ),
+            SnapshotError::CellWidth { found, expected } => write!(
+                f,
+                "the snapshot has {found} bit cells, expected {expected} bit cells"
+            ),
+            SnapshotError::UnknownTapePolicy(policy) => {
+                write!(f, "unknown tape policy {policy}")
+            }
+            SnapshotError::Truncated => f.write_str("the snapshot ends unexpectedly"),
+            SnapshotError::TrailingBytes => f.write_str("unexpected bytes after the last cell"),
+            SnapshotError::PointerOutOfBounds { dp, len } => write!(
+                f,
+                "the data pointer {dp} is outside of the tape of {len} cells"
+            ),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < n {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn usize(&mut self) -> Result<usize, SnapshotError> {
+        let n = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        usize::try_from(n).map_err(|_| SnapshotError::Truncated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let state = VmState::new(7, 1, TapePolicy::Growable, vec![1u16, 0x1234, 0]);
+        let bytes = state.to_bytes();
+        assert_eq!(VmState::from_bytes(&bytes), Ok(state));
+
+        let state = VmState::new(0, 0, TapePolicy::Fixed, vec![0xdeadbeefu32]);
+        assert_eq!(VmState::from_bytes(&state.to_bytes()), Ok(state));
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let state = VmState::new(3, 2, TapePolicy::Fixed, vec![1u8, 2, 3]);
+        let bytes = state.to_bytes();
+
+        assert_eq!(
+            VmState::<u8>::from_bytes(b"BFC\0\x01"),
+            Err(SnapshotError::NotSnapshot)
+        );
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(
+            VmState::<u8>::from_bytes(&version),
+            Err(SnapshotError::UnsupportedVersion(9))
+        );
+        assert_eq!(
+            VmState::<u16>::from_bytes(&bytes),
+            Err(SnapshotError::CellWidth {
+                found: 8,
+                expected: 16
+            })
+        );
+        assert_eq!(
+            VmState::<u8>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            VmState::<u8>::from_bytes(&trailing),
+            Err(SnapshotError::TrailingBytes)
+        );
+        let mut dp = bytes;
+        dp[15] = 3;
+        assert_eq!(
+            VmState::<u8>::from_bytes(&dp),
+            Err(SnapshotError::PointerOutOfBounds { dp: 3, len: 3 })
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Maps generated artifacts back to the brainfuck source they were generated from, so
+//! positions in a generated file can be traced to the original program.
+
+use std::ops::Range;
+
+use crate::compiler::{self, Instruction, Pass};
+use crate::json;
+use crate::passes::{self, OptLevel, Optimization};
+use crate::tokenizer::{tokenize, TokenKind};
+
+/// Version of the JSON format returned by [SourceMap::to_json].
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A range of characters in a source file, where both ends are inclusive.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Span {
+    /// Line of the first character, starting at 1.
+    pub line: usize,
+
+    /// Column of the first character, starting at 1.
+    pub column: usize,
+
+    /// Line of the last character.
+    pub end_line: usize,
+
+    /// Column of the last character.
+    pub end_column: usize,
+}
+
+/// Where an instruction generated by the [compiler](crate::compiler::Compiler) came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// The optimization that created the instruction.
+    pub pass: Pass,
+
+    /// Span of the source the instruction was created from.
+    pub span: Span,
+
+    /// Indices of the instructions it replaces in the program compiled
+    /// [without optimizations](crate::compiler::Compiler::without_optimizations), which has
+    /// one instruction per character.
+    pub original: Range<usize>,
+}
+
+/// Returns the span of every instruction generated by the
+/// [compiler](crate::compiler::Compiler) for `source`, in the order of the instructions.
+///
+/// Repeated instructions are folded into one instruction, so their span covers the whole
+/// run, including comments in between. The same applies to loops that are lowered to
+/// instructions without a loop, like `[-]` or `[->+<]`.
+pub fn instruction_spans(source: &str) -> Vec<Span> {
+    provenance(source)
+        .into_iter()
+        .map(|provenance| provenance.span)
+        .collect()
+}
+
+/// Returns the [Provenance] of every instruction generated by the
+/// [compiler](crate::compiler::Compiler) for `source`, in the order of the instructions.
+pub fn provenance(source: &str) -> Vec<Provenance> {
+    provenance_with_passes(source, OptLevel::default().passes())
+}
+
+/// Returns the span of every instruction generated by the compiler for `source` when it runs
+/// the given [passes](crate::passes), like [instruction_spans] does for the default ones.
+pub fn instruction_spans_with_passes(source: &str, passes: &[Optimization]) -> Vec<Span> {
+    provenance_with_passes(source, passes)
+        .into_iter()
+        .map(|provenance| provenance.span)
+        .collect()
+}
+
+/// Returns the [Provenance] of every instruction generated by the compiler for `source` when it
+/// runs the given [passes](crate::passes).
+pub fn provenance_with_passes(source: &str, passes: &[Optimization]) -> Vec<Provenance> {
+    let tokens: Vec<_> = tokenize(source)
+        .into_iter()
+        .filter(|token| token.kind == TokenKind::Instruction)
+        .collect();
+    let instructions: Vec<Instruction> = tokens
+        .iter()
+        .map(|token| compiler::instruction(token.text.as_bytes()[0], 1))
+        .collect();
+
+    passes::run(passes, &instructions)
+        .into_iter()
+        .map(|lowered| {
+            let (start, end) = (
+                &tokens[lowered.original.start],
+                &tokens[lowered.original.end - 1],
+            );
+            Provenance {
+                pass: lowered.pass,
+                span: Span {
+                    line: start.line,
+                    column: start.column,
+                    end_line: end.line,
+                    end_column: end.column,
+                },
+                original: lowered.original,
+            }
+        })
+        .collect()
+}
+
+/// Returns the span of every instruction generated by the compiler
+/// [without optimizations](crate::compiler::Compiler::without_optimizations), which is a single
+/// character per instruction.
+pub fn unoptimized_instruction_spans(source: &str) -> Vec<Span> {
+    tokenize(source)
+        .iter()
+        .filter(|token| token.kind == TokenKind::Instruction)
+        .map(|token| Span {
+            line: token.line,
+            column: token.column,
+            end_line: token.line,
+            end_column: token.column,
+        })
+        .collect()
+}
+
+/// Mappings from positions in a generated file to spans of the source it was generated from.
+///
+/// A generated position is a line for text artifacts and a byte offset for binary ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    /// Path of the generated file.
+    pub file: String,
+
+    /// Path of the brainfuck source.
+    pub source: String,
+
+    mappings: Vec<(usize, Span)>,
+}
+
+impl SourceMap {
+    /// Creates an empty source map from `source` to `file`.
+    pub fn new(file: &str, source: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            source: source.to_string(),
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Records that the code at `generated` was generated from `span`.
+    pub fn add(&mut self, generated: usize, span: Span) {
+        self.mappings.push((generated, span));
+    }
+
+    /// Returns the span the code at `generated` was generated from, using the closest mapping
+    /// at or before it.
+    pub fn lookup(&self, generated: usize) -> Option<Span> {
+        self.mappings
+            .iter()
+            .filter(|(position, _)| *position <= generated)
+            .max_by_key(|(position, _)| *position)
+            .map(|(_, span)| *span)
+    }
+
+    /// Serializes the source map as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mappings = self.mappings.iter().map(|(generated, span)| {
+            json::Object::default()
+                .field("generated", generated)
+                .field("line", span.line)
+                .field("column", span.column)
+                .field("end_line", span.end_line)
+                .field("end_column", span.end_column)
+                .finish()
+        });
+
+        json::Object::default()
+            .field("format_version", FORMAT_VERSION)
+            .field("file", json::string(&self.file))
+            .field("source", json::string(&self.source))
+            .field("mappings", json::array(mappings))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instruction_spans, provenance, unoptimized_instruction_spans, SourceMap, Span};
+    use crate::compiler::{Compiler, Pass};
+
+    fn span(line: usize, column: usize, end_line: usize, end_column: usize) -> Span {
+        Span {
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+
+    #[test]
+    fn test_spans_match_instructions() {
+        let source = "++ add\n+[[-]>;; name cell 1 x-y\n,,.[--]][->+>++\n<<]";
+        let spans = instruction_spans(source);
+
+        assert_eq!(spans.len(), Compiler::new(source).compile().unwrap().len());
+        assert_eq!(
+            spans,
+            [
+                span(1, 1, 2, 1),
+                span(2, 2, 2, 2),
+                span(2, 3, 2, 5),
+                span(2, 6, 2, 6),
+                span(3, 1, 3, 1),
+                span(3, 2, 3, 2),
+                span(3, 3, 3, 3),
+                span(3, 4, 3, 4),
+                span(3, 5, 3, 6),
+                span(3, 7, 3, 7),
+                span(3, 8, 3, 8),
+                span(3, 9, 4, 3),
+                span(3, 9, 4, 3),
+                span(3, 9, 4, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        let source = "++ add\n[-]>,,[->+<][>]";
+        let provenance = provenance(source);
+
+        let passes: Vec<Pass> = provenance
+            .iter()
+            .map(|provenance| provenance.pass)
+            .collect();
+        assert_eq!(
+            passes,
+            [
+                Pass::Fold,
+                Pass::Clear,
+                Pass::Unoptimized,
+                Pass::Unoptimized,
+                Pass::Unoptimized,
+                Pass::Multiply,
+                Pass::Multiply,
+                Pass::Scan,
+            ]
+        );
+        let original: Vec<_> = provenance
+            .iter()
+            .map(|provenance| provenance.original.clone())
+            .collect();
+        assert_eq!(
+            original,
+            [0..2, 2..5, 5..6, 6..7, 7..8, 8..14, 8..14, 14..17]
+        );
+        assert_eq!(provenance[5].span, span(2, 7, 2, 12));
+    }
+
+    #[test]
+    fn test_unoptimized_spans_match_instructions() {
+        let source = "++\n[-] ,,";
+        let spans = unoptimized_instruction_spans(source);
+
+        assert_eq!(
+            spans.len(),
+            Compiler::new(source)
+                .without_optimizations()
+                .compile()
+                .unwrap()
+                .len()
+        );
+        assert_eq!(spans[2], span(2, 1, 2, 1));
+        assert_eq!(spans[6], span(2, 6, 2, 6));
+    }
+
+    #[test]
+    fn test_lookup_and_json() {
+        let mut map = SourceMap::new("out.c", "main.b");
+        map.add(3, span(1, 1, 1, 2));
+        map.add(7, span(2, 1, 2, 1));
+
+        assert_eq!(map.lookup(2), None);
+        assert_eq!(map.lookup(5), Some(span(1, 1, 1, 2)));
+        assert_eq!(
+            map.to_json(),
+            r#"{"format_version":1,"file":"out.c","source":"main.b","mappings":[{"generated":3,"line":1,"column":1,"end_line":1,"end_column":2},{"generated":7,"line":2,"column":1,"end_line":2,"end_column":1}]}"#
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! The semantics the crate chose where brainfuck leaves them open, as data, and a reference
+//! executor that follows them as directly as possible, so every execution environment can be
+//! checked against it for every combination of options.
+//!
+//! - Cells wrap around at their [width](CellWidth).
+//! - Reading past the end of the input does what the [EofBehavior] says.
+//! - The tape starts with `tape_size` zero cells. Moving before the first cell fails, moving
+//!   past the last cell fails on a [fixed](TapePolicy::Fixed) tape and appends zero cells on a
+//!   [growable](TapePolicy::Growable) one. Where the data pointer ended up outside of the tape
+//!   is not part of the semantics, as optimized moves skip the cells in between.
+//! - Writing outputs the low byte of the cell, reading sets the cell to the byte.
+//! - The dialect is chosen by the program: extensions like `!` are instructions after the
+//!   directive that enables them, see [extensions](crate::extensions).
+
+use std::io;
+
+use crate::engine::ExecOptions;
+use crate::tape::{CellWidth, TapePolicy};
+use crate::tokenizer::{tokenize, TokenKind};
+use crate::{EofBehavior, RuntimeError};
+
+/// The options that change what a program does.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Semantics {
+    pub cell_width: CellWidth,
+    pub eof: EofBehavior,
+    pub tape_policy: TapePolicy,
+    pub tape_size: usize,
+}
+
+impl Semantics {
+    /// Returns every combination of cell width, EOF behavior and tape policy, with a tape of
+    /// `tape_size` cells.
+    pub fn all(tape_size: usize) -> Vec<Semantics> {
+        let mut all = Vec::new();
+        for cell_width in CellWidth::ALL {
+            for eof in EofBehavior::ALL {
+                for tape_policy in TapePolicy::ALL {
+                    all.push(Semantics {
+                        cell_width,
+                        eof,
+                        tape_policy,
+                        tape_size,
+                    });
+                }
+            }
+        }
+        all
+    }
+
+    /// Returns the options that make an execution environment follow these semantics.
+    pub fn options(&self) -> ExecOptions {
+        ExecOptions::new()
+            .with_cell_width(self.cell_width)
+            .with_eof(self.eof)
+            .with_tape_policy(self.tape_policy)
+            .with_tape_size(self.tape_size)
+    }
+}
+
+/// What executing a program observably did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub output: Vec<u8>,
+    pub end: End,
+}
+
+/// How the execution of a program ended.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum End {
+    /// All instructions were executed.
+    Finished,
+
+    /// The data pointer left the tape.
+    OutOfBounds,
+
+    /// The program exited with the code.
+    Exit(u32),
+
+    /// The program selected an input that does not exist, which is every input but the
+    /// regular one for the reference executor.
+    UnknownInput,
+
+    /// Reading or writing failed, e.g. with [UnexpectedEof](io::ErrorKind::UnexpectedEof) at
+    /// the end of the input.
+    Io(io::ErrorKind),
+
+    /// A loop ran more iterations than it was asserted to, which the reference executor does
+    /// not check.
+    LoopLimitExceeded,
+
+    /// The execution was cancelled, which the reference executor never is.
+    Cancelled,
+
+    /// The execution exceeded a limit, which the reference executor does not have.
+    LimitExceeded,
+}
+
+impl End {
+    /// Returns how an execution environment ended with `result`.
+    pub fn of(result: &Result<(), RuntimeError>) -> End {
+        match result {
+            Ok(()) => End::Finished,
+            Err(RuntimeError::PointerOutOfBounds { .. }) => End::OutOfBounds,
+            Err(RuntimeError::Exit(code)) => End::Exit(*code),
+            Err(RuntimeError::UnknownInput(_)) => End::UnknownInput,
+            Err(RuntimeError::Io(err)) => End::Io(err.kind()),
+            Err(RuntimeError::LoopLimitExceeded { .. }) => End::LoopLimitExceeded,
+            Err(RuntimeError::Cancelled) => End::Cancelled,
+            Err(RuntimeError::LimitExceeded(_)) => End::LimitExceeded,
+        }
+    }
+}
+
+/// Executes `source` with `input` according to `semantics`, one character at a time.
+///
+/// The brackets of `source` have to match.
+pub fn execute(source: &str, input: &[u8], semantics: &Semantics) -> Outcome {
+    let code: Vec<char> = tokenize(source)
+        .into_iter()
+        .filter(|token| token.kind == TokenKind::Instruction)
+        .map(|token| token.text.chars().next().unwrap())
+        .collect();
+
+    let mut jumps = vec![0; code.len()];
+    let mut open = Vec::new();
+    for (i, c) in code.iter().enumerate() {
+        match c {
+            '[' => open.push(i),
+            ']' => {
+                let start = open.pop().expect("the brackets have to match");
+                (jumps[start], jumps[i]) = (i, start);
+            }
+            _ => {}
+        }
+    }
+    assert!(open.is_empty(), "the brackets have to match");
+
+    let mask = u64::MAX >> (64 - semantics.cell_width.bits());
+    let mut cells = vec![0u64; semantics.tape_size];
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let (mut ip, mut dp) = (0, 0);
+
+    let end = loop {
+        let Some(c) = code.get(ip) else {
+            break End::Finished;
+        };
+        match c {
+            '>' => {
+                dp += 1;
+                if dp == cells.len() {
+                    match semantics.tape_policy {
+                        TapePolicy::Fixed => break End::OutOfBounds,
+                        TapePolicy::Growable => cells.push(0),
+                    }
+                }
+            }
+            '<' => match dp.checked_sub(1) {
+                Some(left) => dp = left,
+                None => break End::OutOfBounds,
+            },
+            '+' => cells[dp] = cells[dp].wrapping_add(1) & mask,
+            '-' => cells[dp] = cells[dp].wrapping_sub(1) & mask,
+            '.' => output.push(cells[dp] as u8),
+            ',' => match (input.next(), semantics.eof) {
+                (Some(byte), _) => cells[dp] = u64::from(*byte),
+                (None, EofBehavior::SetZero) => cells[dp] = 0,
+                (None, EofBehavior::SetMinusOne) => cells[dp] = mask,
+                (None, EofBehavior::LeaveUnchanged) => {}
+                (None, EofBehavior::Error) => break End::Io(io::ErrorKind::UnexpectedEof),
+            },
+            '[' if cells[dp] == 0 => ip = jumps[ip],
+            ']' if cells[dp] != 0 => ip = jumps[ip],
+            '!' => break End::Exit(cells[dp] as u32),
+            '%' if cells[dp] != 0 => break End::UnknownInput,
+            _ => {}
+        }
+        ip += 1;
+    };
+
+    Outcome { output, end }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{execute, End, Outcome, Semantics};
+    use crate::capabilities::Backend;
+    use crate::compiler::Compiler;
+    use crate::engine;
+    use crate::tape::{CellWidth, TapePolicy};
+    use crate::EofBehavior;
+
+    /// Programs that depend on the semantics, each with its input.
+    const PROGRAMS: &[(&str, &[u8])] = &[
+        // Wrapping below zero, without loops that count down from the largest value.
+        ("-.>+[-[-]]-.", b""),
+        // Wrapping of multiply and scan loops.
+        ("++[->---<]>.++++++++[<+>-]<.+++[>>]<<.", b""),
+        // The end of the input.
+        (",.,.,.", b"a"),
+        (",+.,+.,+.", b"a"),
+        // Every read consumes a byte.
+        (",,.", b"ab"),
+        // Moving past either end of the tape.
+        ("<", b""),
+        (">>>>>>>>>>>>>>>>+.<<<<<<<<<<<<<<<<<", b""),
+        ("+>+>+>+>+>+>+>+>+>+>+>+>+>+>+>+<<<<<<<<<<<<<<<[>]+.", b""),
+        // Bodies that are skipped or repeated.
+        ("[.]++[>+++[>++<-]<-]>>.", b""),
+        // Exiting with the low bits of a wider cell.
+        (";; extension exit\n-!", b""),
+    ];
+
+    #[test]
+    fn test_reference() {
+        let u16 = Semantics {
+            cell_width: CellWidth::U16,
+            eof: EofBehavior::SetMinusOne,
+            tape_policy: TapePolicy::Fixed,
+            tape_size: 4,
+        };
+
+        assert_eq!(
+            execute(",.>,.", b"a", &u16),
+            Outcome {
+                output: vec![b'a', 0xff],
+                end: End::Finished
+            }
+        );
+        assert_eq!(execute(",,.", b"ab", &u16).output, b"b");
+        assert_eq!(execute(">>>>", b"", &u16).end, End::OutOfBounds);
+        assert_eq!(
+            execute(";; extension exit\n-!", b"", &u16).end,
+            End::Exit(65535)
+        );
+        assert_eq!(
+            execute(
+                ">>>>+.",
+                b"",
+                &Semantics {
+                    tape_policy: TapePolicy::Growable,
+                    ..u16
+                }
+            ),
+            Outcome {
+                output: vec![1],
+                end: End::Finished
+            }
+        );
+    }
+
+    #[test]
+    fn test_backends_follow_spec() {
+        for semantics in Semantics::all(16) {
+            let options = semantics.options();
+
+            for (source, input) in PROGRAMS {
+                let expected = execute(source, input, &semantics);
+                let program = Compiler::new(source).compile_program().unwrap();
+
+                for backend in Backend::ALL {
+                    if !backend.is_available() {
+                        continue;
+                    }
+                    let mut reader = Cursor::new(*input);
+                    let mut writer = Vec::new();
+                    let result = engine::select(
+                        backend,
+                        source,
+                        &program,
+                        &mut reader,
+                        &mut writer,
+                        &options,
+                    )
+                    .unwrap()
+                    .run(&options);
+                    let actual = Outcome {
+                        output: writer,
+                        end: End::of(&result),
+                    };
+
+                    assert_eq!(actual, expected, "{backend:?} {semantics:?} `{source}`");
+                }
+            }
+        }
+    }
+}
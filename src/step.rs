@@ -0,0 +1,32 @@
+//! What a single instruction did, so tools like visualizers and test harnesses can drive an
+//! execution one instruction at a time with `Interpreter::step` or `VirtualMachine::step`.
+
+/// What executing one instruction did, where `I` is the type of the instructions of the
+/// execution environment.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StepResult<I> {
+    /// The executed instruction.
+    pub instruction: I,
+
+    /// The instruction pointer after the instruction, pointing at the next one.
+    pub ip: usize,
+
+    /// The data pointer after the instruction.
+    pub dp: usize,
+
+    /// The input read or the output written by the instruction.
+    pub io: StepIo,
+}
+
+/// The input or output of an instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StepIo {
+    /// The instruction neither read nor wrote.
+    None,
+
+    /// The instruction read the byte, or reached the end of the input if it is `None`.
+    Read(Option<u8>),
+
+    /// The instruction wrote the byte `count` times.
+    Write { byte: u8, count: usize },
+}
@@ -11,6 +11,12 @@ pub const IDENT_READ_BYTE: u8 = b',';
 pub const IDENT_JUMP_ZERO: u8 = b'[';
 pub const IDENT_JUMP_NOT_ZERO: u8 = b']';
 
+/// Only an instruction once a program enabled the `exit` [extension](crate::extensions).
+pub const IDENT_EXIT: u8 = b'!';
+
+/// Only an instruction once a program enabled the `env` [extension](crate::extensions).
+pub const IDENT_SELECT_INPUT: u8 = b'%';
+
 pub static IDENTS: Lazy<HashSet<u8>> = Lazy::new(|| {
     let mut idents = HashSet::new();
 
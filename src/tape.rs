@@ -0,0 +1,477 @@
+//! The memory of a program, the width of its cells and what happens when the data pointer moves
+//! past its end.
+
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+
+/// Behavior of the tape when the data pointer moves past its last cell.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum TapePolicy {
+    /// The tape keeps its size, moving past its end panics.
+    #[default]
+    Fixed,
+
+    /// The tape grows on demand, its size is only the initial size.
+    Growable,
+}
+
+impl TapePolicy {
+    /// All policies.
+    pub const ALL: [TapePolicy; 2] = [TapePolicy::Fixed, TapePolicy::Growable];
+
+    /// Returns the name used on the command line and in manifests.
+    pub fn name(self) -> &'static str {
+        match self {
+            TapePolicy::Fixed => "fixed",
+            TapePolicy::Growable => "growable",
+        }
+    }
+}
+
+impl FromStr for TapePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TapePolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| "valid values are `fixed` and `growable`".to_string())
+    }
+}
+
+/// Number of bits of a cell, all arithmetic on cells wraps around at this width.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CellWidth {
+    /// Cells are bytes, like in most implementations.
+    #[default]
+    U8,
+
+    /// Cells are 16-bit numbers.
+    U16,
+
+    /// Cells are 32-bit numbers.
+    U32,
+}
+
+impl CellWidth {
+    /// All widths.
+    pub const ALL: [CellWidth; 3] = [CellWidth::U8, CellWidth::U16, CellWidth::U32];
+
+    /// Returns the number of bits, which is also the name used on the command line and in
+    /// manifests.
+    pub fn bits(self) -> u32 {
+        match self {
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+        }
+    }
+
+    /// Returns the number of bytes of a cell.
+    pub fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// Returns the width with the given number of bits, if it is supported.
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        CellWidth::ALL
+            .into_iter()
+            .find(|width| width.bits() == bits)
+    }
+}
+
+impl FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .ok()
+            .and_then(CellWidth::from_bits)
+            .ok_or_else(|| "valid values are `8`, `16` and `32`".to_string())
+    }
+}
+
+/// The type of the cells of a tape, one for each [CellWidth].
+///
+/// Input and output stay byte oriented: a read byte is stored zero extended and only the lowest
+/// byte of a cell is written.
+pub trait Cell: Copy + Default + Eq + Hash + Debug + Display + Send + 'static {
+    /// The width of the type.
+    const WIDTH: CellWidth;
+
+    const ZERO: Self;
+
+    fn wrapping_add(self, other: Self) -> Self;
+
+    fn wrapping_sub(self, other: Self) -> Self;
+
+    fn wrapping_mul(self, other: Self) -> Self;
+
+    fn wrapping_neg(self) -> Self;
+
+    /// Returns `n` modulo 2 to the power of the width, e.g. the result of adding 1 `n` times.
+    fn truncate(n: u64) -> Self;
+
+    /// Returns the cell as unsigned number.
+    fn to_u64(self) -> u64;
+
+    /// Returns the cell holding `byte`.
+    fn from_byte(byte: u8) -> Self;
+
+    /// Returns the lowest byte of the cell.
+    fn low_byte(self) -> u8;
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty => $width:expr),*) => {$(
+        impl Cell for $ty {
+            const WIDTH: CellWidth = $width;
+            const ZERO: Self = 0;
+
+            fn wrapping_add(self, other: Self) -> Self {
+                <$ty>::wrapping_add(self, other)
+            }
+
+            fn wrapping_sub(self, other: Self) -> Self {
+                <$ty>::wrapping_sub(self, other)
+            }
+
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$ty>::wrapping_mul(self, other)
+            }
+
+            fn wrapping_neg(self) -> Self {
+                <$ty>::wrapping_neg(self)
+            }
+
+            fn truncate(n: u64) -> Self {
+                n as $ty
+            }
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_byte(byte: u8) -> Self {
+                byte as $ty
+            }
+
+            fn low_byte(self) -> u8 {
+                self as u8
+            }
+        }
+    )*};
+}
+
+impl_cell!(u8 => CellWidth::U8, u16 => CellWidth::U16, u32 => CellWidth::U32);
+
+/// The position outside of a [Tape] that the data pointer would move to, which is negative if
+/// it is before the first cell.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct OutOfBounds(pub isize);
+
+/// Number of cells in a line of the [Display] output of a [Tape].
+const CELLS_PER_LINE: usize = 16;
+
+/// Zero initialized cells that grow according to a [TapePolicy], the memory of a program in
+/// every execution environment.
+///
+/// The cells are accessed like a slice, and the tape is displayed as hex dump that leaves
+/// out lines of zero cells.
+///
+/// The cells are stored in `S`, which is a `Vec` owned by the tape unless the tape is created
+/// on memory of the caller with [from_cells](Tape::from_cells).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tape<C = u8, S = Vec<C>> {
+    cells: S,
+    policy: TapePolicy,
+    cell: PhantomData<C>,
+}
+
+/// The memory that stores the cells of a [Tape].
+///
+/// Besides a `Vec`, the cells can be a mutable slice of the caller, e.g. in shared memory or a
+/// memory mapped file. Other types can implement the trait to do the same.
+pub trait Cells<C>: AsRef<[C]> + AsMut<[C]> {
+    /// Grows the memory to `len` cells with the new cells set to zero, returning `false` if it
+    /// can not grow.
+    fn grow(&mut self, len: usize) -> bool {
+        let _ = len;
+        false
+    }
+}
+
+impl<C: Cell> Cells<C> for Vec<C> {
+    fn grow(&mut self, len: usize) -> bool {
+        self.resize(len, C::ZERO);
+        true
+    }
+}
+
+impl<C> Cells<C> for &mut [C] {}
+
+impl<C: Cell> Tape<C> {
+    /// Creates a tape of `size` zero cells.
+    pub fn new(size: usize, policy: TapePolicy) -> Self {
+        Self::from_cells(vec![C::ZERO; size], policy)
+    }
+}
+
+impl<C: Cell, S: Cells<C>> Tape<C, S> {
+    /// Creates a tape on `cells`, which keep their values. A tape on memory that can not
+    /// grow fails to move past its end even if it is growable.
+    pub fn from_cells(cells: S, policy: TapePolicy) -> Self {
+        Self {
+            cells,
+            policy,
+            cell: PhantomData,
+        }
+    }
+
+    /// Returns the memory of the tape, e.g. to get a borrowed slice back.
+    pub fn into_cells(self) -> S {
+        self.cells
+    }
+
+    pub fn policy(&self) -> TapePolicy {
+        self.policy
+    }
+
+    /// Returns the runs of consecutive cells that are not zero, each with the position of its
+    /// first cell.
+    pub fn non_zero_runs(&self) -> impl Iterator<Item = (usize, &[C])> + '_ {
+        let mut start = 0;
+        self.cells
+            .as_ref()
+            .split(|cell| *cell == C::ZERO)
+            .filter_map(move |run| {
+                let position = start;
+                start += run.len() + 1;
+                (!run.is_empty()).then_some((position, run))
+            })
+    }
+
+    /// Returns the position and the values of the cells that differ between `self` and
+    /// `other`, where cells past the end of the shorter tape are zero.
+    pub fn diff<'a, T: Cells<C>>(
+        &'a self,
+        other: &'a Tape<C, T>,
+    ) -> impl Iterator<Item = (usize, C, C)> + 'a {
+        let len = self.len().max(other.len());
+        (0..len).filter_map(|i| {
+            let old = self.get(i).copied().unwrap_or(C::ZERO);
+            let new = other.get(i).copied().unwrap_or(C::ZERO);
+            (old != new).then_some((i, old, new))
+        })
+    }
+
+    /// Returns the position `offset` cells away from `dp`, growing the tape if it is growable
+    /// and the position lies past its end.
+    ///
+    /// Fails if the position is outside of the tape and the tape can not grow.
+    pub(crate) fn offset(&mut self, dp: usize, offset: isize) -> Result<usize, OutOfBounds> {
+        let position = dp
+            .checked_add_signed(offset)
+            .ok_or(OutOfBounds(dp as isize + offset))?;
+        if position >= self.len() {
+            // Doubling keeps the number of reallocations logarithmic.
+            let size = (position + 1).max(self.len() * 2);
+            if self.policy != TapePolicy::Growable || !self.cells.grow(size) {
+                return Err(OutOfBounds(position as isize));
+            }
+        }
+        Ok(position)
+    }
+
+    /// Returns the first position from `dp` in steps of `stride` where the cell is zero.
+    ///
+    /// A growable tape grows if there is no such cell until its end, as the cells past the end
+    /// are zero. Fails if the data pointer moves outside of the tape before it finds a zero.
+    pub(crate) fn find_zero(&mut self, dp: usize, stride: isize) -> Result<usize, OutOfBounds> {
+        let cells = self.cells.as_ref();
+        let found = match stride {
+            1 => cells[dp..]
+                .iter()
+                .position(|cell| *cell == C::ZERO)
+                .map(|i| dp + i),
+            -1 => cells[..=dp].iter().rposition(|cell| *cell == C::ZERO),
+            _ if stride > 0 => (dp..cells.len())
+                .step_by(stride as usize)
+                .find(|i| cells[*i] == C::ZERO),
+            _ => (0..=dp)
+                .rev()
+                .step_by(stride.unsigned_abs())
+                .find(|i| cells[*i] == C::ZERO),
+        };
+
+        match found {
+            Some(position) => Ok(position),
+            None if stride > 0 => {
+                let stride = stride as usize;
+                let steps = (cells.len() - dp).div_ceil(stride);
+                self.offset(dp, (steps * stride) as isize)
+            }
+            None => {
+                let stride = stride.unsigned_abs();
+                let steps = dp / stride + 1;
+                Err(OutOfBounds(dp as isize - (steps * stride) as isize))
+            }
+        }
+    }
+}
+
+impl<C: Cell, S: Cells<C>> Display for Tape<C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = C::WIDTH.bytes() * 2;
+        let mut skipped = false;
+
+        for (line, cells) in self.chunks(CELLS_PER_LINE).enumerate() {
+            if cells.iter().all(|cell| *cell == C::ZERO) {
+                // Like `hexdump`, a star stands for any number of left out lines.
+                if !skipped {
+                    writeln!(f, "*")?;
+                    skipped = true;
+                }
+                continue;
+            }
+            skipped = false;
+
+            let cells: Vec<String> = cells
+                .iter()
+                .map(|cell| format!("{:0digits$x}", cell.to_u64()))
+                .collect();
+            writeln!(f, "{:>8x}: {}", line * CELLS_PER_LINE, cells.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, S: Cells<C>> Deref for Tape<C, S> {
+    type Target = [C];
+
+    fn deref(&self) -> &[C] {
+        self.cells.as_ref()
+    }
+}
+
+impl<C, S: Cells<C>> DerefMut for Tape<C, S> {
+    fn deref_mut(&mut self) -> &mut [C] {
+        self.cells.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cell, CellWidth, OutOfBounds, Tape, TapePolicy};
+
+    #[test]
+    fn test_cell_width() {
+        assert_eq!("16".parse(), Ok(CellWidth::U16));
+        assert!("64".parse::<CellWidth>().is_err());
+        assert_eq!(u16::truncate(70_000), 4464);
+        assert_eq!(u32::truncate(-1_i64 as u64), u32::MAX);
+        assert_eq!(0x1234_u16.low_byte(), 0x34);
+    }
+
+    #[test]
+    fn test_non_zero_runs() {
+        let mut tape = Tape::<u8>::new(8, TapePolicy::Fixed);
+        tape[1] = 1;
+        tape[2] = 2;
+        tape[5] = 3;
+        tape[7] = 4;
+
+        let runs: Vec<(usize, &[u8])> = tape.non_zero_runs().collect();
+        assert_eq!(runs, [(1, &[1, 2][..]), (5, &[3]), (7, &[4])]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = Tape::<u16>::new(4, TapePolicy::Growable);
+        let mut new = Tape::<u16>::new(6, TapePolicy::Growable);
+        old[0] = 1;
+        new[0] = 1;
+        old[2] = 7;
+        new[5] = 300;
+
+        let diff: Vec<(usize, u16, u16)> = old.diff(&new).collect();
+        assert_eq!(diff, [(2, 7, 0), (5, 0, 300)]);
+        assert_eq!(old.diff(&old).count(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut tape = Tape::<u16>::new(64, TapePolicy::Fixed);
+        tape[1] = 0xab;
+        tape[50] = 0x1234;
+
+        assert_eq!(
+            tape.to_string(),
+            "       0: 0000 00ab 0000 0000 0000 0000 0000 0000 \
+            0000 0000 0000 0000 0000 0000 0000 0000\n\
+            *\n      \
+            30: 0000 0000 1234 0000 0000 0000 0000 0000 \
+            0000 0000 0000 0000 0000 0000 0000 0000\n"
+        );
+    }
+
+    #[test]
+    fn test_growable_tape_grows() {
+        let mut tape = Tape::<u8>::new(4, TapePolicy::Growable);
+
+        assert_eq!(tape.offset(2, 1), Ok(3));
+        assert_eq!(tape.len(), 4);
+        assert_eq!(tape.offset(3, 10), Ok(13));
+        assert_eq!(tape.len(), 14);
+        assert_eq!(tape.offset(13, 1), Ok(14));
+        assert_eq!(tape.len(), 28);
+        assert!(tape.iter().all(|cell| *cell == 0));
+    }
+
+    #[test]
+    fn test_fixed_tape_does_not_grow() {
+        let mut tape = Tape::<u8>::new(4, TapePolicy::Fixed);
+
+        assert_eq!(tape.offset(3, 1), Err(OutOfBounds(4)));
+        assert_eq!(tape.offset(1, -3), Err(OutOfBounds(-2)));
+        assert_eq!(tape.find_zero(0, 1), Ok(0));
+        tape.fill(1);
+        assert_eq!(tape.find_zero(0, 3), Err(OutOfBounds(6)));
+        assert_eq!(tape.find_zero(3, -2), Err(OutOfBounds(-1)));
+    }
+
+    #[test]
+    fn test_find_zero() {
+        let mut tape = Tape::<u16>::new(8, TapePolicy::Fixed);
+        tape.copy_from_slice(&[1, 256, 0, 1, 1, 1, 1, 0]);
+
+        assert_eq!(tape.find_zero(0, 1), Ok(2));
+        assert_eq!(tape.find_zero(6, -1), Ok(2));
+        assert_eq!(tape.find_zero(1, 3), Ok(7));
+        assert_eq!(tape.find_zero(6, -2), Ok(2));
+    }
+
+    #[test]
+    fn test_find_zero_grows_tape() {
+        let mut tape = Tape::<u8>::new(4, TapePolicy::Growable);
+        tape.fill(1);
+
+        assert_eq!(tape.find_zero(1, 1), Ok(4));
+        assert_eq!(tape.find_zero(1, 2), Ok(5));
+        assert_eq!(tape.len(), 8);
+    }
+
+    #[test]
+    fn test_tape_on_borrowed_cells() {
+        let mut cells = [1u8, 0, 2];
+        let mut tape = Tape::from_cells(&mut cells[..], TapePolicy::Growable);
+
+        assert_eq!(tape.find_zero(0, 1), Ok(1));
+        assert_eq!(tape.offset(2, 1), Err(OutOfBounds(3)));
+        tape[1] = 3;
+        assert_eq!(cells, [1, 3, 2]);
+    }
+}
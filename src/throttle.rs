@@ -0,0 +1,76 @@
+//! Slows an execution down to a number of instructions per second, e.g. to follow a program in
+//! slow motion during a demo.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of batches per second the instructions are executed in, so a throttled execution
+/// does not sleep after every instruction.
+const BATCHES_PER_SECOND: u64 = 100;
+
+/// Sleeps between batches of instructions so they are executed at a given speed on average.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// Instructions per second.
+    speed: u64,
+    start: Option<Instant>,
+    executed: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(speed: u64) -> Self {
+        Self {
+            speed: speed.max(1),
+            start: None,
+            executed: 0,
+        }
+    }
+
+    /// Returns the number of instructions to execute before calling [wait](Throttle::wait).
+    pub(crate) fn batch(&self) -> u64 {
+        (self.speed / BATCHES_PER_SECOND).max(1)
+    }
+
+    /// Called after `n` instructions have been executed, sleeps until they are due.
+    pub(crate) fn wait(&mut self, n: u64) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        self.executed += n;
+        let due = start + self.due(self.executed);
+        if let Some(ahead) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(ahead);
+        }
+    }
+
+    /// Returns when `executed` instructions are due after the start.
+    fn due(&self, executed: u64) -> Duration {
+        Duration::from_secs(executed / self.speed)
+            + Duration::from_nanos((executed % self.speed) * 1_000_000_000 / self.speed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Throttle;
+
+    #[test]
+    fn test_batch() {
+        assert_eq!(Throttle::new(10).batch(), 1);
+        assert_eq!(Throttle::new(0).batch(), 1);
+        assert_eq!(Throttle::new(1_000_000).batch(), 10_000);
+    }
+
+    #[test]
+    fn test_wait() {
+        let mut throttle = Throttle::new(200);
+        assert_eq!(throttle.due(300), Duration::from_millis(1500));
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            throttle.wait(throttle.batch());
+        }
+        // Every batch of 2 instructions takes 10 ms.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,292 @@
+//! Wall and CPU time spent in the phases of preparing and executing a program, so a slow
+//! execution can be told apart from slow input or output, like a terminal that renders slowly.
+//!
+//! The CPU time is the time of the measuring thread, which does not depend on other processes
+//! and does not include the time the thread waits, e.g. for a pipe to accept more output.
+
+use std::fmt::{self, Display};
+use std::io::{self, Read, Write};
+use std::ops::AddAssign;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A phase of preparing and executing a program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Parsing the source into instructions.
+    Compile,
+
+    /// Lowering loops to faster instructions.
+    Optimize,
+
+    /// Emitting machine code.
+    Codegen,
+
+    /// Executing instructions, without waiting for input or output.
+    Execute,
+
+    /// Reading input and writing and flushing output.
+    IoWait,
+}
+
+impl Phase {
+    /// All phases, in the order they happen.
+    pub const ALL: [Phase; 5] = [
+        Phase::Compile,
+        Phase::Optimize,
+        Phase::Codegen,
+        Phase::Execute,
+        Phase::IoWait,
+    ];
+
+    /// Returns the name of the phase as shown by the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Compile => "compile",
+            Phase::Optimize => "optimize",
+            Phase::Codegen => "codegen",
+            Phase::Execute => "execute",
+            Phase::IoWait => "io wait",
+        }
+    }
+}
+
+/// The time spent in a phase.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PhaseTime {
+    /// Time that passed on the clock on the wall.
+    pub wall: Duration,
+
+    /// Time the thread spent on the CPU, which is zero on platforms without a clock for it.
+    pub cpu: Duration,
+}
+
+impl PhaseTime {
+    /// Returns the time without `other`, or zero if `other` is longer.
+    pub fn saturating_sub(self, other: PhaseTime) -> PhaseTime {
+        PhaseTime {
+            wall: self.wall.saturating_sub(other.wall),
+            cpu: self.cpu.saturating_sub(other.cpu),
+        }
+    }
+}
+
+impl AddAssign for PhaseTime {
+    fn add_assign(&mut self, other: Self) {
+        self.wall += other.wall;
+        self.cpu += other.cpu;
+    }
+}
+
+/// The time spent in each [Phase].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PhaseTimes {
+    times: [PhaseTime; Phase::ALL.len()],
+}
+
+impl PhaseTimes {
+    /// Returns the time spent in `phase`.
+    pub fn get(&self, phase: Phase) -> PhaseTime {
+        self.times[phase as usize]
+    }
+
+    /// Adds `time` to the time spent in `phase`.
+    pub fn add(&mut self, phase: Phase, time: PhaseTime) {
+        self.times[phase as usize] += time;
+    }
+
+    /// Adds the times of all phases of `other`, e.g. of the compiler to those of an execution.
+    pub fn merge(&mut self, other: &PhaseTimes) {
+        for phase in Phase::ALL {
+            self.add(phase, other.get(phase));
+        }
+    }
+
+    /// Calls `f` and adds the time it took to `phase`.
+    pub fn measure<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let stopwatch = Stopwatch::start();
+        let result = f();
+        self.add(phase, stopwatch.elapsed());
+        result
+    }
+}
+
+impl Display for PhaseTimes {
+    /// Lists the phases that took any time, e.g. `compile 1.2ms, execute 40.1ms`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phases: Vec<String> = Phase::ALL
+            .into_iter()
+            .filter(|phase| !self.get(*phase).wall.is_zero())
+            .map(|phase| format!("{} {:.1?}", phase.name(), self.get(phase).wall))
+            .collect();
+        write!(f, "{}", phases.join(", "))
+    }
+}
+
+/// Measures the wall and CPU time since it started.
+#[derive(Debug, Copy, Clone)]
+pub struct Stopwatch {
+    wall: Instant,
+    cpu: Duration,
+}
+
+impl Stopwatch {
+    /// Starts measuring on the current thread.
+    pub fn start() -> Self {
+        Self {
+            wall: Instant::now(),
+            cpu: sys::thread_cpu_time(),
+        }
+    }
+
+    /// Returns the time since the stopwatch started.
+    pub fn elapsed(&self) -> PhaseTime {
+        PhaseTime {
+            wall: self.wall.elapsed(),
+            cpu: sys::thread_cpu_time().saturating_sub(self.cpu),
+        }
+    }
+}
+
+/// A reader or writer that adds the time spent in it to the [IoWait](Phase::IoWait) of shared
+/// times, e.g. to measure the input and output of an execution environment from outside.
+#[derive(Debug)]
+pub struct Timed<T> {
+    inner: T,
+    times: Arc<Mutex<PhaseTimes>>,
+}
+
+impl<T> Timed<T> {
+    /// Wraps `inner`, adding the time of every call to `times`.
+    pub fn new(inner: T, times: Arc<Mutex<PhaseTimes>>) -> Self {
+        Self { inner, times }
+    }
+
+    fn measure<U>(&mut self, f: impl FnOnce(&mut T) -> U) -> U {
+        let stopwatch = Stopwatch::start();
+        let result = f(&mut self.inner);
+        let elapsed = stopwatch.elapsed();
+        self.times.lock().unwrap().add(Phase::IoWait, elapsed);
+        result
+    }
+}
+
+impl<T: Read> Read for Timed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.measure(|inner| inner.read(buf))
+    }
+}
+
+impl<T: Write> Write for Timed<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.measure(|inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.measure(|inner| inner.flush())
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::time::Duration;
+
+    /// Returns the CPU time of the current thread.
+    pub fn thread_cpu_time() -> Duration {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: This call is according to the documentation, and the clock always exists.
+        if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } != 0 {
+            return Duration::ZERO;
+        }
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+    use std::time::Duration;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn GetThreadTimes(
+            thread: *mut c_void,
+            creation_time: *mut u64,
+            exit_time: *mut u64,
+            kernel_time: *mut u64,
+            user_time: *mut u64,
+        ) -> i32;
+    }
+
+    /// Returns the CPU time of the current thread, in kernel and user mode.
+    pub fn thread_cpu_time() -> Duration {
+        let (mut creation, mut exit, mut kernel, mut user) = (0, 0, 0, 0);
+        // SAFETY: This call is according to the documentation, the times are `FILETIME`s
+        // which have the layout of a `u64`.
+        if unsafe {
+            GetThreadTimes(
+                GetCurrentThread(),
+                &mut creation,
+                &mut exit,
+                &mut kernel,
+                &mut user,
+            )
+        } == 0
+        {
+            return Duration::ZERO;
+        }
+        // The times are in units of 100 nanoseconds.
+        Duration::from_nanos((kernel + user) * 100)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod sys {
+    use std::time::Duration;
+
+    pub fn thread_cpu_time() -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Phase, PhaseTime, PhaseTimes, Timed};
+
+    #[test]
+    fn test_measure() {
+        let mut times = PhaseTimes::default();
+        times.measure(Phase::Execute, || {
+            (0..1_000_000u64).map(|i| i % 7).sum::<u64>()
+        });
+        times.measure(Phase::IoWait, || thread::sleep(Duration::from_millis(20)));
+
+        let execute = times.get(Phase::Execute);
+        assert!(execute.wall > Duration::ZERO);
+        let io_wait = times.get(Phase::IoWait);
+        assert!(io_wait.wall >= Duration::from_millis(20));
+        // Sleeping does not use the CPU.
+        assert!(io_wait.cpu < Duration::from_millis(10), "{io_wait:?}");
+        assert_eq!(times.get(Phase::Compile), PhaseTime::default());
+    }
+
+    #[test]
+    fn test_timed_writer() {
+        let times = Arc::new(Mutex::new(PhaseTimes::default()));
+        let mut writer = Timed::new(io::sink(), Arc::clone(&times));
+        writer.write_all(b"Hello").unwrap();
+
+        let times = *times.lock().unwrap();
+        assert!(times.get(Phase::IoWait).wall > Duration::ZERO);
+        assert_eq!(times.to_string().split(',').count(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Splits a program into tokens without dropping anything, so tools like the formatter can
+//! reproduce comments and annotations while the execution environments only look at
+//! instructions.
+
+use crate::extensions;
+use crate::syntax::IDENTS;
+
+/// Prefix of comments that are read by tools, e.g. `;; name cell 3 counter`.
+pub const DIRECTIVE_PREFIX: &str = ";;";
+
+/// The words a directive starts with after its [DIRECTIVE_PREFIX]. Two semicolons followed by
+/// anything else are an ordinary comment, so instructions after them still run.
+pub const DIRECTIVES: [&str; 5] = ["name", "include", "assert", "expect", "extension"];
+
+/// A part of the source of a program.
+///
+/// Concatenating the text of all tokens of a program results in the original source.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+
+    /// The source of the token.
+    pub text: &'a str,
+
+    /// Byte offset of the first character in the source.
+    pub offset: usize,
+
+    /// Line of the first character, starting at 1.
+    pub line: usize,
+
+    /// Column of the first character in characters, starting at 1.
+    pub column: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A single brainfuck instruction.
+    Instruction,
+
+    /// A comment starting with [DIRECTIVE_PREFIX] and one of the [DIRECTIVES] up to the end of
+    /// the line, which is ignored by the execution environments even if it contains
+    /// instructions.
+    Directive,
+
+    /// Any other text, including whitespace and line breaks.
+    Comment,
+}
+
+/// Returns the tokens of `source` in order.
+///
+/// The characters of [extensions](crate::extensions) are instructions after the directive that
+/// enables them.
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let (mut line, mut column) = (1, 1);
+    let mut rest = source;
+    // The characters of the extensions enabled so far.
+    let mut enabled = Vec::new();
+    let is_instruction = |c: char, enabled: &[u8]| {
+        c.is_ascii() && (IDENTS.contains(&(c as u8)) || enabled.contains(&(c as u8)))
+    };
+
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = if is_instruction(c, &enabled) {
+            (TokenKind::Instruction, 1)
+        } else if is_directive(rest) {
+            (TokenKind::Directive, rest.find('\n').unwrap_or(rest.len()))
+        } else {
+            let len = rest
+                .char_indices()
+                .find(|(i, c)| {
+                    is_instruction(*c, &enabled) || (*i > 0 && is_directive(&rest[*i..]))
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (TokenKind::Comment, len)
+        };
+
+        let (text, remaining) = rest.split_at(len);
+        if kind == TokenKind::Directive {
+            if let Some(ident) = extensions::enabled_by(text).and_then(extensions::ident) {
+                enabled.push(ident);
+            }
+        }
+        tokens.push(Token {
+            kind,
+            text,
+            offset: source.len() - rest.len(),
+            line,
+            column,
+        });
+
+        for c in text.chars() {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        rest = remaining;
+    }
+
+    tokens
+}
+
+/// Returns whether `text` starts with a directive, like `;; name` or `;;include "file"`.
+fn is_directive(text: &str) -> bool {
+    let Some(directive) = text.strip_prefix(DIRECTIVE_PREFIX) else {
+        return false;
+    };
+    let directive = directive.trim_start_matches([' ', '\t']);
+    DIRECTIVES.iter().any(|word| {
+        directive
+            .strip_prefix(word)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '\n', '\r', '"']))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, TokenKind};
+
+    #[test]
+    fn test_tokens_preserve_source() {
+        let source = "+ add one\n;; name cell 0 x-y\n>[-]";
+        let tokens = tokenize(source);
+
+        assert_eq!(tokens.iter().map(|t| t.text).collect::<String>(), source);
+        assert_eq!(
+            tokens
+                .iter()
+                .filter(|t| t.kind == TokenKind::Instruction)
+                .map(|t| t.text)
+                .collect::<String>(),
+            "+>[-]"
+        );
+    }
+
+    #[test]
+    fn test_extension_instructions() {
+        let instructions = |source| {
+            tokenize(source)
+                .iter()
+                .filter(|t| t.kind == TokenKind::Instruction)
+                .map(|t| t.text)
+                .collect::<String>()
+        };
+
+        assert_eq!(instructions("Hi!\n+!"), "+");
+        assert_eq!(
+            instructions("Hi!\n;; extension exit\n+!;; name cell 0 !\n!"),
+            "+!!"
+        );
+        assert_eq!(
+            instructions("50%!\n;; extension env\n%!,\n;; extension exit\n%!"),
+            "%,%!"
+        );
+    }
+
+    #[test]
+    fn test_semicolons_without_directive() {
+        let source = format!("x;; {}.\n;;include\"a.b\"\n;;names +", "+".repeat(65));
+        let tokens = tokenize(&source);
+
+        assert_eq!(
+            tokens
+                .iter()
+                .filter(|t| t.kind == TokenKind::Instruction)
+                .count(),
+            67
+        );
+        let directives: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::Directive)
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(directives, [";;include\"a.b\""]);
+    }
+
+    #[test]
+    fn test_directive_positions() {
+        let tokens = tokenize("+\n  ;; name cell 1 a\n");
+        let directive = tokens
+            .iter()
+            .find(|t| t.kind == TokenKind::Directive)
+            .unwrap();
+
+        assert_eq!(directive.text, ";; name cell 1 a");
+        assert_eq!((directive.line, directive.column), (2, 3));
+        assert_eq!(directive.offset, 4);
+    }
+}
@@ -0,0 +1,352 @@
+//! Minimal TOML parsing for project manifests.
+//!
+//! Supported are tables, arrays of tables, bare and quoted keys, basic and literal strings,
+//! integers, booleans and arrays. Dotted keys, floats, dates, inline tables and multi-line
+//! strings are not.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// Returns the name of the type of the value as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+            Value::Table(_) => "a table",
+        }
+    }
+}
+
+/// A syntax error, with the line it occurred on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses `source` into its root table.
+pub fn parse(source: &str) -> Result<Table, ParseError> {
+    let mut parser = Parser {
+        chars: source.chars().peekable(),
+        line: 1,
+    };
+    let mut root = Table::new();
+    // The header of the table the following keys belong to, with whether it is an array of
+    // tables.
+    let mut current: Option<(String, bool)> = None;
+
+    loop {
+        parser.skip_trivia();
+        match parser.chars.peek() {
+            None => return Ok(root),
+            Some('[') => {
+                parser.chars.next();
+                let is_array = parser.chars.next_if_eq(&'[').is_some();
+                parser.skip_whitespace();
+                let name = parser.key()?;
+                parser.skip_whitespace();
+                parser.expect(']')?;
+                if is_array {
+                    parser.expect(']')?;
+                }
+
+                match (root.get_mut(&name), is_array) {
+                    (None, false) => {
+                        root.insert(name.clone(), Value::Table(Table::new()));
+                    }
+                    (None, true) => {
+                        root.insert(name.clone(), Value::Array(vec![Value::Table(Table::new())]));
+                    }
+                    (Some(Value::Array(tables)), true) => tables.push(Value::Table(Table::new())),
+                    _ => return Err(parser.error(format!("table `{name}` is defined twice"))),
+                }
+                current = Some((name, is_array));
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_whitespace();
+                parser.expect('=')?;
+                parser.skip_whitespace();
+                let value = parser.value()?;
+
+                let table = match &current {
+                    None => &mut root,
+                    Some((name, is_array)) => match (root.get_mut(name), is_array) {
+                        (Some(Value::Table(table)), false) => table,
+                        (Some(Value::Array(tables)), true) => match tables.last_mut() {
+                            Some(Value::Table(table)) => table,
+                            _ => unreachable!(),
+                        },
+                        _ => unreachable!(),
+                    },
+                };
+                if table.contains_key(&key) {
+                    return Err(parser.error(format!("key `{key}` is defined twice")));
+                }
+                table.insert(key, value);
+            }
+        }
+
+        parser.skip_whitespace();
+        parser.skip_comment();
+        match parser.chars.next() {
+            None => return Ok(root),
+            Some('\n') => parser.line += 1,
+            Some(c) => return Err(parser.error(format!("expected a new line but found `{c}`"))),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    /// Skips spaces, tabs and carriage returns.
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|c| matches!(c, ' ' | '\t' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn skip_comment(&mut self) {
+        if self.chars.peek() == Some(&'#') {
+            while self.chars.next_if(|c| *c != '\n').is_some() {}
+        }
+    }
+
+    /// Skips whitespace, comments and line breaks.
+    fn skip_trivia(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+            match self.chars.peek() {
+                Some('\n') => self.line += 1,
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected `{expected}` but found `{c}`"))),
+            None => Err(self.error(format!("expected `{expected}`"))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.chars.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+                {
+                    key.push(c);
+                }
+                if key.is_empty() {
+                    return Err(self.error("expected a key".to_string()));
+                }
+                Ok(key)
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.chars.peek().copied() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.chars.next();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_trivia();
+                    if self.chars.next_if_eq(&']').is_some() {
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_trivia();
+                    if self.chars.next_if_eq(&',').is_none() {
+                        self.skip_trivia();
+                        self.expect(']')?;
+                        return Ok(Value::Array(values));
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_alphanumeric() || matches!(c, '+' | '-') => {
+                let mut word = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '_' | '.'))
+                {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .map(Value::Integer)
+                        .map_err(|_| self.error(format!("invalid value `{word}`"))),
+                }
+            }
+            Some(c) => Err(self.error(format!("unexpected `{c}`"))),
+            None => Err(self.error("expected a value".to_string())),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error(format!("invalid escape `\\u{hex}`")))?
+                        }
+                        Some(c) => return Err(self.error(format!("invalid escape `\\{c}`"))),
+                        None => return Err(self.error("unterminated string".to_string())),
+                    };
+                    string.push(c);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.expect('\'')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(string),
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Table, Value};
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_tables_and_values() {
+        let root = parse(
+            r#"
+# A comment.
+title = 'root'
+
+[package]
+name = "hello" # trailing comment
+"cell-width" = 8
+strict = false
+targets = [
+    "c",
+    "rust",
+]
+
+[[test]]
+output = "a\tb\né"
+
+[[test]]
+output = ""
+"#,
+        )
+        .unwrap();
+
+        let mut package = Table::new();
+        package.insert("name".to_string(), string("hello"));
+        package.insert("cell-width".to_string(), Value::Integer(8));
+        package.insert("strict".to_string(), Value::Boolean(false));
+        package.insert(
+            "targets".to_string(),
+            Value::Array(vec![string("c"), string("rust")]),
+        );
+
+        assert_eq!(root["title"], string("root"));
+        assert_eq!(root["package"], Value::Table(package));
+        match &root["test"] {
+            Value::Array(tests) => {
+                assert_eq!(tests.len(), 2);
+                assert_eq!(
+                    tests[0],
+                    Value::Table(Table::from([("output".to_string(), string("a\tb\né"))]))
+                );
+            }
+            value => panic!("unexpected value {value:?}"),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| parse(source).unwrap_err().to_string();
+
+        assert_eq!(error("a = 1\na = 2"), "line 2: key `a` is defined twice");
+        assert_eq!(error("[a]\n[a]"), "line 2: table `a` is defined twice");
+        assert_eq!(error("a = \"open"), "line 1: unterminated string");
+        assert_eq!(
+            error("a = 1 2"),
+            "line 1: expected a new line but found `2`"
+        );
+        assert_eq!(error("\n\na = 1.5"), "line 3: invalid value `1.5`");
+    }
+}
@@ -0,0 +1,850 @@
+//! Translates compiled programs to the source of other languages, so they can be built with
+//! the compiler of that language on platforms without the
+//! [JIT compiler](crate::jit::JitCompiler), or embedded in projects written in it.
+//!
+//! The translations keep the optimized instructions, like `SetZero` and `AddTo`, and behave
+//! like the [virtual machine](crate::virtual_machine::VirtualMachine) with its defaults: a
+//! fixed tape of [DEFAULT_TAPE_SIZE] 8-bit cells, and a failure when the data pointer leaves
+//! it or the input ends.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::compiler::{Instruction, Program};
+use crate::DEFAULT_TAPE_SIZE;
+
+/// A language programs can be translated to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Language {
+    C,
+    Rust,
+    #[cfg(feature = "llvm")]
+    Llvm,
+}
+
+impl Language {
+    /// All languages.
+    pub const ALL: &'static [Language] = &[
+        Language::C,
+        Language::Rust,
+        #[cfg(feature = "llvm")]
+        Language::Llvm,
+    ];
+
+    /// Returns the name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::C => "c",
+            Language::Rust => "rust",
+            #[cfg(feature = "llvm")]
+            Language::Llvm => "llvm",
+        }
+    }
+
+    /// Translates `program` to a self-contained source file of the language.
+    pub fn translate(self, program: &Program) -> String {
+        self.translate_with_lines(program).0
+    }
+
+    /// Like [translate](Language::translate), but also returns the line of the translation
+    /// the code of every instruction starts at, starting at 1, e.g. for a
+    /// [SourceMap](crate::source_map::SourceMap).
+    pub fn translate_with_lines(self, program: &Program) -> (String, Vec<usize>) {
+        match self {
+            Language::C => translate_c(program),
+            Language::Rust => translate_rust(program),
+            #[cfg(feature = "llvm")]
+            Language::Llvm => translate_llvm_ir(program),
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Language::ALL
+                    .iter()
+                    .map(|language| format!("`{}`", language.name()))
+                    .collect();
+                let (last, names) = names.split_last().expect("there are languages");
+                format!(
+                    "unknown language `{s}`, valid values are {} and {last}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// The functions of the C runtime, in the order they are defined. Only the ones a program
+/// calls are emitted, so compilers do not warn about unused functions.
+const C_HELPERS: [(&str, &str); 6] = [
+    (
+        "out_of_bounds",
+        "static void out_of_bounds(long ip, long cell) {
+    fflush(stdout);
+    fprintf(stderr, \"instruction %ld moved the data pointer outside of the tape to cell %ld\\n\",
+            ip, cell);
+    exit(1);
+}
+",
+    ),
+    (
+        "move_dp",
+        "static void move_dp(long ip, long n) {
+    if (dp + n < 0 || dp + n >= TAPE_SIZE) {
+        out_of_bounds(ip, dp + n);
+    }
+    dp += n;
+}
+",
+    ),
+    (
+        "add_to",
+        "static void add_to(long ip, long offset, unsigned char factor) {
+    if (tape[dp] != 0) {
+        if (dp + offset < 0 || dp + offset >= TAPE_SIZE) {
+            out_of_bounds(ip, dp + offset);
+        }
+        tape[dp + offset] += tape[dp] * factor;
+    }
+}
+",
+    ),
+    (
+        "write_byte",
+        "static void write_byte(long count) {
+    for (long i = 0; i < count; i++) {
+        putchar(tape[dp]);
+    }
+}
+",
+    ),
+    (
+        "read_byte",
+        "static void read_byte(void) {
+    int c;
+    /* Show a prompt before waiting for the input. */
+    fflush(stdout);
+    c = getchar();
+    if (c == EOF) {
+        fprintf(stderr, \"the input ended\\n\");
+        exit(1);
+    }
+    tape[dp] = (unsigned char)c;
+}
+",
+    ),
+    (
+        "select_input",
+        "static void select_input(void) {
+    if (tape[dp] != 0) {
+        fflush(stdout);
+        fprintf(stderr, \"the program selected the unknown host input %d\\n\", tape[dp]);
+        exit(1);
+    }
+}
+",
+    ),
+];
+
+/// Returns the statements of `program` in the body of `main`, one per line and indented by the
+/// depth of their loop, where `statements` returns the lines of the instruction at an index.
+/// The lines of a loop are indented after its `JumpZero` up to its `JumpNotZero`.
+///
+/// Also returns the line of the body every instruction starts at, starting at 0.
+fn body(
+    program: &Program,
+    mut statements: impl FnMut(usize, Instruction) -> Vec<String>,
+) -> (String, Vec<usize>) {
+    let mut body = String::new();
+    let mut lines = Vec::with_capacity(program.len());
+    let mut line = 0;
+    let mut depth = 1;
+
+    for (ip, instruction) in program.iter().enumerate() {
+        if let Instruction::JumpNotZero(_) = instruction {
+            depth -= 1;
+        }
+        lines.push(line);
+        for statement in statements(ip, *instruction) {
+            writeln!(body, "{}{statement}", "    ".repeat(depth)).unwrap();
+            line += 1;
+        }
+        if let Instruction::JumpZero(_) = instruction {
+            depth += 1;
+        }
+    }
+    (body, lines)
+}
+
+/// Returns the lines of a body that starts after `prefix`, starting at 1.
+fn lines_after(prefix: &str, lines: Vec<usize>) -> Vec<usize> {
+    let start = prefix.matches('\n').count() + 1;
+    lines.into_iter().map(|line| start + line).collect()
+}
+
+/// Translates `program` to a C file that only needs the standard library, e.g. to build it
+/// with `cc -O2 program.c`.
+///
+/// Loops become `while` loops and every other instruction a statement or a call of a small
+/// function. The program has no [host inputs](crate::host::HostInputs), `%` only selects the
+/// regular input.
+pub fn to_c(program: &Program) -> String {
+    translate_c(program).0
+}
+
+fn translate_c(program: &Program) -> (String, Vec<usize>) {
+    let mut used = [false; C_HELPERS.len()];
+    let (body, lines) = body(program, |ip, instruction| {
+        let (statements, helper) = match instruction {
+            Instruction::IncDP(n) => (vec![format!("move_dp({ip}, {n});")], Some("move_dp")),
+            Instruction::DecDP(n) => (vec![format!("move_dp({ip}, -{n});")], Some("move_dp")),
+            Instruction::IncByteAtDP(n) => (vec![format!("tape[dp] += {};", n % 256)], None),
+            Instruction::DecByteAtDP(n) => (vec![format!("tape[dp] -= {};", n % 256)], None),
+            Instruction::WriteByte(1) => (vec!["putchar(tape[dp]);".to_string()], None),
+            Instruction::WriteByte(n) => (vec![format!("write_byte({n});")], Some("write_byte")),
+            Instruction::ReadByte => (vec!["read_byte();".to_string()], Some("read_byte")),
+            Instruction::SetZero => (vec!["tape[dp] = 0;".to_string()], None),
+            Instruction::FindZero(stride) => (
+                vec![
+                    "while (tape[dp] != 0) {".to_string(),
+                    format!("    move_dp({ip}, {stride});"),
+                    "}".to_string(),
+                ],
+                Some("move_dp"),
+            ),
+            Instruction::AddTo { offset, factor } => (
+                vec![format!(
+                    "add_to({ip}, {offset}, {});",
+                    factor.rem_euclid(256)
+                )],
+                Some("add_to"),
+            ),
+            Instruction::JumpZero(_) => (vec!["while (tape[dp] != 0) {".to_string()], None),
+            Instruction::JumpNotZero(_) => (vec!["}".to_string()], None),
+            Instruction::Exit => (
+                vec!["fflush(stdout);".to_string(), "exit(tape[dp]);".to_string()],
+                None,
+            ),
+            Instruction::SelectInput => (vec!["select_input();".to_string()], Some("select_input")),
+            Instruction::JumpZeroPlaceholder | Instruction::JumpNotZeroPlaceholder => {
+                unreachable!("the jumps of a program are resolved")
+            }
+        };
+        if let Some(helper) = helper {
+            let index = C_HELPERS.iter().position(|(name, _)| *name == helper);
+            used[index.unwrap()] = true;
+            // The functions that move the data pointer fail with `out_of_bounds`.
+            used[0] |= matches!(helper, "move_dp" | "add_to");
+        }
+        statements
+    });
+
+    let mut c = format!(
+        "/* Translated from brainfuck, build it with e.g. `cc -O2 program.c`. */\n\
+         #include <stdio.h>\n\
+         #include <stdlib.h>\n\
+         \n\
+         #define TAPE_SIZE {DEFAULT_TAPE_SIZE}\n\
+         \n\
+         static unsigned char tape[TAPE_SIZE];\n\
+         static long dp = 0;\n\
+         \n"
+    );
+    for ((_, helper), used) in C_HELPERS.iter().zip(used) {
+        if used {
+            c.push_str(helper);
+            c.push('\n');
+        }
+    }
+    c.push_str("int main(void) {\n");
+    let lines = lines_after(&c, lines);
+    write!(c, "{body}    return 0;\n}}\n").unwrap();
+    (c, lines)
+}
+
+/// The machine the Rust translation runs on, with a method for every instruction that is not a
+/// single expression.
+const RUST_MACHINE: &str = "struct Machine {
+    tape: [u8; TAPE_SIZE],
+    dp: usize,
+    input: io::Stdin,
+    output: BufWriter<io::Stdout>,
+}
+
+#[allow(dead_code)]
+impl Machine {
+    fn new() -> Self {
+        Self {
+            tape: [0; TAPE_SIZE],
+            dp: 0,
+            input: io::stdin(),
+            output: BufWriter::new(io::stdout()),
+        }
+    }
+
+    /// Writes the output and stops the program with `code`.
+    fn exit(&mut self, code: i32) -> ! {
+        if let Err(err) = self.output.flush() {
+            eprintln!(\"{err}\");
+            process::exit(1);
+        }
+        process::exit(code)
+    }
+
+    /// Writes the output and stops the program with `message`.
+    fn fail(&mut self, message: impl Display) -> ! {
+        self.output.flush().ok();
+        eprintln!(\"{message}\");
+        process::exit(1)
+    }
+
+    /// Returns the index of the cell at `offset` from the data pointer, failing if it is
+    /// outside of the tape.
+    fn target(&mut self, ip: usize, offset: isize) -> usize {
+        match self.dp.checked_add_signed(offset) {
+            Some(target) if target < TAPE_SIZE => target,
+            _ => self.fail(format!(
+                \"instruction {ip} moved the data pointer outside of the tape to cell {}\",
+                self.dp as isize + offset
+            )),
+        }
+    }
+
+    fn move_dp(&mut self, ip: usize, offset: isize) {
+        self.dp = self.target(ip, offset);
+    }
+
+    fn find_zero(&mut self, ip: usize, stride: isize) {
+        while self.tape[self.dp] != 0 {
+            self.move_dp(ip, stride);
+        }
+    }
+
+    fn add_to(&mut self, ip: usize, offset: isize, factor: u8) {
+        let value = self.tape[self.dp];
+        if value != 0 {
+            let target = self.target(ip, offset);
+            self.tape[target] = self.tape[target].wrapping_add(value.wrapping_mul(factor));
+        }
+    }
+
+    fn write(&mut self, count: usize) {
+        for _ in 0..count {
+            if let Err(err) = self.output.write_all(&[self.tape[self.dp]]) {
+                self.fail(err);
+            }
+        }
+    }
+
+    fn read(&mut self) {
+        // Show a prompt before waiting for the input.
+        if let Err(err) = self.output.flush() {
+            self.fail(err);
+        }
+        let mut byte = [0];
+        match self.input.read_exact(&mut byte) {
+            Ok(()) => self.tape[self.dp] = byte[0],
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => self.fail(\"the input ended\"),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    fn select_input(&mut self) {
+        let n = self.tape[self.dp];
+        if n != 0 {
+            self.fail(format!(\"the program selected the unknown host input {n}\"));
+        }
+    }
+}
+";
+
+/// Translates `program` to a `main.rs` that only needs the standard library, e.g. to build it
+/// with `rustc -O main.rs` or to add it as a binary to a Cargo project.
+///
+/// The tape is a `[u8; N]` array of a `Machine`, loops become `while` loops and every other
+/// instruction a statement or a call of a method of the machine. The program has no
+/// [host inputs](crate::host::HostInputs), `%` only selects the regular input.
+pub fn to_rust(program: &Program) -> String {
+    translate_rust(program).0
+}
+
+fn translate_rust(program: &Program) -> (String, Vec<usize>) {
+    let (body, lines) = body(program, |ip, instruction| {
+        vec![match instruction {
+            Instruction::IncDP(n) => format!("m.move_dp({ip}, {n});"),
+            Instruction::DecDP(n) => format!("m.move_dp({ip}, -{n});"),
+            Instruction::IncByteAtDP(n) => {
+                format!("m.tape[m.dp] = m.tape[m.dp].wrapping_add({});", n % 256)
+            }
+            Instruction::DecByteAtDP(n) => {
+                format!("m.tape[m.dp] = m.tape[m.dp].wrapping_sub({});", n % 256)
+            }
+            Instruction::WriteByte(n) => format!("m.write({n});"),
+            Instruction::ReadByte => "m.read();".to_string(),
+            Instruction::SetZero => "m.tape[m.dp] = 0;".to_string(),
+            Instruction::FindZero(stride) => format!("m.find_zero({ip}, {stride});"),
+            Instruction::AddTo { offset, factor } => {
+                format!("m.add_to({ip}, {offset}, {});", factor.rem_euclid(256))
+            }
+            Instruction::JumpZero(_) => "while m.tape[m.dp] != 0 {".to_string(),
+            Instruction::JumpNotZero(_) => "}".to_string(),
+            Instruction::Exit => "m.exit(m.tape[m.dp].into());".to_string(),
+            Instruction::SelectInput => "m.select_input();".to_string(),
+            Instruction::JumpZeroPlaceholder | Instruction::JumpNotZeroPlaceholder => {
+                unreachable!("the jumps of a program are resolved")
+            }
+        }]
+    });
+
+    let mut rust = format!(
+        "//! Translated from brainfuck, build it with e.g. `rustc -O main.rs`.\n\
+         \n\
+         use std::fmt::Display;\n\
+         use std::io::{{self, BufWriter, Read, Write}};\n\
+         use std::process;\n\
+         \n\
+         const TAPE_SIZE: usize = {DEFAULT_TAPE_SIZE};\n\
+         \n\
+         {RUST_MACHINE}\n\
+         fn main() {{\n    \
+             let mut m = Machine::new();\n"
+    );
+    let lines = lines_after(&rust, lines);
+    write!(rust, "{body}    m.exit(0);\n}}\n").unwrap();
+    (rust, lines)
+}
+
+/// The functions of the LLVM IR translation that fail or loop, which the optimizer inlines.
+/// They use the C library for the input and the output.
+#[cfg(feature = "llvm")]
+const LLVM_RUNTIME: &str = r#"@tape = internal global [TAPE_SIZE x i8] zeroinitializer
+@dp = internal global i64 0
+
+@out_of_bounds_message = private constant [74 x i8] c"instruction %lld moved the data pointer outside of the tape to cell %lld\0A\00"
+@input_ended_message = private constant [17 x i8] c"the input ended\0A\00"
+@unknown_input_message = private constant [48 x i8] c"the program selected the unknown host input %d\0A\00"
+
+declare i32 @putchar(i32)
+declare i32 @getchar()
+declare i32 @fflush(ptr)
+declare i32 @dprintf(i32, ptr, ...)
+declare void @exit(i32) noreturn
+
+define internal void @check(i64 %ip, i64 %cell) {
+entry:
+  %outside = icmp uge i64 %cell, TAPE_SIZE
+  br i1 %outside, label %fail, label %done
+fail:
+  call i32 @fflush(ptr null)
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @out_of_bounds_message, i64 %ip, i64 %cell)
+  call void @exit(i32 1)
+  unreachable
+done:
+  ret void
+}
+
+define internal void @write_byte(i8 %byte, i64 %count) {
+entry:
+  %char = zext i8 %byte to i32
+  br label %loop
+loop:
+  %i = phi i64 [ 0, %entry ], [ %next, %write ]
+  %done = icmp eq i64 %i, %count
+  br i1 %done, label %end, label %write
+write:
+  call i32 @putchar(i32 %char)
+  %next = add i64 %i, 1
+  br label %loop
+end:
+  ret void
+}
+
+define internal void @read_byte(ptr %cell) {
+entry:
+  ; Show a prompt before waiting for the input.
+  call i32 @fflush(ptr null)
+  %char = call i32 @getchar()
+  %ended = icmp slt i32 %char, 0
+  br i1 %ended, label %fail, label %done
+fail:
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @input_ended_message)
+  call void @exit(i32 1)
+  unreachable
+done:
+  %byte = trunc i32 %char to i8
+  store i8 %byte, ptr %cell
+  ret void
+}
+
+define internal void @select_input(i8 %input) {
+entry:
+  %regular = icmp eq i8 %input, 0
+  br i1 %regular, label %done, label %fail
+fail:
+  call i32 @fflush(ptr null)
+  %n = zext i8 %input to i32
+  call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @unknown_input_message, i32 %n)
+  call void @exit(i32 1)
+  unreachable
+done:
+  ret void
+}
+"#;
+
+/// Translates `program` to a textual LLVM IR module, e.g. to optimize it with `opt -O3` or to
+/// build it with `clang -O2 program.ll`. The module uses opaque pointers, so it needs
+/// LLVM 15 or newer, or `-opaque-pointers` before that.
+///
+/// The data pointer and the tape are globals. Every loop becomes a header block that checks
+/// the cell, a body and an exit block, and the arithmetic of the optimized instructions, like
+/// `SetZero` and `AddTo`, adds and stores directly in `main`. Moving the data pointer checks
+/// it with a small function that reports the instruction, like the virtual machine. The
+/// program has no [host inputs](crate::host::HostInputs), `%` only selects the regular input.
+#[cfg(feature = "llvm")]
+pub fn to_llvm_ir(program: &Program) -> String {
+    translate_llvm_ir(program).0
+}
+
+#[cfg(feature = "llvm")]
+fn translate_llvm_ir(program: &Program) -> (String, Vec<usize>) {
+    let tape = format!("[{DEFAULT_TAPE_SIZE} x i8]");
+    // Computes the address of the cell at the data pointer, with values named after the
+    // instruction, and loads it.
+    let address = |ip: usize| {
+        vec![
+            format!("%dp.{ip} = load i64, ptr @dp"),
+            format!("%cell.{ip} = getelementptr inbounds {tape}, ptr @tape, i64 0, i64 %dp.{ip}"),
+        ]
+    };
+    let cell = |ip: usize| {
+        let load = format!("%value.{ip} = load i8, ptr %cell.{ip}");
+        [address(ip), vec![load]].concat()
+    };
+    let mut loops = Vec::new();
+    let mut body = String::new();
+    let mut lines = Vec::with_capacity(program.len());
+    let mut line = 0;
+
+    for (ip, instruction) in program.iter().enumerate() {
+        let code = match *instruction {
+            Instruction::IncDP(n) | Instruction::DecDP(n) => {
+                let n = match instruction {
+                    Instruction::IncDP(_) => n as i64,
+                    _ => -(n as i64),
+                };
+                vec![
+                    format!("%dp.{ip} = load i64, ptr @dp"),
+                    format!("%target.{ip} = add i64 %dp.{ip}, {n}"),
+                    format!("call void @check(i64 {ip}, i64 %target.{ip})"),
+                    format!("store i64 %target.{ip}, ptr @dp"),
+                ]
+            }
+            Instruction::IncByteAtDP(n) | Instruction::DecByteAtDP(n) => {
+                let op = match instruction {
+                    Instruction::IncByteAtDP(_) => "add",
+                    _ => "sub",
+                };
+                [
+                    cell(ip),
+                    vec![
+                        format!("%result.{ip} = {op} i8 %value.{ip}, {}", n % 256),
+                        format!("store i8 %result.{ip}, ptr %cell.{ip}"),
+                    ],
+                ]
+                .concat()
+            }
+            Instruction::WriteByte(n) => [
+                cell(ip),
+                vec![format!("call void @write_byte(i8 %value.{ip}, i64 {n})")],
+            ]
+            .concat(),
+            Instruction::ReadByte => [
+                address(ip),
+                vec![format!("call void @read_byte(ptr %cell.{ip})")],
+            ]
+            .concat(),
+            Instruction::SetZero => {
+                [address(ip), vec![format!("store i8 0, ptr %cell.{ip}")]].concat()
+            }
+            Instruction::FindZero(stride) => [
+                vec![format!("br label %find.{ip}"), format!("find.{ip}:")],
+                cell(ip),
+                vec![
+                    format!("%zero.{ip} = icmp eq i8 %value.{ip}, 0"),
+                    format!("br i1 %zero.{ip}, label %found.{ip}, label %step.{ip}"),
+                    format!("step.{ip}:"),
+                    format!("%target.{ip} = add i64 %dp.{ip}, {stride}"),
+                    format!("call void @check(i64 {ip}, i64 %target.{ip})"),
+                    format!("store i64 %target.{ip}, ptr @dp"),
+                    format!("br label %find.{ip}"),
+                    format!("found.{ip}:"),
+                ],
+            ]
+            .concat(),
+            Instruction::AddTo { offset, factor } => [
+                cell(ip),
+                vec![
+                    format!("%zero.{ip} = icmp eq i8 %value.{ip}, 0"),
+                    format!("br i1 %zero.{ip}, label %added.{ip}, label %add.{ip}"),
+                    format!("add.{ip}:"),
+                    format!("%target.{ip} = add i64 %dp.{ip}, {offset}"),
+                    format!("call void @check(i64 {ip}, i64 %target.{ip})"),
+                    format!(
+                        "%to.{ip} = getelementptr inbounds {tape}, ptr @tape, i64 0, \
+                         i64 %target.{ip}"
+                    ),
+                    format!("%old.{ip} = load i8, ptr %to.{ip}"),
+                    format!(
+                        "%product.{ip} = mul i8 %value.{ip}, {}",
+                        factor.rem_euclid(256)
+                    ),
+                    format!("%sum.{ip} = add i8 %old.{ip}, %product.{ip}"),
+                    format!("store i8 %sum.{ip}, ptr %to.{ip}"),
+                    format!("br label %added.{ip}"),
+                    format!("added.{ip}:"),
+                ],
+            ]
+            .concat(),
+            Instruction::JumpZero(_) => {
+                loops.push(ip);
+                [
+                    vec![format!("br label %loop.{ip}"), format!("loop.{ip}:")],
+                    cell(ip),
+                    vec![
+                        format!("%zero.{ip} = icmp eq i8 %value.{ip}, 0"),
+                        format!("br i1 %zero.{ip}, label %end.{ip}, label %body.{ip}"),
+                        format!("body.{ip}:"),
+                    ],
+                ]
+                .concat()
+            }
+            Instruction::JumpNotZero(_) => {
+                let start = loops.pop().expect("the loops of a program are closed");
+                vec![format!("br label %loop.{start}"), format!("end.{start}:")]
+            }
+            Instruction::Exit => [
+                cell(ip),
+                vec![
+                    format!("%code.{ip} = zext i8 %value.{ip} to i32"),
+                    "call i32 @fflush(ptr null)".to_string(),
+                    format!("call void @exit(i32 %code.{ip})"),
+                    "unreachable".to_string(),
+                    // The instructions after it are unreachable, but still need a block.
+                    format!("exited.{ip}:"),
+                ],
+            ]
+            .concat(),
+            Instruction::SelectInput => [
+                cell(ip),
+                vec![format!("call void @select_input(i8 %value.{ip})")],
+            ]
+            .concat(),
+            Instruction::JumpZeroPlaceholder | Instruction::JumpNotZeroPlaceholder => {
+                unreachable!("the jumps of a program are resolved")
+            }
+        };
+        lines.push(line);
+        line += code.len();
+        for code in code {
+            // Labels are not indented.
+            let indent = if code.ends_with(':') { "" } else { "  " };
+            writeln!(body, "{indent}{code}").unwrap();
+        }
+    }
+
+    let mut ir = format!(
+        "; Translated from brainfuck, build it with e.g. `clang -O2 program.ll`.\n\
+         \n\
+         {}\n\
+         define i32 @main() {{\n\
+         entry:\n",
+        LLVM_RUNTIME.replace("TAPE_SIZE", &DEFAULT_TAPE_SIZE.to_string())
+    );
+    let lines = lines_after(&ir, lines);
+    write!(ir, "{body}  call i32 @fflush(ptr null)\n  ret i32 0\n}}\n").unwrap();
+    (ir, lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use super::*;
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn test_to_rust() {
+        let program = Compiler::new(",[->++<]>..[<]!").compile_program().unwrap();
+
+        assert!(to_rust(&program).contains(
+            "fn main() {\n    let mut m = Machine::new();\n    m.read();\n    \
+             m.add_to(1, 1, 2);\n    m.tape[m.dp] = 0;\n    m.move_dp(3, 1);\n    m.write(2);\n    \
+             m.find_zero(5, -1);\n    m.exit(0);\n}\n"
+        ));
+        assert!(to_rust(&Compiler::new("-[+]").compile_program().unwrap())
+            .contains("    m.tape[m.dp] = m.tape[m.dp].wrapping_sub(1);\n    m.tape[m.dp] = 0;\n"));
+    }
+
+    #[test]
+    fn test_to_c() {
+        let program = Compiler::new(",[->++<]>..[-]").compile_program().unwrap();
+        let c = to_c(&program);
+
+        assert!(c.contains(
+            "int main(void) {\n    read_byte();\n    add_to(1, 1, 2);\n    tape[dp] = 0;\n    \
+             move_dp(3, 1);\n    write_byte(2);\n    tape[dp] = 0;\n    return 0;\n}\n"
+        ));
+        // Only the functions the program calls are defined.
+        assert!(c.contains("static void out_of_bounds("));
+        assert!(!c.contains("static void select_input("));
+
+        let program = Compiler::new("+[-[>]<]").compile_program().unwrap();
+        assert!(to_c(&program).contains(
+            "    while (tape[dp] != 0) {\n        tape[dp] -= 1;\n        \
+             while (tape[dp] != 0) {\n            move_dp(3, 1);\n        }\n"
+        ));
+        assert_eq!("c".parse(), Ok(Language::C));
+        assert_eq!("rust".parse(), Ok(Language::Rust));
+        assert!("cobol".parse::<Language>().is_err());
+    }
+
+    #[test]
+    fn test_translate_with_lines() {
+        let program = Compiler::new(",[->++<]>.[-.]").compile_program().unwrap();
+
+        for &language in Language::ALL {
+            let (translated, lines) = language.translate_with_lines(&program);
+            assert!(lines.windows(2).all(|pair| pair[0] < pair[1]));
+            let lines: Vec<&str> = lines
+                .iter()
+                .map(|line| translated.lines().nth(line - 1).unwrap().trim())
+                .collect();
+
+            assert_eq!(lines.len(), program.len());
+            let expected: &[&str] = match language {
+                Language::C => &["read_byte();", "add_to(1, 1, 2);", "tape[dp] = 0;"],
+                Language::Rust => &["m.read();", "m.add_to(1, 1, 2);", "m.tape[m.dp] = 0;"],
+                #[cfg(feature = "llvm")]
+                Language::Llvm => &[
+                    "%dp.0 = load i64, ptr @dp",
+                    "%dp.1 = load i64, ptr @dp",
+                    "%dp.2 = load i64, ptr @dp",
+                ],
+            };
+            assert_eq!(lines[..3], *expected, "{}", language.name());
+        }
+    }
+
+    /// Builds the translation of a program that fails after some output to `language` with
+    /// `compiler`, and checks that it behaves like the virtual machine. Does nothing if the
+    /// compiler is not installed, like on some machines that run the tests.
+    fn check_build(language: Language, compiler: &str, args: &[&str]) {
+        if Command::new(compiler).arg("--version").output().is_err() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!(
+            "brainfuck-transpile-{}-{}",
+            language.name(),
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("program");
+        let binary = dir.join("binary");
+        let program = Compiler::new("++++++[>++++++++<-]>+.+.<+++[>>+++<<-]>>.<<<")
+            .compile_program()
+            .unwrap();
+        fs::write(&source, language.translate(&program)).unwrap();
+
+        let status = Command::new(compiler)
+            .args(args)
+            .arg("-o")
+            .args([&binary, &source])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(&binary).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"12\x09");
+        // The virtual machine reports the same instruction.
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "instruction 14 moved the data pointer outside of the tape to cell -1\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_c() {
+        check_build(Language::C, "cc", &["-Wall", "-Werror", "-x", "c"]);
+    }
+
+    #[test]
+    fn test_build_rust() {
+        check_build(Language::Rust, "rustc", &["-D", "warnings"]);
+    }
+
+    #[cfg(feature = "llvm")]
+    #[test]
+    fn test_to_llvm_ir() {
+        let program = Compiler::new(",[->++<]>.[-.]").compile_program().unwrap();
+        let ir = to_llvm_ir(&program);
+
+        assert!(ir.contains("@tape = internal global [30000 x i8] zeroinitializer\n"));
+        assert!(ir.contains("  call void @read_byte(ptr %cell.0)\n  %dp.1 = load i64, ptr @dp\n"));
+        // `AddTo` adds and stores in `main`, after checking the target cell.
+        assert!(ir.contains(
+            "  %target.1 = add i64 %dp.1, 1\n  \
+             call void @check(i64 1, i64 %target.1)\n"
+        ));
+        assert!(ir.contains("  %product.1 = mul i8 %value.1, 2\n"));
+        assert!(ir.contains("  call void @write_byte(i8 %value.4, i64 1)\n"));
+        // Loops check the cell in a block of their own and jump back to it.
+        assert!(ir.contains(
+            "  br label %loop.5\n\
+             loop.5:\n"
+        ));
+        assert!(ir.contains("  br i1 %zero.5, label %end.5, label %body.5\nbody.5:\n"));
+        assert!(ir.contains("  br label %loop.5\nend.5:\n"));
+        assert_eq!("llvm".parse(), Ok(Language::Llvm));
+    }
+
+    /// Runs the LLVM IR of a program that fails after some output with `lli`, if it is
+    /// installed, and checks that it behaves like the virtual machine.
+    #[cfg(feature = "llvm")]
+    #[test]
+    fn test_run_llvm_ir() {
+        let Ok(version) = Command::new("lli").arg("--version").output() else {
+            return;
+        };
+        let dir =
+            std::env::temp_dir().join(format!("brainfuck-transpile-llvm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("program.ll");
+        let program = Compiler::new("++++++[>++++++++<-]>+.+.<+++[>>+++<<-]>>.<<<")
+            .compile_program()
+            .unwrap();
+        fs::write(&file, to_llvm_ir(&program)).unwrap();
+
+        let mut lli = Command::new("lli");
+        // Opaque pointers are the default since LLVM 15.
+        if String::from_utf8_lossy(&version.stdout).contains("version 14.") {
+            lli.arg("-opaque-pointers");
+        }
+        let output = lli.arg(&file).output().unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stdout, b"12\x09");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "instruction 14 moved the data pointer outside of the tape to cell -1\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
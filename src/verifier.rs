@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::compiler::Instruction;
+
+/// Describes why a list of instructions is not valid.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// The instruction at `index` repeats its operation zero times.
+    ZeroCount { index: usize },
+
+    /// The instruction at `index` is a placeholder that must not survive compilation.
+    Placeholder { index: usize },
+
+    /// The jump at `index` does not jump to the instruction after its matching jump.
+    InvalidJump { index: usize },
+
+    /// The jump at `index` has no matching jump.
+    UnmatchedJump { index: usize },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::ZeroCount { index } => {
+                write!(f, "instruction {index} has a count of zero")
+            }
+            VerifyError::Placeholder { index } => {
+                write!(f, "instruction {index} is an unresolved jump placeholder")
+            }
+            VerifyError::InvalidJump { index } => {
+                write!(f, "jump {index} does not target its matching jump")
+            }
+            VerifyError::UnmatchedJump { index } => write!(f, "jump {index} has no matching jump"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
+
+/// Checks that `instructions` are well formed, so they can be executed by the
+/// [virtual machine](crate::virtual_machine::VirtualMachine) or the JIT compiler.
+///
+/// Every jump must target the instruction after its matching jump, and no instruction may
+/// repeat its operation zero times.
+pub fn verify(instructions: &[Instruction]) -> Result<(), VerifyError> {
+    // Indices of the `JumpZero` instructions of the currently open loops.
+    let mut open = Vec::new();
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        match *instruction {
+            Instruction::IncDP(0)
+            | Instruction::DecDP(0)
+            | Instruction::IncByteAtDP(0)
+            | Instruction::DecByteAtDP(0)
+            | Instruction::WriteByte(0) => return Err(VerifyError::ZeroCount { index }),
+            Instruction::JumpZeroPlaceholder | Instruction::JumpNotZeroPlaceholder => {
+                return Err(VerifyError::Placeholder { index })
+            }
+            Instruction::JumpZero(_) => open.push(index),
+            Instruction::JumpNotZero(n) => {
+                let start = open.pop().ok_or(VerifyError::UnmatchedJump { index })?;
+                // Decoded instructions can have any offset.
+                if n.checked_add(start + 1) != Some(index) {
+                    return Err(VerifyError::InvalidJump { index });
+                }
+                if instructions[start] != Instruction::JumpZero(index - start + 1) {
+                    return Err(VerifyError::InvalidJump { index: start });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match open.pop() {
+        Some(index) => Err(VerifyError::UnmatchedJump { index }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{Compiler, Instruction};
+
+    use super::{verify, VerifyError};
+
+    #[test]
+    fn test_compiled_programs_are_valid() {
+        for program in [
+            include_str!("../programs/hello_world.b"),
+            include_str!("../programs/bitwidth.b"),
+            include_str!("../programs/mandelbrot.b"),
+        ] {
+            assert_eq!(verify(&Compiler::new(program).compile().unwrap()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_invalid_jumps() {
+        let instructions = [Instruction::JumpZero(3), Instruction::JumpNotZero(0)];
+        assert_eq!(
+            verify(&instructions),
+            Err(VerifyError::InvalidJump { index: 0 })
+        );
+
+        let instructions = [Instruction::JumpZero(2), Instruction::JumpNotZero(1)];
+        assert_eq!(
+            verify(&instructions),
+            Err(VerifyError::InvalidJump { index: 1 })
+        );
+
+        let instructions = [Instruction::JumpZero(2)];
+        assert_eq!(
+            verify(&instructions),
+            Err(VerifyError::UnmatchedJump { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_zero_count() {
+        let instructions = [Instruction::IncDP(1), Instruction::WriteByte(0)];
+
+        assert_eq!(
+            verify(&instructions),
+            Err(VerifyError::ZeroCount { index: 1 })
+        );
+    }
+}
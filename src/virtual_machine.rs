@@ -1,18 +1,98 @@
 use std::io::{self, Read, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use crate::compiler::Instruction;
-use crate::FlushBehavior;
+use crate::compiler::{Instruction, StepCost};
+use crate::dirty_cells::{DirtyCells, DirtyRanges};
+use crate::effect_cache::EffectCache;
+use crate::flush::Flusher;
+use crate::host::{HostInputs, InputSelector};
+use crate::limits::Watchdog;
+use crate::observer::ExecutionObserver;
+use crate::profiler::{Sample, Sampler};
+use crate::report::{Counter, ExecReport};
+use crate::sanitizer::{Issue, Sanitizer};
+use crate::snapshot::VmState;
+use crate::step::{StepIo, StepResult};
+use crate::tape::{Cell, Cells, OutOfBounds, Tape, TapePolicy};
+use crate::throttle::Throttle;
+use crate::timing::{Phase, PhaseTimes, Stopwatch};
+use crate::{EofBehavior, FlushBehavior, RuntimeError, DEFAULT_TAPE_SIZE};
 
-const DATA_SIZE: usize = 30_000;
+/// The result of [VirtualMachine::run].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Slice {
+    /// Number of instructions executed by the call.
+    pub executed: u64,
 
-/// A virtual machine that can execute Brainfuck code.
-pub struct VirtualMachine<'a, R, W> {
+    pub status: Status,
+}
+
+/// Why [VirtualMachine::run] returned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+    /// All instructions have been executed.
+    Finished,
+    /// The budget of instructions was used up.
+    Paused,
+    /// The next instruction reads a byte, but no input is available yet.
+    Blocked,
+}
+
+/// Counts of the executed work, enabled with [VirtualMachine::with_stats].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ExecStats {
+    /// Number of instructions executed.
+    pub instructions: u64,
+
+    /// Number of steps the unoptimized program would have executed for the same work, which
+    /// does not depend on the optimizations.
+    pub steps: u64,
+
+    /// Time spent executing instructions in the [Execute](Phase::Execute) phase, and reading
+    /// and writing in the [IoWait](Phase::IoWait) phase.
+    pub times: PhaseTimes,
+}
+
+/// A virtual machine that can execute Brainfuck code on cells of type `C`, which are stored
+/// in `S`.
+pub struct VirtualMachine<'a, R, W, C = u8, S = Vec<C>> {
     instructions: &'a [Instruction],
     ip: usize,
-    data: Vec<u8>,
+    data: Tape<C, S>,
     dp: usize,
     reader: &'a mut R,
+    input: InputSelector<'a>,
     writer: &'a mut W,
+    effect_cache: Option<EffectCache<C>>,
+    write_counts: Option<Vec<u64>>,
+    instruction_counts: Option<Vec<u64>>,
+    sampler: Option<Sampler>,
+    stats: Option<(&'a [StepCost], ExecStats)>,
+    dirty_cells: Option<DirtyCells>,
+    throttle: Option<Throttle>,
+    loop_limits: Option<(&'a [Option<u64>], Vec<u64>)>,
+    flusher: Flusher,
+    observer: Option<&'a mut dyn ExecutionObserver>,
+    sanitizer: Option<Sanitizer>,
+    watchdog: Option<Watchdog>,
+
+    /// The costs of the instructions the watchdog counts, or one step per instruction if they
+    /// are not known.
+    step_costs: Option<&'a [StepCost]>,
+
+    /// The number of steps the watchdog counted or is about to count.
+    steps: u64,
+
+    /// The byte read by the last read instruction, or `None` at the end of the input.
+    read: Option<u8>,
+
+    /// Counts the instructions of every kind and the bytes read and written so far.
+    counter: Counter<{ Instruction::NAMES.len() }>,
+
+    /// The number of instructions executed so far.
+    executed: u64,
 }
 
 impl<'a, R, W> VirtualMachine<'a, R, W>
@@ -26,79 +106,1247 @@ where
         Self {
             instructions,
             ip: 0,
-            data: vec![0; DATA_SIZE],
+            data: Tape::new(DEFAULT_TAPE_SIZE, TapePolicy::Fixed),
             dp: 0,
             reader,
+            input: InputSelector::default(),
             writer,
+            effect_cache: None,
+            write_counts: None,
+            instruction_counts: None,
+            sampler: None,
+            stats: None,
+            dirty_cells: None,
+            throttle: None,
+            loop_limits: None,
+            flusher: Flusher::default(),
+            observer: None,
+            sanitizer: None,
+            watchdog: None,
+            step_costs: None,
+            steps: 0,
+            read: None,
+            counter: Counter::new(),
+            executed: 0,
         }
     }
+}
+
+impl<'a, R, W, C> VirtualMachine<'a, R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    /// Executes the instructions on cells of type `C` instead of bytes, e.g.
+    /// `VirtualMachine::new(instructions, reader, writer).with_cells::<u16>()`.
+    ///
+    /// The tape is replaced by an empty tape of the same size and policy, the effect cache is
+    /// reset.
+    pub fn with_cells<D: Cell>(self) -> VirtualMachine<'a, R, W, D> {
+        VirtualMachine {
+            instructions: self.instructions,
+            ip: self.ip,
+            data: Tape::new(self.data.len(), self.data.policy()),
+            dp: self.dp,
+            reader: self.reader,
+            input: self.input,
+            writer: self.writer,
+            effect_cache: self.effect_cache.map(|_| EffectCache::default()),
+            write_counts: self.write_counts,
+            instruction_counts: self.instruction_counts,
+            sampler: self.sampler,
+            stats: self.stats,
+            dirty_cells: self.dirty_cells,
+            throttle: self.throttle,
+            loop_limits: self.loop_limits,
+            flusher: self.flusher,
+            observer: self.observer,
+            sanitizer: self.sanitizer,
+            watchdog: self.watchdog,
+            step_costs: self.step_costs,
+            steps: self.steps,
+            read: self.read,
+            counter: self.counter,
+            executed: self.executed,
+        }
+    }
+
+    /// Sets the number of cells of the tape, which is [DEFAULT_TAPE_SIZE] by default.
+    pub fn with_tape_size(mut self, tape_size: usize) -> Self {
+        self.data = Tape::new(tape_size, self.data.policy());
+        if let Some(counts) = &mut self.write_counts {
+            *counts = vec![0; tape_size];
+        }
+        self
+    }
+
+    /// Sets what happens when the data pointer moves past the end of the tape.
+    pub fn with_tape_policy(mut self, policy: TapePolicy) -> Self {
+        self.data = Tape::new(self.data.len(), policy);
+        self
+    }
+
+    /// Continues on `data` at the data pointer `dp`, e.g. the tape of a previous execution.
+    pub(crate) fn with_tape(mut self, data: Tape<C>, dp: usize) -> Self {
+        self.data = data;
+        self.dp = dp;
+        self
+    }
+
+    /// Returns the tape and the data pointer, so a later execution can continue on them.
+    pub(crate) fn into_tape(self) -> (Tape<C>, usize) {
+        (self.data, self.dp)
+    }
+
+    /// Continues at the instruction pointer, the data pointer and the tape of `state`, e.g. a
+    /// [snapshot](VirtualMachine::snapshot) of an earlier execution of the same instructions.
+    ///
+    /// Counters like [written](VirtualMachine::written), the [report](VirtualMachine::report)
+    /// and the loop limits are not reset.
+    pub fn restore(&mut self, state: VmState<C>) {
+        let (ip, dp, policy, cells) = state.into_parts();
+        self.ip = ip;
+        self.dp = dp;
+        self.data = Tape::from_cells(cells, policy);
+        if let Some(counts) = &mut self.write_counts {
+            counts.resize(self.data.len(), 0);
+        }
+    }
+
+    /// Executes on `cells` of the caller instead of an own tape, keeping the tape policy, e.g.
+    /// on a slice of shared memory so other threads or processes can observe the cells while
+    /// the program runs.
+    ///
+    /// The cells keep their values and are not copied. As a slice can not grow, moving past
+    /// its end fails even if the tape is growable.
+    pub fn with_tape_cells<S: Cells<C>>(self, cells: S) -> VirtualMachine<'a, R, W, C, S> {
+        let data = Tape::from_cells(cells, self.data.policy());
+        VirtualMachine {
+            instructions: self.instructions,
+            ip: self.ip,
+            write_counts: self.write_counts.map(|_| vec![0; data.len()]),
+            instruction_counts: self.instruction_counts,
+            data,
+            dp: self.dp,
+            reader: self.reader,
+            input: self.input,
+            writer: self.writer,
+            effect_cache: self.effect_cache,
+            sampler: self.sampler,
+            stats: self.stats,
+            dirty_cells: self.dirty_cells,
+            throttle: self.throttle,
+            loop_limits: self.loop_limits,
+            flusher: self.flusher,
+            observer: self.observer,
+            sanitizer: self.sanitizer,
+            watchdog: self.watchdog,
+            step_costs: self.step_costs,
+            steps: self.steps,
+            read: self.read,
+            counter: self.counter,
+            executed: self.executed,
+        }
+    }
+}
+
+impl<'a, R, W, C, S> VirtualMachine<'a, R, W, C, S>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+    S: Cells<C>,
+{
+    /// Enable the effect cache.
+    ///
+    /// Loops that only increment or decrement bytes and the data pointer, and that end at the
+    /// same data pointer they started at, are executed once per entry value; further executions
+    /// of the same loop with the same entry value replay the recorded changes to the data.
+    pub fn with_effect_cache(mut self) -> Self {
+        self.effect_cache = Some(EffectCache::default());
+        self
+    }
+
+    /// Count how often each byte of the data is written to, which can be retrieved with
+    /// [write_counts](VirtualMachine::write_counts) after the execution.
+    ///
+    /// Increments and decrements are counted once for every repetition, loops replayed by the
+    /// effect cache or lowered by the compiler count as a single write for every byte they
+    /// change.
+    pub fn with_write_counts(mut self) -> Self {
+        self.write_counts = Some(vec![0; self.data.len()]);
+        self
+    }
+
+    /// Returns how often each byte of the data has been written to, if enabled with
+    /// [with_write_counts](VirtualMachine::with_write_counts).
+    pub fn write_counts(&self) -> Option<&[u64]> {
+        self.write_counts.as_deref()
+    }
+
+    /// Count how often each instruction is executed, which can be retrieved with
+    /// [instruction_counts](VirtualMachine::instruction_counts) after the execution, e.g. to
+    /// find the [hot loops](crate::hot_loops) of a program.
+    ///
+    /// Loops replayed by the effect cache only count their first instruction.
+    pub fn with_instruction_counts(mut self) -> Self {
+        self.instruction_counts = Some(vec![0; self.instructions.len()]);
+        self
+    }
+
+    /// Returns how often each instruction has been executed, by its index, if enabled with
+    /// [with_instruction_counts](VirtualMachine::with_instruction_counts).
+    pub fn instruction_counts(&self) -> Option<&[u64]> {
+        self.instruction_counts.as_deref()
+    }
+
+    /// Record the instruction and data pointer every `interval` executed instructions, which
+    /// can be retrieved with [samples](VirtualMachine::samples) after the execution.
+    pub fn with_sampling(mut self, interval: u64) -> Self {
+        self.sampler = Some(Sampler::new(interval));
+        self
+    }
+
+    /// Returns the samples taken during the execution, if enabled with
+    /// [with_sampling](VirtualMachine::with_sampling).
+    pub fn samples(&self) -> Option<&[Sample]> {
+        self.sampler.as_ref().map(Sampler::samples)
+    }
+
+    /// Count the executed instructions and steps, which can be retrieved with
+    /// [stats](VirtualMachine::stats) after the execution.
+    ///
+    /// `step_costs` are the costs of the instructions as returned by
+    /// [compile_with_step_costs](crate::compiler::Compiler::compile_with_step_costs). The effect
+    /// cache is not used while counting, as replayed loops are not executed.
+    pub fn with_stats(mut self, step_costs: &'a [StepCost]) -> Self {
+        assert_eq!(step_costs.len(), self.instructions.len());
+        self.stats = Some((step_costs, ExecStats::default()));
+        self
+    }
+
+    /// Returns the counts of the execution so far, if enabled with
+    /// [with_stats](VirtualMachine::with_stats).
+    pub fn stats(&self) -> Option<ExecStats> {
+        self.stats.map(|(_, stats)| stats)
+    }
+
+    /// Send the ranges of the cells changed by each call of [run](VirtualMachine::run) to
+    /// `sender`, e.g. so a view of the tape only redraws these cells.
+    ///
+    /// Nothing is sent for a call that did not change any cell.
+    pub fn with_dirty_cells(mut self, sender: Sender<DirtyRanges>) -> Self {
+        self.dirty_cells = Some(DirtyCells::new(sender));
+        self
+    }
+
+    /// Execute at most `speed` instructions per second in [execute](VirtualMachine::execute)
+    /// by sleeping between batches of instructions, e.g. to follow a program in slow motion.
+    pub fn with_speed(mut self, speed: u64) -> Self {
+        self.throttle = Some(Throttle::new(speed));
+        self
+    }
+
+    /// Fail with [RuntimeError::LoopLimitExceeded] once a loop repeats its body more often
+    /// than its limit every time it is entered.
+    ///
+    /// `limits` are the maximum numbers of iterations of the instructions as returned by
+    /// [LoopAssertions::limits](crate::loop_assertions::LoopAssertions::limits), which are only
+    /// set for a [JumpZero](Instruction::JumpZero). Loops with a limit are not replayed by the
+    /// effect cache.
+    pub fn with_loop_limits(mut self, limits: &'a [Option<u64>]) -> Self {
+        assert_eq!(limits.len(), self.instructions.len());
+        self.loop_limits = Some((limits, vec![0; limits.len()]));
+        self
+    }
+
+    /// Fail with [RuntimeError::LimitExceeded] instead of executing more than `max` steps,
+    /// e.g. to stop an untrusted program that loops forever.
+    ///
+    /// `step_costs` are the costs of the instructions as returned by
+    /// [compile_with_step_costs](crate::compiler::Compiler::compile_with_step_costs), so the
+    /// steps are those of the unoptimized program and the limit does not depend on the
+    /// optimizations. A loop replayed by the effect cache counts the steps of every iteration.
+    pub fn with_max_steps(mut self, max: u64, step_costs: &'a [StepCost]) -> Self {
+        assert_eq!(step_costs.len(), self.instructions.len());
+        self.step_costs = Some(step_costs);
+        self.watchdog
+            .get_or_insert_with(Watchdog::default)
+            .max_steps = Some(max);
+        self
+    }
+
+    /// Fail with [RuntimeError::LimitExceeded] once the execution took longer than `timeout`.
+    ///
+    /// The time is checked between instructions, so a read that blocks is not interrupted.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).timeout = Some(timeout);
+        self
+    }
+
+    /// Fail with [RuntimeError::Cancelled] once `flag` is set, e.g. by a signal handler on
+    /// Ctrl-C. Like the time, the flag is checked between instructions.
+    pub fn with_cancel_flag(mut self, flag: &'static AtomicBool) -> Self {
+        self.watchdog.get_or_insert_with(Watchdog::default).cancel = Some(flag);
+        self
+    }
+
+    /// Lets the program read the host inputs once it selected them with `%`, see the `env`
+    /// [extension](crate::extensions).
+    pub fn with_host_inputs(mut self, inputs: &'a HostInputs) -> Self {
+        self.input = InputSelector::new(inputs);
+        self
+    }
+
+    /// Reports behavior that differs among brainfuck implementations to `sanitizer` as it
+    /// happens. Loops are executed iteration by iteration instead of applying the cached effect.
+    pub fn with_sanitizer(mut self, sanitizer: Sanitizer) -> Self {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    /// Returns the sanitizer, if it is enabled.
+    pub fn sanitizer(&self) -> Option<&Sanitizer> {
+        self.sanitizer.as_ref()
+    }
+
+    /// Calls `observer` for the events of the execution, e.g. to trace every instruction.
+    ///
+    /// Loops are executed iteration by iteration instead of applying the cached effect, so
+    /// every event is observed.
+    pub fn with_observer(mut self, observer: &'a mut dyn ExecutionObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Returns the data pointer.
+    pub fn dp(&self) -> usize {
+        self.dp
+    }
+
+    /// Returns the memory of the program.
+    pub fn data(&self) -> &[C] {
+        &self.data
+    }
+
+    /// Returns the tape of the program, e.g. to compare it with the tape of another execution.
+    pub fn tape(&self) -> &Tape<C, S> {
+        &self.data
+    }
 
-    /// Executes the instructions.
-    pub fn execute(&mut self, flush: FlushBehavior) -> io::Result<()> {
+    /// Returns the number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.counter.bytes_written
+    }
+
+    /// Returns what the execution did so far, with the instructions named like
+    /// [Instruction::name].
+    pub fn report(&self) -> ExecReport {
+        self.counter.report(&Instruction::NAMES)
+    }
+
+    /// Returns the number of instructions executed so far, where a loop replayed by the effect
+    /// cache counts as one instruction.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Returns the instruction pointer, the data pointer and a copy of the tape, so the
+    /// execution can be resumed later by [restore](VirtualMachine::restore), or compared with
+    /// another execution.
+    pub fn snapshot(&self) -> VmState<C> {
+        VmState::new(self.ip, self.dp, self.data.policy(), self.data.to_vec())
+    }
+
+    /// Executes the instructions, handling reads past the end of the input according to `eof`,
+    /// and returns what the execution did so far.
+    pub fn execute(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<ExecReport, RuntimeError> {
+        let start = Instant::now();
+        let result = self.execute_slices(flush, eof);
+        self.counter.elapsed += start.elapsed();
+        result.map(|()| self.report())
+    }
+
+    fn execute_slices(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            let budget = self.throttle.as_ref().map_or(u64::MAX, Throttle::batch);
+            let slice = self.run(budget, flush, eof)?;
+            if let Some(throttle) = &mut self.throttle {
+                throttle.wait(slice.executed);
+            }
+            match slice.status {
+                Status::Blocked => return Err(RuntimeError::Io(io::ErrorKind::WouldBlock.into())),
+                Status::Finished => return Ok(()),
+                Status::Paused => {}
+            }
+        }
+    }
+
+    /// Executes at most `budget` instructions, so the execution can be interleaved with other
+    /// work and resumed by calling `run` again.
+    ///
+    /// If the reader fails with [WouldBlock](io::ErrorKind::WouldBlock), the read instruction
+    /// is retried by the next call. Fails if the data pointer moves outside of the tape.
+    pub fn run(
+        &mut self,
+        budget: u64,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<Slice, RuntimeError> {
+        let stopwatch = self.stats.is_some().then(Stopwatch::start);
+        let io_wait = self.stats().map(|stats| stats.times.get(Phase::IoWait));
+        let (allowance, limit) = match &mut self.watchdog {
+            Some(watchdog) => (watchdog.allowance(), watchdog.remaining()),
+            None => (u64::MAX, u64::MAX),
+        };
+        let steps = self.steps;
+        let result = self.run_slice(budget, allowance, limit, flush, eof);
+        if let (Some(stopwatch), Some(io_wait), Some((_, stats))) =
+            (stopwatch, io_wait, &mut self.stats)
+        {
+            // The time spent reading and writing in this slice is not spent executing.
+            let io_wait = stats.times.get(Phase::IoWait).saturating_sub(io_wait);
+            let execute = stopwatch.elapsed().saturating_sub(io_wait);
+            stats.times.add(Phase::Execute, execute);
+        }
+        if let Some(dirty_cells) = &mut self.dirty_cells {
+            dirty_cells.send();
+        }
+        let slice = result?;
+        self.executed += slice.executed;
+        let next = match self.instructions.get(self.ip) {
+            Some(_) if self.watchdog.is_some() => self.cost(self.ip, self.data[self.dp]),
+            _ => 0,
+        };
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.count(self.steps - steps)?;
+            // The slice pauses before an instruction that does not fit into the limit.
+            if slice.status == Status::Paused && next > watchdog.remaining() {
+                return Err(watchdog.exceeded());
+            }
+        }
+        Ok(slice)
+    }
+
+    /// Executes the instruction at the instruction pointer and returns what it did, or `None`
+    /// once all instructions have been executed and the output has been flushed.
+    ///
+    /// A loop whose effect is cached is replayed by its first instruction. If the reader fails
+    /// with [WouldBlock](io::ErrorKind::WouldBlock), the read instruction is retried by the
+    /// next call.
+    pub fn step(
+        &mut self,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<Option<StepResult<Instruction>>, RuntimeError> {
+        let Some(&instruction) = self.instructions.get(self.ip) else {
+            self.run(0, flush, eof)?;
+            return Ok(None);
+        };
+
+        let byte = self.data[self.dp].low_byte();
+        if self.run(1, flush, eof)?.status == Status::Blocked {
+            return Err(RuntimeError::Io(io::ErrorKind::WouldBlock.into()));
+        }
+        let io = match instruction {
+            Instruction::ReadByte => StepIo::Read(self.read),
+            Instruction::WriteByte(count) => StepIo::Write { byte, count },
+            _ => StepIo::None,
+        };
+        Ok(Some(StepResult {
+            instruction,
+            ip: self.ip,
+            dp: self.dp,
+            io,
+        }))
+    }
+
+    /// Executes at most `budget` instructions like [run](VirtualMachine::run), pausing once
+    /// they counted `allowance` steps or before an instruction that would exceed `limit` steps.
+    fn run_slice(
+        &mut self,
+        budget: u64,
+        allowance: u64,
+        limit: u64,
+        flush: FlushBehavior,
+        eof: EofBehavior,
+    ) -> Result<Slice, RuntimeError> {
+        let (mut remaining, first_step) = (budget, self.steps);
         while self.ip < self.instructions.len() {
-            match self.instructions[self.ip] {
+            if remaining == 0 {
+                return Ok(Slice {
+                    executed: budget,
+                    status: Status::Paused,
+                });
+            }
+            if self.watchdog.is_some() {
+                let steps = self.steps - first_step;
+                let cost = self.cost(self.ip, self.data[self.dp]);
+                if (steps > 0 && steps >= allowance) || steps + cost > limit {
+                    return Ok(Slice {
+                        executed: budget - remaining,
+                        status: Status::Paused,
+                    });
+                }
+                self.steps += cost;
+            }
+            remaining -= 1;
+
+            if let Some(sampler) = &mut self.sampler {
+                sampler.tick(self.ip, self.dp);
+            }
+
+            let (value, dp) = (self.data[self.dp], self.dp);
+            let instruction = self.instructions[self.ip];
+            let ip = self.ip;
+            let out_of_bounds =
+                |err: OutOfBounds| RuntimeError::PointerOutOfBounds { ip, dp: err.0 };
+            self.counter.instructions[instruction.opcode()] += 1;
+            if let Some(counts) = &mut self.instruction_counts {
+                counts[ip] += 1;
+            }
+            if let Some((costs, stats)) = &mut self.stats {
+                stats.instructions += 1;
+                stats.steps += costs[self.ip].steps(value, 0);
+            }
+            if let Some(observer) = &mut self.observer {
+                observer.on_instruction(ip, &instruction, dp);
+            }
+            if self.sanitizer.is_some() {
+                self.sanitize(ip, instruction);
+            }
+            match instruction {
                 Instruction::IncDP(n) => {
-                    self.dp += n;
-                    assert!(self.dp < DATA_SIZE);
+                    self.dp = self
+                        .data
+                        .offset(self.dp, n as isize)
+                        .map_err(out_of_bounds)?;
+                    self.counter.peak_dp = self.counter.peak_dp.max(self.dp);
+                }
+                Instruction::DecDP(n) => {
+                    self.dp = self
+                        .dp
+                        .checked_sub(n)
+                        .ok_or(out_of_bounds(OutOfBounds(self.dp as isize - n as isize)))?
                 }
-                Instruction::DecDP(n) => self.dp -= n,
                 Instruction::IncByteAtDP(n) => {
-                    self.data[self.dp] = self.data[self.dp].wrapping_add(n as u8)
+                    self.data[self.dp] = self.data[self.dp].wrapping_add(C::truncate(n as u64));
+                    self.count_writes(self.dp, n as u64);
                 }
                 Instruction::DecByteAtDP(n) => {
-                    self.data[self.dp] = self.data[self.dp].wrapping_sub(n as u8)
+                    self.data[self.dp] = self.data[self.dp].wrapping_sub(C::truncate(n as u64));
+                    self.count_writes(self.dp, n as u64);
                 }
-                Instruction::ReadByte => self
-                    .reader
-                    .read_exact(&mut self.data[self.dp..self.dp + 1])?,
-                Instruction::WriteByte(n) => {
-                    for _ in 0..n {
-                        self.writer.write_all(&self.data[self.dp..self.dp + 1])?;
+                Instruction::ReadByte => {
+                    self.io(|vm| vm.flusher.reading(flush, vm.writer))?;
+                    self.read = match self
+                        .io(|vm| eof.read(vm.input.reader(vm.reader), &mut vm.data[vm.dp]))
+                    {
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            return Ok(Slice {
+                                executed: budget - remaining - 1,
+                                status: Status::Blocked,
+                            });
+                        }
+                        result => result?,
+                    };
+                    if self.read.is_some() {
+                        self.counter.bytes_read += 1;
+                    }
+                    if let (Some(sanitizer), None) = (&mut self.sanitizer, self.read) {
+                        sanitizer.flag(Issue::ReadAtEof, ip, dp);
+                    }
+                    if let Some(observer) = &mut self.observer {
+                        observer.on_read(self.dp, self.read);
+                    }
+                    self.count_writes(self.dp, 1);
+                }
+                Instruction::SetZero => {
+                    self.data[self.dp] = C::ZERO;
+                    self.count_writes(self.dp, 1);
+                }
+                Instruction::FindZero(stride) => {
+                    self.dp = self
+                        .data
+                        .find_zero(self.dp, stride)
+                        .map_err(out_of_bounds)?;
+                    self.counter.peak_dp = self.counter.peak_dp.max(self.dp);
+                    if let Some(sanitizer) = &mut self.sanitizer {
+                        sanitizer.check_dp(ip, dp, self.dp);
                     }
-                    if flush == FlushBehavior::OnWrite {
-                        self.writer.flush()?;
+                    if let Some((costs, stats)) = &mut self.stats {
+                        // The scan has been counted above as if it did not move.
+                        let cost = costs[self.ip];
+                        stats.steps +=
+                            cost.steps(value, self.dp.abs_diff(dp)) - cost.steps(value, 0);
+                    }
+                    if let (Some(costs), Some(_)) = (self.step_costs, &self.watchdog) {
+                        let cost = costs[self.ip];
+                        self.steps +=
+                            cost.steps(value, self.dp.abs_diff(dp)) - cost.steps(value, 0);
+                    }
+                }
+                Instruction::AddTo { offset, factor } => {
+                    let value = self.data[self.dp];
+                    if value != C::ZERO {
+                        let target = self.data.offset(self.dp, offset).map_err(out_of_bounds)?;
+                        let factor = C::truncate(factor as u64);
+                        self.data[target] =
+                            self.data[target].wrapping_add(value.wrapping_mul(factor));
+                        self.count_writes(target, 1);
+                    }
+                }
+                Instruction::WriteByte(n) => {
+                    let byte = self.data[self.dp].low_byte();
+                    self.io(|vm| {
+                        for _ in 0..n {
+                            vm.writer.write_all(&[byte])?;
+                        }
+                        vm.flusher.written(flush, vm.writer, n)
+                    })?;
+                    self.counter.bytes_written += n as u64;
+                    if let Some(observer) = &mut self.observer {
+                        observer.on_write(byte, n);
                     }
                 }
-                Instruction::JumpZero(n) if self.data[self.dp] == 0 => {
+                Instruction::JumpZero(n) if self.data[self.dp] == C::ZERO => {
                     self.ip += n;
                     continue;
                 }
-                Instruction::JumpNotZero(n) if self.data[self.dp] != 0 => {
+                Instruction::JumpZero(n) if self.replay_loop(limit - (self.steps - first_step)) => {
+                    self.ip += n;
+                    continue;
+                }
+                Instruction::JumpZero(_) => {
+                    if let Some(observer) = &mut self.observer {
+                        observer.on_loop_enter(self.ip);
+                    }
+                    self.count_iteration(self.ip, true)?
+                }
+                Instruction::JumpNotZero(n) if self.data[self.dp] != C::ZERO => {
                     self.ip -= n;
+                    // The jump skips the `JumpZero` of the loop.
+                    self.count_iteration(self.ip - 1, false)?;
                     continue;
                 }
+                Instruction::JumpNotZero(n) => {
+                    if let Some(observer) = &mut self.observer {
+                        observer.on_loop_exit(self.ip - n - 1);
+                    }
+                }
+                Instruction::Exit => {
+                    self.io(|vm| vm.flusher.finished(flush, vm.writer))?;
+                    return Err(RuntimeError::Exit(self.data[self.dp].to_u64() as u32));
+                }
+                Instruction::SelectInput => self.input.select(self.data[self.dp].to_u64())?,
                 _ => {}
             }
 
             self.ip += 1;
         }
 
-        if flush == FlushBehavior::OnEnd {
-            self.writer.flush()
-        } else {
-            Ok(())
+        self.io(|vm| vm.flusher.finished(flush, vm.writer))?;
+        Ok(Slice {
+            executed: budget - remaining,
+            status: Status::Finished,
+        })
+    }
+
+    /// Checks the `instruction` at `ip` for behavior that differs among implementations before
+    /// it is executed.
+    fn sanitize(&mut self, ip: usize, instruction: Instruction) {
+        let Some(sanitizer) = &mut self.sanitizer else {
+            return;
+        };
+        let (dp, value) = (self.dp, self.data[self.dp].to_u64());
+        let max = u64::MAX >> (64 - C::WIDTH.bits());
+        match instruction {
+            Instruction::IncByteAtDP(n) => sanitizer.check_wrap(ip, dp, value, n as i128, max),
+            Instruction::DecByteAtDP(n) => sanitizer.check_wrap(ip, dp, value, -(n as i128), max),
+            Instruction::AddTo { offset, factor } if value != 0 => {
+                // Targets before the tape fail when the instruction is executed, targets past
+                // its end are zero once the tape grows.
+                if let Some(target) = dp.checked_add_signed(offset) {
+                    sanitizer.check_dp(ip, dp, target);
+                    let cell = self.data.get(target).map_or(0, |cell| cell.to_u64());
+                    let delta = i128::from(value) * i128::from(factor);
+                    sanitizer.check_wrap(ip, dp, cell, delta, max);
+                }
+            }
+            Instruction::IncDP(n) => sanitizer.check_dp(ip, dp, dp.saturating_add(n)),
+            Instruction::WriteByte(_) if !self.data[dp].low_byte().is_ascii() => {
+                sanitizer.flag(Issue::NonAsciiOutput, ip, dp)
+            }
+            _ => {}
+        }
+    }
+
+    fn count_writes(&mut self, i: usize, n: u64) {
+        if let Some(dirty_cells) = &mut self.dirty_cells {
+            dirty_cells.mark(i);
+        }
+        if let Some(counts) = &mut self.write_counts {
+            // The tape may have grown since the last write.
+            if counts.len() < self.data.len() {
+                counts.resize(self.data.len(), 0);
+            }
+            counts[i] += n;
+        }
+    }
+
+    fn has_loop_limit(&self, ip: usize) -> bool {
+        matches!(self.loop_limits, Some((limits, _)) if limits[ip].is_some())
+    }
+
+    /// Counts an iteration of the loop that starts at `ip`, starting over if it is `entered`.
+    fn count_iteration(&mut self, ip: usize, entered: bool) -> Result<(), RuntimeError> {
+        if let Some((limits, counts)) = &mut self.loop_limits {
+            if let Some(max) = limits[ip] {
+                counts[ip] = if entered { 1 } else { counts[ip] + 1 };
+                if counts[ip] > max {
+                    return Err(RuntimeError::LoopLimitExceeded { ip, max });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` to read or write, adding the time it takes to the stats if they are enabled.
+    fn io<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let stopwatch = self.stats.is_some().then(Stopwatch::start);
+        let result = f(self);
+        if let (Some(stopwatch), Some((_, stats))) = (stopwatch, &mut self.stats) {
+            stats.times.add(Phase::IoWait, stopwatch.elapsed());
+        }
+        result
+    }
+
+    /// Returns the steps the instruction at `ip` counts if the cell at the data pointer is
+    /// `value`, which are only known in full after a scan.
+    fn cost(&self, ip: usize, value: C) -> u64 {
+        self.step_costs.map_or(1, |costs| costs[ip].steps(value, 0))
+    }
+
+    /// Applies the cached effect of the loop at the instruction pointer, returning `false` if
+    /// the loop has to be executed normally, e.g. as its steps exceed the `limit`.
+    fn replay_loop(&mut self, limit: u64) -> bool {
+        if self.has_loop_limit(self.ip) {
+            return false;
+        }
+        // Every iteration executes the body and the jump back.
+        let iteration = match (self.instructions[self.ip], &self.watchdog) {
+            (Instruction::JumpZero(n), Some(_)) => (self.ip + 1..self.ip + n)
+                .map(|ip| self.cost(ip, C::ZERO))
+                .sum(),
+            _ => 0,
+        };
+        let cache = match &mut self.effect_cache {
+            Some(cache)
+                if self.stats.is_none() && self.observer.is_none() && self.sanitizer.is_none() =>
+            {
+                cache
+            }
+            _ => return false,
+        };
+
+        match cache.lookup(
+            self.instructions,
+            self.ip,
+            self.data[self.dp],
+            self.dp,
+            self.data.len(),
+        ) {
+            Some(replay) => {
+                let steps = replay.iterations.saturating_mul(iteration);
+                if steps > limit {
+                    return false;
+                }
+                self.steps += steps;
+                for (offset, delta) in &replay.effect {
+                    let i = (self.dp as isize + offset) as usize;
+                    self.data[i] = self.data[i].wrapping_add(*delta);
+                    if let Some(counts) = &mut self.write_counts {
+                        counts[i] += 1;
+                    }
+                    if let Some(dirty_cells) = &mut self.dirty_cells {
+                        dirty_cells.mark(i);
+                    }
+                }
+                true
+            }
+            None => false,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io;
+    use std::io::{self, Cursor, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::compiler::{Compiler, Instruction};
+    use crate::interpreter::Interpreter;
+    use crate::loop_assertions::LoopAssertions;
+    use crate::observer::ExecutionObserver;
+    use crate::sanitizer::{Issue, Sanitizer};
+    use crate::snapshot::VmState;
+    use crate::source_map;
+    use crate::step::StepIo;
+    use crate::tape::TapePolicy;
+    use crate::timing::{Phase, PhaseTime};
+    use crate::{EofBehavior, FlushBehavior, Limit, RuntimeError};
+
+    use super::{Slice, Status, VirtualMachine};
+
+    #[test]
+    fn test_step() {
+        let mut reader = Cursor::new(b"a");
+        let mut writer = Vec::new();
+        let instructions = Compiler::new(",..>[-]").compile().unwrap();
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer);
+        let mut steps = Vec::new();
+        while let Some(step) = vm
+            .step(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap()
+        {
+            steps.push((step.instruction, step.ip, step.dp, step.io));
+        }
 
-    use crate::compiler::Compiler;
-    use crate::FlushBehavior;
+        assert_eq!(
+            steps,
+            [
+                (Instruction::ReadByte, 1, 0, StepIo::Read(Some(b'a'))),
+                (
+                    Instruction::WriteByte(2),
+                    2,
+                    0,
+                    StepIo::Write {
+                        byte: b'a',
+                        count: 2
+                    }
+                ),
+                (Instruction::IncDP(1), 3, 1, StepIo::None),
+                (Instruction::SetZero, 4, 1, StepIo::None),
+            ]
+        );
+        assert_eq!(writer, b"aa");
+    }
+
+    #[test]
+    fn test_max_steps() {
+        let (instructions, step_costs) =
+            Compiler::new("+++.>+.").compile_with_step_costs().unwrap();
+        let (mut reader, mut writer) = (io::empty(), Vec::new());
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_max_steps(5, &step_costs);
 
-    use super::VirtualMachine;
+        assert_eq!(
+            vm.run(2, FlushBehavior::OnEnd, EofBehavior::default())
+                .unwrap(),
+            Slice {
+                executed: 2,
+                status: Status::Paused
+            }
+        );
+        let err = vm
+            .execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::LimitExceeded(Limit::Steps(5))));
+        assert_eq!(vm.snapshot().ip(), 3);
+    }
+
+    #[test]
+    fn test_report() {
+        let instructions = Compiler::new(",[->>+<<]>>[.-]").compile().unwrap();
+        let mut reader = Cursor::new([2]);
+        let mut writer = Vec::new();
+        let report = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+
+        assert_eq!(
+            report.instructions,
+            [
+                ("IncDP", 1),
+                ("DecByteAtDP", 2),
+                ("WriteByte", 2),
+                ("ReadByte", 1),
+                ("SetZero", 1),
+                ("AddTo", 1),
+                ("JumpZero", 1),
+                ("JumpNotZero", 2)
+            ]
+        );
+        assert_eq!(report.total_instructions(), 11);
+        assert_eq!(report.bytes_read, 1);
+        assert_eq!(report.bytes_written, 2);
+        assert_eq!(report.peak_dp, 2);
+        assert_eq!(writer, [2, 1]);
+    }
+
+    #[test]
+    fn test_report_reads_past_end() {
+        let instructions = Compiler::new(",>,>,").compile().unwrap();
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let report = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::LeaveUnchanged)
+            .unwrap();
+
+        assert_eq!(report.instructions, [("IncDP", 2), ("ReadByte", 3)]);
+        assert_eq!(report.bytes_read, 1);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let instructions = Compiler::new("++++++++[>++++[>++>+++<<-]>+<<-]>>.>+.")
+            .compile()
+            .unwrap();
+        let mut reader = io::empty();
+        let mut expected = Vec::new();
+        VirtualMachine::new(&instructions, &mut reader, &mut expected)
+            .execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer).with_tape_size(8);
+        vm.run(20, FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+        let state = vm.snapshot();
+        assert_eq!(state.dp(), vm.dp());
+        assert_eq!(state.cells(), vm.data());
+        let bytes = state.to_bytes();
+
+        let mut reader = io::empty();
+        let mut resumed = Vec::new();
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut resumed);
+        vm.restore(VmState::from_bytes(&bytes).unwrap());
+        assert_eq!(vm.snapshot(), state);
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+        assert_eq!(vm.data().len(), 8);
+        drop(vm);
+        assert_eq!(resumed, expected);
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Default)]
+        struct Events {
+            instructions: usize,
+            events: Vec<String>,
+        }
+
+        impl ExecutionObserver for Events {
+            fn on_instruction(&mut self, _ip: usize, _instruction: &Instruction, _dp: usize) {
+                self.instructions += 1;
+            }
+
+            fn on_read(&mut self, dp: usize, byte: Option<u8>) {
+                self.events.push(format!("read {byte:?} at {dp}"));
+            }
+
+            fn on_write(&mut self, byte: u8, count: usize) {
+                self.events.push(format!("write {byte} {count} times"));
+            }
+
+            fn on_loop_enter(&mut self, ip: usize) {
+                self.events.push(format!("enter {ip}"));
+            }
+
+            fn on_loop_exit(&mut self, ip: usize) {
+                self.events.push(format!("exit {ip}"));
+            }
+        }
+
+        let mut reader = Cursor::new([2]);
+        let mut writer = Vec::new();
+        let mut events = Events::default();
+        let instructions = Compiler::new(",[>+<-]>..[-],")
+            .without_optimizations()
+            .compile()
+            .unwrap();
+        VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_effect_cache()
+            .with_observer(&mut events)
+            .execute(FlushBehavior::OnEnd, EofBehavior::SetZero)
+            .unwrap();
+
+        // The cached loop is still observed iteration by iteration.
+        assert_eq!(events.instructions, 1 + 1 + 2 * 5 + 3 + 1 + 2 * 2 + 1);
+        assert_eq!(
+            events.events,
+            [
+                "read Some(2) at 0",
+                "enter 1",
+                "exit 1",
+                "write 2 1 times",
+                "write 2 1 times",
+                "enter 10",
+                "exit 10",
+                "read None at 1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sanitizer() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let source = format!("--[>+++<-]{}+.", ">".repeat(30_000));
+        let instructions = Compiler::new(&source).compile().unwrap();
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_tape_size(30_005)
+            .with_sanitizer(Sanitizer::new());
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::default())
+            .unwrap();
+
+        let sanitizer = vm.sanitizer().unwrap();
+        let findings: Vec<_> = sanitizer
+            .findings()
+            .iter()
+            .map(|finding| (finding.issue, finding.ip, finding.dp))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                (Issue::Wraparound, 0, 0),
+                (Issue::Wraparound, 1, 0),
+                (Issue::BeyondTape, 3, 0)
+            ]
+        );
+        assert_eq!(sanitizer.occurrences(), 3);
+    }
+
+    #[test]
+    fn test_run_with_budget() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new("++++[--]+.").compile().unwrap();
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer);
+
+        let slice = |executed, status| Slice { executed, status };
+        assert_eq!(
+            vm.run(4, FlushBehavior::OnEnd, EofBehavior::Error).unwrap(),
+            slice(4, Status::Paused)
+        );
+        assert_eq!(
+            vm.run(100, FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap(),
+            slice(4, Status::Finished)
+        );
+        assert_eq!(writer, [1]);
+    }
+
+    #[test]
+    fn test_tape_size() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new(">>>+[<]>>+").compile().unwrap();
+
+        let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_write_counts()
+            .with_tape_size(5);
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(vm.data(), [0, 0, 0, 1, 1]);
+        assert_eq!(vm.write_counts().unwrap(), [0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let cases = [
+            ("><<", 1, -1),
+            (">>>>", 0, 4),
+            ("+[<<]", 1, -2),
+            ("+[->>>>+<<<<]", 1, 4),
+        ];
+
+        for (code, expected_ip, expected_dp) in cases {
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+            let instructions = Compiler::new(code).compile().unwrap();
+
+            let err = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+                .with_tape_size(4)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    RuntimeError::PointerOutOfBounds { ip, dp } if ip == expected_ip && dp == expected_dp
+                ),
+                "{code}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_loop_limits() {
+        // The inner loop runs 3 times on each of its 2 entries.
+        for (max, fails) in [(3, false), (2, true)] {
+            let source = format!("++[>+++\n;; assert loop<={max}\n[-.]<-]");
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+            let instructions = Compiler::new(&source).compile().unwrap();
+            let spans = source_map::instruction_spans(&source);
+            let (limits, _) = LoopAssertions::parse(&source)
+                .unwrap()
+                .limits(&instructions, &spans);
+
+            let result = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+                .with_effect_cache()
+                .with_loop_limits(&limits)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error);
+            if fails {
+                assert!(matches!(
+                    result,
+                    Err(RuntimeError::LoopLimitExceeded { ip: 4, max: 2 })
+                ));
+            } else {
+                result.unwrap();
+                assert_eq!(writer, [2, 1, 0, 2, 1, 0]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        let code = ";; extension exit\n+++[>+++[>+++<-]<-]>>.!.";
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new(code).compile().unwrap();
+
+        let err = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_cells::<u16>()
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+
+        assert!(matches!(err, RuntimeError::Exit(27)));
+        assert_eq!(writer, [27]);
+    }
+
+    #[test]
+    fn test_tape_cells() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new("[>]+>++>").compile().unwrap();
+        let mut cells = [7u16, 0, 0];
+
+        let err = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .with_cells::<u16>()
+            .with_tape_policy(TapePolicy::Growable)
+            .with_tape_cells(&mut cells[..])
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+
+        // The program wrote to the cells of the caller before it moved past their end.
+        assert!(matches!(
+            err,
+            RuntimeError::PointerOutOfBounds { ip: 4, dp: 3 }
+        ));
+        assert_eq!(cells, [7, 1, 2]);
+    }
+
+    #[test]
+    fn test_dirty_cells() {
+        let mut reader = io::empty();
+        let mut writer = Vec::new();
+        let instructions = Compiler::new("+>>+<<[-]+++[->>>+<<<]>+>>+")
+            .compile()
+            .unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let mut vm =
+            VirtualMachine::new(&instructions, &mut reader, &mut writer).with_dirty_cells(sender);
+
+        while vm
+            .run(4, FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap()
+            .status
+            != Status::Finished
+        {}
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [vec![0..1, 2..3], vec![0..1, 3..4], vec![1..2, 3..4]]
+        );
+    }
+
+    #[test]
+    fn test_cell_width() {
+        for mut compiler in [
+            Compiler::new("--[->---<]"),
+            Compiler::new("--[->---<]").without_optimizations(),
+        ] {
+            let instructions = compiler.compile().unwrap();
+            let mut reader = io::empty();
+            let mut writer = Vec::new();
+
+            let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+                .with_tape_size(2)
+                .with_cells::<u16>()
+                .with_effect_cache();
+            vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+
+            // The loop runs 65534 times.
+            assert_eq!(vm.data(), [0, 6]);
+        }
+    }
+
+    #[test]
+    fn test_read_at_eof() {
+        let instructions = Compiler::new("+,>+,>+,").compile().unwrap();
+        let cases = [
+            (EofBehavior::SetZero, [1, 0, 0]),
+            (EofBehavior::SetMinusOne, [1, u16::MAX, u16::MAX]),
+            (EofBehavior::LeaveUnchanged, [1, 1, 1]),
+        ];
+
+        for (eof, data) in cases {
+            let mut reader = Cursor::new([1]);
+            let mut writer = Vec::new();
+
+            let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+                .with_tape_size(3)
+                .with_cells::<u16>();
+            vm.execute(FlushBehavior::OnEnd, eof).unwrap();
+
+            assert_eq!(vm.data(), data, "{eof:?}");
+        }
+
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let err = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap_err();
+        assert!(matches!(err, RuntimeError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof));
+    }
 
     #[test]
     fn test_program_hello_world() {
         let mut reader = io::empty();
         let mut writer = Vec::new();
 
-        let instructions = Compiler::new(include_str!("../programs/hello_world.b")).compile();
+        let instructions = Compiler::new(include_str!("../programs/hello_world.b"))
+            .compile()
+            .unwrap();
 
         VirtualMachine::new(&instructions, &mut reader, &mut writer)
-            .execute(FlushBehavior::OnEnd)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
             .unwrap();
 
         assert_eq!(String::from_utf8(writer), Ok("Hello World!\n".into()));
@@ -109,12 +1357,126 @@ mod tests {
         let mut reader = io::empty();
         let mut writer = Vec::new();
 
-        let instructions = Compiler::new(include_str!("../programs/bitwidth.b")).compile();
+        let instructions = Compiler::new(include_str!("../programs/bitwidth.b"))
+            .compile()
+            .unwrap();
 
         VirtualMachine::new(&instructions, &mut reader, &mut writer)
-            .execute(FlushBehavior::OnEnd)
+            .execute(FlushBehavior::OnEnd, EofBehavior::Error)
             .unwrap();
 
         assert_eq!(String::from_utf8(writer), Ok("Hello World! 255\n".into()));
     }
+
+    #[test]
+    fn test_effect_cache_matches_interpreter() {
+        let programs = [
+            include_str!("../programs/hello_world.b"),
+            include_str!("../programs/bitwidth.b"),
+            "++++++++[>++++++++<-]>[<++++>-]<[->+>++>+++<<<]>.>.>.",
+            ",[->+>+<<]>[-<+>]>[-<<+>>]<<.>.>.",
+            "+++[>++[>+++<-]<-]>>.",
+            ">>+++++[-<<+>>]<<[->>>>+<<<<]>>>>.<<<<.",
+        ];
+
+        for program in programs {
+            let mut interpreter_writer = Vec::new();
+            Interpreter::new(program, &mut Cursor::new([7]), &mut interpreter_writer)
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+
+            let instructions = Compiler::new(program).compile().unwrap();
+            let mut vm_writer = Vec::new();
+            let mut reader = Cursor::new([7]);
+            VirtualMachine::new(&instructions, &mut reader, &mut vm_writer)
+                .with_effect_cache()
+                .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                .unwrap();
+
+            assert_eq!(vm_writer, interpreter_writer, "output of {program}");
+        }
+    }
+
+    #[test]
+    fn test_stats_count_unoptimized_steps() {
+        let programs = [
+            include_str!("../programs/hello_world.b"),
+            include_str!("../programs/bitwidth.b"),
+            ",[->+>+<<]>[-<+>]>[-<<+>>]<<.>.>.",
+            "+++[>++[>+++<-]<-]>>.>[]+[-[]]",
+            "+>>+>>+<<<<[>>]>>>>>[<<]",
+        ];
+
+        for program in programs {
+            let stats = |mut compiler: Compiler| {
+                let (instructions, costs) = compiler.compile_with_step_costs().unwrap();
+                let mut reader = Cursor::new([7]);
+                let mut writer = Vec::new();
+                let mut vm = VirtualMachine::new(&instructions, &mut reader, &mut writer)
+                    .with_effect_cache()
+                    .with_stats(&costs);
+                vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+                    .unwrap();
+                vm.stats().unwrap()
+            };
+
+            let unoptimized = stats(Compiler::new(program).without_optimizations());
+            let optimized = stats(Compiler::new(program));
+
+            assert_eq!(unoptimized.steps, unoptimized.instructions, "{program}");
+            assert_eq!(optimized.steps, unoptimized.steps, "{program}");
+            assert!(
+                optimized.instructions < unoptimized.instructions,
+                "{program}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stats_times() {
+        /// Takes 5 milliseconds for every write, like a slow terminal.
+        struct SlowWriter;
+
+        impl Write for SlowWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_millis(5));
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (instructions, costs) = Compiler::new("++++[>+.<-]")
+            .compile_with_step_costs()
+            .unwrap();
+        let mut reader = io::empty();
+        let mut writer = SlowWriter;
+        let mut vm =
+            VirtualMachine::new(&instructions, &mut reader, &mut writer).with_stats(&costs);
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        let times = vm.stats().unwrap().times;
+        let io_wait = times.get(Phase::IoWait);
+        assert!(io_wait.wall >= Duration::from_millis(20), "{io_wait:?}");
+        // The writes are not counted as executing the program.
+        assert!(times.get(Phase::Execute).wall < Duration::from_millis(20));
+        assert_eq!(times.get(Phase::Compile), PhaseTime::default());
+    }
+
+    #[test]
+    fn test_write_counts() {
+        let mut reader = Cursor::new([1]);
+        let mut writer = Vec::new();
+        let instructions = Compiler::new(",>++++[>+<--]").compile().unwrap();
+
+        let mut vm =
+            VirtualMachine::new(&instructions, &mut reader, &mut writer).with_write_counts();
+        vm.execute(FlushBehavior::OnEnd, EofBehavior::Error)
+            .unwrap();
+
+        assert_eq!(&vm.write_counts().unwrap()[..4], &[1, 8, 2, 0]);
+    }
 }
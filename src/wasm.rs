@@ -0,0 +1,442 @@
+//! Compiles programs to WebAssembly modules, so they can run in browsers and WASI runtimes.
+//!
+//! The module imports two functions from the `env` module:
+//!
+//! - `read() -> i32` returns the next byte of the input, or a negative value once it ended
+//! - `write(byte: i32)` writes a byte to the output
+//!
+//! It exports its `memory`, whose first [DEFAULT_TAPE_SIZE] bytes are the tape, and
+//! `run() -> i32`, which executes the program and returns [FINISHED], the exit code of the
+//! `exit` [extension](crate::extensions) or one of the negative errors [OUT_OF_BOUNDS],
+//! [END_OF_INPUT] and [UNKNOWN_INPUT]. Like the
+//! [virtual machine](crate::virtual_machine::VirtualMachine) with its defaults, the cells have
+//! 8 bits and the tape does not grow.
+
+use crate::compiler::Instruction;
+use crate::DEFAULT_TAPE_SIZE;
+
+/// The bytes every module starts with, followed by the version 1.
+pub const MAGIC: &[u8; 4] = b"\0asm";
+
+/// Returned by `run` once the program finished.
+pub const FINISHED: i32 = 0;
+
+/// Returned by `run` when the data pointer left the tape.
+pub const OUT_OF_BOUNDS: i32 = -1;
+
+/// Returned by `run` when the program read after the end of the input.
+pub const END_OF_INPUT: i32 = -2;
+
+/// Returned by `run` when the program selected a host input with `%`, which modules do not
+/// have.
+pub const UNKNOWN_INPUT: i32 = -3;
+
+/// Size of a page of the linear memory.
+const PAGE_SIZE: usize = 65_536;
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNCTION: u8 = 0x60;
+const TYPE_I32: u8 = 0x7f;
+const BLOCK_EMPTY: u8 = 0x40;
+const EXPORT_FUNCTION: u8 = 0x00;
+const EXPORT_MEMORY: u8 = 0x02;
+
+const OP_BLOCK: u8 = 0x02;
+const OP_LOOP: u8 = 0x03;
+const OP_IF: u8 = 0x04;
+const OP_END: u8 = 0x0b;
+const OP_BR: u8 = 0x0c;
+const OP_BR_IF: u8 = 0x0d;
+const OP_RETURN: u8 = 0x0f;
+const OP_CALL: u8 = 0x10;
+const OP_LOCAL_GET: u8 = 0x20;
+const OP_LOCAL_SET: u8 = 0x21;
+const OP_LOCAL_TEE: u8 = 0x22;
+const OP_I32_LOAD8_U: u8 = 0x2d;
+const OP_I32_STORE8: u8 = 0x3a;
+const OP_I32_CONST: u8 = 0x41;
+const OP_I32_EQZ: u8 = 0x45;
+const OP_I32_LT_S: u8 = 0x48;
+const OP_I32_GE_U: u8 = 0x4f;
+const OP_I32_ADD: u8 = 0x6a;
+const OP_I32_SUB: u8 = 0x6b;
+const OP_I32_MUL: u8 = 0x6c;
+
+/// Indices of the imported functions, which come before the functions of the module.
+const FUNCTION_READ: u32 = 0;
+const FUNCTION_WRITE: u32 = 1;
+const FUNCTION_RUN: u32 = 2;
+
+/// The local holding the data pointer.
+const LOCAL_DP: u32 = 0;
+
+/// A local for intermediate values, like the byte read or a target cell.
+const LOCAL_TEMP: u32 = 1;
+
+/// Compiles `instructions` to the bytes of a WebAssembly module.
+pub fn compile(instructions: &[Instruction]) -> Vec<u8> {
+    compile_with_offsets(instructions).0
+}
+
+/// Like [compile], but also returns the byte offset in the module the code of every
+/// instruction starts at, e.g. for a [SourceMap](crate::source_map::SourceMap).
+pub fn compile_with_offsets(instructions: &[Instruction]) -> (Vec<u8>, Vec<usize>) {
+    let mut module = MAGIC.to_vec();
+    module.extend_from_slice(&1u32.to_le_bytes());
+
+    // The type of `read` and `run`, then the type of `write`.
+    let mut types = Vec::new();
+    write_unsigned(&mut types, 2);
+    types.extend_from_slice(&[TYPE_FUNCTION, 0, 1, TYPE_I32]);
+    types.extend_from_slice(&[TYPE_FUNCTION, 1, TYPE_I32, 0]);
+    section(&mut module, SECTION_TYPE, &types);
+
+    let mut imports = Vec::new();
+    write_unsigned(&mut imports, 2);
+    for (name, type_index) in [("read", 0), ("write", 1)] {
+        write_name(&mut imports, "env");
+        write_name(&mut imports, name);
+        imports.extend_from_slice(&[EXPORT_FUNCTION, type_index]);
+    }
+    section(&mut module, SECTION_IMPORT, &imports);
+
+    section(&mut module, SECTION_FUNCTION, &[1, 0]);
+
+    let mut memories = vec![1, 0];
+    write_unsigned(&mut memories, DEFAULT_TAPE_SIZE.div_ceil(PAGE_SIZE) as u64);
+    section(&mut module, SECTION_MEMORY, &memories);
+
+    let mut exports = Vec::new();
+    write_unsigned(&mut exports, 2);
+    write_name(&mut exports, "memory");
+    exports.extend_from_slice(&[EXPORT_MEMORY, 0]);
+    write_name(&mut exports, "run");
+    exports.push(EXPORT_FUNCTION);
+    write_unsigned(&mut exports, FUNCTION_RUN.into());
+    section(&mut module, SECTION_EXPORT, &exports);
+
+    let (body, offsets) = function_body(instructions);
+    let mut code = Vec::new();
+    write_unsigned(&mut code, 1);
+    write_unsigned(&mut code, body.len() as u64);
+    code.extend_from_slice(&body);
+    section(&mut module, SECTION_CODE, &code);
+
+    // The body is the end of the module.
+    let start = module.len() - body.len();
+    let offsets = offsets.into_iter().map(|offset| start + offset).collect();
+    (module, offsets)
+}
+
+/// Returns the body of `run`, which executes `instructions`, and the offset in the body the
+/// code of every instruction starts at.
+fn function_body(instructions: &[Instruction]) -> (Vec<u8>, Vec<usize>) {
+    // The data pointer and the temporary local.
+    let mut code = vec![1, 2, TYPE_I32];
+    let mut offsets = Vec::with_capacity(instructions.len());
+
+    for instruction in instructions {
+        offsets.push(code.len());
+        match *instruction {
+            Instruction::IncDP(n) => move_dp(&mut code, clamp(n as i64)),
+            Instruction::DecDP(n) => move_dp(&mut code, -clamp(n as i64)),
+            Instruction::IncByteAtDP(n) => add_to_cell(&mut code, (n % 256) as i32, OP_I32_ADD),
+            Instruction::DecByteAtDP(n) => add_to_cell(&mut code, (n % 256) as i32, OP_I32_SUB),
+            Instruction::WriteByte(n) => {
+                let n = n.min(i32::MAX as usize) as i32;
+                i32_const(&mut code, n);
+                local(&mut code, OP_LOCAL_SET, LOCAL_TEMP);
+                code.extend_from_slice(&[OP_LOOP, BLOCK_EMPTY]);
+                load_cell(&mut code);
+                call(&mut code, FUNCTION_WRITE);
+                local(&mut code, OP_LOCAL_GET, LOCAL_TEMP);
+                i32_const(&mut code, 1);
+                code.push(OP_I32_SUB);
+                local(&mut code, OP_LOCAL_TEE, LOCAL_TEMP);
+                code.extend_from_slice(&[OP_BR_IF, 0, OP_END]);
+            }
+            Instruction::ReadByte => {
+                call(&mut code, FUNCTION_READ);
+                local(&mut code, OP_LOCAL_TEE, LOCAL_TEMP);
+                i32_const(&mut code, 0);
+                code.push(OP_I32_LT_S);
+                return_if(&mut code, END_OF_INPUT);
+                local(&mut code, OP_LOCAL_GET, LOCAL_DP);
+                local(&mut code, OP_LOCAL_GET, LOCAL_TEMP);
+                store(&mut code);
+            }
+            Instruction::SetZero => {
+                local(&mut code, OP_LOCAL_GET, LOCAL_DP);
+                i32_const(&mut code, 0);
+                store(&mut code);
+            }
+            Instruction::FindZero(stride) => {
+                open_loop(&mut code);
+                move_dp(&mut code, clamp(stride as i64));
+                close_loop(&mut code);
+            }
+            Instruction::AddTo { offset, factor } => {
+                // Nothing is accessed if the cell is zero.
+                load_cell(&mut code);
+                code.extend_from_slice(&[OP_IF, BLOCK_EMPTY]);
+                local(&mut code, OP_LOCAL_GET, LOCAL_DP);
+                i32_const(&mut code, clamp(offset as i64));
+                code.push(OP_I32_ADD);
+                local(&mut code, OP_LOCAL_TEE, LOCAL_TEMP);
+                check_bounds(&mut code);
+                local(&mut code, OP_LOCAL_GET, LOCAL_TEMP);
+                local(&mut code, OP_LOCAL_GET, LOCAL_TEMP);
+                load(&mut code);
+                load_cell(&mut code);
+                i32_const(&mut code, factor.rem_euclid(256));
+                code.extend_from_slice(&[OP_I32_MUL, OP_I32_ADD]);
+                store(&mut code);
+                code.push(OP_END);
+            }
+            Instruction::JumpZero(_) => open_loop(&mut code),
+            Instruction::JumpNotZero(_) => close_loop(&mut code),
+            Instruction::Exit => {
+                load_cell(&mut code);
+                code.push(OP_RETURN);
+            }
+            Instruction::SelectInput => {
+                load_cell(&mut code);
+                return_if(&mut code, UNKNOWN_INPUT);
+            }
+            Instruction::JumpZeroPlaceholder | Instruction::JumpNotZeroPlaceholder => {
+                unreachable!("the jumps of a program are resolved")
+            }
+        }
+    }
+
+    i32_const(&mut code, FINISHED);
+    code.push(OP_END);
+    (code, offsets)
+}
+
+/// Limits a move of the data pointer to the size of the tape, which fails the same way, so it
+/// fits into an `i32`.
+fn clamp(n: i64) -> i32 {
+    let max = DEFAULT_TAPE_SIZE as i64;
+    n.clamp(-max, max) as i32
+}
+
+/// Moves the data pointer by `n`, returning [OUT_OF_BOUNDS] if it leaves the tape.
+fn move_dp(code: &mut Vec<u8>, n: i32) {
+    local(code, OP_LOCAL_GET, LOCAL_DP);
+    i32_const(code, n);
+    code.push(OP_I32_ADD);
+    local(code, OP_LOCAL_TEE, LOCAL_DP);
+    check_bounds(code);
+}
+
+/// Returns [OUT_OF_BOUNDS] if the cell on the stack is outside of the tape. Cells before the
+/// tape are negative, which are greater than the tape size as unsigned values.
+fn check_bounds(code: &mut Vec<u8>) {
+    i32_const(code, DEFAULT_TAPE_SIZE as i32);
+    code.push(OP_I32_GE_U);
+    return_if(code, OUT_OF_BOUNDS);
+}
+
+/// Adds or subtracts `n` from the cell at the data pointer with `op`.
+fn add_to_cell(code: &mut Vec<u8>, n: i32, op: u8) {
+    local(code, OP_LOCAL_GET, LOCAL_DP);
+    load_cell(code);
+    i32_const(code, n);
+    code.push(op);
+    store(code);
+}
+
+/// Starts a loop that is skipped, or left, once the cell at the data pointer is zero.
+fn open_loop(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[OP_BLOCK, BLOCK_EMPTY, OP_LOOP, BLOCK_EMPTY]);
+    load_cell(code);
+    code.extend_from_slice(&[OP_I32_EQZ, OP_BR_IF, 1]);
+}
+
+/// Ends a loop started by [open_loop], checking the cell again.
+fn close_loop(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[OP_BR, 0, OP_END, OP_END]);
+}
+
+/// Returns `value` from `run` if the value on the stack is not zero.
+fn return_if(code: &mut Vec<u8>, value: i32) {
+    code.extend_from_slice(&[OP_IF, BLOCK_EMPTY]);
+    i32_const(code, value);
+    code.extend_from_slice(&[OP_RETURN, OP_END]);
+}
+
+fn load_cell(code: &mut Vec<u8>) {
+    local(code, OP_LOCAL_GET, LOCAL_DP);
+    load(code);
+}
+
+/// Loads the byte at the address on the stack, with an alignment of 1 and no offset.
+fn load(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[OP_I32_LOAD8_U, 0, 0]);
+}
+
+/// Stores the low byte of the value on the stack at the address below it.
+fn store(code: &mut Vec<u8>) {
+    code.extend_from_slice(&[OP_I32_STORE8, 0, 0]);
+}
+
+fn local(code: &mut Vec<u8>, op: u8, index: u32) {
+    code.push(op);
+    write_unsigned(code, index.into());
+}
+
+fn call(code: &mut Vec<u8>, function: u32) {
+    code.push(OP_CALL);
+    write_unsigned(code, function.into());
+}
+
+fn i32_const(code: &mut Vec<u8>, value: i32) {
+    code.push(OP_I32_CONST);
+    write_signed(code, value.into());
+}
+
+/// Appends a section with its id and size.
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    write_unsigned(module, contents.len() as u64);
+    module.extend_from_slice(contents);
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_unsigned(bytes, name.len() as u64);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+/// Appends `value` LEB128 encoded.
+fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Appends `value` signed LEB128 encoded.
+fn write_signed(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        // Done once the rest is the sign extension of the last byte.
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use super::*;
+    use crate::compiler::Compiler;
+
+    /// Instantiates the module in the file given as first argument with the input given as
+    /// second argument, and prints the output followed by the value `run` returned.
+    const RUNNER: &str = "
+        const input = Buffer.from(process.argv[2]);
+        const output = [];
+        let read = 0;
+        const module = new WebAssembly.Module(require('fs').readFileSync(process.argv[1]));
+        const env = {
+            read: () => (read < input.length ? input[read++] : -1),
+            write: (byte) => output.push(byte),
+        };
+        const result = new WebAssembly.Instance(module, { env }).exports.run();
+        process.stdout.write(Buffer.from(output).toString() + ' ' + result);
+    ";
+
+    #[test]
+    fn test_leb128() {
+        let encode = |value| {
+            let mut bytes = Vec::new();
+            write_signed(&mut bytes, value);
+            bytes
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(63), [0x3f]);
+        assert_eq!(encode(64), [0xc0, 0x00]);
+        assert_eq!(encode(-1), [0x7f]);
+        assert_eq!(encode(-65), [0xbf, 0x7f]);
+        assert_eq!(encode(30_000), [0xb0, 0xea, 0x01]);
+
+        let mut bytes = Vec::new();
+        write_unsigned(&mut bytes, 624_485);
+        assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn test_module() {
+        let module = compile(&Compiler::new("+.").compile().unwrap());
+        assert!(module.starts_with(b"\0asm\x01\0\0\0"));
+        // The imports come after the types, named by their module and field.
+        assert!(module
+            .windows(13)
+            .any(|window| window == b"\x03env\x04read\0\0\x03e"));
+    }
+
+    #[test]
+    fn test_offsets() {
+        let (module, offsets) = compile_with_offsets(&Compiler::new("+.,").compile().unwrap());
+
+        // Adding starts with the data pointer, writing with the count of the loop and reading
+        // with the call of `read`.
+        assert_eq!(
+            module[offsets[0]..offsets[0] + 2],
+            [OP_LOCAL_GET, LOCAL_DP as u8]
+        );
+        assert_eq!(module[offsets[1]], OP_I32_CONST);
+        assert_eq!(
+            module[offsets[2]..offsets[2] + 2],
+            [OP_CALL, FUNCTION_READ as u8]
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        // Not every machine that runs the tests has node.
+        if Command::new("node").arg("--version").output().is_err() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("brainfuck-wasm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let run = |source: &str, input: &str| {
+            let file = dir.join("program.wasm");
+            fs::write(&file, compile(&Compiler::new(source).compile().unwrap())).unwrap();
+            let output = Command::new("node")
+                .args(["-e", RUNNER])
+                .arg(&file)
+                .arg(input)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        assert_eq!(run(",[->++<]>.+++..[<]", "!"), "BEE 0");
+        assert_eq!(run("+.<", ""), "\x01 -1");
+        assert_eq!(run(">+[-<]>", ""), " 0");
+        assert_eq!(run(",.,", "a"), "a -2");
+        assert_eq!(run(";; extension exit\n+++!+.", ""), " 3");
+        assert_eq!(run(";; extension env\n%+%", ""), " -3");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
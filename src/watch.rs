@@ -0,0 +1,291 @@
+//! Expressions over the cells of the tape and the data pointer, e.g. `cell(5) + cell(6) * 256`,
+//! that can be watched while debugging a program.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// A parsed watch expression.
+///
+/// Supported are integer literals, `dp` for the data pointer, `cell(i)` for the value of the
+/// cell at index `i`, the binary operators `+`, `-`, `*`, `/` and `%`, negation and parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(i64),
+    DataPointer,
+    Cell(Box<Node>),
+    Neg(Box<Node>),
+    Binary(Box<Node>, Operator, Box<Node>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// An error that occurred while parsing or evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprError {
+    /// The expression is not valid, with a description of what was expected.
+    Syntax(String),
+
+    /// A cell outside of the tape was accessed.
+    CellOutOfBounds(i64),
+
+    /// The expression divides by zero.
+    DivisionByZero,
+
+    /// The result does not fit into an `i64`.
+    Overflow,
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::Syntax(message) => write!(f, "invalid expression: {message}"),
+            ExprError::CellOutOfBounds(i) => write!(f, "cell {i} is outside of the tape"),
+            ExprError::DivisionByZero => write!(f, "division by zero"),
+            ExprError::Overflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl Error for ExprError {}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        let node = parser.expr()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            Some(c) => Err(ExprError::Syntax(format!("unexpected `{c}`"))),
+            None => Ok(Self {
+                source: s.trim().to_string(),
+                node,
+            }),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression for the given tape and data pointer.
+    pub fn evaluate(&self, data: &[u8], dp: usize) -> Result<i64, ExprError> {
+        self.node.evaluate(data, dp)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, data: &[u8], dp: usize) -> Result<i64, ExprError> {
+        match self {
+            Node::Number(n) => Ok(*n),
+            Node::DataPointer => Ok(dp as i64),
+            Node::Cell(index) => {
+                let i = index.evaluate(data, dp)?;
+                usize::try_from(i)
+                    .ok()
+                    .and_then(|i| data.get(i))
+                    .map(|cell| *cell as i64)
+                    .ok_or(ExprError::CellOutOfBounds(i))
+            }
+            Node::Neg(node) => node
+                .evaluate(data, dp)?
+                .checked_neg()
+                .ok_or(ExprError::Overflow),
+            Node::Binary(left, operator, right) => {
+                let left = left.evaluate(data, dp)?;
+                let right = right.evaluate(data, dp)?;
+                if right == 0 && matches!(operator, Operator::Div | Operator::Rem) {
+                    return Err(ExprError::DivisionByZero);
+                }
+                match operator {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Sub => left.checked_sub(right),
+                    Operator::Mul => left.checked_mul(right),
+                    Operator::Div => left.checked_div(right),
+                    Operator::Rem => left.checked_rem(right),
+                }
+                .ok_or(ExprError::Overflow)
+            }
+        }
+    }
+}
+
+/// A recursive descent parser for expressions.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().copied()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ExprError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            Some(c) => Err(ExprError::Syntax(format!(
+                "expected `{expected}` but found `{c}`"
+            ))),
+            None => Err(ExprError::Syntax(format!("expected `{expected}`"))),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Sub,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(Box::new(node), operator, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, ExprError> {
+        let mut node = self.factor()?;
+        loop {
+            let operator = match self.peek() {
+                Some('*') => Operator::Mul,
+                Some('/') => Operator::Div,
+                Some('%') => Operator::Rem,
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = Node::Binary(Box::new(node), operator, Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Result<Node, ExprError> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Node::Neg(Box::new(self.factor()?)))
+            }
+            Some('(') => {
+                self.chars.next();
+                let node = self.expr()?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                digits
+                    .parse()
+                    .map(Node::Number)
+                    .map_err(|_| ExprError::Overflow)
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                match name.as_str() {
+                    "dp" => Ok(Node::DataPointer),
+                    "cell" => {
+                        self.expect('(')?;
+                        let index = self.expr()?;
+                        self.expect(')')?;
+                        Ok(Node::Cell(Box::new(index)))
+                    }
+                    _ => Err(ExprError::Syntax(format!("unknown name `{name}`"))),
+                }
+            }
+            Some(c) => Err(ExprError::Syntax(format!("unexpected `{c}`"))),
+            None => Err(ExprError::Syntax(
+                "unexpected end of expression".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, ExprError};
+
+    fn evaluate(expr: &str, data: &[u8], dp: usize) -> Result<i64, ExprError> {
+        expr.parse::<Expr>()?.evaluate(data, dp)
+    }
+
+    #[test]
+    fn test_multi_cell_value() {
+        assert_eq!(evaluate("cell(1) + cell(2)*256", &[0, 4, 1], 0), Ok(260));
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        assert_eq!(evaluate("2 + 3 * 4", &[], 0), Ok(14));
+        assert_eq!(evaluate("(2 + 3) * 4", &[], 0), Ok(20));
+        assert_eq!(evaluate("10 - 4 - 3", &[], 0), Ok(3));
+        assert_eq!(evaluate("-7 % 4 / -1", &[], 0), Ok(3));
+    }
+
+    #[test]
+    fn test_data_pointer() {
+        assert_eq!(evaluate("cell(dp) + cell(dp - 1)", &[1, 2, 3], 2), Ok(5));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            evaluate("cell(3)", &[0; 3], 0),
+            Err(ExprError::CellOutOfBounds(3))
+        );
+        assert_eq!(
+            evaluate("cell(dp - 1)", &[0; 3], 0),
+            Err(ExprError::CellOutOfBounds(-1))
+        );
+        assert_eq!(
+            evaluate("1 / cell(0)", &[0], 0),
+            Err(ExprError::DivisionByZero)
+        );
+        assert_eq!(
+            evaluate("cell(1", &[], 0),
+            Err(ExprError::Syntax("expected `)`".to_string()))
+        );
+        assert_eq!(
+            evaluate("ip", &[], 0),
+            Err(ExprError::Syntax("unknown name `ip`".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_display_source() {
+        let expr: Expr = " cell(5) + cell(6)*256 ".parse().unwrap();
+
+        assert_eq!(expr.to_string(), "cell(5) + cell(6)*256");
+    }
+}
@@ -0,0 +1,1193 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+const HELLO_WORLD: &str = "programs/hello_world.b";
+
+/// Runs the binary with the given arguments, passing `stdin` as its standard input.
+fn brainfuck(args: &[&str], stdin: &[u8]) -> Output {
+    brainfuck_in(Path::new("."), args, stdin)
+}
+
+/// Runs the binary like [brainfuck] in the directory `dir`.
+fn brainfuck_in(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The binary might exit before it read its input, e.g. because of invalid arguments.
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+/// Returns a path in the temporary directory that is unique for the given test.
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("brainfuck-{}-{name}", std::process::id()))
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+fn stderr(output: &Output) -> &str {
+    std::str::from_utf8(&output.stderr).unwrap()
+}
+
+#[test]
+fn test_run_is_default_command() {
+    let output = brainfuck(&[HELLO_WORLD], b"");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Hello World!\n");
+}
+
+#[test]
+fn test_env_selection() {
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["run", "--env", env, HELLO_WORLD], b"");
+
+        assert!(output.status.success(), "env {env}");
+        assert_eq!(stdout(&output), "Hello World!\n", "env {env}");
+    }
+}
+
+#[test]
+fn test_invalid_env() {
+    let output = brainfuck(&["--env", "gpu", HELLO_WORLD], b"");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("valid values"));
+}
+
+#[test]
+fn test_exit_extension() {
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(
+            &["run", "--env", env],
+            b";; extension exit\n++++++++[>++++++++<-]>+.++!.",
+        );
+
+        assert_eq!(output.status.code(), Some(67), "env {env}");
+        assert_eq!(stdout(&output), "A", "env {env}");
+    }
+}
+
+#[test]
+fn test_env_extension() {
+    std::env::set_var("BRAINFUCK_TEST_GREETING", "hi");
+    let program = temp_file("env.b");
+    fs::write(&program, ";; extension env\n,.+%>,.,.<+%>,.,.<--%,.").unwrap();
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(
+            &[
+                "--env",
+                env,
+                "--host-input",
+                "name=bf",
+                "--host-input",
+                "BRAINFUCK_TEST_GREETING",
+                program.to_str().unwrap(),
+            ],
+            b"\0!",
+        );
+
+        assert!(output.status.success(), "env {env}: {}", stderr(&output));
+        assert_eq!(stdout(&output), "\0bfhi!", "env {env}");
+    }
+
+    let output = brainfuck(&[program.to_str().unwrap()], b"\0");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("the program selected the unknown host input 1"));
+}
+
+#[test]
+fn test_program_from_stdin() {
+    let output = brainfuck(&["--env", "vm"], b"++++++++[>++++++++<-]>+.");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "A");
+}
+
+/// Writes a program that echoes three bytes to a temporary file.
+fn echo_program(name: &str) -> PathBuf {
+    let program = temp_file(name);
+    fs::write(&program, ",.,.,.").unwrap();
+    program
+}
+
+#[test]
+fn test_input_from_stdin() {
+    let program = echo_program("stdin-program");
+
+    let output = brainfuck(&["--env", "vm", program.to_str().unwrap()], b"abc");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "abc");
+
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_input_and_output_redirection() {
+    let program = echo_program("redirect-program");
+    let input = temp_file("redirect-input");
+    let output_file = temp_file("redirect-output");
+    fs::write(&input, "xyz").unwrap();
+
+    for env in ["interpreter", "vm", "closures"] {
+        let output = brainfuck(
+            &[
+                "--env",
+                env,
+                "--input",
+                input.to_str().unwrap(),
+                "--output",
+                output_file.to_str().unwrap(),
+                program.to_str().unwrap(),
+            ],
+            b"ignored",
+        );
+
+        assert!(output.status.success(), "env {env}");
+        assert_eq!(stdout(&output), "", "env {env}");
+        assert_eq!(
+            fs::read_to_string(&output_file).unwrap(),
+            "xyz",
+            "env {env}"
+        );
+    }
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(input).unwrap();
+    fs::remove_file(output_file).unwrap();
+}
+
+#[test]
+fn test_tee() {
+    let log = temp_file("tee-log");
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(
+            &[
+                "--env",
+                env,
+                "--tee",
+                log.to_str().unwrap(),
+                "--newline-mode",
+                "raw,crlf",
+                HELLO_WORLD,
+            ],
+            b"",
+        );
+
+        assert!(output.status.success(), "env {env}");
+        assert_eq!(stdout(&output), "Hello World!\r\n", "env {env}");
+        // The log gets the same bytes as the terminal.
+        assert_eq!(fs::read(&log).unwrap(), output.stdout, "env {env}");
+    }
+
+    fs::remove_file(log).unwrap();
+}
+
+#[test]
+#[cfg(jit)]
+fn test_jit_with_redirection() {
+    let output_file = temp_file("jit-output");
+
+    let output = brainfuck(
+        &[
+            "--env",
+            "jit",
+            "--newline-mode",
+            "raw,crlf",
+            "--output",
+            output_file.to_str().unwrap(),
+            HELLO_WORLD,
+        ],
+        b"",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+    assert_eq!(
+        fs::read_to_string(&output_file).unwrap(),
+        "Hello World!\r\n"
+    );
+
+    fs::remove_file(output_file).unwrap();
+}
+
+#[test]
+#[cfg(jit)]
+fn test_jit_does_not_fall_back() {
+    let output = brainfuck(&["--env", "jit", HELLO_WORLD], b"");
+
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+#[cfg(not(jit))]
+fn test_jit_fallback_notice() {
+    let output = brainfuck(&["--env", "jit", HELLO_WORLD], b"");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Hello World!\n");
+    assert!(stderr(&output).contains("not available on this platform"));
+}
+
+#[test]
+fn test_newline_mode() {
+    let program = echo_program("newline-mode");
+    let output = brainfuck(
+        &[
+            "--env",
+            "vm",
+            "--newline-mode",
+            "cr,crlf",
+            program.to_str().unwrap(),
+        ],
+        b"a\nb",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a\r\nb");
+}
+
+#[test]
+fn test_decode_output() {
+    let program = echo_program("decode");
+    let program = program.to_str().unwrap();
+
+    let output = brainfuck(&["--decode", "utf8", program], b"a\xffb");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "a\u{fffd}b");
+    assert!(stderr(&output).contains("replaced 1 invalid UTF-8 sequence at byte 1"));
+
+    let output = brainfuck(&["--decode", "hex", program], b"a\xffb");
+    assert!(stdout(&output).starts_with("00000000  61 ff 62 "));
+    assert!(stdout(&output).ends_with("|a.b|\n"));
+}
+
+#[test]
+fn test_profile_writes_chrome_trace() {
+    let program = temp_file("profile.b");
+    fs::write(&program, "++++[>++++[>+<--]<-]").unwrap();
+    let trace = temp_file("profile.json");
+
+    let output = brainfuck(
+        &[
+            "--env",
+            "vm",
+            "--profile",
+            trace.to_str().unwrap(),
+            "--sample-interval",
+            "1",
+            program.to_str().unwrap(),
+        ],
+        b"",
+    );
+    assert!(output.status.success());
+
+    let trace = fs::read_to_string(trace).unwrap();
+    assert!(trace.starts_with(r#"{"traceEvents":["#));
+    assert!(trace.contains(r#""name":"loop at 1:5","ph":"X""#));
+    assert!(trace.contains(r#""name":"loop at 1:11","ph":"X""#));
+    assert!(trace.contains(r#""name":"dp","ph":"C""#));
+}
+
+#[test]
+fn test_stats() {
+    let program = temp_file("stats.b");
+    fs::write(&program, ",[->+<]>.").unwrap();
+
+    let output = brainfuck(&["--stats", program.to_str().unwrap()], b"a");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "a");
+    let stats = stderr(&output);
+    assert!(stats.contains("instructions: 5\n  IncDP: 1\n  WriteByte: 1\n  ReadByte: 1\n"));
+    assert!(stats.contains("bytes read: 1\nbytes written: 1\npeak dp: 1\nelapsed: "));
+}
+
+#[test]
+fn test_hot_loops() {
+    let program = temp_file("hot_loops.b");
+    fs::write(&program, "++[>+++\n  [.-]<-]").unwrap();
+
+    let output = brainfuck(
+        &["--env", "vm", "--hot-loops", program.to_str().unwrap()],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "\x03\x02\x01\x03\x02\x01");
+    assert!(stderr(&output).starts_with(
+        "hot loops:\n  loop at line 1 column 3 executed 2 times, 97% of instructions\n  \
+         loop at line 2 column 3 executed 6 times, 62% of instructions\nhot positions:\n  \
+         line 2 column 4 executed 6 times, 19% of instructions\n"
+    ));
+}
+
+#[test]
+fn test_explain() {
+    let program = temp_file("explain.b");
+    fs::write(&program, "+++ set up\n[->++<]").unwrap();
+    let program = program.to_str().unwrap();
+
+    let output = brainfuck(&["explain", "--at", "2:3", program], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "line 2 column 1 to line 2 column 7: this loop was recognized as multiply: \
+        cell[dp+1] += 2*cell[dp], then cell[dp] = 0\n\
+        pass `multiply`, replaces instructions 3..10 of the unoptimized program\n     \
+        1  AddTo { offset: 1, factor: 2 }\n     \
+        2  SetZero\n"
+    );
+
+    let output = brainfuck(&["explain", "--at", "1:6", program], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("there is no instruction at line 1 column 6"));
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_no_opt() {
+    let program = temp_file("no-opt.b");
+    fs::write(&program, ",,.").unwrap();
+    let program = program.to_str().unwrap();
+
+    // Every read consumes a byte, with or without optimizations.
+    let output = brainfuck(&["--env", "vm", program], b"ab");
+    assert_eq!(stdout(&output), "b");
+
+    let output = brainfuck(&["--env", "vm", "--no-opt", program], b"ab");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "b");
+
+    fs::write(program, "++.").unwrap();
+    let output = brainfuck(&["compile", "--no-opt", program], b"");
+    assert_eq!(
+        stdout(&output),
+        "     0  IncByteAtDP(1)\n     1  IncByteAtDP(1)\n     2  WriteByte(1)\n"
+    );
+}
+
+#[test]
+fn test_opt_level() {
+    let program = temp_file("opt-level.b");
+    fs::write(&program, "[-]+-[.>]>><.").unwrap();
+    let program = program.to_str().unwrap();
+
+    // The loop after the clear loop never runs and is removed at level 3.
+    let output = brainfuck(&["compile", "--opt-level", "3", program], b"");
+    assert_eq!(
+        stdout(&output),
+        "     0  SetZero\n     1  IncDP(1)\n     2  WriteByte(1)\n"
+    );
+
+    for level in ["0", "1", "2", "3"] {
+        let output = brainfuck(&["--env", "vm", "--opt-level", level, program], b"");
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(output.stdout, [0]);
+    }
+
+    let output = brainfuck(&["--no-opt", "--opt-level", "2", program], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("`--no-opt` can not be combined with `--opt-level 2`"));
+
+    let output = brainfuck(&["compile", "--opt-level", "4", program], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("valid values are `0`, `1`, `2` and `3`"));
+}
+
+#[test]
+fn test_compile_wasm() {
+    let module = temp_file("hello_world.wasm");
+    let module = module.to_str().unwrap();
+
+    let output = brainfuck(
+        &["compile", "--target", "wasm", HELLO_WORLD, "-o", module],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fs::read(module).unwrap().starts_with(b"\0asm"));
+
+    let output = brainfuck(&["compile", "--target", "wasm", HELLO_WORLD], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("write it to a file with `-o`"));
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_compile_elf() {
+    let executable = temp_file("hello_world");
+    let executable = executable.to_str().unwrap();
+
+    let output = brainfuck(
+        &["compile", "--target", "elf", HELLO_WORLD, "-o", executable],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = Command::new(executable).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Hello World!\n");
+}
+
+#[test]
+fn test_translate() {
+    let output = brainfuck(&["translate", HELLO_WORLD], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with("/* Translated from brainfuck"));
+    assert!(stdout(&output).contains("int main(void) {\n"));
+
+    let file = temp_file("translated.c");
+    let output = brainfuck(
+        &[
+            "translate",
+            "--to",
+            "c",
+            HELLO_WORLD,
+            "-o",
+            file.to_str().unwrap(),
+        ],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(fs::read_to_string(&file)
+        .unwrap()
+        .contains("putchar(tape[dp]);"));
+
+    let output = brainfuck(&["translate", "--to", "rust", HELLO_WORLD], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("fn main() {\n    let mut m = Machine::new();\n"));
+
+    let output = brainfuck(&["translate", "--to", "cobol", HELLO_WORLD], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("unknown language `cobol`"));
+}
+
+#[test]
+fn test_source_maps() {
+    let program = temp_file("source-map.b");
+    fs::write(&program, ",\n[.,]").unwrap();
+    let program = program.to_str().unwrap();
+    let translated = temp_file("source-map.c");
+    let translated = translated.to_str().unwrap();
+
+    let output = brainfuck(
+        &["translate", "--source-map", program, "-o", translated],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let c = fs::read_to_string(translated).unwrap();
+    let map = fs::read_to_string(format!("{translated}.map")).unwrap();
+    assert!(map.contains(&format!(r#""file":"{translated}","source":"{program}""#)));
+    // The read in the loop is at line 2 column 3.
+    let lines: Vec<&str> = c.lines().collect();
+    let line = lines.iter().rposition(|line| line.trim() == "read_byte();");
+    assert!(map.contains(&format!(
+        r#"{{"generated":{},"line":2,"column":3,"end_line":2,"end_column":3}}"#,
+        line.unwrap() + 1
+    )));
+
+    let module = temp_file("source-map.wasm");
+    let module = module.to_str().unwrap();
+    let output = brainfuck(
+        &[
+            "compile",
+            "--target",
+            "wasm",
+            "--source-map",
+            program,
+            "-o",
+            module,
+        ],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    let map = fs::read_to_string(format!("{module}.map")).unwrap();
+    assert!(map.contains(r#""line":2,"column":3,"end_line":2,"end_column":3}"#));
+
+    let output = brainfuck(&["translate", "--source-map", program], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("`--source-map` needs the translation written with `-o`"));
+
+    let output = brainfuck(&["compile", "--source-map", program, "-o", module], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("`--source-map` is only supported for `--target wasm`"));
+}
+
+#[test]
+fn test_bytecode() {
+    let bytecode = temp_file("hello_world.bfc");
+    let bytecode = bytecode.to_str().unwrap();
+
+    let output = brainfuck(&["compile", HELLO_WORLD, "-o", bytecode], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["run", "--env", env, bytecode], b"");
+
+        assert!(output.status.success(), "env {env}: {}", stderr(&output));
+        assert_eq!(stdout(&output), "Hello World!\n", "env {env}");
+    }
+
+    let output = brainfuck(&["--dump-tape", bytecode], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("`--dump-tape` requires the source"));
+
+    let mut corrupt = fs::read(bytecode).unwrap();
+    corrupt.truncate(corrupt.len() - 1);
+    fs::write(bytecode, corrupt).unwrap();
+    let output = brainfuck(&[bytecode], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("invalid compiled program"));
+}
+
+#[test]
+fn test_dump_ir() {
+    let program = temp_file("dump-ir.b");
+    fs::write(&program, "+[.-]").unwrap();
+    let program = program.to_str().unwrap();
+
+    let output = brainfuck(&["--dump-ir", program], b"");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [1]);
+    assert_eq!(
+        stderr(&output),
+        "     0  IncByteAtDP(1)\n\
+         \x20    1  JumpZero(4) -> 5\n\
+         \x20    2    WriteByte(1)\n\
+         \x20    3    DecByteAtDP(1)\n\
+         \x20    4  JumpNotZero(2) -> 2\n"
+    );
+}
+
+#[test]
+#[cfg(jit)]
+fn test_dump_asm() {
+    let output = brainfuck(&["--dump-asm", HELLO_WORLD], b"");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Hello World!\n");
+    assert!(stderr(&output).starts_with("; setup\n00000000  "));
+    assert!(stderr(&output).contains("\n; 0  IncByteAtDP(1)\n"));
+}
+
+#[test]
+fn test_speed() {
+    let program = temp_file("speed.b");
+    fs::write(&program, "+++.").unwrap();
+    let program = program.to_str().unwrap();
+
+    // The interpreter executes 4 instructions, at 40 per second they take 100 ms.
+    let start = Instant::now();
+    let output = brainfuck(&["--env", "interpreter", "--speed", "40", program], b"");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(output.stdout, [3]);
+
+    let output = brainfuck(&["--env", "closures", "--speed", "40", program], b"");
+    assert_eq!(output.stdout, [3]);
+    assert!(stderr(&output).contains("note: limiting the speed requires the virtual machine"));
+
+    let output = brainfuck(&["--speed", "0", program], b"");
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_tape_size() {
+    let program = temp_file("tape-size.b");
+    fs::write(&program, ">".repeat(30_000) + "+++.").unwrap();
+    let program = program.to_str().unwrap();
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["--env", env, "--tape-size", "30001", program], b"");
+        assert!(output.status.success(), "{env}: {}", stderr(&output));
+        assert_eq!(output.stdout, [3], "{env}");
+    }
+
+    let program = temp_file("growable-tape.b");
+    fs::write(&program, ">".repeat(100) + "+++.[<]>[>]<.").unwrap();
+    let program = program.to_str().unwrap();
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let args = [
+            "--env",
+            env,
+            "--tape-size",
+            "10",
+            "--tape",
+            "growable",
+            program,
+        ];
+        let output = brainfuck(&args, b"");
+        assert!(output.status.success(), "{env}: {}", stderr(&output));
+        assert_eq!(output.stdout, [3, 3], "{env}");
+    }
+
+    let output = brainfuck(&["--tape-size", "0", program], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("the tape needs at least one cell"));
+}
+
+#[test]
+fn test_cell_width() {
+    let expected = [
+        ("8", "Hello World! 255\n"),
+        ("16", "Hello world! 65535\n"),
+        ("32", "Hello, world!\n"),
+    ];
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        for (width, expected) in expected {
+            let args = ["--env", env, "--cell-width", width, "programs/bitwidth.b"];
+            let output = brainfuck(&args, b"");
+            assert!(output.status.success(), "{env}: {}", stderr(&output));
+            assert_eq!(stdout(&output), expected, "{env} with {width} bit cells");
+        }
+    }
+
+    // Reads are zero extended and only the lowest byte is written.
+    let program = temp_file("cell-width.b");
+    fs::write(&program, ",+.[[-]+++.[-]]").unwrap();
+    let program = program.to_str().unwrap();
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["--env", env, "--cell-width", "16", program], &[255]);
+        assert!(output.status.success(), "{env}: {}", stderr(&output));
+        assert_eq!(output.stdout, [0, 3], "{env}");
+    }
+    fs::remove_file(program).unwrap();
+
+    let output = brainfuck(&["--cell-width", "64", HELLO_WORLD], b"");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_eof_behavior() {
+    let program = temp_file("eof.b");
+    fs::write(&program, "+,.+,.").unwrap();
+    let program = program.to_str().unwrap();
+    let expected = [
+        ("zero", [7, 0]),
+        ("minus-one", [7, 255]),
+        ("unchanged", [7, 8]),
+    ];
+
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        for width in ["8", "16"] {
+            for (eof, expected) in expected {
+                let args = ["--env", env, "--cell-width", width, "--eof", eof, program];
+                let output = brainfuck(&args, &[7]);
+                assert!(output.status.success(), "{env}: {}", stderr(&output));
+                assert_eq!(output.stdout, expected, "{env} with {eof} at {width} bits");
+            }
+        }
+
+        let output = brainfuck(&["--env", env, program], &[7]);
+        assert_eq!(output.status.code(), Some(1), "{env}");
+        assert_eq!(output.stdout, [7], "{env}");
+    }
+    fs::remove_file(program).unwrap();
+}
+
+#[test]
+fn test_record_and_replay() {
+    let program = echo_program("record.b");
+    let recording = temp_file("record.bfrun");
+    let recording = recording.to_str().unwrap();
+
+    let output = brainfuck(
+        &[
+            "--env",
+            "closures",
+            "--record",
+            recording,
+            program.to_str().unwrap(),
+        ],
+        b"hi!",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "hi!");
+
+    let output = brainfuck(&["replay", recording], b"ignored");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "hi!");
+
+    let tampered = fs::read_to_string(recording)
+        .unwrap()
+        .replace("output = [104,105,33]", "output = [104,111,33]");
+    fs::write(recording, tampered).unwrap();
+    let output = brainfuck(&["replay", recording], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("the output differs from the recording starting at byte 1"));
+
+    fs::remove_file(program).unwrap();
+    fs::remove_file(recording).unwrap();
+}
+
+#[test]
+fn test_missing_file() {
+    let output = brainfuck(&["does-not-exist.b"], b"");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("failed to open file does-not-exist.b"));
+}
+
+#[test]
+fn test_check_reports_unbalanced_brackets() {
+    let output = brainfuck(&["check"], b"+[>+<-]]");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("unmatched `]` at line 1 column 8"));
+}
+
+#[test]
+fn test_run_reports_unbalanced_brackets() {
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["--env", env], b"+[>+\n<-]].");
+
+        assert_eq!(output.status.code(), Some(1), "{env}");
+        assert!(
+            stderr(&output).contains("unmatched `]` at line 2 column 4"),
+            "{env}: {}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn test_run_reports_pointer_out_of_bounds() {
+    for env in ["interpreter", "vm", "closures", "jit"] {
+        let output = brainfuck(&["--env", env], b"+.<.");
+
+        assert_eq!(output.status.code(), Some(1), "{env}");
+        assert_eq!(output.stdout, [1], "{env}");
+        assert!(
+            stderr(&output).contains("moved the data pointer outside of the tape to cell -1"),
+            "{env}: {}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn test_run_reports_source_position() {
+    let program = temp_file("source_position.b");
+    fs::write(&program, "+.\n  >><<<").unwrap();
+
+    for args in [
+        &["--env", "vm"][..],
+        &["--env", "closures"],
+        &["--env", "jit"],
+        &["--stats"],
+    ] {
+        let output = brainfuck(&[args, &[program.to_str().unwrap()]].concat(), b"");
+
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        // Folded instructions start at their first character.
+        assert!(
+            stderr(&output).contains("failed to execute the program")
+                && stderr(&output).contains(" at line 2 column 5\n"),
+            "{args:?}: {}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn test_check_assertions() {
+    let program = b"++[>+++\n;; assert loop<=2\n[-.]<-]";
+
+    let output = brainfuck(&["--env", "vm", "--check-assertions"], program);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, [2, 1]);
+    assert!(stderr(&output).contains(
+        "assertion `loop<=2` at line 2 column 1 failed: \
+         the loop at line 3 column 1 ran more than 2 iterations"
+    ));
+
+    // Assertions are only checked on request.
+    let output = brainfuck(&["--env", "vm"], program);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [2, 1, 0, 2, 1, 0]);
+}
+
+#[test]
+fn test_limits() {
+    for env in ["interpreter", "vm", "jit", "closures"] {
+        let output = brainfuck(&["--env", env, "--max-steps", "1000"], b"+.+[]");
+        assert!(!output.status.success(), "{env}");
+        assert_eq!(output.stdout, [1], "{env}");
+        assert!(
+            stderr(&output).contains("the program exceeded the limit of 1000 steps"),
+            "{env}: {}",
+            stderr(&output)
+        );
+    }
+
+    // The steps are those of the source, however the program is optimized.
+    let sources: [&[u8]; 2] = [b"++++++++++++++++++++.", b"+++[->+<]>."];
+    for env in ["interpreter", "vm", "jit", "closures"] {
+        for level in ["0", "1", "2", "3"] {
+            for source in sources {
+                let args = ["--env", env, "--opt-level", level, "--max-steps", "10"];
+                let output = brainfuck(&args, source);
+                assert!(!output.status.success(), "{env} {level}");
+                assert_eq!(output.stdout, [], "{env} {level}");
+                assert!(
+                    stderr(&output).contains("the program exceeded the limit of 10 steps"),
+                    "{env} {level}: {}",
+                    stderr(&output)
+                );
+            }
+        }
+    }
+
+    let output = brainfuck(&["--env", "closures", "--timeout", "0.05"], b"+[]");
+    assert!(!output.status.success());
+    assert!(stderr(&output).starts_with(
+        "note: limiting the execution requires the virtual machine, using it instead\n"
+    ));
+    assert!(stderr(&output).contains("the program exceeded the timeout of 50ms"));
+
+    let output = brainfuck(&["--timeout", "soon"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("expected a number of seconds, e.g. `1.5`"));
+}
+
+#[test]
+fn test_bench_compile() {
+    let output = brainfuck(&["bench", "compile", "--max-size", "10000"], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines: Vec<&str> = stdout(&output).lines().collect();
+    assert_eq!(lines[0], "size,phase,nanoseconds");
+    assert!(lines[1].starts_with("1000,tokenize,"));
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with("10000,fold-runs,")));
+
+    let output = brainfuck(
+        &["bench", "compile", "--max-size", "1000", "--format", "json"],
+        b"",
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with(r#"[{"size":1000,"phase":"tokenize","nanoseconds":"#));
+
+    let output = brainfuck(&["bench"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("missing the program to measure"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt() {
+    let file = temp_file("interrupt.b");
+    fs::write(&file, ">+++[]").unwrap();
+    let file = file.to_str().unwrap();
+    let runs: [&[&str]; 4] = [
+        &["--env", "interpreter"],
+        &["--env", "interpreter", "--speed", "100"],
+        &["--env", "vm"],
+        &["--env", "jit"],
+    ];
+    for args in runs {
+        let child = Command::new(env!("CARGO_BIN_EXE_brainfuck"))
+            .args(args)
+            .arg(file)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.status.code(), Some(130), "{args:?}");
+        assert!(
+            stderr(&output).starts_with("interrupted after "),
+            "{args:?}: {}",
+            stderr(&output)
+        );
+        assert!(
+            stderr(&output)
+                .ends_with(" instructions at cell 1\ncells 0..10: 0 [3] 0 0 0 0 0 0 0 0\n"),
+            "{args:?}: {}",
+            stderr(&output)
+        );
+    }
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn test_sanitize() {
+    let output = brainfuck(
+        &["--env", "vm", "--sanitize", "--eof", "zero"],
+        b"-.\n>,[<+>-]++++++++[-<+++++>]<",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [255]);
+    assert_eq!(
+        stderr(&output),
+        "warning: cell wraparound at line 1 column 1 (cell 0)
+warning: output of a non-ASCII byte at line 1 column 2 (cell 0)
+warning: read at the end of the input at line 2 column 2 (cell 1)
+warning: cell wraparound at line 2 column 17 (cell 1)
+"
+    );
+
+    // Without optimizations, the clear loop `[+]` wraps the cell.
+    let output = brainfuck(
+        &["--env", "vm", "--sanitize", "--no-opt"],
+        b"++[>[-]-<-]+[+]",
+    );
+    assert_eq!(
+        stderr(&output),
+        "warning: cell wraparound at line 1 column 8 (cell 1)
+warning: cell wraparound at line 1 column 14 (cell 0)
+note: 1 repeated occurrences at the same positions were not reported
+"
+    );
+}
+
+#[test]
+fn test_check_reports_invalid_loop_assertions() {
+    let output = brainfuck(&["check"], b"+\n;; assert loop<=3\n+[-]\n");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output)
+        .contains("invalid loop assertion at line 2 column 1: expected a loop, found `+`"));
+}
+
+#[test]
+fn test_check_balanced_program() {
+    let output = brainfuck(&["check", HELLO_WORLD], b"");
+
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_check_reports_invalid_cell_names() {
+    let output = brainfuck(&["check"], b"+\n;; name cell 0 my-counter\n");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output)
+        .contains("invalid cell name at line 2 column 1: `my-counter` is not an identifier"));
+}
+
+#[test]
+fn test_check_reports_unknown_extensions() {
+    let output = brainfuck(&["check"], b"+\n;; extension goto\n");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains(
+        "invalid extension at line 2 column 1: unknown extension `goto`, expected one of: exit"
+    ));
+}
+
+#[test]
+fn test_semicolons_without_directive_are_comments() {
+    let program = temp_file("semicolons.b");
+    fs::write(&program, format!("x;; {}.\n", "+".repeat(65))).unwrap();
+
+    let output = brainfuck(&[program.to_str().unwrap()], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "A");
+}
+
+#[test]
+fn test_fmt_keeps_directives() {
+    let output = brainfuck(
+        &["fmt"],
+        b"comment ;; name cell 1 counter\n+[>+ ;; expect \"[body]\"\n<-]",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        ";; name cell 1 counter\n+[\n    >+\n    ;; expect \"[body]\"\n    <-\n]\n"
+    );
+}
+
+#[test]
+fn test_dump_tape_shows_cell_names() {
+    let output = brainfuck(
+        &["--env", "vm", "--dump-tape"],
+        b";; name cell 1 counter\n>+++",
+    );
+
+    assert!(output.status.success());
+    assert!(stderr(&output).ends_with("     0: 0 3\ncounter=3\n"));
+}
+
+/// Creates a project directory with a manifest whose tests expect the output of `echo_program`.
+fn project(name: &str, tests: &str) -> PathBuf {
+    let dir = temp_file(name);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/echo.b"), ",.,.,.").unwrap();
+    fs::write(
+        dir.join("bf.toml"),
+        format!("[package]\nname = \"echo\"\nmain = \"src/echo.b\"\n\n[build]\ntargets = [\"instructions\"]\n\n{tests}"),
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn test_run_project() {
+    let dir = project("run-project", "");
+    let output = brainfuck_in(&dir.join("src"), &["--env", "vm"], b"abc");
+
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "abc");
+}
+
+#[test]
+fn test_project_tests() {
+    let dir = project(
+        "project-tests",
+        "[[test]]\nname = \"echo\"\ninput = \"abc\"\noutput = \"abc\"\n\n\
+        [[test]]\nname = \"wrong\"\ninput = \"abc\"\noutput = \"abd\"\n",
+    );
+    let output = brainfuck_in(&dir, &["test"], b"");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("test echo ... ok\ntest wrong ... FAILED\n"));
+    assert!(stdout(&output).contains("expected: \"abd\"\nactual:   \"abc\"\n"));
+    assert!(stderr(&output).contains("1 of 2 tests failed"));
+}
+
+#[test]
+fn test_expected_output() {
+    let file = temp_file("expect.b");
+    fs::write(&file, ";; expect \"\\x01\"\n+.\n;; expect \"\\x02\"\n+.").unwrap();
+    let output = brainfuck(&["test", file.to_str().unwrap()], b"");
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(" ... ok\n"));
+
+    fs::write(&file, ";; expect \"hi\"\n+.").unwrap();
+    let output = brainfuck(&["test", file.to_str().unwrap()], b"");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("expected: \"hi\"\nactual:   \"\\u{1}\"\n"));
+    assert!(stderr(&output).contains("1 of 1 tests failed"));
+}
+
+#[test]
+fn test_new_project() {
+    let dir = temp_file("new-project");
+    fs::create_dir_all(&dir).unwrap();
+    let output = brainfuck_in(&dir, &["new", "greeter"], b"");
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).starts_with("created greeter/bf.toml\n"));
+    let project = dir.join("greeter");
+    assert_eq!(
+        fs::read_to_string(project.join(".gitignore")).unwrap(),
+        "/target\n"
+    );
+
+    let output = brainfuck_in(&project, &["test"], b"");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("test hello ... ok\n"));
+    let output = brainfuck_in(&project, &["--env", "vm"], b"");
+    assert_eq!(stdout(&output), "Hello World!\n");
+
+    let output = brainfuck_in(&dir, &["new", "greeter"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("greeter already exists"));
+    let output = brainfuck_in(&dir, &["new", "my project"], b"");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("invalid project name `my project`"));
+}
+
+#[test]
+fn test_build_project() {
+    let dir = project("build-project", "");
+    let output = brainfuck_in(&dir, &["build"], b"");
+
+    assert!(output.status.success());
+    assert!(fs::read_to_string(dir.join("target/echo.instructions"))
+        .unwrap()
+        .starts_with("     0  ReadByte\n     1  WriteByte(1)\n"));
+}
+
+#[test]
+fn test_build_source_map() {
+    let dir = project("build-source-map", "");
+    let manifest = fs::read_to_string(dir.join("bf.toml")).unwrap();
+    fs::write(
+        dir.join("bf.toml"),
+        manifest.replace("[build]", "[build]\nsource-maps = true"),
+    )
+    .unwrap();
+    let output = brainfuck_in(&dir, &["build"], b"");
+
+    assert!(output.status.success());
+    let map = fs::read_to_string(dir.join("target/echo.instructions.map")).unwrap();
+    assert!(map.contains(
+        r#""mappings":[{"generated":1,"line":1,"column":1,"end_line":1,"end_column":1},"#
+    ));
+}
+
+#[test]
+fn test_invalid_manifest() {
+    let dir = project("invalid-manifest", "[[test]]\ninput = \"\"\n");
+    let output = brainfuck_in(&dir, &["test"], b"");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("`test[0]` needs `output` or `output-file`"));
+}
+
+#[test]
+fn test_preprocess_includes() {
+    let dir = temp_file("includes");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.b"), ";;include \"lib/echo.b\"\n,.").unwrap();
+    fs::write(dir.join("lib/echo.b"), ",.\n").unwrap();
+
+    let output = brainfuck_in(&dir, &["--env", "vm", "--preprocess", "main.b"], b"ab");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "ab");
+
+    let output = brainfuck_in(&dir, &["--env", "vm", "main.b"], b"ab");
+    assert_eq!(stdout(&output), "a");
+}
+
+#[test]
+fn test_check_maps_positions_to_included_files() {
+    let dir = temp_file("include-errors");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.b"), "+\n;;include \"lib.b\"\n+").unwrap();
+    fs::write(dir.join("lib.b"), "[-]\n>]").unwrap();
+
+    let output = brainfuck_in(&dir, &["check", "--preprocess", "main.b"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("unmatched `]` at lib.b:2:2"));
+
+    fs::write(dir.join("lib.b"), ";;include \"main.b\"").unwrap();
+    let output = brainfuck_in(&dir, &["check", "--preprocess", "main.b"], b"");
+    assert!(stderr(&output).contains("cyclic include: main.b -> lib.b -> main.b"));
+}
+
+#[test]
+fn test_capabilities_json() {
+    let output = brainfuck(&["capabilities", "--format", "json"], b"");
+
+    assert!(output.status.success());
+    let json = stdout(&output);
+    assert!(json.starts_with(r#"{"format_version":1,"#));
+    assert!(json.contains(r#""backends":["interpreter","vm""#));
+}
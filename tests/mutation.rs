@@ -0,0 +1,227 @@
+//! Applies random rewrites to compiled programs and checks that the ones changing the semantics
+//! are rejected by the verifier or by comparing the output with the interpreter, while the ones
+//! preserving the semantics are accepted by both.
+
+use std::io;
+
+use brainfuck::compiler::{Compiler, Instruction};
+use brainfuck::interpreter::Interpreter;
+use brainfuck::verifier::verify;
+use brainfuck::virtual_machine::VirtualMachine;
+use brainfuck::{EofBehavior, FlushBehavior};
+
+/// Programs whose write instructions are all executed, so changing one of them always changes
+/// the output.
+const PROGRAMS: &[&str] = &[
+    include_str!("../programs/hello_world.b"),
+    "++++++++[>++++++++<-]>+.+.+.>++++[<++++>-]<.",
+    "+++[>+++++<-]>[>+>++<<-]>.>.<<++++++[>>+++++<<-]>>.",
+];
+
+const MUTATIONS_PER_PROGRAM: usize = 200;
+
+/// A small xorshift generator, good enough to pick mutations reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Recomputes the offsets of all jumps from the nesting of the loops.
+fn relink(instructions: &mut [Instruction]) {
+    let mut open = Vec::new();
+    for i in 0..instructions.len() {
+        match instructions[i] {
+            Instruction::JumpZero(_) => open.push(i),
+            Instruction::JumpNotZero(_) => {
+                let start = open.pop().unwrap();
+                instructions[start] = Instruction::JumpZero(i - start + 1);
+                instructions[i] = Instruction::JumpNotZero(i - start - 1);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn positions(instructions: &[Instruction], f: impl Fn(&Instruction) -> bool) -> Vec<usize> {
+    (0..instructions.len())
+        .filter(|i| f(&instructions[*i]))
+        .collect()
+}
+
+/// Rewrites that must not change what the program does.
+fn preserving_mutation(rng: &mut Rng, mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    match rng.below(3) {
+        0 => {
+            // Split a folded instruction into two.
+            let candidates = positions(&instructions, |instruction| {
+                matches!(
+                    instruction,
+                    Instruction::IncByteAtDP(2..) | Instruction::DecByteAtDP(2..)
+                )
+            });
+            if let Some(&i) = candidates.get(rng.below(candidates.len().max(1))) {
+                let (first, second) = match instructions[i] {
+                    Instruction::IncByteAtDP(n) => {
+                        let k = 1 + rng.below(n - 1);
+                        (Instruction::IncByteAtDP(k), Instruction::IncByteAtDP(n - k))
+                    }
+                    Instruction::DecByteAtDP(n) => {
+                        let k = 1 + rng.below(n - 1);
+                        (Instruction::DecByteAtDP(k), Instruction::DecByteAtDP(n - k))
+                    }
+                    _ => unreachable!(),
+                };
+                instructions[i] = first;
+                instructions.insert(i + 1, second);
+            }
+        }
+        1 => {
+            // Move the data pointer forth and back.
+            let i = rng.below(instructions.len() + 1);
+            let n = 1 + rng.below(4);
+            instructions.splice(i..i, [Instruction::IncDP(n), Instruction::DecDP(n)]);
+        }
+        _ => {
+            // Increment and decrement the byte at the data pointer by the same amount.
+            let i = rng.below(instructions.len() + 1);
+            let n = 1 + rng.below(255);
+            instructions.splice(
+                i..i,
+                [Instruction::IncByteAtDP(n), Instruction::DecByteAtDP(n)],
+            );
+        }
+    }
+
+    relink(&mut instructions);
+    instructions
+}
+
+/// Rewrites that break the program, returning whether the verifier has to reject it.
+fn breaking_mutation(rng: &mut Rng, instructions: &mut Vec<Instruction>) -> bool {
+    let jumps = positions(instructions, |instruction| {
+        matches!(
+            instruction,
+            Instruction::JumpZero(_) | Instruction::JumpNotZero(_)
+        )
+    });
+    let writes = positions(instructions, |instruction| {
+        matches!(instruction, Instruction::WriteByte(_))
+    });
+
+    match rng.below(5) {
+        0 if !jumps.is_empty() => {
+            // Change the offset of a jump.
+            let i = jumps[rng.below(jumps.len())];
+            let delta = 1 + rng.below(3);
+            instructions[i] = match instructions[i] {
+                Instruction::JumpZero(n) => Instruction::JumpZero(n + delta),
+                Instruction::JumpNotZero(n) => Instruction::JumpNotZero(n + delta),
+                _ => unreachable!(),
+            };
+            true
+        }
+        1 => {
+            // Repeat an operation zero times.
+            let i = rng.below(instructions.len());
+            match instructions[i] {
+                Instruction::IncDP(_) => instructions[i] = Instruction::IncDP(0),
+                Instruction::IncByteAtDP(_) => instructions[i] = Instruction::IncByteAtDP(0),
+                _ => instructions.insert(i, Instruction::WriteByte(0)),
+            }
+            relink(instructions);
+            true
+        }
+        2 => {
+            // Write a byte a different number of times.
+            let i = writes[rng.below(writes.len())];
+            if let Instruction::WriteByte(n) = instructions[i] {
+                instructions[i] = Instruction::WriteByte(n + 1 + rng.below(3));
+            }
+            false
+        }
+        3 => {
+            // Write a different byte, but restore the data afterwards.
+            let i = writes[rng.below(writes.len())];
+            let n = 1 + rng.below(255);
+            instructions.insert(i + 1, Instruction::DecByteAtDP(n));
+            instructions.insert(i, Instruction::IncByteAtDP(n));
+            relink(instructions);
+            false
+        }
+        _ => {
+            // Drop a write.
+            let i = writes[rng.below(writes.len())];
+            instructions.remove(i);
+            relink(instructions);
+            false
+        }
+    }
+}
+
+fn interpret(program: &str) -> Vec<u8> {
+    let mut writer = Vec::new();
+    Interpreter::new(program, &mut io::empty(), &mut writer)
+        .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+        .unwrap();
+    writer
+}
+
+fn run(instructions: &[Instruction]) -> Vec<u8> {
+    let mut writer = Vec::new();
+    VirtualMachine::new(instructions, &mut io::empty(), &mut writer)
+        .execute(FlushBehavior::OnEnd, EofBehavior::Error)
+        .unwrap();
+    writer
+}
+
+#[test]
+fn test_preserving_mutations_are_accepted() {
+    let mut rng = Rng(0x5eed_1986);
+
+    for program in PROGRAMS {
+        let expected = interpret(program);
+        let compiled = Compiler::new(program).compile().unwrap();
+
+        for _ in 0..MUTATIONS_PER_PROGRAM {
+            let mut mutant = compiled.clone();
+            for _ in 0..1 + rng.below(3) {
+                mutant = preserving_mutation(&mut rng, mutant);
+            }
+
+            assert_eq!(verify(&mutant), Ok(()), "mutant {mutant:?}");
+            assert_eq!(run(&mutant), expected, "mutant {mutant:?}");
+        }
+    }
+}
+
+#[test]
+fn test_breaking_mutations_are_caught() {
+    let mut rng = Rng(0xdead_1986);
+
+    for program in PROGRAMS {
+        let expected = interpret(program);
+        let compiled = Compiler::new(program).compile().unwrap();
+
+        for _ in 0..MUTATIONS_PER_PROGRAM {
+            let mut mutant = compiled.clone();
+            let invalid = breaking_mutation(&mut rng, &mut mutant);
+
+            if invalid {
+                assert!(verify(&mutant).is_err(), "mutant {mutant:?}");
+            } else {
+                assert_eq!(verify(&mutant), Ok(()), "mutant {mutant:?}");
+                assert_ne!(run(&mutant), expected, "mutant {mutant:?}");
+            }
+        }
+    }
+}